@@ -175,6 +175,7 @@ class NotificationBanner(
         val messageText = when (cause) {
             null -> null
             is ErrorStateCause.AuthFailed -> R.string.auth_failed
+            is ErrorStateCause.AccountExpired -> R.string.account_expired
             is ErrorStateCause.Ipv6Unavailable -> R.string.ipv6_unavailable
             is ErrorStateCause.SetFirewallPolicyError -> R.string.set_firewall_policy_error
             is ErrorStateCause.SetDnsError -> R.string.set_dns_error
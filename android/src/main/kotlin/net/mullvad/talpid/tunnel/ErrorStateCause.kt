@@ -2,6 +2,7 @@ package net.mullvad.talpid.tunnel
 
 sealed class ErrorStateCause {
     class AuthFailed(val reason: String?) : ErrorStateCause()
+    class AccountExpired : ErrorStateCause()
     class Ipv6Unavailable : ErrorStateCause()
     class SetFirewallPolicyError : ErrorStateCause()
     class SetDnsError : ErrorStateCause()
@@ -2,6 +2,7 @@
 set -eu
 
 if which systemctl &> /dev/null; then
+    systemctl enable "/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service"
     systemctl enable "/opt/Mullvad VPN/resources/mullvad-daemon.service"
     systemctl start mullvad-daemon.service
 elif /sbin/init --version | grep upstart &> /dev/null; then
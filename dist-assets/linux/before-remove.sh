@@ -18,6 +18,11 @@ if which systemctl &> /dev/null; then
     # the user might've disabled or stopped the service themselves already
     systemctl stop mullvad-daemon.service || true
     systemctl disable mullvad-daemon.service || true
+    systemctl disable mullvad-early-boot-blocking.service || true
+    # remove the rules of the daemon, and keep lockdown mode from blocking traffic on the next boot
+    "/opt/Mullvad VPN/resources/mullvad-setup" reset-firewall || true
+    # restore the DNS settings in case the daemon was killed while it had changed them
+    "/opt/Mullvad VPN/resources/mullvad-setup" restore-dns || true
 elif /sbin/init --version | grep upstart &> /dev/null; then
     stop mullvad-daemon
     rm -f /etc/init/mullvad-daemon.conf
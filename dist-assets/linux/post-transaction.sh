@@ -2,5 +2,6 @@
 # This is to mitigate post-uninstall hooks being ran AFTER post-install hooks
 # during an upgrade on Fedora.
 set -eu
+systemctl enable "/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service" || true
 systemctl enable "/opt/Mullvad VPN/resources/mullvad-daemon.service" || true
 systemctl start mullvad-daemon.service || true
@@ -254,6 +254,16 @@ const tunnelStateSchema = oneOf(
         ),
       }),
       location: maybe(locationSchema),
+      quality: maybe(
+        partialObject({
+          score: number,
+          degraded: boolean,
+          handshake_age: maybe(number),
+          ping_rtt_ms: maybe(number),
+          ping_loss: number,
+          rx_stall: number,
+        }),
+      ),
     }),
   }),
   object({
@@ -263,14 +273,22 @@ const tunnelStateSchema = oneOf(
       cause: oneOf(
         object({
           reason: enumeration(
+            'account_expired',
             'ipv6_unavailable',
-            'set_firewall_policy_error',
-            'set_dns_error',
-            'start_tunnel_error',
             'is_offline',
             'tap_adapter_problem',
           ),
         }),
+        object({
+          reason: enumeration('set_firewall_policy_error', 'set_dns_error', 'start_tunnel_error'),
+          details: partialObject({
+            backend: string,
+            errno: maybe(number),
+            kind: maybe(string),
+            retry_likely: boolean,
+            message: string,
+          }),
+        }),
         object({
           reason: enumeration('auth_failed'),
           details: maybe(string),
@@ -364,6 +382,25 @@ const keygenEventSchema = oneOf(
   }),
 );
 
+const accountExpiryEventSchema = oneOf(
+  object({
+    expires_soon: object({
+      expiry: string,
+      threshold_hours: number,
+    }),
+  }),
+  object({
+    expired: object({
+      expiry: string,
+    }),
+  }),
+  object({
+    renewed: object({
+      expiry: string,
+    }),
+  }),
+);
+
 const daemonEventSchema = oneOf(
   object({
     tunnel_state: tunnelStateSchema,
@@ -380,6 +417,16 @@ const daemonEventSchema = oneOf(
   object({
     app_version_info: appVersionInfoSchema,
   }),
+  object({
+    account_expiry: accountExpiryEventSchema,
+  }),
+  object({
+    firewall_incident: object({
+      pid: maybe(number),
+      process_name: maybe(string),
+      restored: boolean,
+    }),
+  }),
 );
 
 export class ResponseParseError extends Error {
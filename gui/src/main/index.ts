@@ -548,6 +548,17 @@ class ApplicationMain {
           this.handleWireguardKeygenEvent(daemonEvent.wireguardKey);
         } else if ('appVersionInfo' in daemonEvent) {
           this.setLatestVersion(daemonEvent.appVersionInfo);
+        } else if ('accountExpiry' in daemonEvent) {
+          // The daemon noticed a change in the account expiry, so the cached one is stale.
+          this.accountDataCache.invalidate();
+          this.updateAccountData();
+        } else if ('firewallIncident' in daemonEvent) {
+          // The daemon restores the rules, or blocks in the error state if it can't
+          const incident = daemonEvent.firewallIncident;
+          log.warn(
+            `Firewall rules changed by ${incident.processName || 'an unknown process'}, ` +
+              (incident.restored ? 'restored' : 'unable to restore'),
+          );
         }
       },
       (error: Error) => {
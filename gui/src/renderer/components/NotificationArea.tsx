@@ -66,6 +66,8 @@ function getErrorCauseMessage(blockReason: ErrorStateCause): string {
   switch (blockReason.reason) {
     case 'auth_failed':
       return parseAuthFailure(blockReason.details).message;
+    case 'account_expired':
+      return messages.pgettext('in-app-notifications', 'Your account has no time left');
     case 'ipv6_unavailable':
       return messages.pgettext(
         'in-app-notifications',
@@ -23,17 +23,23 @@ export type TunnelParameterError =
 
 export type ErrorStateCause =
   | {
-      reason:
-        | 'ipv6_unavailable'
-        | 'set_firewall_policy_error'
-        | 'set_dns_error'
-        | 'start_tunnel_error'
-        | 'is_offline'
-        | 'tap_adapter_problem';
+      reason: 'account_expired' | 'ipv6_unavailable' | 'is_offline' | 'tap_adapter_problem';
+    }
+  | {
+      reason: 'set_firewall_policy_error' | 'set_dns_error' | 'start_tunnel_error';
+      details: IErrorDetails;
     }
   | { reason: 'tunnel_parameter_error'; details: TunnelParameterError }
   | { reason: 'auth_failed'; details?: string };
 
+export interface IErrorDetails {
+  backend: string;
+  errno?: number;
+  kind?: string;
+  retryLikely: boolean;
+  message: string;
+}
+
 export type AfterDisconnect = 'nothing' | 'block' | 'reconnect';
 
 export type TunnelType = 'wireguard' | 'openvpn';
@@ -87,11 +93,34 @@ export type DaemonEvent =
   | { settings: ISettings }
   | { relayList: IRelayList }
   | { wireguardKey: KeygenEvent }
-  | { appVersionInfo: IAppVersionInfo };
+  | { appVersionInfo: IAppVersionInfo }
+  | { accountExpiry: AccountExpiryEvent }
+  | { firewallIncident: IFirewallIncident };
+
+export interface IFirewallIncident {
+  pid?: number;
+  processName?: string;
+  restored: boolean;
+}
+
+export type AccountExpiryEvent =
+  | { expiresSoon: { expiry: string; thresholdHours: number } }
+  | { expired: { expiry: string } }
+  | { renewed: { expiry: string } };
 
 export interface ITunnelStateRelayInfo {
   endpoint: ITunnelEndpoint;
   location?: ILocation;
+  quality?: IConnectionQuality;
+}
+
+export interface IConnectionQuality {
+  score: number;
+  degraded: boolean;
+  handshakeAge?: number;
+  pingRttMs?: number;
+  pingLoss: number;
+  rxStall: number;
 }
 
 export type TunnelState =
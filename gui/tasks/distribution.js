@@ -113,6 +113,7 @@ const config = {
       { from: distAssets('binaries/x86_64-unknown-linux-gnu/sslocal'), to: '.' },
       { from: distAssets('linux/mullvad-daemon.conf'), to: '.' },
       { from: distAssets('linux/mullvad-daemon.service'), to: '.' },
+      { from: distAssets('linux/mullvad-early-boot-blocking.service'), to: '.' },
     ],
   },
 
@@ -126,6 +127,8 @@ const config = {
       '/opt/Mullvad VPN/resources/mullvad-daemon.service',
       '--config-files',
       '/opt/Mullvad VPN/resources/mullvad-daemon.conf',
+      '--config-files',
+      '/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service',
       distAssets('mullvad') + '=/usr/bin/',
       distAssets('linux/problem-report-link') + '=/usr/bin/mullvad-problem-report',
       distAssets('shell-completions/mullvad.bash') +
@@ -149,6 +152,8 @@ const config = {
       '/opt/Mullvad VPN/resources/mullvad-daemon.service',
       '--config-files',
       '/opt/Mullvad VPN/resources/mullvad-daemon.conf',
+      '--config-files',
+      '/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service',
       distAssets('mullvad') + '=/usr/bin/',
       distAssets('linux/problem-report-link') + '=/usr/bin/mullvad-problem-report',
       distAssets('shell-completions/mullvad.bash') +
@@ -1,5 +1,5 @@
 use crate::{new_rpc_client, Command, Result};
-use clap::value_t_or_exit;
+use clap::{value_t_or_exit, values_t_or_exit};
 use mullvad_types::account::{AccountToken, VoucherError};
 
 pub struct Account;
@@ -43,6 +43,24 @@ impl Command for Account {
                             .required(true),
                     ),
             )
+            .subcommand(
+                clap::SubCommand::with_name("expiry-warnings")
+                    .about("Control when to be warned about the account running out of time")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("get")
+                            .about("Display how many hours before the expiry to warn"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Set how many hours before the expiry to warn")
+                            .arg(
+                                clap::Arg::with_name("hours")
+                                    .help("Hours before the expiry. Leave out to disable warnings")
+                                    .multiple(true),
+                            ),
+                    ),
+            )
     }
 
     fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -58,6 +76,17 @@ impl Command for Account {
         } else if let Some(matches) = matches.subcommand_matches("redeem") {
             let voucher = value_t_or_exit!(matches.value_of("voucher"), String);
             self.redeem_voucher(voucher)
+        } else if let Some(matches) = matches.subcommand_matches("expiry-warnings") {
+            if let Some(set_matches) = matches.subcommand_matches("set") {
+                let warnings = if set_matches.is_present("hours") {
+                    values_t_or_exit!(set_matches.values_of("hours"), u32)
+                } else {
+                    Vec::new()
+                };
+                self.set_expiry_warnings(warnings)
+            } else {
+                self.get_expiry_warnings()
+            }
         } else {
             unreachable!("No account command given");
         }
@@ -119,6 +148,29 @@ impl Account {
         }
     }
 
+    fn set_expiry_warnings(&self, warnings: Vec<u32>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_account_expiry_warnings(warnings)?;
+        println!("Account expiry warnings have been updated");
+        Ok(())
+    }
+
+    fn get_expiry_warnings(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let warnings = rpc.get_settings()?.account_expiry_warnings;
+        if warnings.is_empty() {
+            println!("Account expiry warnings: disabled");
+        } else {
+            let hours = warnings
+                .iter()
+                .map(|hours| hours.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("Account expiry warnings: {} hours before expiry", hours);
+        }
+        Ok(())
+    }
+
     fn format_duration(seconds: u64) -> String {
         let dur = chrono::Duration::seconds(seconds as i64);
         if dur.num_days() > 0 {
@@ -0,0 +1,106 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+use ipnetwork::IpNetwork;
+use talpid_types::net::{AllowedEndpoint as Endpoint, TransportProtocol};
+
+pub struct AllowedEndpoint;
+
+impl Command for AllowedEndpoint {
+    fn name(&self) -> &'static str {
+        "allowed-endpoint"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage endpoints that can be reached outside the tunnel while connecting or \
+                 blocked, such as captive portals and login services",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Allow an endpoint, such as 192.0.2.10/32 443 tcp")
+                    .args(&endpoint_args()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("delete")
+                    .about("Stop allowing an endpoint")
+                    .args(&endpoint_args()),
+            )
+            .subcommand(clap::SubCommand::with_name("list").about("List the allowed endpoints"))
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(matches) = matches.subcommand_matches("add") {
+            self.add(parse_endpoint(matches))
+        } else if let Some(matches) = matches.subcommand_matches("delete") {
+            self.delete(parse_endpoint(matches))
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list()
+        } else {
+            unreachable!("No allowed-endpoint command given");
+        }
+    }
+}
+
+impl AllowedEndpoint {
+    fn add(&self, endpoint: Endpoint) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut endpoints = rpc.get_settings()?.allowed_endpoints;
+        if endpoints.contains(&endpoint) {
+            println!("{} is already allowed", endpoint);
+            return Ok(());
+        }
+        endpoints.push(endpoint);
+        rpc.set_allowed_endpoints(endpoints)?;
+        println!("Allowed {}", endpoint);
+        Ok(())
+    }
+
+    fn delete(&self, endpoint: Endpoint) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut endpoints = rpc.get_settings()?.allowed_endpoints;
+        let count = endpoints.len();
+        endpoints.retain(|allowed| *allowed != endpoint);
+        if endpoints.len() == count {
+            println!("{} is not allowed", endpoint);
+            return Ok(());
+        }
+        rpc.set_allowed_endpoints(endpoints)?;
+        println!("{} is no longer allowed", endpoint);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<()> {
+        let endpoints = new_rpc_client()?.get_settings()?.allowed_endpoints;
+        if endpoints.is_empty() {
+            println!("No endpoints are allowed");
+        } else {
+            println!("Allowed endpoints:");
+            for endpoint in endpoints {
+                println!("{}", endpoint);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn endpoint_args() -> [clap::Arg<'static, 'static>; 3] {
+    [
+        clap::Arg::with_name("network")
+            .help("The IP address or network of the endpoint")
+            .required(true),
+        clap::Arg::with_name("port").required(true),
+        clap::Arg::with_name("protocol")
+            .required(true)
+            .possible_values(&["udp", "tcp"]),
+    ]
+}
+
+fn parse_endpoint(matches: &clap::ArgMatches<'_>) -> Endpoint {
+    Endpoint {
+        network: value_t_or_exit!(matches.value_of("network"), IpNetwork),
+        port: value_t_or_exit!(matches.value_of("port"), u16),
+        protocol: value_t_or_exit!(matches.value_of("protocol"), TransportProtocol),
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::{value_t, value_t_or_exit, values_t};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use talpid_types::{
+    dns::{DnsLeakCheckerEndpoint, DnsLeakVerdict},
+    firewall::FirewallPolicyQuery,
+    net::{Endpoint, TransportProtocol},
+};
+
+pub struct Debug;
+
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Inspect what the daemon does without changing anything")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("firewall-policy")
+                    .about(
+                        "Print the firewall rules of a tunnel state as an nft script, without \
+                         applying them. LAN and excluded network settings are taken from the \
+                         current settings. Only supported on Linux",
+                    )
+                    .arg(
+                        clap::Arg::with_name("state")
+                            .long("state")
+                            .takes_value(true)
+                            .required(true)
+                            .possible_values(&["connecting", "connected", "blocked"]),
+                    )
+                    .arg(
+                        clap::Arg::with_name("relay")
+                            .help("The relay endpoint, such as 185.65.134.1:51820")
+                            .long("relay")
+                            .takes_value(true)
+                            .required_ifs(&[("state", "connecting"), ("state", "connected")]),
+                    )
+                    .arg(
+                        clap::Arg::with_name("protocol")
+                            .help("Transport protocol of the relay endpoint")
+                            .long("protocol")
+                            .default_value("udp")
+                            .possible_values(&["udp", "tcp"]),
+                    )
+                    .arg(
+                        clap::Arg::with_name("pingable-host")
+                            .help("Host that is pinged while connecting")
+                            .long("pingable-host")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1),
+                    )
+                    .arg(
+                        clap::Arg::with_name("interface")
+                            .help("Name of the tunnel interface")
+                            .long("interface")
+                            .takes_value(true)
+                            .required_if("state", "connected"),
+                    )
+                    .arg(
+                        clap::Arg::with_name("tunnel-ip")
+                            .help("Local IP on the tunnel interface")
+                            .long("tunnel-ip")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .required_if("state", "connected"),
+                    )
+                    .arg(
+                        clap::Arg::with_name("gateway")
+                            .help("IPv4 gateway on the tunnel interface")
+                            .long("gateway")
+                            .takes_value(true)
+                            .required_if("state", "connected"),
+                    )
+                    .arg(
+                        clap::Arg::with_name("ipv6-gateway")
+                            .help("IPv6 gateway on the tunnel interface")
+                            .long("ipv6-gateway")
+                            .takes_value(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("firewall-stats")
+                    .about(
+                        "Print how many packets and bytes each rule of the applied firewall \
+                         policy has matched. Only supported on Linux",
+                    )
+                    .after_help(
+                        "Dropped packets are logged to an nflog group if the daemon is started \
+                         with TALPID_FIREWALL_LOG_GROUP set to the group number. They can then be \
+                         read with `tcpdump -i nflog:<group>`.",
+                    )
+                    .arg(
+                        clap::Arg::with_name("all")
+                            .help("Also print rules that have not matched any packets")
+                            .long("all"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("dns-leak-test")
+                    .about(
+                        "Look up unique names while connected and print which resolvers queried \
+                         them, and check that queries to resolvers outside the tunnel are \
+                         blocked. Fails if a leak is detected. Only supported on Linux",
+                    )
+                    .arg(
+                        clap::Arg::with_name("checker-domain")
+                            .help("Domain whose name server records the resolvers querying it")
+                            .long("checker-domain")
+                            .takes_value(true)
+                            .requires("checker-url"),
+                    )
+                    .arg(
+                        clap::Arg::with_name("checker-url")
+                            .help(
+                                "HTTPS URL that returns the resolvers that queried a name, when \
+                                 the first label of the name is appended to it",
+                            )
+                            .long("checker-url")
+                            .takes_value(true)
+                            .requires("checker-domain"),
+                    ),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(matches) = matches.subcommand_matches("firewall-policy") {
+            self.firewall_policy(matches)
+        } else if let Some(matches) = matches.subcommand_matches("firewall-stats") {
+            self.firewall_stats(matches.is_present("all"))
+        } else if let Some(matches) = matches.subcommand_matches("dns-leak-test") {
+            self.dns_leak_test(matches)
+        } else {
+            unreachable!("No debug command given");
+        }
+    }
+}
+
+impl Debug {
+    fn firewall_policy(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let query = match matches.value_of("state").unwrap() {
+            "connecting" => FirewallPolicyQuery::Connecting {
+                peer_endpoint: Self::peer_endpoint(matches),
+                pingable_hosts: if matches.is_present("pingable-host") {
+                    values_t!(matches.values_of("pingable-host"), IpAddr)
+                        .unwrap_or_else(|e| e.exit())
+                } else {
+                    Vec::new()
+                },
+            },
+            "connected" => FirewallPolicyQuery::Connected {
+                peer_endpoint: Self::peer_endpoint(matches),
+                interface: value_t_or_exit!(matches.value_of("interface"), String),
+                ips: values_t!(matches.values_of("tunnel-ip"), IpAddr).unwrap_or_else(|e| e.exit()),
+                ipv4_gateway: value_t_or_exit!(matches.value_of("gateway"), Ipv4Addr),
+                ipv6_gateway: if matches.is_present("ipv6-gateway") {
+                    Some(value_t_or_exit!(matches.value_of("ipv6-gateway"), Ipv6Addr))
+                } else {
+                    None
+                },
+            },
+            "blocked" => FirewallPolicyQuery::Blocked,
+            _ => unreachable!("Invalid tunnel state"),
+        };
+        print!("{}", new_rpc_client()?.get_firewall_policy_script(query)?);
+        Ok(())
+    }
+
+    fn firewall_stats(&self, all: bool) -> Result<()> {
+        let stats = new_rpc_client()?.get_firewall_stats()?;
+        if stats.is_empty() {
+            println!("No firewall policy is applied");
+            return Ok(());
+        }
+        println!("{:>10} {:>12}  {:<10} rule", "packets", "bytes", "chain");
+        for rule_stats in stats
+            .iter()
+            .filter(|rule_stats| all || rule_stats.packets > 0)
+        {
+            println!(
+                "{:>10} {:>12}  {:<10} {}",
+                rule_stats.packets, rule_stats.bytes, rule_stats.chain, rule_stats.rule
+            );
+        }
+        Ok(())
+    }
+
+    fn dns_leak_test(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let checker = match (
+            matches.value_of("checker-domain"),
+            matches.value_of("checker-url"),
+        ) {
+            (Some(domain), Some(url)) => Some(DnsLeakCheckerEndpoint {
+                domain: domain.to_owned(),
+                url: url.to_owned(),
+            }),
+            _ => None,
+        };
+        let report = new_rpc_client()?.run_dns_leak_test(checker)?;
+        println!("{}", report);
+        match report.verdict() {
+            DnsLeakVerdict::Leak => Err(Error::DnsLeakDetected),
+            DnsLeakVerdict::NoLeak | DnsLeakVerdict::Inconclusive => Ok(()),
+        }
+    }
+
+    fn peer_endpoint(matches: &clap::ArgMatches<'_>) -> Endpoint {
+        let address = value_t_or_exit!(matches.value_of("relay"), SocketAddr);
+        let protocol =
+            value_t!(matches.value_of("protocol"), TransportProtocol).unwrap_or_else(|e| e.exit());
+        Endpoint { address, protocol }
+    }
+}
@@ -0,0 +1,445 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::{value_t_or_exit, values_t};
+use std::net::IpAddr;
+use talpid_types::dns::{
+    BlockedResponse, BlocklistCategory, DnsManager, DnsMode, EncryptedDnsProtocol,
+    EncryptedDnsServer, SplitDnsRule,
+};
+
+pub struct Dns;
+
+impl Command for Dns {
+    fn name(&self) -> &'static str {
+        "dns"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Configure which DNS servers are used while connected")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the DNS servers")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("default")
+                            .about("Use the DNS server of the relay"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("custom")
+                            .about(
+                                "Use the given DNS servers, in order of preference. They are \
+                                 reached through the tunnel, or on the local network if it is \
+                                 one and local network sharing is enabled",
+                            )
+                            .arg(
+                                clap::Arg::with_name("servers")
+                                    .help("The IP addresses of the DNS servers")
+                                    .required(true)
+                                    .multiple(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("https")
+                            .about(
+                                "Encrypt queries with DNS-over-HTTPS, sent through the tunnel by \
+                                 the local resolver",
+                            )
+                            .arg(
+                                clap::Arg::with_name("url")
+                                    .help(
+                                        "The URL of the server, such as \
+                                         https://dns.example/dns-query",
+                                    )
+                                    .required(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("addresses")
+                                    .help("The IP addresses to reach the server at")
+                                    .required(true)
+                                    .multiple(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("tls")
+                            .about(
+                                "Encrypt queries with DNS-over-TLS, sent through the tunnel by \
+                                 the local resolver",
+                            )
+                            .arg(
+                                clap::Arg::with_name("hostname")
+                                    .help(
+                                        "The name that the certificate of the server is valid \
+                                         for",
+                                    )
+                                    .required(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("addresses")
+                                    .help("The IP addresses to reach the server at")
+                                    .required(true)
+                                    .multiple(true),
+                            ),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get").about("Display the DNS servers that are used"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("local-resolver")
+                    .about(
+                        "Control whether DNS queries are sent through a caching resolver in the \
+                         daemon while connected",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Change the local resolver setting")
+                            .arg(
+                                clap::Arg::with_name("policy")
+                                    .required(true)
+                                    .possible_values(&["on", "off"]),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("get")
+                            .about("Display the current local resolver setting"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("block")
+                    .about(
+                        "Control which domains the local resolver blocks. Blocking any category \
+                         sends DNS queries through the local resolver while connected",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Change whether the domains of a category are blocked")
+                            .arg(
+                                clap::Arg::with_name("category")
+                                    .required(true)
+                                    .possible_values(&["ads", "trackers", "malware"]),
+                            )
+                            .arg(
+                                clap::Arg::with_name("policy")
+                                    .required(true)
+                                    .possible_values(&["on", "off"]),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("response")
+                            .about("Change how queries for blocked domains are answered")
+                            .arg(
+                                clap::Arg::with_name("response")
+                                    .help(
+                                        "Answer that the domain does not exist, or with the \
+                                         unspecified address",
+                                    )
+                                    .required(true)
+                                    .possible_values(&["nxdomain", "unspecified"]),
+                            ),
+                    )
+                    .subcommand(clap::SubCommand::with_name("get").about(
+                        "Display the blocked categories and how many queries they have \
+                             blocked",
+                    )),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("split")
+                    .about(
+                        "Control which domains are resolved by other DNS servers, reached \
+                         outside the tunnel, while connected",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about(
+                                "Resolve a domain and its subdomains with the given DNS \
+                                 servers. Replaces the servers if the domain was already added",
+                            )
+                            .arg(
+                                clap::Arg::with_name("domain")
+                                    .help("The domain, such as corp.example")
+                                    .required(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("servers")
+                                    .help("The IP addresses of the DNS servers")
+                                    .required(true)
+                                    .multiple(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove")
+                            .about("Stop resolving a domain with other DNS servers")
+                            .arg(clap::Arg::with_name("domain").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("list")
+                            .about("Display the domains and the DNS servers they are resolved by"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("manager")
+                    .about("Control which program the system DNS servers are set through")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about(
+                                "Force the DNS servers to be set through a program, or detect                                  it. The TALPID_DNS_MODULE environment variable of the daemon                                  takes precedence",
+                            )
+                            .arg(clap::Arg::with_name("manager").required(true).possible_values(
+                                &["auto", "systemd", "network-manager", "resolvconf", "static-file"],
+                            )),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("get")
+                            .about("Display the current DNS manager setting"),
+                    )
+                    .subcommand(clap::SubCommand::with_name("diagnose").about(
+                        "Display which program the DNS servers are set through and why, what \
+                         was set and whether the system still uses it",
+                    )),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            if let Some(custom_matches) = set_matches.subcommand_matches("custom") {
+                let servers = values_t!(custom_matches.values_of("servers"), IpAddr)
+                    .unwrap_or_else(|e| e.exit());
+                self.set(servers)
+            } else if let Some(_matches) = set_matches.subcommand_matches("default") {
+                self.set(vec![])
+            } else if let Some(https_matches) = set_matches.subcommand_matches("https") {
+                let url = https_matches.value_of("url").unwrap();
+                let (hostname, path) = parse_https_url(url)?;
+                let bootstrap_addresses = values_t!(https_matches.values_of("addresses"), IpAddr)
+                    .unwrap_or_else(|e| e.exit());
+                self.set_encrypted(EncryptedDnsServer {
+                    protocol: EncryptedDnsProtocol::Https,
+                    hostname,
+                    path,
+                    bootstrap_addresses,
+                })
+            } else if let Some(tls_matches) = set_matches.subcommand_matches("tls") {
+                let hostname = value_t_or_exit!(tls_matches.value_of("hostname"), String);
+                let bootstrap_addresses = values_t!(tls_matches.values_of("addresses"), IpAddr)
+                    .unwrap_or_else(|e| e.exit());
+                self.set_encrypted(EncryptedDnsServer {
+                    protocol: EncryptedDnsProtocol::Tls,
+                    hostname,
+                    path: String::new(),
+                    bootstrap_addresses,
+                })
+            } else {
+                unreachable!("No dns set command given");
+            }
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get()
+        } else if let Some(resolver_matches) = matches.subcommand_matches("local-resolver") {
+            self.run_local_resolver(resolver_matches)
+        } else if let Some(block_matches) = matches.subcommand_matches("block") {
+            self.run_block(block_matches)
+        } else if let Some(split_matches) = matches.subcommand_matches("split") {
+            self.run_split(split_matches)
+        } else if let Some(manager_matches) = matches.subcommand_matches("manager") {
+            self.run_manager(manager_matches)
+        } else {
+            unreachable!("No dns command given");
+        }
+    }
+}
+
+impl Dns {
+    fn set(&self, servers: Vec<IpAddr>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_dns_mode(DnsMode::Plain)?;
+        rpc.set_custom_dns(servers)?;
+        println!("Changed DNS settings");
+        Ok(())
+    }
+
+    fn set_encrypted(&self, server: EncryptedDnsServer) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_dns_mode(DnsMode::Encrypted(server))?;
+        println!("Changed DNS settings");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let settings = new_rpc_client()?.get_settings()?;
+        if let DnsMode::Encrypted(server) = settings.dns_mode {
+            println!("DNS servers: {}", server);
+            return Ok(());
+        }
+        let servers = settings.custom_dns;
+        if servers.is_empty() {
+            println!("DNS servers: the DNS server of the relay");
+        } else {
+            println!(
+                "DNS servers: {}",
+                servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    fn run_local_resolver(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+            let mut rpc = new_rpc_client()?;
+            rpc.set_local_dns_resolver(enabled == "on")?;
+            println!("Changed local resolver setting");
+            Ok(())
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            let enabled = new_rpc_client()?.get_settings()?.local_dns_resolver;
+            println!("Local resolver: {}", if enabled { "on" } else { "off" });
+            Ok(())
+        } else {
+            unreachable!("No local-resolver command given");
+        }
+    }
+
+    fn run_block(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let category = value_t_or_exit!(set_matches.value_of("category"), BlocklistCategory);
+            let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+            let mut rpc = new_rpc_client()?;
+            rpc.set_dns_blocklist(category, enabled == "on")?;
+            println!("Changed blocklist setting");
+            Ok(())
+        } else if let Some(response_matches) = matches.subcommand_matches("response") {
+            let response = match response_matches.value_of("response").unwrap() {
+                "nxdomain" => BlockedResponse::Nxdomain,
+                "unspecified" => BlockedResponse::Unspecified,
+                _ => unreachable!("Invalid blocked response"),
+            };
+            let mut rpc = new_rpc_client()?;
+            rpc.set_dns_blocked_response(response)?;
+            println!("Changed blocked response setting");
+            Ok(())
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get_block()
+        } else {
+            unreachable!("No block command given");
+        }
+    }
+
+    fn get_block(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let dns_blocking = rpc.get_settings()?.dns_blocking;
+        let stats = rpc.get_dns_blocklist_stats()?;
+        for category in BlocklistCategory::ALL.iter() {
+            if !dns_blocking.categories.contains(category) {
+                println!("{:<10} off", category);
+                continue;
+            }
+            match stats.iter().find(|stats| stats.category == *category) {
+                Some(stats) => println!(
+                    "{:<10} on, {} domains, {} queries blocked",
+                    category, stats.domains, stats.blocked_queries
+                ),
+                None => println!("{:<10} on", category),
+            }
+        }
+        println!(
+            "Blocked domains are answered with: {}",
+            dns_blocking.response
+        );
+        Ok(())
+    }
+
+    fn run_split(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(add_matches) = matches.subcommand_matches("add") {
+            let domain = normalize_domain(add_matches.value_of("domain").unwrap());
+            let servers =
+                values_t!(add_matches.values_of("servers"), IpAddr).unwrap_or_else(|e| e.exit());
+            let mut rpc = new_rpc_client()?;
+            let mut split_dns = rpc.get_settings()?.split_dns;
+            split_dns.retain(|rule| rule.domain != domain);
+            split_dns.push(SplitDnsRule { domain, servers });
+            rpc.set_split_dns(split_dns)?;
+            println!("Changed split DNS setting");
+            Ok(())
+        } else if let Some(remove_matches) = matches.subcommand_matches("remove") {
+            let domain = normalize_domain(remove_matches.value_of("domain").unwrap());
+            let mut rpc = new_rpc_client()?;
+            let mut split_dns = rpc.get_settings()?.split_dns;
+            let rule_count = split_dns.len();
+            split_dns.retain(|rule| rule.domain != domain);
+            if split_dns.len() == rule_count {
+                return Err(Error::InvalidCommand(
+                    "The domain is not resolved by other DNS servers",
+                ));
+            }
+            rpc.set_split_dns(split_dns)?;
+            println!("Changed split DNS setting");
+            Ok(())
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            let split_dns = new_rpc_client()?.get_settings()?.split_dns;
+            if split_dns.is_empty() {
+                println!("No domains are resolved by other DNS servers");
+            }
+            for rule in split_dns {
+                println!("{}", rule);
+            }
+            Ok(())
+        } else {
+            unreachable!("No split command given");
+        }
+    }
+
+    fn run_manager(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let dns_manager = match set_matches.value_of("manager").unwrap() {
+                "auto" => None,
+                manager => Some(manager.parse::<DnsManager>().expect("Invalid DNS manager")),
+            };
+            let mut rpc = new_rpc_client()?;
+            rpc.set_dns_manager(dns_manager)?;
+            println!("Changed DNS manager setting");
+            Ok(())
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            match new_rpc_client()?.get_settings()?.dns_manager {
+                Some(manager) => println!("DNS manager: {}", manager),
+                None => println!("DNS manager: auto"),
+            }
+            Ok(())
+        } else if let Some(_matches) = matches.subcommand_matches("diagnose") {
+            println!("{}", new_rpc_client()?.get_dns_manager_diagnostics()?);
+            Ok(())
+        } else {
+            unreachable!("No manager command given");
+        }
+    }
+}
+
+/// Lowercases the domain and removes any leading or trailing dot, so that it is written the way
+/// the daemon expects.
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_matches('.').to_ascii_lowercase()
+}
+
+/// Splits a DNS-over-HTTPS URL into the hostname and the path.
+fn parse_https_url(url: &str) -> Result<(String, String)> {
+    if !url.starts_with("https://") {
+        return Err(Error::InvalidCommand("The URL must start with https://"));
+    }
+    let url = &url["https://".len()..];
+    let (hostname, path) = match url.find('/') {
+        Some(index) => url.split_at(index),
+        None => (url, ""),
+    };
+    if hostname.contains(':') {
+        return Err(Error::InvalidCommand(
+            "The server must be reached on the default HTTPS port",
+        ));
+    }
+    Ok((hostname.to_owned(), path.to_owned()))
+}
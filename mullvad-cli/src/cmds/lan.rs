@@ -1,5 +1,7 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{new_rpc_client, Command, Error, Result};
 use clap::value_t_or_exit;
+use ipnetwork::IpNetwork;
+use talpid_types::net::{validate_lan_networks, LanNetworks};
 
 pub struct Lan;
 
@@ -25,6 +27,35 @@ impl Command for Lan {
                 clap::SubCommand::with_name("get")
                     .about("Display the current local network sharing setting"),
             )
+            .subcommand(
+                clap::SubCommand::with_name("network")
+                    .about("Change which networks are considered local networks")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Allow a network in addition to the default private networks")
+                            .arg(clap::Arg::with_name("network").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("delete")
+                            .about("Stop allowing a previously added network")
+                            .arg(clap::Arg::with_name("network").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove-default")
+                            .about("Stop allowing one of the default private networks")
+                            .arg(clap::Arg::with_name("network").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("restore-default")
+                            .about("Allow a removed default private network again")
+                            .arg(clap::Arg::with_name("network").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("list")
+                            .about("Display the added and removed networks"),
+                    ),
+            )
     }
 
     fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -33,6 +64,8 @@ impl Command for Lan {
             self.set(allow_lan == "allow")
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get()
+        } else if let Some(network_matches) = matches.subcommand_matches("network") {
+            self.run_network(network_matches)
         } else {
             unreachable!("No lan command given");
         }
@@ -56,4 +89,60 @@ impl Lan {
         );
         Ok(())
     }
+
+    fn run_network(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(_matches) = matches.subcommand_matches("list") {
+            return self.list_networks();
+        }
+        let (command, matches) = matches.subcommand();
+        let network = value_t_or_exit!(
+            matches
+                .expect("No lan network command given")
+                .value_of("network"),
+            IpNetwork
+        );
+
+        let mut rpc = new_rpc_client()?;
+        let mut lan_networks = rpc.get_settings()?.lan_networks;
+        match command {
+            "add" => add_network(&mut lan_networks.extra, network),
+            "delete" => lan_networks.extra.retain(|extra| *extra != network),
+            "remove-default" => add_network(&mut lan_networks.removed_defaults, network),
+            "restore-default" => lan_networks
+                .removed_defaults
+                .retain(|removed| *removed != network),
+            _ => unreachable!("No lan network command given"),
+        }
+        validate_lan_networks(&lan_networks).map_err(Error::InvalidLanNetwork)?;
+        rpc.set_lan_networks(lan_networks)?;
+        println!("Changed local networks");
+        Ok(())
+    }
+
+    fn list_networks(&self) -> Result<()> {
+        let LanNetworks {
+            extra,
+            removed_defaults,
+        } = new_rpc_client()?.get_settings()?.lan_networks;
+        print_networks("Added networks", &extra);
+        print_networks("Removed default networks", &removed_defaults);
+        Ok(())
+    }
+}
+
+fn add_network(networks: &mut Vec<IpNetwork>, network: IpNetwork) {
+    if !networks.contains(&network) {
+        networks.push(network);
+    }
+}
+
+fn print_networks(title: &str, networks: &[IpNetwork]) {
+    if networks.is_empty() {
+        println!("{}: none", title);
+    } else {
+        println!("{}:", title);
+        for network in networks {
+            println!("    {}", network);
+        }
+    }
 }
@@ -0,0 +1,62 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+
+pub struct LockdownMode;
+
+impl Command for LockdownMode {
+    fn name(&self) -> &'static str {
+        "lockdown-mode"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Control if network access should be blocked from boot, and whenever the system \
+                 service is not running or the VPN is disconnected",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the lockdown mode setting")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current lockdown mode setting"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let lockdown_mode = value_t_or_exit!(set_matches.value_of("policy"), String);
+            self.set(lockdown_mode == "on")
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get()
+        } else {
+            unreachable!("No lockdown-mode command given");
+        }
+    }
+}
+
+impl LockdownMode {
+    fn set(&self, lockdown_mode: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_lockdown_mode(lockdown_mode)?;
+        println!("Changed lockdown mode setting");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let lockdown_mode = rpc.get_settings()?.lockdown_mode;
+        println!(
+            "Lockdown mode: {}",
+            if lockdown_mode { "on" } else { "off" }
+        );
+        Ok(())
+    }
+}
@@ -4,6 +4,9 @@ use std::collections::HashMap;
 mod account;
 pub use self::account::Account;
 
+mod allowed_endpoint;
+pub use self::allowed_endpoint::AllowedEndpoint;
+
 mod auto_connect;
 pub use self::auto_connect::AutoConnect;
 
@@ -19,12 +22,30 @@ pub use self::bridge::Bridge;
 mod connect;
 pub use self::connect::Connect;
 
+mod debug;
+pub use self::debug::Debug;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
+#[cfg(target_os = "linux")]
+mod dns;
+#[cfg(target_os = "linux")]
+pub use self::dns::Dns;
+
 mod lan;
 pub use self::lan::Lan;
 
+#[cfg(target_os = "linux")]
+mod lockdown_mode;
+#[cfg(target_os = "linux")]
+pub use self::lockdown_mode::LockdownMode;
+
+#[cfg(target_os = "linux")]
+mod port_forward;
+#[cfg(target_os = "linux")]
+pub use self::port_forward::PortForward;
+
 mod reconnect;
 pub use self::reconnect::Reconnect;
 
@@ -34,6 +55,16 @@ pub use self::relay::Relay;
 mod reset;
 pub use self::reset::Reset;
 
+#[cfg(target_os = "linux")]
+mod share_tunnel;
+#[cfg(target_os = "linux")]
+pub use self::share_tunnel::ShareTunnel;
+
+#[cfg(target_os = "linux")]
+mod split_tunnel;
+#[cfg(target_os = "linux")]
+pub use self::split_tunnel::SplitTunnel;
+
 mod status;
 pub use self::status::Status;
 
@@ -45,13 +76,16 @@ pub use self::version::Version;
 
 /// Returns a map of all available subcommands with their name as key.
 pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
-    let commands: Vec<Box<dyn Command>> = vec![
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    let mut commands: Vec<Box<dyn Command>> = vec![
         Box::new(Account),
+        Box::new(AllowedEndpoint),
         Box::new(AutoConnect),
         Box::new(BetaProgram),
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
         Box::new(Connect),
+        Box::new(Debug),
         Box::new(Disconnect),
         Box::new(Reconnect),
         Box::new(Lan),
@@ -61,6 +95,16 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(Tunnel),
         Box::new(Version),
     ];
+    #[cfg(target_os = "linux")]
+    commands.push(Box::new(Dns));
+    #[cfg(target_os = "linux")]
+    commands.push(Box::new(LockdownMode));
+    #[cfg(target_os = "linux")]
+    commands.push(Box::new(PortForward));
+    #[cfg(target_os = "linux")]
+    commands.push(Box::new(ShareTunnel));
+    #[cfg(target_os = "linux")]
+    commands.push(Box::new(SplitTunnel));
     let mut map = HashMap::new();
     for cmd in commands {
         if map.insert(cmd.name(), cmd).is_some() {
@@ -0,0 +1,129 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+use talpid_types::net::{ForwardedPort, TransportProtocol};
+
+pub struct PortForward;
+
+impl Command for PortForward {
+    fn name(&self) -> &'static str {
+        "port-forward"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage ports that accept incoming connections through the tunnel. Other \
+                 incoming connections through the tunnel are blocked",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Accept connections to a port or range, such as 50000-50100 udp")
+                    .args(&port_args()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("delete")
+                    .about("Stop accepting connections to a port or range")
+                    .args(&port_args()),
+            )
+            .subcommand(clap::SubCommand::with_name("list").about("List the forwarded ports"))
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(matches) = matches.subcommand_matches("add") {
+            self.add(parse_forwarded_port(matches))
+        } else if let Some(matches) = matches.subcommand_matches("delete") {
+            self.delete(parse_forwarded_port(matches))
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list()
+        } else {
+            unreachable!("No port-forward command given");
+        }
+    }
+}
+
+impl PortForward {
+    fn add(&self, forwarded_port: ForwardedPort) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut forwarded_ports = rpc.get_settings()?.tunnel_options.generic.forwarded_ports;
+        if forwarded_ports.contains(&forwarded_port) {
+            println!("{} is already forwarded", forwarded_port);
+            return Ok(());
+        }
+        forwarded_ports.push(forwarded_port);
+        rpc.set_forwarded_ports(forwarded_ports)?;
+        println!("Forwarding {}", forwarded_port);
+        Ok(())
+    }
+
+    fn delete(&self, forwarded_port: ForwardedPort) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut forwarded_ports = rpc.get_settings()?.tunnel_options.generic.forwarded_ports;
+        let count = forwarded_ports.len();
+        forwarded_ports.retain(|forwarded| *forwarded != forwarded_port);
+        if forwarded_ports.len() == count {
+            println!("{} is not forwarded", forwarded_port);
+            return Ok(());
+        }
+        rpc.set_forwarded_ports(forwarded_ports)?;
+        println!("{} is no longer forwarded", forwarded_port);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<()> {
+        let forwarded_ports = new_rpc_client()?
+            .get_settings()?
+            .tunnel_options
+            .generic
+            .forwarded_ports;
+        if forwarded_ports.is_empty() {
+            println!("No ports are forwarded");
+        } else {
+            println!("Forwarded ports:");
+            for forwarded_port in forwarded_ports {
+                println!("{}", forwarded_port);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn port_args() -> [clap::Arg<'static, 'static>; 2] {
+    [
+        clap::Arg::with_name("ports")
+            .help("A port, or an inclusive range of ports separated by a dash")
+            .required(true)
+            .validator(ports_validator),
+        clap::Arg::with_name("protocol")
+            .required(true)
+            .possible_values(&["udp", "tcp"]),
+    ]
+}
+
+fn parse_forwarded_port(matches: &clap::ArgMatches<'_>) -> ForwardedPort {
+    let (start, end) = parse_ports(matches.value_of("ports").unwrap()).unwrap();
+    ForwardedPort {
+        protocol: value_t_or_exit!(matches.value_of("protocol"), TransportProtocol),
+        start,
+        end,
+    }
+}
+
+fn ports_validator(ports: String) -> std::result::Result<(), String> {
+    match parse_ports(&ports) {
+        Some((start, end)) if start != 0 && start <= end => Ok(()),
+        _ => Err(String::from(
+            "Ports must be a number from 1 to 65535, or a range such as 50000-50100",
+        )),
+    }
+}
+
+fn parse_ports(ports: &str) -> Option<(u16, u16)> {
+    let mut parts = ports.splitn(2, '-');
+    let start = parts.next()?.parse().ok()?;
+    let end = match parts.next() {
+        Some(end) => end.parse().ok()?,
+        None => start,
+    };
+    Some((start, end))
+}
@@ -0,0 +1,117 @@
+use crate::{new_rpc_client, Command, Result};
+
+/// Interface names are limited to 15 bytes by the kernel.
+const MAX_IFACE_NAME_LEN: usize = 15;
+
+pub struct ShareTunnel;
+
+impl Command for ShareTunnel {
+    fn name(&self) -> &'static str {
+        "share-tunnel"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage interfaces, such as container and virtual machine bridges, whose forwarded \
+                 traffic is translated to the tunnel address. Forwarded traffic is only allowed \
+                 through the tunnel, or to local networks if LAN access is allowed. IP forwarding \
+                 must be enabled separately",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Share the tunnel with an interface, such as virbr0")
+                    .arg(interface_arg()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("delete")
+                    .about("Stop sharing the tunnel with an interface")
+                    .arg(interface_arg()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List the interfaces that the tunnel is shared with"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(matches) = matches.subcommand_matches("add") {
+            self.add(matches.value_of("interface").unwrap())
+        } else if let Some(matches) = matches.subcommand_matches("delete") {
+            self.delete(matches.value_of("interface").unwrap())
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list()
+        } else {
+            unreachable!("No share-tunnel command given");
+        }
+    }
+}
+
+impl ShareTunnel {
+    fn add(&self, interface: &str) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut interfaces = rpc.get_settings()?.tunnel_options.generic.shared_interfaces;
+        if interfaces.iter().any(|shared| shared == interface) {
+            println!("The tunnel is already shared with {}", interface);
+            return Ok(());
+        }
+        interfaces.push(interface.to_owned());
+        rpc.set_shared_interfaces(interfaces)?;
+        println!("Sharing the tunnel with {}", interface);
+        Ok(())
+    }
+
+    fn delete(&self, interface: &str) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut interfaces = rpc.get_settings()?.tunnel_options.generic.shared_interfaces;
+        let count = interfaces.len();
+        interfaces.retain(|shared| shared != interface);
+        if interfaces.len() == count {
+            println!("The tunnel is not shared with {}", interface);
+            return Ok(());
+        }
+        rpc.set_shared_interfaces(interfaces)?;
+        println!("The tunnel is no longer shared with {}", interface);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<()> {
+        let interfaces = new_rpc_client()?
+            .get_settings()?
+            .tunnel_options
+            .generic
+            .shared_interfaces;
+        if interfaces.is_empty() {
+            println!("The tunnel is not shared with any interfaces");
+        } else {
+            println!("Shared interfaces:");
+            for interface in interfaces {
+                println!("{}", interface);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn interface_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("interface")
+        .help("The name of the interface. It does not have to exist yet")
+        .required(true)
+        .validator(interface_validator)
+}
+
+fn interface_validator(interface: String) -> std::result::Result<(), String> {
+    if interface.is_empty()
+        || interface.len() > MAX_IFACE_NAME_LEN
+        || interface
+            .chars()
+            .any(|c| c == '/' || c == '\0' || c.is_whitespace())
+    {
+        Err(String::from(
+            "Interface names must be 1 to 15 bytes, without slashes or whitespace",
+        ))
+    } else {
+        Ok(())
+    }
+}
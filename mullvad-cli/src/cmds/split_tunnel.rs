@@ -0,0 +1,218 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::{value_t_or_exit, values_t_or_exit};
+use ipnetwork::IpNetwork;
+use std::os::unix::process::CommandExt;
+
+pub struct SplitTunnel;
+
+impl Command for SplitTunnel {
+    fn name(&self) -> &'static str {
+        "split-tunnel"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage processes and networks whose traffic bypasses the tunnel. Excluded \
+                 traffic is still blocked while the app is in the blocked state",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("pid")
+                    .about("Exclude running processes from the tunnel")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Exclude a process, and any processes it starts")
+                            .arg(clap::Arg::with_name("pid").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("delete")
+                            .about("Stop excluding a process")
+                            .arg(clap::Arg::with_name("pid").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("list")
+                            .about("List the PIDs of all excluded processes"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("subnet")
+                    .about("Reach destination networks outside the tunnel")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Exclude a network, such as 192.168.100.0/24")
+                            .arg(clap::Arg::with_name("subnet").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("delete")
+                            .about("Stop excluding a network")
+                            .arg(clap::Arg::with_name("subnet").required(true)),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("list").about("List the excluded networks"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("launch")
+                    .about("Run a command with its traffic excluded from the tunnel")
+                    .setting(clap::AppSettings::TrailingVarArg)
+                    .arg(
+                        clap::Arg::with_name("command")
+                            .help("The command to run, followed by its arguments")
+                            .required(true)
+                            .multiple(true),
+                    ),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(pid_matches) = matches.subcommand_matches("pid") {
+            if let Some(matches) = pid_matches.subcommand_matches("add") {
+                let pid = value_t_or_exit!(matches.value_of("pid"), i32);
+                self.add(pid)
+            } else if let Some(matches) = pid_matches.subcommand_matches("delete") {
+                let pid = value_t_or_exit!(matches.value_of("pid"), i32);
+                self.delete(pid)
+            } else if let Some(_matches) = pid_matches.subcommand_matches("list") {
+                self.list()
+            } else {
+                unreachable!("No split-tunnel pid command given");
+            }
+        } else if let Some(subnet_matches) = matches.subcommand_matches("subnet") {
+            if let Some(matches) = subnet_matches.subcommand_matches("add") {
+                let subnet = value_t_or_exit!(matches.value_of("subnet"), IpNetwork);
+                self.add_subnet(subnet)
+            } else if let Some(matches) = subnet_matches.subcommand_matches("delete") {
+                let subnet = value_t_or_exit!(matches.value_of("subnet"), IpNetwork);
+                self.delete_subnet(subnet)
+            } else if let Some(_matches) = subnet_matches.subcommand_matches("list") {
+                self.list_subnets()
+            } else {
+                unreachable!("No split-tunnel subnet command given");
+            }
+        } else if let Some(matches) = matches.subcommand_matches("launch") {
+            let command = values_t_or_exit!(matches.values_of("command"), String);
+            self.launch(command)
+        } else {
+            unreachable!("No split-tunnel command given");
+        }
+    }
+}
+
+impl SplitTunnel {
+    fn add(&self, pid: i32) -> Result<()> {
+        new_rpc_client()?.add_split_tunnel_process(pid)?;
+        println!("Excluded process {} from the tunnel", pid);
+        Ok(())
+    }
+
+    fn delete(&self, pid: i32) -> Result<()> {
+        new_rpc_client()?.remove_split_tunnel_process(pid)?;
+        println!("Process {} is no longer excluded from the tunnel", pid);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<()> {
+        let pids = new_rpc_client()?.get_split_tunnel_processes()?;
+        if pids.is_empty() {
+            println!("No processes are excluded from the tunnel");
+        } else {
+            println!("Excluded PIDs:");
+            for pid in pids {
+                println!("{}", pid);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_subnet(&self, subnet: IpNetwork) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut subnets = rpc.get_settings()?.tunnel_options.generic.excluded_subnets;
+        if subnets.contains(&subnet) {
+            println!("{} is already excluded from the tunnel", subnet);
+            return Ok(());
+        }
+        subnets.push(subnet);
+        rpc.set_excluded_subnets(subnets)?;
+        println!("Excluded {} from the tunnel", subnet);
+        Ok(())
+    }
+
+    fn delete_subnet(&self, subnet: IpNetwork) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut subnets = rpc.get_settings()?.tunnel_options.generic.excluded_subnets;
+        let count = subnets.len();
+        subnets.retain(|excluded| *excluded != subnet);
+        if subnets.len() == count {
+            println!("{} is not excluded from the tunnel", subnet);
+            return Ok(());
+        }
+        rpc.set_excluded_subnets(subnets)?;
+        println!("{} is no longer excluded from the tunnel", subnet);
+        Ok(())
+    }
+
+    fn list_subnets(&self) -> Result<()> {
+        let subnets = new_rpc_client()?
+            .get_settings()?
+            .tunnel_options
+            .generic
+            .excluded_subnets;
+        if subnets.is_empty() {
+            println!("No networks are excluded from the tunnel");
+        } else {
+            println!("Excluded networks:");
+            for subnet in subnets {
+                println!("{}", subnet);
+            }
+        }
+        Ok(())
+    }
+
+    /// Excludes this process and then replaces it with the command, which keeps the PID and
+    /// thus the exclusion.
+    fn launch(&self, command: Vec<String>) -> Result<()> {
+        new_rpc_client()?.add_split_tunnel_process(std::process::id() as i32)?;
+        let error = std::process::Command::new(&command[0])
+            .args(&command[1..])
+            .exec();
+        Err(Error::LaunchError(command[0].clone(), error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn launch_command(args: &[&str]) -> Vec<String> {
+        let matches = SplitTunnel
+            .clap_subcommand()
+            .get_matches_from_safe(std::iter::once(&"split-tunnel").chain(args))
+            .unwrap();
+        matches
+            .subcommand_matches("launch")
+            .unwrap()
+            .values_of("command")
+            .unwrap()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn test_launch_passes_options_to_command() {
+        assert_eq!(
+            launch_command(&["launch", "curl", "-s", "--ipv4", "https://am.i.mullvad.net"]),
+            vec!["curl", "-s", "--ipv4", "https://am.i.mullvad.net"]
+        );
+    }
+
+    #[test]
+    fn test_launch_requires_command() {
+        let result = SplitTunnel
+            .clap_subcommand()
+            .get_matches_from_safe(&["split-tunnel", "launch"]);
+        assert!(result.is_err());
+    }
+}
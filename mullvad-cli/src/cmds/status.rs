@@ -2,7 +2,7 @@ use crate::{new_rpc_client, Command, Error, Result};
 use futures::{Future, Stream};
 use mullvad_ipc_client::DaemonRpcClient;
 use mullvad_types::{auth_failed::AuthFailed, states::TunnelState, DaemonEvent};
-use talpid_types::tunnel::{ErrorState, ErrorStateCause};
+use talpid_types::tunnel::{ErrorDetails, ErrorState, ErrorStateCause};
 
 pub struct Status;
 
@@ -92,8 +92,13 @@ fn print_state(state: &TunnelState) {
     print!("Tunnel status: ");
     match state {
         Error(reason) => print_error_state(reason),
-        Connected { endpoint, .. } => {
+        Connected {
+            endpoint, quality, ..
+        } => {
             println!("Connected to {}", endpoint);
+            if let Some(quality) = quality {
+                println!("Connection quality: {}", quality);
+            }
         }
         Connecting { endpoint, .. } => println!("Connecting to {}...", endpoint),
         Disconnected => println!("Disconnected"),
@@ -120,14 +125,35 @@ fn print_blocked_reason(reason: &ErrorStateCause) {
             println!("Blocked: {}", AuthFailed::from(auth_failure_str));
         }
         #[cfg(target_os = "linux")]
-        ErrorStateCause::SetFirewallPolicyError => {
-            println!("Blocked: {}", ErrorStateCause::SetFirewallPolicyError);
+        ErrorStateCause::SetFirewallPolicyError(details) => {
+            println!("Blocked: {}", reason);
+            print_error_details(details);
             println!("Your kernel might be terribly out of date or missing nftables");
         }
-        other => println!("Blocked: {}", other),
+        other => {
+            println!("Blocked: {}", other);
+            if let Some(details) = other.details() {
+                print_error_details(details);
+            }
+        }
     }
 }
 
+fn print_error_details(details: &ErrorDetails) {
+    println!("Failed component: {}", details.backend);
+    println!("Error: {}", details.message);
+    if let Some(kind) = &details.kind {
+        println!("Error kind: {}", kind);
+    }
+    if let Some(errno) = details.errno {
+        println!("OS error code: {}", errno);
+    }
+    println!(
+        "Retrying is {}likely to help",
+        if details.retry_likely { "" } else { "not " }
+    );
+}
+
 fn print_location(rpc: &mut DaemonRpcClient) -> Result<()> {
     let location = match rpc.get_current_location()? {
         Some(loc) => loc,
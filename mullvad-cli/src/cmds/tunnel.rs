@@ -37,6 +37,8 @@ fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
         .subcommand(create_wireguard_keys_subcommand())
+        .subcommand(create_wireguard_degraded_timeout_subcommand())
+        .subcommand(create_wireguard_policy_routing_subcommand())
 }
 
 fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
@@ -70,6 +72,34 @@ fn create_wireguard_keys_rotation_interval_subcommand() -> clap::App<'static, 's
         )
 }
 
+fn create_wireguard_degraded_timeout_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("degraded-timeout")
+        .about("Switch relay when the tunnel has been degraded for this long (in seconds)")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset").about("Never switch relay"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(clap::Arg::with_name("timeout").required(true)),
+        )
+}
+
+fn create_wireguard_policy_routing_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("policy-routing")
+        .about(
+            "Add the tunnel routes to a dedicated routing table instead of the main table. Only \
+             supported on Linux",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("enable")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["on", "off"]),
+            ),
+        )
+}
 
 fn create_openvpn_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("openvpn")
@@ -143,6 +173,19 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            ("degraded-timeout", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_degraded_timeout_get(),
+                ("set", Some(matches)) => Self::process_wireguard_degraded_timeout_set(matches),
+                ("unset", _) => Self::process_wireguard_degraded_timeout_unset(),
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("policy-routing", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_policy_routing_get(),
+                ("set", Some(matches)) => Self::process_wireguard_policy_routing_set(matches),
+                _ => unreachable!("unhandled command"),
+            },
+
             _ => unreachable!("unhandled command"),
         }
     }
@@ -234,6 +277,55 @@ impl Tunnel {
         Ok(())
     }
 
+    fn process_wireguard_degraded_timeout_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        println!(
+            "Degraded timeout: {}",
+            tunnel_options
+                .wireguard
+                .degraded_timeout
+                .map(|timeout| format!("{} second(s)", timeout))
+                .unwrap_or_else(|| "unset".to_owned())
+        );
+        Ok(())
+    }
+
+    fn process_wireguard_degraded_timeout_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let timeout = value_t!(matches.value_of("timeout"), u32).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_degraded_timeout(Some(timeout))?;
+        println!("Set degraded timeout: {} second(s)", timeout);
+        Ok(())
+    }
+
+    fn process_wireguard_degraded_timeout_unset() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_degraded_timeout(None)?;
+        println!("Degraded timeout has been unset");
+        Ok(())
+    }
+
+    fn process_wireguard_policy_routing_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        println!(
+            "Policy routing: {}",
+            if tunnel_options.wireguard.policy_routing {
+                "on"
+            } else {
+                "off"
+            }
+        );
+        Ok(())
+    }
+
+    fn process_wireguard_policy_routing_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let enabled = matches.value_of("enable").unwrap() == "on";
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_policy_routing(enabled)?;
+        println!("Policy routing setting has been updated");
+        Ok(())
+    }
+
     fn handle_ipv6_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get()
@@ -27,6 +27,19 @@ pub enum Error {
     /// The given command is not correct in some way
     #[error(display = "Invalid command: {}", _0)]
     InvalidCommand(&'static str),
+
+    /// The local networks can't be applied
+    #[error(display = "Invalid local network")]
+    InvalidLanNetwork(#[error(source)] talpid_types::net::LanNetworksError),
+
+    /// Failed to run a command excluded from the tunnel
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to launch {}", _0)]
+    LaunchError(String, #[error(source)] io::Error),
+
+    /// The DNS leak test saw queries leave outside the tunnel
+    #[error(display = "The DNS leak test detected a leak")]
+    DnsLeakDetected,
 }
 
 pub fn new_rpc_client() -> Result<DaemonRpcClient> {
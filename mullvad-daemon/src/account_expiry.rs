@@ -0,0 +1,200 @@
+use crate::{
+    wireguard::{CancelHandle, Cancellable},
+    DaemonEventSender, InternalDaemonEvent,
+};
+use chrono::{offset::Utc, DateTime};
+use futures::{future::Executor, Async, Future, Poll};
+use mullvad_rpc::{AccountsProxy, HttpHandle};
+use mullvad_types::account::AccountToken;
+use std::{
+    cmp,
+    time::{Duration, Instant},
+};
+use talpid_core::mpsc::Sender;
+use talpid_types::ErrorExt;
+use tokio_core::reactor::Remote;
+use tokio_timer::{TimeoutError, Timer};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often the watcher should wake up to check if the expiry should be refreshed.
+/// A short interval is used so that a suspended computer does not delay refreshes by much.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Wait at most this long until the next refresh after a successful refresh.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Wait this long until the next refresh while the account is expired, so that time bought
+/// elsewhere is noticed.
+const EXPIRED_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 5);
+/// Wait this long until the next try if a refresh failed.
+const REFRESH_INTERVAL_ERROR: Duration = Duration::from_secs(60 * 5);
+
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Timed out when trying to fetch the account expiry")]
+    Timeout,
+
+    #[error(display = "Failed to fetch the account expiry")]
+    Rpc(#[error(source)] mullvad_rpc::Error),
+}
+
+impl<T> From<TimeoutError<T>> for Error {
+    fn from(_: TimeoutError<T>) -> Error {
+        Error::Timeout
+    }
+}
+
+
+/// Periodically fetches the expiry of the current account and sends it to the daemon as
+/// `InternalDaemonEvent::AccountExpiry` events.
+pub(crate) struct AccountExpiryWatcher {
+    daemon_tx: DaemonEventSender,
+    http_handle: HttpHandle,
+    tokio_remote: Remote,
+    current_job: Option<CancelHandle>,
+}
+
+impl AccountExpiryWatcher {
+    pub fn new(
+        daemon_tx: DaemonEventSender,
+        http_handle: HttpHandle,
+        tokio_remote: Remote,
+    ) -> Self {
+        Self {
+            daemon_tx,
+            http_handle,
+            tokio_remote,
+            current_job: None,
+        }
+    }
+
+    /// Starts refreshing the expiry of the given account, replacing the account that was watched
+    /// before. The first refresh happens right away.
+    pub fn watch(&mut self, account_token: AccountToken) {
+        self.stop();
+
+        let updater = ExpiryUpdater::new(
+            AccountsProxy::new(self.http_handle.clone()),
+            account_token,
+            self.daemon_tx.clone(),
+        );
+        let (fut, cancel_handle) = Cancellable::new(updater);
+
+        if let Err(e) = self.tokio_remote.execute(fut.map_err(|_| ())) {
+            log::error!("Failed to start the account expiry watcher: {:?}", e.kind());
+            return;
+        }
+        self.current_job = Some(cancel_handle);
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(cancel_handle) = self.current_job.take() {
+            cancel_handle.cancel();
+        }
+    }
+}
+
+
+struct ExpiryUpdater {
+    accounts_proxy: AccountsProxy<HttpHandle>,
+    account_token: AccountToken,
+    daemon_tx: DaemonEventSender,
+    next_refresh_time: Instant,
+    state: ExpiryUpdaterState,
+}
+
+enum ExpiryUpdaterState {
+    Sleeping(tokio_timer::Sleep),
+    Refreshing(Box<dyn Future<Item = DateTime<Utc>, Error = Error> + Send + 'static>),
+}
+
+impl ExpiryUpdater {
+    fn new(
+        mut accounts_proxy: AccountsProxy<HttpHandle>,
+        account_token: AccountToken,
+        daemon_tx: DaemonEventSender,
+    ) -> Self {
+        let first_refresh = Self::create_refresh_future(&mut accounts_proxy, &account_token);
+        Self {
+            accounts_proxy,
+            account_token,
+            daemon_tx,
+            next_refresh_time: Instant::now(),
+            state: ExpiryUpdaterState::Refreshing(first_refresh),
+        }
+    }
+
+    fn create_sleep_future() -> tokio_timer::Sleep {
+        Timer::default().sleep(CHECK_INTERVAL)
+    }
+
+    fn create_refresh_future(
+        accounts_proxy: &mut AccountsProxy<HttpHandle>,
+        account_token: &AccountToken,
+    ) -> Box<dyn Future<Item = DateTime<Utc>, Error = Error> + Send + 'static> {
+        let request = accounts_proxy
+            .get_expiry(account_token.clone())
+            .map_err(Error::Rpc);
+        Box::new(Timer::default().timeout(request, REQUEST_TIMEOUT))
+    }
+
+    /// Refreshes regularly, but also right when the account is about to expire.
+    fn refresh_delay(expiry: DateTime<Utc>) -> Duration {
+        match expiry.signed_duration_since(Utc::now()).to_std() {
+            Ok(remaining) => cmp::min(remaining, REFRESH_INTERVAL),
+            Err(_) => EXPIRED_REFRESH_INTERVAL,
+        }
+    }
+}
+
+impl Future for ExpiryUpdater {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match &mut self.state {
+                ExpiryUpdaterState::Sleeping(timer) => match timer.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        log::error!("Account expiry check sleep error: {}", e);
+                        return Err(());
+                    }
+                    Ok(Async::Ready(())) => {
+                        if Instant::now() >= self.next_refresh_time {
+                            ExpiryUpdaterState::Refreshing(Self::create_refresh_future(
+                                &mut self.accounts_proxy,
+                                &self.account_token,
+                            ))
+                        } else {
+                            ExpiryUpdaterState::Sleeping(Self::create_sleep_future())
+                        }
+                    }
+                },
+                ExpiryUpdaterState::Refreshing(future) => match future.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Account expiry refresh failed")
+                        );
+                        self.next_refresh_time = Instant::now() + REFRESH_INTERVAL_ERROR;
+                        ExpiryUpdaterState::Sleeping(Self::create_sleep_future())
+                    }
+                    Ok(Async::Ready(expiry)) => {
+                        log::debug!("Account expires at {}", expiry);
+                        self.next_refresh_time = Instant::now() + Self::refresh_delay(expiry);
+                        let event =
+                            InternalDaemonEvent::AccountExpiry(self.account_token.clone(), expiry);
+                        if self.daemon_tx.send(event).is_err() {
+                            log::warn!("Daemon is gone, stopping account expiry watcher");
+                            return Ok(Async::Ready(()));
+                        }
+                        ExpiryUpdaterState::Sleeping(Self::create_sleep_future())
+                    }
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
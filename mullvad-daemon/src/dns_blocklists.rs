@@ -0,0 +1,231 @@
+use crate::DaemonEventSender;
+use futures::{Async, Future, Poll};
+use mullvad_rpc::{DnsBlocklistProxy, HttpHandle};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use talpid_core::{
+    dns::stub::{blocklist, Blocklist},
+    mpsc::Sender,
+};
+use talpid_types::{dns::BlocklistCategory, ErrorExt};
+use tokio_timer::{TimeoutError, Timer};
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the updater should wake up to check whether any list is due for an update. Lists of
+/// newly enabled categories are downloaded at the next check.
+const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 5);
+/// Wait this long until updating a list again after it was downloaded
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+/// Wait this long until next try if downloading a list failed
+const UPDATE_INTERVAL_ERROR: Duration = Duration::from_secs(60 * 60 * 6);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to write the DNS blocklist to the cache")]
+    WriteCache(#[error(source)] io::Error),
+
+    #[error(display = "Timed out when trying to download the DNS blocklist")]
+    DownloadTimeout,
+
+    #[error(display = "Failed to download the DNS blocklist")]
+    Download(#[error(source)] mullvad_rpc::Error),
+}
+
+impl<T> From<TimeoutError<T>> for Error {
+    fn from(_: TimeoutError<T>) -> Error {
+        Error::DownloadTimeout
+    }
+}
+
+/// Loads the lists of the given categories. Categories without a list in the cache or resource
+/// directory are blocked with an empty list until one has been downloaded.
+pub fn load(
+    categories: &BTreeSet<BlocklistCategory>,
+    resource_dir: &Path,
+    cache_dir: &Path,
+) -> Vec<Blocklist> {
+    categories
+        .iter()
+        .map(
+            |category| match Blocklist::load(*category, resource_dir, cache_dir) {
+                Ok(list) => {
+                    log::debug!("Loaded {} domains to block as {}", list.len(), category);
+                    list
+                }
+                Err(error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Unable to load the DNS blocklist of {}",
+                            category
+                        ))
+                    );
+                    Blocklist::parse(*category, "")
+                }
+            },
+        )
+        .collect()
+}
+
+/// Downloads the lists of the enabled categories from the API, and stores them in the cache
+/// directory. The category of each updated list is sent to the daemon, which reloads it.
+pub(crate) struct BlocklistUpdater {
+    blocklist_proxy: DnsBlocklistProxy<HttpHandle>,
+    cache_dir: PathBuf,
+    update_sender: DaemonEventSender<BlocklistCategory>,
+    categories: Arc<Mutex<BTreeSet<BlocklistCategory>>>,
+    next_update_times: HashMap<BlocklistCategory, Instant>,
+    state: BlocklistUpdaterState,
+}
+
+enum BlocklistUpdaterState {
+    Sleeping(tokio_timer::Sleep),
+    Updating(
+        BlocklistCategory,
+        Box<dyn Future<Item = Vec<String>, Error = Error> + Send + 'static>,
+    ),
+}
+
+impl BlocklistUpdater {
+    pub fn new(
+        rpc_handle: HttpHandle,
+        cache_dir: PathBuf,
+        update_sender: DaemonEventSender<BlocklistCategory>,
+        categories: Arc<Mutex<BTreeSet<BlocklistCategory>>>,
+    ) -> Self {
+        Self {
+            blocklist_proxy: DnsBlocklistProxy::new(rpc_handle),
+            cache_dir,
+            update_sender,
+            categories,
+            next_update_times: HashMap::new(),
+            state: BlocklistUpdaterState::Sleeping(Self::create_sleep_future()),
+        }
+    }
+
+    fn create_sleep_future() -> tokio_timer::Sleep {
+        Timer::default().sleep(UPDATE_CHECK_INTERVAL)
+    }
+
+    /// Returns the first enabled category whose list has not been downloaded recently.
+    fn next_due_category(&self) -> Option<BlocklistCategory> {
+        let now = Instant::now();
+        self.categories
+            .lock()
+            .iter()
+            .find(|category| {
+                self.next_update_times
+                    .get(category)
+                    .map(|next_update_time| now >= *next_update_time)
+                    .unwrap_or(true)
+            })
+            .copied()
+    }
+
+    fn create_update_future(
+        &mut self,
+        category: BlocklistCategory,
+    ) -> Box<dyn Future<Item = Vec<String>, Error = Error> + Send + 'static> {
+        let download_future = self
+            .blocklist_proxy
+            .dns_blocklist(category)
+            .map_err(Error::Download);
+        let future = Timer::default().timeout(download_future, DOWNLOAD_TIMEOUT);
+        Box::new(future)
+    }
+
+    fn write_cache(&self, category: BlocklistCategory, domains: &[String]) -> Result<(), Error> {
+        let path = blocklist::cache_path(category, &self.cache_dir);
+        log::debug!("Writing DNS blocklist cache to {}", path.display());
+        let mut contents = domains.join("\n");
+        contents.push('\n');
+        fs::write(path, contents).map_err(Error::WriteCache)
+    }
+}
+
+impl Future for BlocklistUpdater {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if self.update_sender.is_closed() {
+                log::warn!("Blocklist update receiver is closed, stopping blocklist updater");
+                return Ok(Async::Ready(()));
+            }
+            let next_state = match &mut self.state {
+                BlocklistUpdaterState::Sleeping(timer) => match timer.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        log::error!("Blocklist update sleep error: {}", e);
+                        return Err(());
+                    }
+                    Ok(Async::Ready(())) => match self.next_due_category() {
+                        Some(category) => BlocklistUpdaterState::Updating(
+                            category,
+                            self.create_update_future(category),
+                        ),
+                        None => BlocklistUpdaterState::Sleeping(Self::create_sleep_future()),
+                    },
+                },
+                BlocklistUpdaterState::Updating(category, future) => {
+                    let category = *category;
+                    match future.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(error) => {
+                            log::error!(
+                                "{}",
+                                error.display_chain_with_msg(&format!(
+                                    "Unable to update the DNS blocklist of {}",
+                                    category
+                                ))
+                            );
+                            self.next_update_times
+                                .insert(category, Instant::now() + UPDATE_INTERVAL_ERROR);
+                        }
+                        Ok(Async::Ready(domains)) => {
+                            log::debug!(
+                                "Downloaded {} domains to block as {}",
+                                domains.len(),
+                                category
+                            );
+                            self.next_update_times
+                                .insert(category, Instant::now() + UPDATE_INTERVAL);
+                            match self.write_cache(category, &domains) {
+                                Ok(()) => {
+                                    if self.update_sender.send(category).is_err() {
+                                        log::warn!(
+                                            "Blocklist update receiver is closed, stopping \
+                                             blocklist updater"
+                                        );
+                                        return Ok(Async::Ready(()));
+                                    }
+                                }
+                                Err(e) => log::error!(
+                                    "{}",
+                                    e.display_chain_with_msg("Unable to cache DNS blocklist")
+                                ),
+                            }
+                        }
+                    }
+                    // Update the next list that is due without waiting
+                    match self.next_due_category() {
+                        Some(category) => BlocklistUpdaterState::Updating(
+                            category,
+                            self.create_update_future(category),
+                        ),
+                        None => BlocklistUpdaterState::Sleeping(Self::create_sleep_future()),
+                    }
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
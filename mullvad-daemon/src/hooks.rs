@@ -0,0 +1,527 @@
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::{
+    net::TunnelEndpoint,
+    tunnel::{ErrorState, TunnelInterface, TunnelStateTransition},
+    ErrorExt,
+};
+
+const HOOKS_FILENAME: &str = "hooks.json";
+
+/// Hooks that do not finish within this many seconds are killed, unless configured otherwise.
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+/// How often to check if a running hook has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long to keep logging the output of a hook after it has exited. Processes started by the
+/// hook may hold on to its output long after the hook itself is gone.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to read {}", _0)]
+    ReadFile(String, #[error(source)] io::Error),
+
+    #[error(display = "Unable to parse the hooks file")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(
+        display = "{} must be owned by root and not writable by other users",
+        _0
+    )]
+    InsecurePermissions(String),
+
+    #[error(display = "Unable to start the hook")]
+    Spawn(#[error(source)] io::Error),
+
+    #[error(display = "Unable to wait for the hook to exit")]
+    Wait(#[error(source)] io::Error),
+}
+
+/// Commands to run when the tunnel enters a state. Read from a file in the settings directory
+/// rather than the regular settings, so that only root can configure them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HookSettings {
+    /// Seconds a hook is allowed to run before it is killed.
+    timeout: Option<u64>,
+    connecting: Vec<Hook>,
+    connected: Vec<Hook>,
+    disconnected: Vec<Hook>,
+    error: Vec<Hook>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Hook {
+    path: PathBuf,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+struct HookJob {
+    hook: Hook,
+    env: Vec<(&'static str, String)>,
+}
+
+/// Runs the configured hooks on tunnel state transitions. The hooks are run one at a time, in
+/// order, on a separate thread.
+pub struct HookRunner {
+    settings: HookSettings,
+    job_tx: Option<mpsc::Sender<HookJob>>,
+}
+
+impl HookRunner {
+    /// Loads the hooks from the settings directory. No hooks are run if the file is missing or
+    /// can't be trusted.
+    pub fn load(settings_dir: &Path) -> Self {
+        let path = settings_dir.join(HOOKS_FILENAME);
+        let settings = match Self::read_settings(&path) {
+            Ok(Some(settings)) => settings,
+            Ok(None) => HookSettings::default(),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Not running any tunnel state hooks")
+                );
+                HookSettings::default()
+            }
+        };
+
+        let job_tx = if settings.is_empty() {
+            None
+        } else {
+            log::info!("Loaded tunnel state hooks from {}", path.display());
+            Some(Self::spawn_worker(Duration::from_secs(
+                settings.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS),
+            )))
+        };
+
+        HookRunner { settings, job_tx }
+    }
+
+    fn read_settings(path: &Path) -> Result<Option<HookSettings>, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(Error::ReadFile(path.display().to_string(), error)),
+        };
+        check_permissions(path)?;
+        serde_json::from_reader(BufReader::new(file))
+            .map(Some)
+            .map_err(Error::Parse)
+    }
+
+    fn spawn_worker(timeout: Duration) -> mpsc::Sender<HookJob> {
+        let (job_tx, job_rx) = mpsc::channel::<HookJob>();
+        thread::spawn(move || {
+            for job in job_rx {
+                let path = job.hook.path.display().to_string();
+                if let Err(error) = run_hook(job, timeout) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!("Hook {} failed", path))
+                    );
+                }
+            }
+        });
+        job_tx
+    }
+
+    /// Queues the hooks of the state that is entered by the transition. Returns right away.
+    pub fn on_transition(&self, transition: &TunnelStateTransition) {
+        let job_tx = match &self.job_tx {
+            Some(job_tx) => job_tx,
+            None => return,
+        };
+        let (hooks, env) = match transition {
+            TunnelStateTransition::Connecting(endpoint) => {
+                let mut env = vec![("MULLVAD_TUNNEL_STATE", "connecting".to_owned())];
+                env.extend(endpoint_env(endpoint));
+                (&self.settings.connecting, env)
+            }
+            TunnelStateTransition::Connected(endpoint, interface) => {
+                let mut env = vec![("MULLVAD_TUNNEL_STATE", "connected".to_owned())];
+                env.extend(endpoint_env(endpoint));
+                env.extend(interface_env(interface));
+                (&self.settings.connected, env)
+            }
+            TunnelStateTransition::Disconnected => (
+                &self.settings.disconnected,
+                vec![("MULLVAD_TUNNEL_STATE", "disconnected".to_owned())],
+            ),
+            TunnelStateTransition::Error(error_state) => {
+                let mut env = vec![("MULLVAD_TUNNEL_STATE", "error".to_owned())];
+                env.extend(error_env(error_state));
+                (&self.settings.error, env)
+            }
+            TunnelStateTransition::Disconnecting(_)
+            | TunnelStateTransition::QualityChanged(_)
+            | TunnelStateTransition::FirewallTampered(_) => return,
+        };
+
+        for hook in hooks {
+            let job = HookJob {
+                hook: hook.clone(),
+                env: env.clone(),
+            };
+            if job_tx.send(job).is_err() {
+                log::error!(
+                    "Hook worker has stopped, unable to run {}",
+                    hook.path.display()
+                );
+            }
+        }
+    }
+}
+
+impl HookSettings {
+    fn is_empty(&self) -> bool {
+        self.connecting.is_empty()
+            && self.connected.is_empty()
+            && self.disconnected.is_empty()
+            && self.error.is_empty()
+    }
+}
+
+fn endpoint_env(endpoint: &TunnelEndpoint) -> Vec<(&'static str, String)> {
+    vec![
+        ("MULLVAD_ENDPOINT", endpoint.endpoint.address.to_string()),
+        (
+            "MULLVAD_ENDPOINT_PROTOCOL",
+            endpoint.endpoint.protocol.to_string(),
+        ),
+        ("MULLVAD_TUNNEL_TYPE", endpoint.tunnel_type.to_string()),
+    ]
+}
+
+fn interface_env(interface: &TunnelInterface) -> Vec<(&'static str, String)> {
+    let mut env = vec![("MULLVAD_TUNNEL_INTERFACE", interface.name.clone())];
+    if let Some(ip) = interface.ips.iter().find(|ip| ip.is_ipv4()) {
+        env.push(("MULLVAD_TUNNEL_IPV4", ip.to_string()));
+    }
+    if let Some(ip) = interface.ips.iter().find(|ip| ip.is_ipv6()) {
+        env.push(("MULLVAD_TUNNEL_IPV6", ip.to_string()));
+    }
+    env.push((
+        "MULLVAD_TUNNEL_IPS",
+        interface
+            .ips
+            .iter()
+            .map(IpAddr::to_string)
+            .collect::<Vec<_>>()
+            .join(" "),
+    ));
+    env
+}
+
+fn error_env(error_state: &ErrorState) -> Vec<(&'static str, String)> {
+    vec![
+        ("MULLVAD_ERROR_CAUSE", error_state.cause().to_string()),
+        (
+            "MULLVAD_ERROR_BLOCKING",
+            error_state.is_blocking().to_string(),
+        ),
+    ]
+}
+
+fn run_hook(job: HookJob, timeout: Duration) -> Result<(), Error> {
+    let path = job.hook.path.display().to_string();
+    // The file may have been replaced since the hooks were loaded.
+    check_permissions(&job.hook.path)?;
+
+    log::debug!("Running hook {}", path);
+    let mut command = Command::new(&job.hook.path);
+    command
+        .args(&job.hook.args)
+        .envs(job.env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    start_in_new_session(&mut command);
+    let mut child = command.spawn().map_err(Error::Spawn)?;
+
+    let stdout_logger = child
+        .stdout
+        .take()
+        .map(|stdout| log_output(stdout, path.clone(), log::Level::Info));
+    let stderr_logger = child
+        .stderr
+        .take()
+        .map(|stderr| log_output(stderr, path.clone(), log::Level::Warn));
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(Error::Wait)? {
+            break Some(status);
+        }
+        if started.elapsed() >= timeout {
+            log::warn!(
+                "Killing hook {} since it did not finish within {} seconds",
+                path,
+                timeout.as_secs()
+            );
+            kill_hook(&mut child);
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let drain_deadline = Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+    for logger in stdout_logger.into_iter().chain(stderr_logger) {
+        let remaining = drain_deadline.saturating_duration_since(Instant::now());
+        if let Err(mpsc::RecvTimeoutError::Timeout) = logger.recv_timeout(remaining) {
+            log::debug!("No longer waiting for output from hook {}", path);
+            break;
+        }
+    }
+
+    match status {
+        Some(status) if status.success() => log::debug!("Hook {} finished", path),
+        Some(status) => log::warn!("Hook {} exited with {}", path, status),
+        None => (),
+    }
+    Ok(())
+}
+
+/// Logs the lines read from `output` on a separate thread. The returned channel is signaled, or
+/// closed, once the end of the output is reached.
+fn log_output(
+    output: impl Read + Send + 'static,
+    path: String,
+    level: log::Level,
+) -> mpsc::Receiver<()> {
+    let (done_tx, done_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            match line {
+                Ok(line) => log::log!(level, "[hook {}] {}", path, line),
+                Err(_) => break,
+            }
+        }
+        let _ = done_tx.send(());
+    });
+    done_rx
+}
+
+/// Makes the hook the leader of a new session and process group, so that any processes it starts
+/// can be killed along with it.
+#[cfg(unix)]
+fn start_in_new_session(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(unix)]
+fn kill_hook(child: &mut Child) {
+    // The hook leads its own process group, so this kills everything it has started as well.
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } == -1 {
+        let _ = child.kill();
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_hook(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path)
+        .map_err(|error| Error::ReadFile(path.display().to_string(), error))?;
+    if metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+        return Err(Error::InsecurePermissions(path.display().to_string()));
+    }
+    Ok(())
+}
+
+/// The settings directory is only writable by administrators on Windows.
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use talpid_types::{
+        net::{Endpoint, TransportProtocol, TunnelType},
+        tunnel::ErrorStateCause,
+    };
+
+    fn env(vars: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+        vars.iter()
+            .map(|(name, value)| (*name, (*value).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let settings: HookSettings = serde_json::from_str(
+            r#"{
+                "timeout": 5,
+                "connected": [{ "path": "/usr/local/bin/on-connect", "args": ["--quiet"] }],
+                "error": [{ "path": "/usr/local/bin/on-error" }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.timeout, Some(5));
+        assert!(settings.connecting.is_empty());
+        assert!(settings.disconnected.is_empty());
+        assert_eq!(
+            settings.connected[0].path,
+            Path::new("/usr/local/bin/on-connect")
+        );
+        assert_eq!(settings.connected[0].args, vec!["--quiet"]);
+        assert!(settings.error[0].args.is_empty());
+        assert!(!settings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_empty_settings() {
+        let settings: HookSettings = serde_json::from_str("{}").unwrap();
+        assert_eq!(settings.timeout, None);
+        assert!(settings.is_empty());
+    }
+
+    #[test]
+    fn test_hook_without_path_is_rejected() {
+        let result = serde_json::from_str::<HookSettings>(r#"{ "connected": [{ "args": [] }] }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_endpoint_env() {
+        let endpoint = TunnelEndpoint {
+            endpoint: Endpoint::new(Ipv4Addr::new(10, 0, 0, 1), 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            proxy: None,
+        };
+        assert_eq!(
+            endpoint_env(&endpoint),
+            env(&[
+                ("MULLVAD_ENDPOINT", "10.0.0.1:51820"),
+                ("MULLVAD_ENDPOINT_PROTOCOL", "UDP"),
+                ("MULLVAD_TUNNEL_TYPE", "WireGuard"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_interface_env() {
+        let interface = TunnelInterface {
+            name: "wg-mullvad".to_owned(),
+            ips: vec![
+                Ipv4Addr::new(10, 64, 0, 2).into(),
+                Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 2).into(),
+            ],
+        };
+        assert_eq!(
+            interface_env(&interface),
+            env(&[
+                ("MULLVAD_TUNNEL_INTERFACE", "wg-mullvad"),
+                ("MULLVAD_TUNNEL_IPV4", "10.64.0.2"),
+                ("MULLVAD_TUNNEL_IPV6", "fc00:bbbb:bbbb:bb01::2"),
+                ("MULLVAD_TUNNEL_IPS", "10.64.0.2 fc00:bbbb:bbbb:bb01::2"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_interface_env_without_ipv6() {
+        let interface = TunnelInterface {
+            name: "tun0".to_owned(),
+            ips: vec![Ipv4Addr::new(10, 8, 0, 2).into()],
+        };
+        assert_eq!(
+            interface_env(&interface),
+            env(&[
+                ("MULLVAD_TUNNEL_INTERFACE", "tun0"),
+                ("MULLVAD_TUNNEL_IPV4", "10.8.0.2"),
+                ("MULLVAD_TUNNEL_IPS", "10.8.0.2"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_error_env() {
+        let error_state = ErrorState::new(ErrorStateCause::IsOffline, true);
+        assert_eq!(
+            error_env(&error_state),
+            env(&[
+                (
+                    "MULLVAD_ERROR_CAUSE",
+                    "This device is offline, no tunnels can be established"
+                ),
+                ("MULLVAD_ERROR_BLOCKING", "true"),
+            ])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_permissions() {
+        use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+        let path = std::env::temp_dir().join(format!("mullvad-hook-test-{}", std::process::id()));
+        File::create(&path).unwrap();
+        let set_mode =
+            |mode| std::fs::set_permissions(&path, Permissions::from_mode(mode)).unwrap();
+
+        set_mode(0o755);
+        let owned_by_root = unsafe { libc::geteuid() } == 0;
+        assert_eq!(check_permissions(&path).is_ok(), owned_by_root);
+
+        for &mode in &[0o775, 0o757] {
+            set_mode(mode);
+            match check_permissions(&path) {
+                Err(Error::InsecurePermissions(_)) => (),
+                result => panic!("Mode {:o} gave {:?}", mode, result),
+            }
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        match check_permissions(&path) {
+            Err(Error::ReadFile(..)) => (),
+            result => panic!("Missing file gave {:?}", result),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_kills_processes_started_by_hook() {
+        let job = HookJob {
+            hook: Hook {
+                path: PathBuf::from("/bin/sh"),
+                args: vec!["-c".to_owned(), "sleep 60 & sleep 60".to_owned()],
+            },
+            env: vec![],
+        };
+        let started = Instant::now();
+        run_hook(job, Duration::from_secs(1)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
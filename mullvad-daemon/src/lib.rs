@@ -4,9 +4,14 @@
 extern crate serde;
 
 
+mod account_expiry;
 mod account_history;
+mod dns_blocklists;
 pub mod exception_logging;
 mod geoip;
+mod hooks;
+#[cfg(target_os = "linux")]
+mod lockdown;
 pub mod logging;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
@@ -17,6 +22,7 @@ mod settings;
 pub mod version;
 mod version_check;
 
+use chrono::{offset::Utc, DateTime};
 use futures::{
     future::{self, Executor},
     stream::Wait,
@@ -26,10 +32,11 @@ use futures::{
     },
     Future, Stream,
 };
+use ipnetwork::IpNetwork;
 use log::{debug, error, info, warn};
 use mullvad_rpc::{AccountsProxy, HttpHandle, WireguardKeyProxy};
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{AccountData, AccountExpiryEvent, AccountToken, ExpiryStage, VoucherSubmission},
     endpoint::MullvadEndpoint,
     location::GeoIpLocation,
     relay_constraints::{
@@ -43,9 +50,12 @@ use mullvad_types::{
     wireguard::KeygenEvent,
 };
 use settings::SettingsPersister;
+#[cfg(target_os = "linux")]
+use std::net::IpAddr;
 #[cfg(not(target_os = "android"))]
 use std::path::Path;
 use std::{
+    collections::BTreeSet,
     fs::{self, File},
     io,
     marker::PhantomData,
@@ -56,16 +66,40 @@ use std::{
     time::Duration,
 };
 use talpid_core::{
+    dns::stub::Blocklists,
     mpsc::Sender,
-    tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
+    tunnel_state_machine::{self, DnsConfig, TunnelCommand, TunnelParametersGenerator},
+};
+#[cfg(target_os = "linux")]
+use talpid_core::{
+    firewall::{self, FirewallPolicy},
+    split_tunnel,
+    tunnel::TunnelMetadata,
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
 use talpid_types::{
-    net::{openvpn, TransportProtocol, TunnelParameters, TunnelType},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    dns::BlocklistCategory,
+    net::{
+        openvpn, validate_excluded_subnets, validate_forwarded_ports, validate_lan_networks,
+        AllowedEndpoint, ExcludedSubnetError, ForwardedPort, InvalidPortRange, LanNetworks,
+        LanNetworksError, TransportProtocol, TunnelParameters, TunnelType,
+    },
+    tunnel::{
+        ConnectionQuality, ErrorStateCause, FirewallIncident, ParameterGenerationError,
+        TunnelStateTransition,
+    },
     ErrorExt,
 };
+#[cfg(target_os = "linux")]
+use talpid_types::{
+    dns::{
+        split_dns_servers, validate_dns_mode, validate_split_dns_rule, BlockedResponse,
+        BlocklistStats, DnsLeakCheckerEndpoint, DnsLeakReport, DnsManager, DnsManagerDiagnostics,
+        DnsMode, InvalidEncryptedDnsServer, InvalidSplitDnsRule, SplitDnsRule,
+    },
+    firewall::{FirewallPolicyQuery, RuleStats},
+};
 
 #[path = "wireguard.rs"]
 mod wireguard;
@@ -175,10 +209,66 @@ pub enum DaemonCommand {
     UpdateRelaySettings(oneshot::Sender<()>, RelaySettingsUpdate),
     /// Set the allow LAN setting.
     SetAllowLan(oneshot::Sender<()>, bool),
+    /// Set which networks are considered LAN networks
+    SetLanNetworks(
+        oneshot::Sender<std::result::Result<(), LanNetworksError>>,
+        LanNetworks,
+    ),
+    /// Set which endpoints can be reached while connecting or blocked
+    SetAllowedEndpoints(oneshot::Sender<()>, Vec<AllowedEndpoint>),
+    /// Set the DNS servers to use inside the tunnel instead of the relay
+    #[cfg(target_os = "linux")]
+    SetCustomDns(oneshot::Sender<()>, Vec<IpAddr>),
+    /// Set whether DNS queries are sent through the local DNS resolver
+    #[cfg(target_os = "linux")]
+    SetLocalDnsResolver(oneshot::Sender<()>, bool),
+    /// Set whether the local DNS resolver blocks the domains of a category
+    #[cfg(target_os = "linux")]
+    SetDnsBlocklist(oneshot::Sender<()>, BlocklistCategory, bool),
+    /// Set how the local DNS resolver answers queries for blocked domains
+    #[cfg(target_os = "linux")]
+    SetDnsBlockedResponse(oneshot::Sender<()>, BlockedResponse),
+    /// Return how many domains and blocked queries each blocklist in use has
+    #[cfg(target_os = "linux")]
+    GetDnsBlocklistStats(oneshot::Sender<Vec<BlocklistStats>>),
+    /// Set whether DNS queries are sent to an encrypted DNS server while connected
+    #[cfg(target_os = "linux")]
+    SetDnsMode(
+        oneshot::Sender<std::result::Result<(), InvalidEncryptedDnsServer>>,
+        DnsMode,
+    ),
+    /// Set which domains are resolved by other DNS servers while connected
+    #[cfg(target_os = "linux")]
+    SetSplitDns(
+        oneshot::Sender<std::result::Result<(), InvalidSplitDnsRule>>,
+        Vec<SplitDnsRule>,
+    ),
+    /// Set which program the system DNS is set through, or detect it if `None`
+    #[cfg(target_os = "linux")]
+    SetDnsManager(oneshot::Sender<()>, Option<DnsManager>),
+    /// Return which DNS manager is used and why, what was set through it and whether the system
+    /// still uses it
+    #[cfg(target_os = "linux")]
+    GetDnsManagerDiagnostics(oneshot::Sender<DnsManagerDiagnostics>),
+    /// Check which resolvers answer queries while connected, and whether queries to resolvers
+    /// outside the tunnel are blocked
+    #[cfg(target_os = "linux")]
+    RunDnsLeakTest(
+        oneshot::Sender<std::result::Result<DnsLeakReport, talpid_core::dns::leak_test::Error>>,
+        Option<DnsLeakCheckerEndpoint>,
+    ),
     /// Set the beta program setting.
     SetShowBetaReleases(oneshot::Sender<()>, bool),
+    /// Set how many hours before the account expiry to warn about it
+    SetAccountExpiryWarnings(oneshot::Sender<()>, Vec<u32>),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(oneshot::Sender<()>, bool),
+    /// Set the lockdown mode setting.
+    #[cfg(target_os = "linux")]
+    SetLockdownMode(
+        oneshot::Sender<std::result::Result<(), lockdown::Error>>,
+        bool,
+    ),
     /// Set the auto-connect setting.
     SetAutoConnect(oneshot::Sender<()>, bool),
     /// Set the mssfix argument for OpenVPN
@@ -195,10 +285,26 @@ pub enum DaemonCommand {
     ),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(oneshot::Sender<()>, bool),
+    /// Set destination networks that should be reached outside the tunnel
+    SetExcludedSubnets(
+        oneshot::Sender<std::result::Result<(), ExcludedSubnetError>>,
+        Vec<IpNetwork>,
+    ),
+    /// Set ports that accept incoming connections through the tunnel
+    SetForwardedPorts(
+        oneshot::Sender<std::result::Result<(), InvalidPortRange>>,
+        Vec<ForwardedPort>,
+    ),
+    /// Set interfaces whose forwarded traffic is sent through the tunnel
+    SetSharedInterfaces(oneshot::Sender<()>, Vec<String>),
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(oneshot::Sender<()>, Option<u16>),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(oneshot::Sender<()>, Option<u32>),
+    /// Set how long a wireguard tunnel may stay degraded before switching relay
+    SetWireguardDegradedTimeout(oneshot::Sender<()>, Option<u32>),
+    /// Set whether wireguard tunnels use a dedicated routing table
+    SetWireguardPolicyRouting(oneshot::Sender<()>, bool),
     /// Get the daemon settings
     GetSettings(oneshot::Sender<Settings>),
     /// Generate new wireguard key
@@ -214,6 +320,27 @@ pub enum DaemonCommand {
     /// Remove settings and clear the cache
     #[cfg(not(target_os = "android"))]
     FactoryReset(oneshot::Sender<()>),
+    /// Exclude a process, and the processes it starts, from the tunnel
+    #[cfg(target_os = "linux")]
+    AddSplitTunnelProcess(
+        oneshot::Sender<std::result::Result<(), split_tunnel::Error>>,
+        i32,
+    ),
+    /// Stop excluding a process from the tunnel
+    #[cfg(target_os = "linux")]
+    RemoveSplitTunnelProcess(
+        oneshot::Sender<std::result::Result<(), split_tunnel::Error>>,
+        i32,
+    ),
+    /// Return the PIDs of all processes that are excluded from the tunnel
+    #[cfg(target_os = "linux")]
+    GetSplitTunnelProcesses(oneshot::Sender<std::result::Result<Vec<i32>, split_tunnel::Error>>),
+    /// Render the firewall rules of a tunnel state as an nft script, without applying them
+    #[cfg(target_os = "linux")]
+    GetFirewallPolicyScript(oneshot::Sender<String>, FirewallPolicyQuery),
+    /// Return how many packets and bytes each rule of the applied firewall policy has matched
+    #[cfg(target_os = "linux")]
+    GetFirewallStats(oneshot::Sender<std::result::Result<Vec<RuleStats>, firewall::Error>>),
     /// Makes the daemon exit the main loop and quit.
     Shutdown,
     /// Saves the target tunnel state and enters a blocking state. The state is restored
@@ -248,6 +375,10 @@ pub(crate) enum InternalDaemonEvent {
     ),
     /// The background job fetching new `AppVersionInfo`s got a new info object.
     NewAppVersionInfo(AppVersionInfo),
+    /// The expiry of an account was fetched.
+    AccountExpiry(AccountToken, DateTime<Utc>),
+    /// The background job updating DNS blocklists downloaded a new list of a category.
+    DnsBlocklistUpdated(BlocklistCategory),
 }
 
 impl From<TunnelStateTransition> for InternalDaemonEvent {
@@ -268,6 +399,12 @@ impl From<AppVersionInfo> for InternalDaemonEvent {
     }
 }
 
+impl From<BlocklistCategory> for InternalDaemonEvent {
+    fn from(category: BlocklistCategory) -> Self {
+        InternalDaemonEvent::DnsBlocklistUpdated(category)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DaemonExecutionState {
     Running,
@@ -426,6 +563,12 @@ pub trait EventListener {
 
     /// Notify clients of a key generation event.
     fn notify_key_event(&self, key_event: KeygenEvent);
+
+    /// Notify that the expiry of the current account crossed a warning threshold.
+    fn notify_account_expiry(&self, account_expiry_event: AccountExpiryEvent);
+
+    /// Notify that another program changed the firewall rules.
+    fn notify_firewall_incident(&self, incident: FirewallIncident);
 }
 
 pub struct Daemon<L: EventListener> {
@@ -443,6 +586,16 @@ pub struct Daemon<L: EventListener> {
     accounts_proxy: AccountsProxy<HttpHandle>,
     https_handle: mullvad_rpc::rest::RequestSender,
     wireguard_key_manager: wireguard::KeyManager,
+    account_expiry_watcher: account_expiry::AccountExpiryWatcher,
+    /// Last fetched expiry of the current account, and how close to it the account is.
+    account_expiry: Option<(DateTime<Utc>, ExpiryStage)>,
+    hooks: hooks::HookRunner,
+    /// Created the first time split tunneling is used.
+    #[cfg(target_os = "linux")]
+    split_tunnel: Option<split_tunnel::PidManager>,
+    /// The name of the tunnel interface while connected.
+    #[cfg(target_os = "linux")]
+    tunnel_interface: Option<String>,
     tokio_remote: tokio_core::reactor::Remote,
     relay_selector: relays::RelaySelector,
     last_generated_relay: Option<Relay>,
@@ -451,6 +604,11 @@ pub struct Daemon<L: EventListener> {
     shutdown_callbacks: Vec<Box<dyn FnOnce()>>,
     /// oneshot channel that completes once the tunnel state machine has been shut down
     tunnel_state_machine_shutdown_signal: oneshot::Receiver<()>,
+    /// The blocklists of the local DNS resolver, which count the queries they have blocked
+    dns_blocklists: Blocklists,
+    /// The categories that the blocklist updater keeps the lists of up to date
+    dns_blocklist_categories: Arc<parking_lot::Mutex<BTreeSet<BlocklistCategory>>>,
+    resource_dir: PathBuf,
     cache_dir: PathBuf,
 }
 
@@ -509,11 +667,37 @@ where
         tokio_remote.spawn(|_| version_check_future);
 
         let mut settings = SettingsPersister::load(&settings_dir);
+        let hooks = hooks::HookRunner::load(&settings_dir);
+        // Refresh the persistent ruleset, in case it was removed or changed by an upgrade.
+        #[cfg(target_os = "linux")]
+        {
+            if let Err(e) = lockdown::update_ruleset(settings.lockdown_mode) {
+                error!(
+                    "{}",
+                    e.display_chain_with_msg("Unable to update the lockdown ruleset")
+                );
+            }
+        }
 
         if version::is_beta_version() && settings.show_beta_releases.is_none() {
             let _ = settings.set_show_beta_releases(true);
         }
 
+        let dns_blocklists = Blocklists::new(
+            dns_blocklists::load(&settings.dns_blocking.categories, &resource_dir, &cache_dir),
+            settings.dns_blocking.response,
+        );
+        let dns_blocklist_categories = Arc::new(parking_lot::Mutex::new(
+            settings.dns_blocking.categories.clone(),
+        ));
+        let blocklist_updater = dns_blocklists::BlocklistUpdater::new(
+            rpc_handle.clone(),
+            cache_dir.clone(),
+            internal_event_tx.to_specialized_sender(),
+            dns_blocklist_categories.clone(),
+        );
+        tokio_remote.spawn(|_| blocklist_updater);
+
         let account_history = account_history::AccountHistory::new(
             &cache_dir,
             rpc_handle.clone(),
@@ -546,10 +730,13 @@ where
         };
         let tunnel_command_tx = tunnel_state_machine::spawn(
             settings.allow_lan,
-            settings.block_when_disconnected,
+            settings.lan_networks.clone(),
+            settings.allowed_endpoints.clone(),
+            dns_config(&settings, &dns_blocklists),
+            settings.block_when_disconnected || settings.lockdown_mode,
             tunnel_parameters_generator,
             log_dir,
-            resource_dir,
+            resource_dir.clone(),
             cache_dir.clone(),
             internal_event_tx.to_specialized_sender(),
             tunnel_state_machine_shutdown_tx,
@@ -564,6 +751,15 @@ where
             tokio_remote.clone(),
         );
 
+        let mut account_expiry_watcher = account_expiry::AccountExpiryWatcher::new(
+            internal_event_tx.clone(),
+            rpc_handle.clone(),
+            tokio_remote.clone(),
+        );
+        if let Some(token) = settings.get_account_token() {
+            account_expiry_watcher.watch(token);
+        }
+
         // Attempt to download a fresh relay list
         relay_selector.update();
 
@@ -595,6 +791,13 @@ where
             accounts_proxy: AccountsProxy::new(rpc_handle),
             https_handle,
             wireguard_key_manager,
+            account_expiry_watcher,
+            account_expiry: None,
+            hooks,
+            #[cfg(target_os = "linux")]
+            split_tunnel: None,
+            #[cfg(target_os = "linux")]
+            tunnel_interface: None,
             tokio_remote,
             relay_selector,
             last_generated_relay: None,
@@ -602,6 +805,9 @@ where
             app_version_info,
             shutdown_callbacks: vec![],
             tunnel_state_machine_shutdown_signal,
+            dns_blocklists,
+            dns_blocklist_categories,
+            resource_dir,
             cache_dir,
         };
 
@@ -699,24 +905,47 @@ where
             NewAppVersionInfo(app_version_info) => {
                 self.handle_new_app_version_info(app_version_info)
             }
+            AccountExpiry(account_token, expiry) => {
+                self.handle_account_expiry(account_token, expiry)
+            }
+            DnsBlocklistUpdated(category) => self.handle_dns_blocklist_updated(category),
         }
     }
 
     fn handle_tunnel_state_transition(&mut self, tunnel_state_transition: TunnelStateTransition) {
+        self.hooks.on_transition(&tunnel_state_transition);
+
+        #[cfg(target_os = "linux")]
+        match &tunnel_state_transition {
+            TunnelStateTransition::Connected(_, interface) => {
+                self.tunnel_interface = Some(interface.name.clone())
+            }
+            TunnelStateTransition::QualityChanged(_)
+            | TunnelStateTransition::FirewallTampered(_) => (),
+            _ => self.tunnel_interface = None,
+        }
+
         let tunnel_state = match tunnel_state_transition {
             TunnelStateTransition::Disconnected => TunnelState::Disconnected,
             TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
                 endpoint,
                 location: self.build_location_from_relay(),
             },
-            TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
+            TunnelStateTransition::Connected(endpoint, _) => TunnelState::Connected {
                 endpoint,
                 location: self.build_location_from_relay(),
+                quality: None,
             },
             TunnelStateTransition::Disconnecting(after_disconnect) => {
                 TunnelState::Disconnecting(after_disconnect)
             }
             TunnelStateTransition::Error(error_state) => TunnelState::Error(error_state),
+            TunnelStateTransition::QualityChanged(quality) => {
+                return self.handle_connection_quality_change(quality);
+            }
+            TunnelStateTransition::FirewallTampered(incident) => {
+                return self.handle_firewall_incident(incident);
+            }
         };
 
         self.unschedule_reconnect();
@@ -738,7 +967,16 @@ where
                 }
 
                 if let ErrorStateCause::AuthFailed(_) = error_state.cause() {
-                    self.schedule_reconnect(Duration::from_secs(60))
+                    if self.account_is_expired() {
+                        self.send_tunnel_command(TunnelCommand::Block(
+                            ErrorStateCause::AccountExpired,
+                        ));
+                    } else {
+                        self.schedule_reconnect(Duration::from_secs(60))
+                    }
+                    // The known expiry might be outdated, so make sure the next attempt uses
+                    // a fresh one
+                    self.refresh_account_expiry();
                 }
             }
             _ => {}
@@ -748,6 +986,35 @@ where
         self.event_listener.notify_new_state(tunnel_state);
     }
 
+    fn handle_firewall_incident(&mut self, incident: FirewallIncident) {
+        if incident.restored {
+            warn!("{}", incident);
+        } else {
+            error!("{}", incident);
+        }
+        self.event_listener.notify_firewall_incident(incident);
+    }
+
+    fn handle_connection_quality_change(&mut self, new_quality: ConnectionQuality) {
+        if let TunnelState::Connected {
+            ref mut quality, ..
+        } = self.tunnel_state
+        {
+            let was_degraded = quality.as_ref().map(|q| q.degraded).unwrap_or(false);
+            if new_quality.degraded && !was_degraded {
+                warn!("Tunnel connection is degraded: {}", new_quality);
+            } else if !new_quality.degraded && was_degraded {
+                info!("Tunnel connection is no longer degraded: {}", new_quality);
+            } else {
+                debug!("Tunnel connection quality: {}", new_quality);
+            }
+
+            *quality = Some(new_quality);
+            self.event_listener
+                .notify_new_state(self.tunnel_state.clone());
+        }
+    }
+
     fn handle_generate_tunnel_parameters(
         &mut self,
         tunnel_parameters_tx: &mpsc::Sender<Result<TunnelParameters, ParameterGenerationError>>,
@@ -968,10 +1235,41 @@ where
             }
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update),
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan),
+            SetLanNetworks(tx, lan_networks) => self.on_set_lan_networks(tx, lan_networks),
+            SetAllowedEndpoints(tx, allowed_endpoints) => {
+                self.on_set_allowed_endpoints(tx, allowed_endpoints)
+            }
+            #[cfg(target_os = "linux")]
+            SetCustomDns(tx, custom_dns) => self.on_set_custom_dns(tx, custom_dns),
+            #[cfg(target_os = "linux")]
+            SetLocalDnsResolver(tx, enabled) => self.on_set_local_dns_resolver(tx, enabled),
+            #[cfg(target_os = "linux")]
+            SetDnsBlocklist(tx, category, enabled) => {
+                self.on_set_dns_blocklist(tx, category, enabled)
+            }
+            #[cfg(target_os = "linux")]
+            SetDnsBlockedResponse(tx, response) => self.on_set_dns_blocked_response(tx, response),
+            #[cfg(target_os = "linux")]
+            GetDnsBlocklistStats(tx) => self.on_get_dns_blocklist_stats(tx),
+            #[cfg(target_os = "linux")]
+            SetDnsMode(tx, dns_mode) => self.on_set_dns_mode(tx, dns_mode),
+            #[cfg(target_os = "linux")]
+            SetSplitDns(tx, split_dns) => self.on_set_split_dns(tx, split_dns),
+            #[cfg(target_os = "linux")]
+            SetDnsManager(tx, dns_manager) => self.on_set_dns_manager(tx, dns_manager),
+            #[cfg(target_os = "linux")]
+            GetDnsManagerDiagnostics(tx) => self.on_get_dns_manager_diagnostics(tx),
+            #[cfg(target_os = "linux")]
+            RunDnsLeakTest(tx, checker) => self.on_run_dns_leak_test(tx, checker),
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled),
+            SetAccountExpiryWarnings(tx, warnings) => {
+                self.on_set_account_expiry_warnings(tx, warnings)
+            }
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
             }
+            #[cfg(target_os = "linux")]
+            SetLockdownMode(tx, lockdown_mode) => self.on_set_lockdown_mode(tx, lockdown_mode),
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg),
             SetBridgeSettings(tx, bridge_settings) => {
@@ -979,10 +1277,25 @@ where
             }
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state),
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6),
+            SetExcludedSubnets(tx, excluded_subnets) => {
+                self.on_set_excluded_subnets(tx, excluded_subnets)
+            }
+            SetForwardedPorts(tx, forwarded_ports) => {
+                self.on_set_forwarded_ports(tx, forwarded_ports)
+            }
+            SetSharedInterfaces(tx, shared_interfaces) => {
+                self.on_set_shared_interfaces(tx, shared_interfaces)
+            }
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu),
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval)
             }
+            SetWireguardDegradedTimeout(tx, timeout) => {
+                self.on_set_wireguard_degraded_timeout(tx, timeout)
+            }
+            SetWireguardPolicyRouting(tx, policy_routing) => {
+                self.on_set_wireguard_policy_routing(tx, policy_routing)
+            }
             GetSettings(tx) => self.on_get_settings(tx),
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx),
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx),
@@ -991,6 +1304,16 @@ where
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
             #[cfg(not(target_os = "android"))]
             FactoryReset(tx) => self.on_factory_reset(tx),
+            #[cfg(target_os = "linux")]
+            AddSplitTunnelProcess(tx, pid) => self.on_add_split_tunnel_process(tx, pid),
+            #[cfg(target_os = "linux")]
+            RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
+            #[cfg(target_os = "linux")]
+            GetSplitTunnelProcesses(tx) => self.on_get_split_tunnel_processes(tx),
+            #[cfg(target_os = "linux")]
+            GetFirewallPolicyScript(tx, query) => self.on_get_firewall_policy_script(tx, query),
+            #[cfg(target_os = "linux")]
+            GetFirewallStats(tx) => self.on_get_firewall_stats(tx),
             Shutdown => self.trigger_shutdown_event(),
             PrepareRestart => self.on_prepare_restart(),
         }
@@ -1081,6 +1404,77 @@ where
         self.event_listener.notify_app_version(app_version_info);
     }
 
+    fn handle_dns_blocklist_updated(&mut self, category: BlocklistCategory) {
+        if self.settings.dns_blocking.categories.contains(&category) {
+            debug!("Reloading the updated DNS blocklist of {}", category);
+            self.reload_dns_blocklists();
+        }
+    }
+
+    /// Loads the lists of the enabled blocklist categories and hands them to the tunnel state
+    /// machine.
+    fn reload_dns_blocklists(&mut self) {
+        let dns_blocking = &self.settings.dns_blocking;
+        let lists = dns_blocklists::load(
+            &dns_blocking.categories,
+            &self.resource_dir,
+            &self.cache_dir,
+        );
+        self.dns_blocklists.set(lists, dns_blocking.response);
+        self.send_dns_config();
+    }
+
+    fn handle_account_expiry(&mut self, account_token: AccountToken, expiry: DateTime<Utc>) {
+        if self.settings.get_account_token() != Some(account_token) {
+            debug!("Dropping account expiry since the account has been changed");
+            return;
+        }
+        self.update_account_expiry(expiry);
+    }
+
+    fn update_account_expiry(&mut self, expiry: DateTime<Utc>) {
+        let stage = ExpiryStage::new(expiry, Utc::now(), &self.settings.account_expiry_warnings);
+        let previous_stage = self.account_expiry.map(|(_, stage)| stage);
+        self.account_expiry = Some((expiry, stage));
+
+        if let Some(event) = stage.event_since(previous_stage, expiry) {
+            info!("Account expiry event: {:?}", event);
+            self.event_listener.notify_account_expiry(event);
+        }
+
+        let blocked_by_expiry = self.is_blocked_by_account_expiry();
+        if stage == ExpiryStage::Expired {
+            if self.target_state == TargetState::Secured && !blocked_by_expiry {
+                info!("Blocking all network connections since the account has expired");
+                self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::AccountExpired));
+            }
+        } else if blocked_by_expiry {
+            info!("Reconnecting since the account is no longer expired");
+            self.reconnect_tunnel();
+        }
+    }
+
+    fn account_is_expired(&self) -> bool {
+        self.account_expiry
+            .map(|(_, stage)| stage == ExpiryStage::Expired)
+            .unwrap_or(false)
+    }
+
+    fn is_blocked_by_account_expiry(&self) -> bool {
+        if let TunnelState::Error(error_state) = &self.tunnel_state {
+            if let ErrorStateCause::AccountExpired = error_state.cause() {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn refresh_account_expiry(&mut self) {
+        if let Some(token) = self.settings.get_account_token() {
+            self.account_expiry_watcher.watch(token);
+        }
+    }
+
     fn on_set_target_state(
         &mut self,
         tx: oneshot::Sender<Result<(), ()>>,
@@ -1194,10 +1588,14 @@ where
         tx: oneshot::Sender<BoxFuture<AccountData, mullvad_rpc::Error>>,
         account_token: AccountToken,
     ) {
+        let daemon_tx = self.tx.clone();
         let rpc_call = self
             .accounts_proxy
-            .get_expiry(account_token)
-            .map(|expiry| AccountData { expiry });
+            .get_expiry(account_token.clone())
+            .map(move |expiry| {
+                let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiry(account_token, expiry));
+                AccountData { expiry }
+            });
         Self::oneshot_send(tx, Box::new(rpc_call), "account data")
     }
 
@@ -1217,7 +1615,17 @@ where
         voucher: String,
     ) {
         if let Some(account_token) = self.settings.get_account_token() {
-            let rpc_call = self.accounts_proxy.submit_voucher(account_token, voucher);
+            let daemon_tx = self.tx.clone();
+            let rpc_call = self
+                .accounts_proxy
+                .submit_voucher(account_token.clone(), voucher)
+                .map(move |submission| {
+                    let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiry(
+                        account_token,
+                        submission.new_expiry,
+                    ));
+                    submission
+                });
             Self::oneshot_send(tx, Box::new(rpc_call), "submit_voucher response");
         }
     }
@@ -1268,6 +1676,12 @@ where
 
             self.ensure_wireguard_keys_for_current_account();
 
+            self.account_expiry = None;
+            match account_token {
+                Some(ref token) => self.account_expiry_watcher.watch(token.clone()),
+                None => self.account_expiry_watcher.stop(),
+            }
+
             if let Some(token) = account_token {
                 // update automatic rotation
                 self.wireguard_key_manager
@@ -1382,6 +1796,261 @@ where
         }
     }
 
+    fn on_set_lan_networks(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), LanNetworksError>>,
+        lan_networks: LanNetworks,
+    ) {
+        if let Err(error) = validate_lan_networks(&lan_networks) {
+            Self::oneshot_send(tx, Err(error), "set_lan_networks response");
+            return;
+        }
+        // The local networks are hardcoded in the Windows firewall module
+        #[cfg(windows)]
+        {
+            if lan_networks != LanNetworks::default() {
+                Self::oneshot_send(
+                    tx,
+                    Err(LanNetworksError::Unsupported),
+                    "set_lan_networks response",
+                );
+                return;
+            }
+        }
+        let save_result = self.settings.set_lan_networks(lan_networks.clone());
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_lan_networks response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::LanNetworks(lan_networks));
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_allowed_endpoints(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        allowed_endpoints: Vec<AllowedEndpoint>,
+    ) {
+        let save_result = self
+            .settings
+            .set_allowed_endpoints(allowed_endpoints.clone());
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_allowed_endpoints response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowedEndpoints(allowed_endpoints));
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_custom_dns(&mut self, tx: oneshot::Sender<()>, custom_dns: Vec<IpAddr>) {
+        let save_result = self.settings.set_custom_dns(custom_dns);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_custom_dns response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_dns_config();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_local_dns_resolver(&mut self, tx: oneshot::Sender<()>, enabled: bool) {
+        let save_result = self.settings.set_local_dns_resolver(enabled);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_local_dns_resolver response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_dns_config();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_dns_blocklist(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        category: BlocklistCategory,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_dns_blocklist(category, enabled);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_dns_blocklist response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    *self.dns_blocklist_categories.lock() =
+                        self.settings.dns_blocking.categories.clone();
+                    self.reload_dns_blocklists();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_dns_blocked_response(&mut self, tx: oneshot::Sender<()>, response: BlockedResponse) {
+        let save_result = self.settings.set_dns_blocked_response(response);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_dns_blocked_response response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.reload_dns_blocklists();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_dns_blocklist_stats(&mut self, tx: oneshot::Sender<Vec<BlocklistStats>>) {
+        Self::oneshot_send(
+            tx,
+            self.dns_blocklists.stats(),
+            "get_dns_blocklist_stats response",
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_dns_mode(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), InvalidEncryptedDnsServer>>,
+        dns_mode: DnsMode,
+    ) {
+        if let Err(error) = validate_dns_mode(&dns_mode) {
+            Self::oneshot_send(tx, Err(error), "set_dns_mode response");
+            return;
+        }
+        let save_result = self.settings.set_dns_mode(dns_mode);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_dns_mode response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_dns_config();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_split_dns(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), InvalidSplitDnsRule>>,
+        split_dns: Vec<SplitDnsRule>,
+    ) {
+        if let Err(error) = split_dns.iter().try_for_each(validate_split_dns_rule) {
+            Self::oneshot_send(tx, Err(error), "set_split_dns response");
+            return;
+        }
+        let save_result = self.settings.set_split_dns(split_dns);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_dns response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_dns_config();
+                    info!("Initiating tunnel restart because the split DNS servers changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_dns_manager(&mut self, tx: oneshot::Sender<()>, dns_manager: Option<DnsManager>) {
+        let save_result = self.settings.set_dns_manager(dns_manager);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_dns_manager response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_dns_config();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_dns_manager_diagnostics(&mut self, tx: oneshot::Sender<DnsManagerDiagnostics>) {
+        Self::oneshot_send(
+            tx,
+            talpid_core::dns::manager_diagnostics(self.settings.dns_manager),
+            "get_dns_manager_diagnostics response",
+        );
+    }
+
+    /// Runs the DNS leak test on a separate thread, since it waits for lookups and responses.
+    #[cfg(target_os = "linux")]
+    fn on_run_dns_leak_test(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<DnsLeakReport, talpid_core::dns::leak_test::Error>>,
+        checker: Option<DnsLeakCheckerEndpoint>,
+    ) {
+        use talpid_core::dns::leak_test;
+
+        let tunnel_interface = match (&self.tunnel_state, &self.tunnel_interface) {
+            (TunnelState::Connected { .. }, Some(interface)) => interface.clone(),
+            _ => {
+                Self::oneshot_send(
+                    tx,
+                    Err(leak_test::Error::NotConnected),
+                    "run_dns_leak_test response",
+                );
+                return;
+            }
+        };
+        let checker = match leak_test::HttpLeakChecker::new(&checker.unwrap_or_default()) {
+            Ok(checker) => checker,
+            Err(error) => {
+                Self::oneshot_send(tx, Err(error), "run_dns_leak_test response");
+                return;
+            }
+        };
+        // The firewall lets queries to split DNS servers out, so they are not tested
+        let split_dns_servers = split_dns_servers(&self.settings.split_dns);
+        let direct_servers: Vec<IpAddr> = leak_test::DEFAULT_DIRECT_SERVERS
+            .iter()
+            .filter(|server| !split_dns_servers.contains(server))
+            .cloned()
+            .collect();
+
+        thread::spawn(move || {
+            let report = leak_test::run(&checker, &tunnel_interface, &direct_servers);
+            info!("DNS leak test result: {}", report.verdict());
+            if tx.send(Ok(report)).is_err() {
+                warn!("Unable to send run_dns_leak_test response to the daemon command sender");
+            }
+        });
+    }
+
     fn on_set_show_beta_releases(&mut self, tx: oneshot::Sender<()>, enabled: bool) {
         let save_result = self.settings.set_show_beta_releases(enabled);
         match save_result {
@@ -1396,6 +2065,180 @@ where
         }
     }
 
+    #[cfg(target_os = "linux")]
+    fn on_add_split_tunnel_process(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), split_tunnel::Error>>,
+        pid: i32,
+    ) {
+        let result = self.split_tunnel().and_then(|pids| pids.add(pid));
+        match &result {
+            Ok(()) => {
+                info!("Excluding process {} from the tunnel", pid);
+                self.send_split_tunnel_state();
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to exclude process")),
+        }
+        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_remove_split_tunnel_process(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), split_tunnel::Error>>,
+        pid: i32,
+    ) {
+        let result = self.split_tunnel().and_then(|pids| pids.remove(pid));
+        match &result {
+            Ok(()) => {
+                info!("No longer excluding process {} from the tunnel", pid);
+                self.send_split_tunnel_state();
+            }
+            Err(e) => error!(
+                "{}",
+                e.display_chain_with_msg("Unable to stop excluding process")
+            ),
+        }
+        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_split_tunnel_processes(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<Vec<i32>, split_tunnel::Error>>,
+    ) {
+        let result = self.split_tunnel().and_then(|pids| pids.list());
+        if let Err(e) = &result {
+            error!(
+                "{}",
+                e.display_chain_with_msg("Unable to list excluded processes")
+            );
+        }
+        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_firewall_policy_script(
+        &mut self,
+        tx: oneshot::Sender<String>,
+        query: FirewallPolicyQuery,
+    ) {
+        let allow_lan = self.settings.allow_lan;
+        let lan_networks = self.settings.lan_networks.clone();
+        let allowed_endpoints = self.settings.allowed_endpoints.clone();
+        let dns_config = dns_config(&self.settings, &self.dns_blocklists);
+        let generic_options = &self.settings.tunnel_options.generic;
+        let excluded_subnets = generic_options.excluded_subnets.clone();
+        let split_tunnel = self.split_tunnel_in_use();
+        let forwarded_ports = generic_options.forwarded_ports.clone();
+        let shared_interfaces = generic_options.shared_interfaces.clone();
+        let policy = match query {
+            FirewallPolicyQuery::Connecting {
+                peer_endpoint,
+                pingable_hosts,
+            } => FirewallPolicy::Connecting {
+                peer_endpoint,
+                pingable_hosts,
+                allow_lan,
+                lan_networks,
+                excluded_subnets,
+                split_tunnel,
+                allowed_endpoints,
+            },
+            FirewallPolicyQuery::Connected {
+                peer_endpoint,
+                interface,
+                ips,
+                ipv4_gateway,
+                ipv6_gateway,
+            } => {
+                let tunnel = TunnelMetadata {
+                    interface,
+                    ips,
+                    ipv4_gateway,
+                    ipv6_gateway,
+                };
+                FirewallPolicy::Connected {
+                    peer_endpoint,
+                    dns_servers: dns_config.dns_servers(&tunnel),
+                    encrypted_dns: match &dns_config.dns_mode {
+                        DnsMode::Encrypted(server) => Some(server.protocol),
+                        DnsMode::Plain => None,
+                    },
+                    split_dns_servers: split_dns_servers(&dns_config.split_dns),
+                    tunnel,
+                    allow_lan,
+                    lan_networks,
+                    excluded_subnets,
+                    split_tunnel,
+                    forwarded_ports,
+                    shared_interfaces,
+                }
+            }
+            FirewallPolicyQuery::Blocked => FirewallPolicy::Blocked {
+                allow_lan,
+                lan_networks,
+                allowed_endpoints,
+            },
+        };
+        Self::oneshot_send(
+            tx,
+            firewall::render_policy(&policy),
+            "get_firewall_policy_script response",
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_firewall_stats(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<Vec<RuleStats>, firewall::Error>>,
+    ) {
+        Self::oneshot_send(tx, firewall::rule_stats(), "get_firewall_stats response");
+    }
+
+    /// Tells the tunnel state machine whether any processes are excluded from the tunnel, so
+    /// that the rules and routes of excluded traffic only exist while they are needed.
+    #[cfg(target_os = "linux")]
+    fn send_split_tunnel_state(&mut self) {
+        let split_tunnel = self.split_tunnel_in_use();
+        self.send_tunnel_command(TunnelCommand::SplitTunnel(split_tunnel));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn split_tunnel_in_use(&self) -> bool {
+        match &self.split_tunnel {
+            Some(pids) => pids.list().map(|pids| !pids.is_empty()).unwrap_or(false),
+            None => false,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn split_tunnel(
+        &mut self,
+    ) -> std::result::Result<&split_tunnel::PidManager, split_tunnel::Error> {
+        if self.split_tunnel.is_none() {
+            self.split_tunnel = Some(split_tunnel::PidManager::new()?);
+        }
+        Ok(self.split_tunnel.as_ref().unwrap())
+    }
+
+    fn on_set_account_expiry_warnings(&mut self, tx: oneshot::Sender<()>, warnings: Vec<u32>) {
+        let save_result = self.settings.set_account_expiry_warnings(warnings);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_account_expiry_warnings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some((expiry, _)) = self.account_expiry {
+                        self.update_account_expiry(expiry);
+                    }
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn on_set_block_when_disconnected(
         &mut self,
         tx: oneshot::Sender<()>,
@@ -1411,7 +2254,39 @@ where
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
-                        block_when_disconnected,
+                        block_when_disconnected || self.settings.lockdown_mode,
+                    ));
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    /// Writes or removes the persistent ruleset before saving the setting, so that the setting is
+    /// left unchanged if that fails.
+    #[cfg(target_os = "linux")]
+    fn on_set_lockdown_mode(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), lockdown::Error>>,
+        lockdown_mode: bool,
+    ) {
+        if let Err(e) = lockdown::update_ruleset(lockdown_mode) {
+            error!(
+                "{}",
+                e.display_chain_with_msg("Unable to update the lockdown ruleset")
+            );
+            Self::oneshot_send(tx, Err(e), "set_lockdown_mode response");
+            return;
+        }
+        let save_result = self.settings.set_lockdown_mode(lockdown_mode);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_lockdown_mode response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                        lockdown_mode || self.settings.block_when_disconnected,
                     ));
                 }
             }
@@ -1521,6 +2396,75 @@ where
         }
     }
 
+    fn on_set_excluded_subnets(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), ExcludedSubnetError>>,
+        excluded_subnets: Vec<IpNetwork>,
+    ) {
+        // The relay and tunnel addresses are checked when connecting
+        if let Err(error) = validate_excluded_subnets(&excluded_subnets, &[]) {
+            Self::oneshot_send(tx, Err(error), "set_excluded_subnets response");
+            return;
+        }
+        let save_result = self.settings.set_excluded_subnets(excluded_subnets);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_excluded_subnets response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the excluded networks changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_forwarded_ports(
+        &mut self,
+        tx: oneshot::Sender<std::result::Result<(), InvalidPortRange>>,
+        forwarded_ports: Vec<ForwardedPort>,
+    ) {
+        if let Err(error) = validate_forwarded_ports(&forwarded_ports) {
+            Self::oneshot_send(tx, Err(error), "set_forwarded_ports response");
+            return;
+        }
+        let save_result = self.settings.set_forwarded_ports(forwarded_ports);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_forwarded_ports response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the forwarded ports changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_shared_interfaces(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        shared_interfaces: Vec<String>,
+    ) {
+        let save_result = self.settings.set_shared_interfaces(shared_interfaces);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_shared_interfaces response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the shared interfaces changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn on_set_wireguard_mtu(&mut self, tx: oneshot::Sender<()>, mtu: Option<u16>) {
         let save_result = self.settings.set_wireguard_mtu(mtu);
         match save_result {
@@ -1569,6 +2513,48 @@ where
         }
     }
 
+    fn on_set_wireguard_degraded_timeout(&mut self, tx: oneshot::Sender<()>, timeout: Option<u32>) {
+        let save_result = self.settings.set_wireguard_degraded_timeout(timeout);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_degraded_timeout response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard degraded timeout \
+                             changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
+    fn on_set_wireguard_policy_routing(&mut self, tx: oneshot::Sender<()>, policy_routing: bool) {
+        let save_result = self.settings.set_wireguard_policy_routing(policy_routing);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_policy_routing response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard policy routing \
+                             setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => error!("{}", e.display_chain_with_msg("Unable to save settings")),
+        }
+    }
+
     fn ensure_wireguard_keys_for_current_account(&mut self) {
         if let Some(account) = self.settings.get_account_token() {
             if self
@@ -1798,6 +2784,12 @@ where
             .expect("Tunnel state machine has stopped");
     }
 
+    /// Hands the DNS settings and the blocklists to the tunnel state machine.
+    fn send_dns_config(&mut self) {
+        let dns_config = dns_config(&self.settings, &self.dns_blocklists);
+        self.send_tunnel_command(TunnelCommand::Dns(dns_config));
+    }
+
     #[cfg(not(target_os = "android"))]
     fn clear_log_directory() -> Result<(), Error> {
         let log_dir = mullvad_paths::get_log_dir().map_err(Error::PathError)?;
@@ -1863,6 +2855,19 @@ impl DaemonShutdownHandle {
     }
 }
 
+/// How the tunnel state machine should resolve DNS while connected, according to the settings.
+fn dns_config(settings: &Settings, dns_blocklists: &Blocklists) -> DnsConfig {
+    DnsConfig {
+        custom_dns: settings.custom_dns.clone(),
+        local_dns_resolver: settings.local_dns_resolver,
+        blocklists: dns_blocklists.clone(),
+        dns_mode: settings.dns_mode.clone(),
+        split_dns: settings.split_dns.clone(),
+        #[cfg(target_os = "linux")]
+        dns_manager: settings.dns_manager,
+    }
+}
+
 struct MullvadTunnelParametersGenerator {
     tx: DaemonEventSender,
 }
@@ -0,0 +1,35 @@
+//! Lockdown mode keeps traffic blocked while the daemon is not running, including during boot.
+//! The blocking rules are written to a persistent `nft -f` script in the settings directory, and
+//! the `mullvad-early-boot-blocking` unit loads them with `mullvad-setup apply-lockdown`, which
+//! runs `nft -f`, before any network is configured. The daemon replaces them atomically when it
+//! applies its own policy, since both use the same table.
+
+use std::{fs, io};
+use talpid_core::firewall;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to get the path of the lockdown ruleset")]
+    RulesetPath(#[error(source)] mullvad_paths::Error),
+
+    #[error(display = "Unable to write the lockdown ruleset")]
+    WriteRuleset(#[error(source)] io::Error),
+
+    #[error(display = "Unable to remove the lockdown ruleset")]
+    RemoveRuleset(#[error(source)] io::Error),
+}
+
+/// Writes the persistent ruleset if lockdown mode is enabled, or removes it if it is not.
+pub fn update_ruleset(lockdown_mode: bool) -> Result<(), Error> {
+    let path = mullvad_paths::get_lockdown_ruleset_path().map_err(Error::RulesetPath)?;
+    if lockdown_mode {
+        fs::write(&path, firewall::render_boot_ruleset()).map_err(Error::WriteRuleset)
+    } else {
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::RemoveRuleset(e)),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{BoxFuture, DaemonCommand, DaemonCommandSender, EventListener};
+use ipnetwork::IpNetwork;
 use jsonrpc_core::{
     futures::{future, sync, Future},
     Error, ErrorCode, MetaIoHandler, Metadata,
@@ -9,7 +10,7 @@ use jsonrpc_pubsub::{PubSubHandler, PubSubMetadata, Session, SubscriptionId};
 use mullvad_paths;
 use mullvad_rpc;
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{AccountData, AccountExpiryEvent, AccountToken, VoucherSubmission},
     location::GeoIpLocation,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
     relay_list::RelayList,
@@ -20,10 +21,20 @@ use mullvad_types::{
 use parking_lot::RwLock;
 use std::{
     collections::{hash_map::Entry, HashMap},
+    net::IpAddr,
     sync::Arc,
 };
 use talpid_ipc;
-use talpid_types::ErrorExt;
+use talpid_types::{
+    dns::{
+        BlockedResponse, BlocklistCategory, BlocklistStats, DnsLeakCheckerEndpoint, DnsLeakReport,
+        DnsManager, DnsManagerDiagnostics, DnsMode, SplitDnsRule,
+    },
+    firewall::{FirewallPolicyQuery, RuleStats},
+    net::{AllowedEndpoint, ForwardedPort, LanNetworks},
+    tunnel::FirewallIncident,
+    ErrorExt,
+};
 use uuid;
 
 build_rpc_trait! {
@@ -69,14 +80,98 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_allow_lan")]
         fn set_allow_lan(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set networks to allow in addition to, or remove from, the default LAN networks
+        #[rpc(meta, name = "set_lan_networks")]
+        fn set_lan_networks(&self, Self::Metadata, LanNetworks) -> BoxFuture<(), Error>;
+
+        /// Set endpoints that can be reached outside the tunnel while connecting or blocked
+        #[rpc(meta, name = "set_allowed_endpoints")]
+        fn set_allowed_endpoints(
+            &self,
+            Self::Metadata,
+            Vec<AllowedEndpoint>,
+        ) -> BoxFuture<(), Error>;
+
+        /// Set the DNS servers to use inside the tunnel instead of the relay. The relay is used
+        /// if the list is empty. Only supported on Linux.
+        #[rpc(meta, name = "set_custom_dns")]
+        fn set_custom_dns(&self, Self::Metadata, Vec<IpAddr>) -> BoxFuture<(), Error>;
+
+        /// Set whether DNS queries are sent through a caching resolver in the daemon while
+        /// connected. Only supported on Linux.
+        #[rpc(meta, name = "set_local_dns_resolver")]
+        fn set_local_dns_resolver(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
+        /// Set whether the local DNS resolver blocks the domains of a category. Only supported
+        /// on Linux.
+        #[rpc(meta, name = "set_dns_blocklist")]
+        fn set_dns_blocklist(
+            &self,
+            Self::Metadata,
+            BlocklistCategory,
+            bool
+        ) -> BoxFuture<(), Error>;
+
+        /// Set how the local DNS resolver answers queries for blocked domains. Only supported on
+        /// Linux.
+        #[rpc(meta, name = "set_dns_blocked_response")]
+        fn set_dns_blocked_response(
+            &self,
+            Self::Metadata,
+            BlockedResponse
+        ) -> BoxFuture<(), Error>;
+
+        /// Set whether DNS queries are sent to an encrypted DNS server while connected. Only
+        /// supported on Linux.
+        #[rpc(meta, name = "set_dns_mode")]
+        fn set_dns_mode(&self, Self::Metadata, DnsMode) -> BoxFuture<(), Error>;
+
+        /// Set which domains are resolved by other DNS servers, outside the tunnel, while
+        /// connected. Only supported on Linux.
+        #[rpc(meta, name = "set_split_dns")]
+        fn set_split_dns(&self, Self::Metadata, Vec<SplitDnsRule>) -> BoxFuture<(), Error>;
+
+        /// Set which program the system DNS is set through, instead of detecting it. Only
+        /// supported on Linux.
+        #[rpc(meta, name = "set_dns_manager")]
+        fn set_dns_manager(&self, Self::Metadata, Option<DnsManager>) -> BoxFuture<(), Error>;
+
+        /// Returns which program the system DNS is set through and why, what was set through it,
+        /// and whether the system still uses it. Only supported on Linux.
+        #[rpc(meta, name = "get_dns_manager_diagnostics")]
+        fn get_dns_manager_diagnostics(&self, Self::Metadata) -> BoxFuture<DnsManagerDiagnostics, Error>;
+
+        /// Looks up unique names while connected and returns which resolvers queried them, as
+        /// told by the given checker or the default one, and whether queries to resolvers outside
+        /// the tunnel are blocked. Only supported on Linux.
+        #[rpc(meta, name = "run_dns_leak_test")]
+        fn run_dns_leak_test(
+            &self,
+            Self::Metadata,
+            Option<DnsLeakCheckerEndpoint>
+        ) -> BoxFuture<DnsLeakReport, Error>;
+
+        /// Returns how many domains and blocked queries each blocklist in use has. Only
+        /// supported on Linux.
+        #[rpc(meta, name = "get_dns_blocklist_stats")]
+        fn get_dns_blocklist_stats(&self, Self::Metadata) -> BoxFuture<Vec<BlocklistStats>, Error>;
+
         /// Set whether to enable the beta program.
         #[rpc(meta, name = "set_show_beta_releases")]
         fn set_show_beta_releases(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set how many hours before the account expiry to warn about it.
+        #[rpc(meta, name = "set_account_expiry_warnings")]
+        fn set_account_expiry_warnings(&self, Self::Metadata, Vec<u32>) -> BoxFuture<(), Error>;
+
         /// Set if the client should allow network communication when in the disconnected state.
         #[rpc(meta, name = "set_block_when_disconnected")]
         fn set_block_when_disconnected(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set if traffic should be blocked from boot and while the daemon is not running.
+        #[rpc(meta, name = "set_lockdown_mode")]
+        fn set_lockdown_mode(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
         /// Set if the daemon should automatically establish a tunnel on start or not.
         #[rpc(meta, name = "set_auto_connect")]
         fn set_auto_connect(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
@@ -137,6 +232,19 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_enable_ipv6")]
         fn set_enable_ipv6(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set destination networks that should be reached outside the tunnel. Only applied on
+        /// Linux.
+        #[rpc(meta, name = "set_excluded_subnets")]
+        fn set_excluded_subnets(&self, Self::Metadata, Vec<IpNetwork>) -> BoxFuture<(), Error>;
+
+        /// Set ports that accept incoming connections through the tunnel
+        #[rpc(meta, name = "set_forwarded_ports")]
+        fn set_forwarded_ports(&self, Self::Metadata, Vec<ForwardedPort>) -> BoxFuture<(), Error>;
+
+        /// Set interfaces whose forwarded traffic is sent through the tunnel
+        #[rpc(meta, name = "set_shared_interfaces")]
+        fn set_shared_interfaces(&self, Self::Metadata, Vec<String>) -> BoxFuture<(), Error>;
+
         /// Set MTU for wireguard tunnels
         #[rpc(meta, name = "set_wireguard_mtu")]
         fn set_wireguard_mtu(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
@@ -145,6 +253,14 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_wireguard_rotation_interval")]
         fn set_wireguard_rotation_interval(&self, Self::Metadata, Option<u32>) -> BoxFuture<(), Error>;
 
+        /// Set how many seconds a wireguard tunnel may stay degraded before switching relay
+        #[rpc(meta, name = "set_wireguard_degraded_timeout")]
+        fn set_wireguard_degraded_timeout(&self, Self::Metadata, Option<u32>) -> BoxFuture<(), Error>;
+
+        /// Set whether wireguard tunnels add their routes to a dedicated routing table
+        #[rpc(meta, name = "set_wireguard_policy_routing")]
+        fn set_wireguard_policy_routing(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
         /// Returns the current daemon settings
         #[rpc(meta, name = "get_settings")]
         fn get_settings(&self, Self::Metadata) -> BoxFuture<Settings, Error>;
@@ -173,6 +289,34 @@ build_rpc_trait! {
         #[rpc(meta, name = "factory_reset")]
         fn factory_reset(&self, Self::Metadata) -> BoxFuture<(), Error>;
 
+        /// Exclude a process, and any processes it starts, from the tunnel. Only supported on
+        /// Linux.
+        #[rpc(meta, name = "add_split_tunnel_process")]
+        fn add_split_tunnel_process(&self, Self::Metadata, i32) -> BoxFuture<(), Error>;
+
+        /// Stop excluding a process from the tunnel. Only supported on Linux.
+        #[rpc(meta, name = "remove_split_tunnel_process")]
+        fn remove_split_tunnel_process(&self, Self::Metadata, i32) -> BoxFuture<(), Error>;
+
+        /// Returns the PIDs of the processes that are excluded from the tunnel. Only supported
+        /// on Linux.
+        #[rpc(meta, name = "get_split_tunnel_processes")]
+        fn get_split_tunnel_processes(&self, Self::Metadata) -> BoxFuture<Vec<i32>, Error>;
+
+        /// Returns the firewall rules that the given tunnel state would result in, as a script
+        /// that `nft -f` accepts. Nothing is applied. Only supported on Linux.
+        #[rpc(meta, name = "get_firewall_policy_script")]
+        fn get_firewall_policy_script(
+            &self,
+            Self::Metadata,
+            FirewallPolicyQuery
+        ) -> BoxFuture<String, Error>;
+
+        /// Returns how many packets and bytes each rule of the applied firewall policy has
+        /// matched. Only supported on Linux.
+        #[rpc(meta, name = "get_firewall_stats")]
+        fn get_firewall_stats(&self, Self::Metadata) -> BoxFuture<Vec<RuleStats>, Error>;
+
         #[pubsub(name = "daemon_event")] {
             /// Subscribes to events from the daemon.
             #[rpc(name = "daemon_event_subscribe")]
@@ -266,6 +410,16 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
         log::debug!("Broadcasting new wireguard key event");
         self.notify(DaemonEvent::WireguardKey(key_event));
     }
+
+    fn notify_account_expiry(&self, account_expiry_event: AccountExpiryEvent) {
+        log::debug!("Broadcasting new account expiry event");
+        self.notify(DaemonEvent::AccountExpiry(account_expiry_event));
+    }
+
+    fn notify_firewall_incident(&self, incident: FirewallIncident) {
+        log::debug!("Broadcasting new firewall incident");
+        self.notify(DaemonEvent::FirewallIncident(incident));
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
@@ -306,6 +460,26 @@ impl ManagementInterface {
         future::result(self.tx.send(command)).map_err(|_| Error::internal_error())
     }
 
+    /// Reports split tunneling failures to the caller, since they are usually caused by the
+    /// given PID or the system configuration rather than by the daemon.
+    #[cfg(target_os = "linux")]
+    fn map_split_tunnel_error(error: &talpid_core::split_tunnel::Error) -> Error {
+        Error {
+            code: ErrorCode::ServerError(-901),
+            message: error.display_chain(),
+            data: None,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn split_tunnel_unsupported() -> Error {
+        Error {
+            code: ErrorCode::MethodNotFound,
+            message: "Split tunneling is only supported on Linux".to_owned(),
+            data: None,
+        }
+    }
+
     /// Converts the given error to an error that can be given to the caller of the API.
     /// Will let any actual RPC error through as is, any other error is changed to an internal
     /// error.
@@ -446,6 +620,292 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn set_lan_networks(
+        &self,
+        _: Self::Metadata,
+        lan_networks: LanNetworks,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_lan_networks({:?})", lan_networks);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetLanNetworks(tx, lan_networks))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::InvalidParams,
+                    message: error.to_string(),
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_allowed_endpoints(
+        &self,
+        _: Self::Metadata,
+        allowed_endpoints: Vec<AllowedEndpoint>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_allowed_endpoints({:?})", allowed_endpoints);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetAllowedEndpoints(tx, allowed_endpoints))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_custom_dns(&self, _: Self::Metadata, custom_dns: Vec<IpAddr>) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_custom_dns({:?})", custom_dns);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetCustomDns(tx, custom_dns))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = custom_dns;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "Custom DNS servers are only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn set_local_dns_resolver(&self, _: Self::Metadata, enabled: bool) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_local_dns_resolver({})", enabled);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetLocalDnsResolver(tx, enabled))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = enabled;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "The local DNS resolver is only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn set_dns_blocklist(
+        &self,
+        _: Self::Metadata,
+        category: BlocklistCategory,
+        enabled: bool,
+    ) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_dns_blocklist({}, {})", category, enabled);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetDnsBlocklist(tx, category, enabled))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (category, enabled);
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "DNS blocklists are only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn set_dns_blocked_response(
+        &self,
+        _: Self::Metadata,
+        response: BlockedResponse,
+    ) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_dns_blocked_response({:?})", response);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetDnsBlockedResponse(tx, response))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = response;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "DNS blocklists are only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn set_dns_mode(&self, _: Self::Metadata, dns_mode: DnsMode) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_dns_mode({})", dns_mode);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetDnsMode(tx, dns_mode))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| {
+                    result.map_err(|error| Error {
+                        code: ErrorCode::InvalidParams,
+                        message: error.to_string(),
+                        data: None,
+                    })
+                });
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = dns_mode;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "Encrypted DNS is only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn set_split_dns(
+        &self,
+        _: Self::Metadata,
+        split_dns: Vec<SplitDnsRule>,
+    ) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_split_dns({:?})", split_dns);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetSplitDns(tx, split_dns))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| {
+                    result.map_err(|error| Error {
+                        code: ErrorCode::InvalidParams,
+                        message: error.to_string(),
+                        data: None,
+                    })
+                });
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = split_dns;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "Split DNS is only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn set_dns_manager(
+        &self,
+        _: Self::Metadata,
+        dns_manager: Option<DnsManager>,
+    ) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_dns_manager({:?})", dns_manager);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetDnsManager(tx, dns_manager))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = dns_manager;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "Choosing the DNS manager is only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn get_dns_manager_diagnostics(
+        &self,
+        _: Self::Metadata,
+    ) -> BoxFuture<DnsManagerDiagnostics, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_dns_manager_diagnostics");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::GetDnsManagerDiagnostics(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "DNS manager diagnostics are only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn run_dns_leak_test(
+        &self,
+        _: Self::Metadata,
+        checker: Option<DnsLeakCheckerEndpoint>,
+    ) -> BoxFuture<DnsLeakReport, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("run_dns_leak_test({:?})", checker);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::RunDnsLeakTest(tx, checker))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| {
+                    result.map_err(|error| Error {
+                        code: ErrorCode::ServerError(-904),
+                        message: error.display_chain(),
+                        data: None,
+                    })
+                });
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = checker;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "The DNS leak test is only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn get_dns_blocklist_stats(&self, _: Self::Metadata) -> BoxFuture<Vec<BlocklistStats>, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_dns_blocklist_stats");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::GetDnsBlocklistStats(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "DNS blocklists are only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
     fn set_show_beta_releases(&self, _: Self::Metadata, enabled: bool) -> BoxFuture<(), Error> {
         log::debug!("set_show_beta_releases({})", enabled);
         let (tx, rx) = sync::oneshot::channel();
@@ -455,6 +915,19 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn set_account_expiry_warnings(
+        &self,
+        _: Self::Metadata,
+        warnings: Vec<u32>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_account_expiry_warnings({:?})", warnings);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetAccountExpiryWarnings(tx, warnings))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn set_block_when_disconnected(
         &self,
         _: Self::Metadata,
@@ -471,6 +944,34 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn set_lockdown_mode(&self, _: Self::Metadata, lockdown_mode: bool) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_lockdown_mode({})", lockdown_mode);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::SetLockdownMode(tx, lockdown_mode))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| {
+                    result.map_err(|error| Error {
+                        code: ErrorCode::ServerError(-902),
+                        message: error.display_chain(),
+                        data: None,
+                    })
+                });
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = lockdown_mode;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "Lockdown mode is only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
     fn set_auto_connect(&self, _: Self::Metadata, auto_connect: bool) -> BoxFuture<(), Error> {
         log::debug!("set_auto_connect({})", auto_connect);
         let (tx, rx) = sync::oneshot::channel();
@@ -612,6 +1113,59 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    fn set_excluded_subnets(
+        &self,
+        _: Self::Metadata,
+        excluded_subnets: Vec<IpNetwork>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_excluded_subnets({:?})", excluded_subnets);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetExcludedSubnets(tx, excluded_subnets))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::InvalidParams,
+                    message: error.to_string(),
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_forwarded_ports(
+        &self,
+        _: Self::Metadata,
+        forwarded_ports: Vec<ForwardedPort>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_forwarded_ports({:?})", forwarded_ports);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetForwardedPorts(tx, forwarded_ports))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|error| Error {
+                    code: ErrorCode::InvalidParams,
+                    message: error.to_string(),
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_shared_interfaces(
+        &self,
+        _: Self::Metadata,
+        shared_interfaces: Vec<String>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_shared_interfaces({:?})", shared_interfaces);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetSharedInterfaces(tx, shared_interfaces))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     /// Set MTU for wireguard tunnels
     fn set_wireguard_mtu(&self, _: Self::Metadata, mtu: Option<u16>) -> BoxFuture<(), Error> {
         log::debug!("set_wireguard_mtu({:?})", mtu);
@@ -636,6 +1190,34 @@ impl ManagementInterfaceApi for ManagementInterface {
         Box::new(future)
     }
 
+    /// Set how many seconds a wireguard tunnel may stay degraded before switching relay
+    fn set_wireguard_degraded_timeout(
+        &self,
+        _: Self::Metadata,
+        timeout: Option<u32>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_degraded_timeout({:?})", timeout);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetWireguardDegradedTimeout(tx, timeout))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    /// Set whether wireguard tunnels add their routes to a dedicated routing table
+    fn set_wireguard_policy_routing(
+        &self,
+        _: Self::Metadata,
+        policy_routing: bool,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_policy_routing({})", policy_routing);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(DaemonCommand::SetWireguardPolicyRouting(tx, policy_routing))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn get_settings(&self, _: Self::Metadata) -> BoxFuture<Settings, Error> {
         log::debug!("get_settings");
         let (tx, rx) = sync::oneshot::channel();
@@ -715,6 +1297,111 @@ impl ManagementInterfaceApi for ManagementInterface {
         }
     }
 
+    fn add_split_tunnel_process(&self, _: Self::Metadata, pid: i32) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("add_split_tunnel_process({})", pid);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::AddSplitTunnelProcess(tx, pid))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| result.map_err(|error| Self::map_split_tunnel_error(&error)));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = pid;
+            Box::new(future::err(Self::split_tunnel_unsupported()))
+        }
+    }
+
+    fn remove_split_tunnel_process(&self, _: Self::Metadata, pid: i32) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("remove_split_tunnel_process({})", pid);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::RemoveSplitTunnelProcess(tx, pid))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| result.map_err(|error| Self::map_split_tunnel_error(&error)));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = pid;
+            Box::new(future::err(Self::split_tunnel_unsupported()))
+        }
+    }
+
+    fn get_split_tunnel_processes(&self, _: Self::Metadata) -> BoxFuture<Vec<i32>, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_split_tunnel_processes");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::GetSplitTunnelProcesses(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| result.map_err(|error| Self::map_split_tunnel_error(&error)));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::err(Self::split_tunnel_unsupported()))
+        }
+    }
+
+    fn get_firewall_policy_script(
+        &self,
+        _: Self::Metadata,
+        query: FirewallPolicyQuery,
+    ) -> BoxFuture<String, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_firewall_policy_script({:?})", query);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::GetFirewallPolicyScript(tx, query))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = query;
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "Rendering firewall policies is only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
+    fn get_firewall_stats(&self, _: Self::Metadata) -> BoxFuture<Vec<RuleStats>, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("get_firewall_stats");
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(DaemonCommand::GetFirewallStats(tx))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| {
+                    result.map_err(|error| Error {
+                        code: ErrorCode::ServerError(-903),
+                        message: error.display_chain(),
+                        data: None,
+                    })
+                });
+            Box::new(future)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Box::new(future::err(Error {
+                code: ErrorCode::MethodNotFound,
+                message: "Firewall statistics are only supported on Linux".to_owned(),
+                data: None,
+            }))
+        }
+    }
+
 
     fn daemon_event_subscribe(
         &self,
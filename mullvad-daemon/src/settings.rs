@@ -1,3 +1,4 @@
+use ipnetwork::IpNetwork;
 use log::{debug, error, info};
 use mullvad_types::{
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
@@ -9,7 +10,11 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{
+    dns::{BlockedResponse, BlocklistCategory, DnsManager, DnsMode, SplitDnsRule},
+    net::{AllowedEndpoint, ForwardedPort, LanNetworks},
+    ErrorExt,
+};
 
 #[cfg(not(target_os = "android"))]
 use std::fs;
@@ -206,6 +211,65 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_lan_networks(&mut self, lan_networks: LanNetworks) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.lan_networks, lan_networks);
+        self.update(should_save)
+    }
+
+    pub fn set_allowed_endpoints(
+        &mut self,
+        allowed_endpoints: Vec<AllowedEndpoint>,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.allowed_endpoints, allowed_endpoints);
+        self.update(should_save)
+    }
+
+    pub fn set_custom_dns(&mut self, custom_dns: Vec<IpAddr>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.custom_dns, custom_dns);
+        self.update(should_save)
+    }
+
+    pub fn set_local_dns_resolver(&mut self, local_dns_resolver: bool) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.local_dns_resolver, local_dns_resolver);
+        self.update(should_save)
+    }
+
+    pub fn set_dns_blocklist(
+        &mut self,
+        category: BlocklistCategory,
+        enabled: bool,
+    ) -> Result<bool, Error> {
+        let categories = &mut self.settings.dns_blocking.categories;
+        let should_save = if enabled {
+            categories.insert(category)
+        } else {
+            categories.remove(&category)
+        };
+        self.update(should_save)
+    }
+
+    pub fn set_dns_blocked_response(&mut self, response: BlockedResponse) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.dns_blocking.response, response);
+        self.update(should_save)
+    }
+
+    pub fn set_dns_mode(&mut self, dns_mode: DnsMode) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.dns_mode, dns_mode);
+        self.update(should_save)
+    }
+
+    pub fn set_split_dns(&mut self, split_dns: Vec<SplitDnsRule>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.split_dns, split_dns);
+        self.update(should_save)
+    }
+
+    pub fn set_dns_manager(&mut self, dns_manager: Option<DnsManager>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.dns_manager, dns_manager);
+        self.update(should_save)
+    }
+
     pub fn set_block_when_disconnected(
         &mut self,
         block_when_disconnected: bool,
@@ -217,6 +281,11 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_lockdown_mode(&mut self, lockdown_mode: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.lockdown_mode, lockdown_mode);
+        self.update(should_save)
+    }
+
     pub fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.auto_connect, auto_connect);
         self.update(should_save)
@@ -238,6 +307,36 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_excluded_subnets(
+        &mut self,
+        excluded_subnets: Vec<IpNetwork>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.excluded_subnets,
+            excluded_subnets,
+        );
+        self.update(should_save)
+    }
+
+    pub fn set_forwarded_ports(
+        &mut self,
+        forwarded_ports: Vec<ForwardedPort>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.forwarded_ports,
+            forwarded_ports,
+        );
+        self.update(should_save)
+    }
+
+    pub fn set_shared_interfaces(&mut self, shared_interfaces: Vec<String>) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.shared_interfaces,
+            shared_interfaces,
+        );
+        self.update(should_save)
+    }
+
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.tunnel_options.wireguard.mtu, mtu);
         self.update(should_save)
@@ -254,6 +353,25 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_wireguard_degraded_timeout(
+        &mut self,
+        degraded_timeout: Option<u32>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.degraded_timeout,
+            degraded_timeout,
+        );
+        self.update(should_save)
+    }
+
+    pub fn set_wireguard_policy_routing(&mut self, policy_routing: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.policy_routing,
+            policy_routing,
+        );
+        self.update(should_save)
+    }
+
     pub fn set_show_beta_releases(&mut self, show_beta_releases: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(
             &mut self.settings.show_beta_releases,
@@ -262,6 +380,11 @@ impl SettingsPersister {
         self.update(should_save)
     }
 
+    pub fn set_account_expiry_warnings(&mut self, warnings: Vec<u32>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.account_expiry_warnings, warnings);
+        self.update(should_save)
+    }
+
     pub fn set_bridge_settings(&mut self, bridge_settings: BridgeSettings) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.bridge_settings, bridge_settings);
         self.update(should_save)
@@ -415,7 +415,7 @@ pub struct CancelHandle {
 }
 
 impl CancelHandle {
-    fn cancel(self) {
+    pub fn cancel(self) {
         let _ = self.tx.send(());
     }
 }
@@ -425,7 +425,7 @@ impl<T, E, F> Cancellable<T, E, F>
 where
     F: Future<Item = T, Error = E>,
 {
-    fn new(f: F) -> (Self, CancelHandle) {
+    pub fn new(f: F) -> (Self, CancelHandle) {
         let (tx, rx) = oneshot::channel();
         (Self { f, rx }, CancelHandle { tx })
     }
@@ -1,6 +1,7 @@
 #![deny(rust_2018_idioms)]
 
 use futures::sync::oneshot;
+use ipnetwork::IpNetwork;
 use jsonrpc_client_core::{Client, ClientHandle, Future};
 use jsonrpc_client_ipc::IpcTransport;
 use mullvad_types::{
@@ -14,7 +15,15 @@ use mullvad_types::{
     wireguard, DaemonEvent,
 };
 use serde::{Deserialize, Serialize};
-use std::{io, path::Path, thread};
+use std::{io, net::IpAddr, path::Path, thread};
+use talpid_types::{
+    dns::{
+        BlockedResponse, BlocklistCategory, BlocklistStats, DnsLeakCheckerEndpoint, DnsLeakReport,
+        DnsManager, DnsManagerDiagnostics, DnsMode, SplitDnsRule,
+    },
+    firewall::{FirewallPolicyQuery, RuleStats},
+    net::{AllowedEndpoint, ForwardedPort, LanNetworks},
+};
 
 static NO_ARGS: [u8; 0] = [];
 
@@ -123,14 +132,73 @@ impl DaemonRpcClient {
         self.call("set_allow_lan", &[allow_lan])
     }
 
+    pub fn set_lan_networks(&mut self, lan_networks: LanNetworks) -> Result<()> {
+        self.call("set_lan_networks", &[lan_networks])
+    }
+
+    pub fn set_allowed_endpoints(&mut self, allowed_endpoints: Vec<AllowedEndpoint>) -> Result<()> {
+        self.call("set_allowed_endpoints", &[allowed_endpoints])
+    }
+
+    pub fn set_custom_dns(&mut self, custom_dns: Vec<IpAddr>) -> Result<()> {
+        self.call("set_custom_dns", &[custom_dns])
+    }
+
+    pub fn set_local_dns_resolver(&mut self, enabled: bool) -> Result<()> {
+        self.call("set_local_dns_resolver", &[enabled])
+    }
+
+    pub fn set_dns_blocklist(&mut self, category: BlocklistCategory, enabled: bool) -> Result<()> {
+        self.call("set_dns_blocklist", &(category, enabled))
+    }
+
+    pub fn set_dns_blocked_response(&mut self, response: BlockedResponse) -> Result<()> {
+        self.call("set_dns_blocked_response", &[response])
+    }
+
+    pub fn get_dns_blocklist_stats(&mut self) -> Result<Vec<BlocklistStats>> {
+        self.call("get_dns_blocklist_stats", &NO_ARGS)
+    }
+
+    pub fn set_dns_mode(&mut self, dns_mode: DnsMode) -> Result<()> {
+        self.call("set_dns_mode", &[dns_mode])
+    }
+
+    pub fn set_split_dns(&mut self, split_dns: Vec<SplitDnsRule>) -> Result<()> {
+        self.call("set_split_dns", &[split_dns])
+    }
+
+    pub fn set_dns_manager(&mut self, dns_manager: Option<DnsManager>) -> Result<()> {
+        self.call("set_dns_manager", &[dns_manager])
+    }
+
+    pub fn get_dns_manager_diagnostics(&mut self) -> Result<DnsManagerDiagnostics> {
+        self.call("get_dns_manager_diagnostics", &NO_ARGS)
+    }
+
+    pub fn run_dns_leak_test(
+        &mut self,
+        checker: Option<DnsLeakCheckerEndpoint>,
+    ) -> Result<DnsLeakReport> {
+        self.call("run_dns_leak_test", &[checker])
+    }
+
     pub fn set_show_beta_releases(&mut self, enabled: bool) -> Result<()> {
         self.call("set_show_beta_releases", &[enabled])
     }
 
+    pub fn set_account_expiry_warnings(&mut self, warnings: Vec<u32>) -> Result<()> {
+        self.call("set_account_expiry_warnings", &[warnings])
+    }
+
     pub fn set_block_when_disconnected(&mut self, block_when_disconnected: bool) -> Result<()> {
         self.call("set_block_when_disconnected", &[block_when_disconnected])
     }
 
+    pub fn set_lockdown_mode(&mut self, lockdown_mode: bool) -> Result<()> {
+        self.call("set_lockdown_mode", &[lockdown_mode])
+    }
+
     pub fn get_allow_lan(&mut self) -> Result<bool> {
         self.call("get_allow_lan", &NO_ARGS)
     }
@@ -199,6 +267,18 @@ impl DaemonRpcClient {
         self.call("set_enable_ipv6", &[enabled])
     }
 
+    pub fn set_excluded_subnets(&mut self, excluded_subnets: Vec<IpNetwork>) -> Result<()> {
+        self.call("set_excluded_subnets", &[excluded_subnets])
+    }
+
+    pub fn set_forwarded_ports(&mut self, forwarded_ports: Vec<ForwardedPort>) -> Result<()> {
+        self.call("set_forwarded_ports", &[forwarded_ports])
+    }
+
+    pub fn set_shared_interfaces(&mut self, shared_interfaces: Vec<String>) -> Result<()> {
+        self.call("set_shared_interfaces", &[shared_interfaces])
+    }
+
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<()> {
         self.call("set_wireguard_mtu", &[mtu])
     }
@@ -207,6 +287,14 @@ impl DaemonRpcClient {
         self.call("set_wireguard_rotation_interval", &[interval])
     }
 
+    pub fn set_wireguard_degraded_timeout(&mut self, timeout: Option<u32>) -> Result<()> {
+        self.call("set_wireguard_degraded_timeout", &[timeout])
+    }
+
+    pub fn set_wireguard_policy_routing(&mut self, policy_routing: bool) -> Result<()> {
+        self.call("set_wireguard_policy_routing", &[policy_routing])
+    }
+
     pub fn set_openvpn_mssfix(&mut self, mssfix: Option<u16>) -> Result<()> {
         self.call("set_openvpn_mssfix", &[mssfix])
     }
@@ -231,6 +319,26 @@ impl DaemonRpcClient {
         self.call("factory_reset", &NO_ARGS)
     }
 
+    pub fn add_split_tunnel_process(&mut self, pid: i32) -> Result<()> {
+        self.call("add_split_tunnel_process", &[pid])
+    }
+
+    pub fn remove_split_tunnel_process(&mut self, pid: i32) -> Result<()> {
+        self.call("remove_split_tunnel_process", &[pid])
+    }
+
+    pub fn get_split_tunnel_processes(&mut self) -> Result<Vec<i32>> {
+        self.call("get_split_tunnel_processes", &NO_ARGS)
+    }
+
+    pub fn get_firewall_policy_script(&mut self, query: FirewallPolicyQuery) -> Result<String> {
+        self.call("get_firewall_policy_script", &[query])
+    }
+
+    pub fn get_firewall_stats(&mut self) -> Result<Vec<RuleStats>> {
+        self.call("get_firewall_stats", &NO_ARGS)
+    }
+
     pub fn update_relay_settings(&mut self, update: RelaySettingsUpdate) -> Result<()> {
         self.call("update_relay_settings", &[update])
     }
@@ -46,6 +46,7 @@ pub const CLASSES: &[&str] = &[
     "net/mullvad/talpid/tun_provider/TunConfig",
     "net/mullvad/talpid/tunnel/ActionAfterDisconnect",
     "net/mullvad/talpid/tunnel/ErrorState",
+    "net/mullvad/talpid/tunnel/ErrorStateCause$AccountExpired",
     "net/mullvad/talpid/tunnel/ErrorStateCause$AuthFailed",
     "net/mullvad/talpid/tunnel/ErrorStateCause$Ipv6Unavailable",
     "net/mullvad/talpid/tunnel/ErrorStateCause$SetFirewallPolicyError",
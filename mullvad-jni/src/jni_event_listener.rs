@@ -7,11 +7,11 @@ use jnix::{
 };
 use mullvad_daemon::EventListener;
 use mullvad_types::{
-    relay_list::RelayList, settings::Settings, states::TunnelState, version::AppVersionInfo,
-    wireguard::KeygenEvent,
+    account::AccountExpiryEvent, relay_list::RelayList, settings::Settings, states::TunnelState,
+    version::AppVersionInfo, wireguard::KeygenEvent,
 };
 use std::{sync::mpsc, thread};
-use talpid_types::ErrorExt;
+use talpid_types::{tunnel::FirewallIncident, ErrorExt};
 
 #[derive(Debug, err_derive::Error)]
 #[error(no_from)]
@@ -63,6 +63,16 @@ impl EventListener for JniEventListener {
     fn notify_app_version(&self, app_version_info: AppVersionInfo) {
         let _ = self.0.send(Event::AppVersionInfo(app_version_info));
     }
+
+    fn notify_account_expiry(&self, account_expiry_event: AccountExpiryEvent) {
+        // The app fetches the account expiry on its own
+        log::debug!("Ignoring account expiry event: {:?}", account_expiry_event);
+    }
+
+    fn notify_firewall_incident(&self, incident: FirewallIncident) {
+        // Incidents are only detected on Linux desktops
+        log::debug!("Ignoring firewall incident: {:?}", incident);
+    }
 }
 
 struct JniEventHandler<'env> {
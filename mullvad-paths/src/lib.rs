@@ -64,4 +64,6 @@ mod rpc_socket;
 pub use crate::rpc_socket::{get_default_rpc_socket_path, get_rpc_socket_path};
 
 mod settings;
+#[cfg(target_os = "linux")]
+pub use crate::settings::get_lockdown_ruleset_path;
 pub use crate::settings::{get_default_settings_dir, settings_dir};
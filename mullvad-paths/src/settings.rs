@@ -14,6 +14,12 @@ fn get_settings_dir() -> Result<PathBuf> {
     }
 }
 
+/// Returns the path of the firewall ruleset that keeps traffic blocked from boot in lockdown mode.
+#[cfg(target_os = "linux")]
+pub fn get_lockdown_ruleset_path() -> Result<PathBuf> {
+    get_settings_dir().map(|dir| dir.join("lockdown.nft"))
+}
+
 pub fn get_default_settings_dir() -> Result<PathBuf> {
     #[cfg(not(target_os = "android"))]
     {
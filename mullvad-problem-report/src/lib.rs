@@ -11,6 +11,8 @@ use std::{
     io::{self, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
+#[cfg(target_os = "linux")]
+use talpid_types::dns::DnsManagerDiagnostics;
 use talpid_types::ErrorExt;
 use tokio_core::reactor::Core;
 
@@ -87,6 +89,14 @@ pub enum LogError {
     #[error(display = "Error reading the contents of log file: {}", path)]
     ReadLogError { path: String },
 
+    #[cfg(target_os = "linux")]
+    #[error(display = "Unable to connect to the daemon")]
+    DaemonConnect(#[error(source)] io::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Unable to get the DNS manager diagnostics from the daemon")]
+    DnsManagerDiagnostics(#[error(source)] mullvad_ipc_client::Error),
+
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     #[error(display = "No home directory for current user")]
     NoHomeDir,
@@ -159,6 +169,14 @@ pub fn collect_report(
         Err(error) => problem_report.add_error("Failed to collect logcat", &error),
     }
 
+    #[cfg(target_os = "linux")]
+    match dns_manager_diagnostics() {
+        Ok(diagnostics) => {
+            problem_report.add_section("DNS manager diagnostics", &diagnostics.to_string())
+        }
+        Err(error) => problem_report.add_error("Failed to get the DNS manager diagnostics", &error),
+    }
+
     problem_report.add_logs(extra_logs);
 
     write_problem_report(&output_path, &problem_report).map_err(|source| Error::WriteReportError {
@@ -229,6 +247,17 @@ fn frontend_log_dir() -> Option<Result<PathBuf, LogError>> {
     }
 }
 
+/// Asks the daemon which DNS manager the system DNS is set through, and whether the system still
+/// uses what was set.
+#[cfg(target_os = "linux")]
+fn dns_manager_diagnostics() -> Result<DnsManagerDiagnostics, LogError> {
+    let mut rpc =
+        mullvad_ipc_client::new_standalone_ipc_client(&mullvad_paths::get_rpc_socket_path())
+            .map_err(LogError::DaemonConnect)?;
+    rpc.get_dns_manager_diagnostics()
+        .map_err(LogError::DnsManagerDiagnostics)
+}
+
 fn is_tunnel_log(path: &Path) -> bool {
     match path.file_name() {
         Some(file_name) => file_name.to_string_lossy().contains("openvpn"),
@@ -338,6 +367,12 @@ impl ProblemReport {
         }
     }
 
+    /// Attach text that was not read from a file to the report, under the given label.
+    pub fn add_section(&mut self, label: &'static str, content: &str) {
+        let redacted_content = self.redact(content);
+        self.logs.push((label.to_string(), redacted_content));
+    }
+
     /// Attach an error to the report.
     pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
         let redacted_error = self.redact(&error.display_chain());
@@ -14,7 +14,7 @@ use std::{
     path::{Path, PathBuf},
     time::Duration,
 };
-use talpid_types::net::wireguard;
+use talpid_types::{dns::BlocklistCategory, net::wireguard};
 use tokio_core::reactor::Handle;
 
 pub use jsonrpc_client_core::{Error, ErrorKind};
@@ -120,6 +120,10 @@ jsonrpc_client!(pub struct RelayListProxy {
     pub fn relay_list_v3(&mut self) -> RpcRequest<RelayList>;
 });
 
+jsonrpc_client!(pub struct DnsBlocklistProxy {
+    pub fn dns_blocklist(&mut self, category: BlocklistCategory) -> RpcRequest<Vec<String>>;
+});
+
 jsonrpc_client!(pub struct AppVersionProxy {
     pub fn app_version_check(&mut self, version: &version::AppVersion, platform: &str) -> RpcRequest<version::AppVersionInfo>;
 });
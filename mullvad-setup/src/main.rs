@@ -1,5 +1,7 @@
 use clap::{crate_authors, crate_description, crate_name, SubCommand};
 use mullvad_ipc_client::{new_standalone_ipc_client, DaemonRpcClient};
+#[cfg(target_os = "linux")]
+use std::{fs, process::ExitStatus};
 use std::{io, process};
 use talpid_core::firewall::{self, Firewall, FirewallArguments};
 use talpid_types::ErrorExt;
@@ -7,6 +9,7 @@ use talpid_types::ErrorExt;
 pub const PRODUCT_VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/product-version.txt"));
 
 #[derive(err_derive::Error, Debug)]
+#[error(no_from)]
 pub enum Error {
     #[error(display = "Failed to connect to daemon")]
     DaemonConnect(#[error(source)] io::Error),
@@ -19,17 +22,52 @@ pub enum Error {
 
     #[error(display = "Firewall error")]
     FirewallError(#[error(source)] firewall::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Unable to get the path of the lockdown ruleset")]
+    LockdownRulesetPath(#[error(source)] mullvad_paths::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Unable to remove the lockdown ruleset")]
+    RemoveLockdownRuleset(#[error(source)] io::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to run nft")]
+    RunNft(#[error(source)] io::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "nft failed to load the lockdown ruleset: {}", _0)]
+    LoadLockdownRuleset(ExitStatus),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Unable to get the cache directory")]
+    CacheDir(#[error(source)] mullvad_paths::Error),
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "Unable to restore the DNS settings")]
+    RestoreDns(#[error(source)] talpid_core::dns::Error),
 }
 
 fn main() {
     env_logger::init();
 
-    let subcommands = vec![
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    let mut subcommands = vec![
         SubCommand::with_name("prepare-restart")
             .about("Move a running daemon into a blocking state and save its target state"),
         SubCommand::with_name("reset-firewall")
             .about("Remove any firewall rules introduced by the daemon"),
     ];
+    #[cfg(target_os = "linux")]
+    subcommands.push(SubCommand::with_name("apply-lockdown").about(
+        "Block all traffic except loopback and DHCP if lockdown mode is enabled and the daemon is \
+         not running",
+    ));
+    #[cfg(target_os = "linux")]
+    subcommands.push(
+        SubCommand::with_name("restore-dns")
+            .about("Restore DNS settings left behind by a daemon that did not exit cleanly"),
+    );
 
     let app = clap::App::new(crate_name!())
         .version(PRODUCT_VERSION)
@@ -46,6 +84,10 @@ fn main() {
     let result = match matches.subcommand_name().expect("Subcommand has no name") {
         "prepare-restart" => prepare_restart(),
         "reset-firewall" => reset_firewall(),
+        #[cfg(target_os = "linux")]
+        "apply-lockdown" => apply_lockdown(),
+        #[cfg(target_os = "linux")]
+        "restore-dns" => restore_dns(),
         _ => unreachable!("No command matched"),
     };
 
@@ -72,7 +114,61 @@ fn reset_firewall() -> Result<(), Error> {
     })
     .map_err(Error::FirewallError)?;
 
-    firewall.reset_policy().map_err(Error::FirewallError)
+    firewall.reset_policy().map_err(Error::FirewallError)?;
+
+    // Keep lockdown mode from blocking traffic again on the next boot. The daemon writes the
+    // ruleset again when it starts, if lockdown mode is still enabled.
+    #[cfg(target_os = "linux")]
+    {
+        let ruleset_path =
+            mullvad_paths::get_lockdown_ruleset_path().map_err(Error::LockdownRulesetPath)?;
+        match fs::remove_file(ruleset_path) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(Error::RemoveLockdownRuleset(e)),
+        }
+    }
+    Ok(())
+}
+
+/// Loads the persistent ruleset written by the daemon in lockdown mode with `nft -f`. Run early
+/// during boot, so that no traffic leaks before the daemon has started and taken over.
+#[cfg(target_os = "linux")]
+fn apply_lockdown() -> Result<(), Error> {
+    let ruleset_path =
+        mullvad_paths::get_lockdown_ruleset_path().map_err(Error::LockdownRulesetPath)?;
+    if !ruleset_path.exists() {
+        return Ok(());
+    }
+    if let Ok(_) = new_rpc_client() {
+        return Err(Error::DaemonIsRunning);
+    }
+
+    let status = process::Command::new("nft")
+        .arg("-f")
+        .arg(&ruleset_path)
+        .status()
+        .map_err(Error::RunNft)?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::LoadLockdownRuleset(status))
+    }
+}
+
+/// Restores the DNS settings that the daemon saved before changing them, if it was stopped
+/// without resetting them.
+#[cfg(target_os = "linux")]
+fn restore_dns() -> Result<(), Error> {
+    if let Ok(_) = new_rpc_client() {
+        return Err(Error::DaemonIsRunning);
+    }
+
+    let cache_dir = mullvad_paths::cache_dir().map_err(Error::CacheDir)?;
+    if talpid_core::dns::restore_leftover_state(cache_dir).map_err(Error::RestoreDns)? {
+        println!("Restored the DNS settings");
+    }
+    Ok(())
 }
 
 fn new_rpc_client() -> Result<DaemonRpcClient, Error> {
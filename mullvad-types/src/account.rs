@@ -1,4 +1,4 @@
-use chrono::{offset::Utc, DateTime};
+use chrono::{offset::Utc, DateTime, Duration};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -13,6 +13,84 @@ pub struct AccountData {
     pub expiry: DateTime<Utc>,
 }
 
+/// Event sent by the daemon when the expiry of the current account changes between
+/// [`ExpiryStage`]s.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountExpiryEvent {
+    /// The account expires within `threshold_hours` hours.
+    ExpiresSoon {
+        expiry: DateTime<Utc>,
+        threshold_hours: u32,
+    },
+    /// The account has no time left.
+    Expired { expiry: DateTime<Utc> },
+    /// Time was added to an account that had expired or was about to expire.
+    Renewed { expiry: DateTime<Utc> },
+}
+
+/// How close an account is to its expiry, relative to a set of warning thresholds in hours.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExpiryStage {
+    /// No warning threshold has been crossed.
+    Active,
+    /// The account expires within the given number of hours. This is the smallest threshold
+    /// that has been crossed.
+    ExpiresSoon(u32),
+    /// The account has expired.
+    Expired,
+}
+
+impl ExpiryStage {
+    pub fn new(expiry: DateTime<Utc>, now: DateTime<Utc>, thresholds: &[u32]) -> Self {
+        if expiry <= now {
+            return ExpiryStage::Expired;
+        }
+        let remaining = expiry.signed_duration_since(now);
+        thresholds
+            .iter()
+            .cloned()
+            .filter(|hours| remaining <= Duration::hours(i64::from(*hours)))
+            .min()
+            .map(ExpiryStage::ExpiresSoon)
+            .unwrap_or(ExpiryStage::Active)
+    }
+
+    /// Returns the event describing a change from `previous` to this stage, if there is one.
+    /// Without a previous stage, only warnings are returned.
+    pub fn event_since(
+        self,
+        previous: Option<ExpiryStage>,
+        expiry: DateTime<Utc>,
+    ) -> Option<AccountExpiryEvent> {
+        if previous == Some(self) {
+            return None;
+        }
+        let renewed = previous
+            .map(|previous| previous.is_more_urgent_than(self))
+            .unwrap_or(false);
+        match self {
+            ExpiryStage::Expired => Some(AccountExpiryEvent::Expired { expiry }),
+            _ if renewed => Some(AccountExpiryEvent::Renewed { expiry }),
+            ExpiryStage::ExpiresSoon(threshold_hours) => Some(AccountExpiryEvent::ExpiresSoon {
+                expiry,
+                threshold_hours,
+            }),
+            ExpiryStage::Active => None,
+        }
+    }
+
+    fn is_more_urgent_than(self, other: ExpiryStage) -> bool {
+        use self::ExpiryStage::*;
+        match (self, other) {
+            (Expired, Expired) | (Active, _) => false,
+            (Expired, _) | (ExpiresSoon(_), Active) => true,
+            (ExpiresSoon(hours), ExpiresSoon(other_hours)) => hours < other_hours,
+            (ExpiresSoon(_), Expired) => false,
+        }
+    }
+}
+
 /// Data-structure that's returned from successfuly invocation of the mullvad API's
 /// `submit_voucher(account, voucher)` RPC
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
@@ -50,3 +128,66 @@ impl VoucherError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    const THRESHOLDS: &[u32] = &[7 * 24, 24];
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2020, 1, 1).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn test_stage_from_expiry() {
+        let stage = |expiry| ExpiryStage::new(expiry, now(), THRESHOLDS);
+
+        assert_eq!(stage(now() + Duration::days(30)), ExpiryStage::Active);
+        assert_eq!(
+            stage(now() + Duration::days(3)),
+            ExpiryStage::ExpiresSoon(7 * 24)
+        );
+        assert_eq!(
+            stage(now() + Duration::hours(2)),
+            ExpiryStage::ExpiresSoon(24)
+        );
+        assert_eq!(stage(now()), ExpiryStage::Expired);
+        assert_eq!(stage(now() - Duration::days(1)), ExpiryStage::Expired);
+        assert_eq!(
+            ExpiryStage::new(now() + Duration::hours(2), now(), &[]),
+            ExpiryStage::Active
+        );
+    }
+
+    #[test]
+    fn test_stage_events() {
+        let expiry = now();
+
+        assert_eq!(ExpiryStage::Active.event_since(None, expiry), None);
+        assert_eq!(
+            ExpiryStage::ExpiresSoon(24).event_since(None, expiry),
+            Some(AccountExpiryEvent::ExpiresSoon {
+                expiry,
+                threshold_hours: 24
+            })
+        );
+        assert_eq!(
+            ExpiryStage::ExpiresSoon(24).event_since(Some(ExpiryStage::ExpiresSoon(24)), expiry),
+            None
+        );
+        assert_eq!(
+            ExpiryStage::Expired.event_since(Some(ExpiryStage::ExpiresSoon(24)), expiry),
+            Some(AccountExpiryEvent::Expired { expiry })
+        );
+        assert_eq!(
+            ExpiryStage::ExpiresSoon(7 * 24).event_since(Some(ExpiryStage::Expired), expiry),
+            Some(AccountExpiryEvent::Renewed { expiry })
+        );
+        assert_eq!(
+            ExpiryStage::Active.event_since(Some(ExpiryStage::ExpiresSoon(24)), expiry),
+            Some(AccountExpiryEvent::Renewed { expiry })
+        );
+    }
+}
@@ -32,4 +32,10 @@ pub enum DaemonEvent {
 
     /// Key event
     WireguardKey(wireguard::KeygenEvent),
+
+    /// The expiry of the current account crossed a warning threshold.
+    AccountExpiry(account::AccountExpiryEvent),
+
+    /// Another program changed the firewall rules that enforce the tunnel state.
+    FirewallIncident(talpid_types::tunnel::FirewallIncident),
 }
@@ -67,10 +67,20 @@ impl super::SettingsMigration for Migration {
                 bridge_settings: old.bridge_settings,
                 bridge_state: old.bridge_state,
                 allow_lan: old.allow_lan,
+                lan_networks: Default::default(),
+                allowed_endpoints: vec![],
+                custom_dns: vec![],
+                local_dns_resolver: false,
+                dns_blocking: Default::default(),
+                dns_mode: Default::default(),
+                split_dns: vec![],
+                dns_manager: None,
                 block_when_disconnected: old.block_when_disconnected,
+                lockdown_mode: false,
                 auto_connect: old.auto_connect,
                 tunnel_options: old.tunnel_options,
                 show_beta_releases: None,
+                account_expiry_warnings: crate::settings::DEFAULT_ACCOUNT_EXPIRY_WARNINGS.to_vec(),
                 settings_version: super::SettingsVersion::V2,
             }),
             VersionedSettings::V2(new) => VersionedSettings::V2(new),
@@ -7,12 +7,20 @@ use jnix::IntoJava;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use talpid_types::net::{openvpn, wireguard, GenericTunnelOptions};
+use std::net::IpAddr;
+use talpid_types::{
+    dns::{DnsBlocking, DnsManager, DnsMode, SplitDnsRule},
+    net::{openvpn, wireguard, AllowedEndpoint, GenericTunnelOptions, LanNetworks},
+};
 
 mod migrations;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Number of hours before the account expiry at which to warn about it, unless configured
+/// otherwise.
+pub const DEFAULT_ACCOUNT_EXPIRY_WARNINGS: [u32; 2] = [7 * 24, 24];
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -38,10 +46,48 @@ pub struct Settings {
     bridge_state: BridgeState,
     /// If the daemon should allow communication with private (LAN) networks.
     pub allow_lan: bool,
+    /// Networks to allow in addition to, or remove from, the default private networks when LAN
+    /// access is allowed.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub lan_networks: LanNetworks,
+    /// Endpoints that can be reached outside the tunnel while connecting or blocked, such as
+    /// captive portals or login services that are needed to get online.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub allowed_endpoints: Vec<AllowedEndpoint>,
+    /// DNS servers to use inside the tunnel instead of the relay. The relay is used if empty.
+    /// Only supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub custom_dns: Vec<IpAddr>,
+    /// Send DNS queries through a resolver in the daemon, which caches the responses, instead of
+    /// pointing the system directly at the DNS servers. Only supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub local_dns_resolver: bool,
+    /// Categories of domains that the local DNS resolver blocks, which makes it be used even if
+    /// `local_dns_resolver` is off. Only supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub dns_blocking: DnsBlocking,
+    /// Whether DNS queries are encrypted while connected, and to which server. Encrypted queries
+    /// are sent by the local DNS resolver instead of to the relay or custom DNS servers. Only
+    /// supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub dns_mode: DnsMode,
+    /// Domains that are resolved by other DNS servers while connected, such as internal domains
+    /// that only the DNS servers of a local network know. The servers are reached outside the
+    /// tunnel. Only supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub split_dns: Vec<SplitDnsRule>,
+    /// The program that the system DNS is set through, instead of the detected one. The
+    /// `TALPID_DNS_MODULE` environment variable takes precedence. Only supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub dns_manager: Option<DnsManager>,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
+    /// Keeps all traffic blocked from boot until the daemon has started, and while it is not
+    /// running. Implies `block_when_disconnected`. Only supported on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub lockdown_mode: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
@@ -49,6 +95,9 @@ pub struct Settings {
     pub tunnel_options: TunnelOptions,
     /// Whether to notify users of beta updates.
     pub show_beta_releases: Option<bool>,
+    /// How many hours before the expiry of the account to warn about it.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub account_expiry_warnings: Vec<u32>,
     /// Specifies settings schema version
     #[cfg_attr(target_os = "android", jnix(skip))]
     settings_version: migrations::SettingsVersion,
@@ -67,10 +116,20 @@ impl Default for Settings {
             }),
             bridge_state: BridgeState::Auto,
             allow_lan: false,
+            lan_networks: LanNetworks::default(),
+            allowed_endpoints: vec![],
+            custom_dns: vec![],
+            local_dns_resolver: false,
+            dns_blocking: DnsBlocking::default(),
+            dns_mode: DnsMode::default(),
+            split_dns: vec![],
+            dns_manager: None,
             block_when_disconnected: false,
+            lockdown_mode: false,
             auto_connect: false,
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: None,
+            account_expiry_warnings: DEFAULT_ACCOUNT_EXPIRY_WARNINGS.to_vec(),
             settings_version: migrations::SettingsVersion::V2,
         }
     }
@@ -174,10 +233,15 @@ impl Default for TunnelOptions {
             wireguard: wireguard::TunnelOptions {
                 mtu: None,
                 automatic_rotation: None,
+                degraded_timeout: None,
+                policy_routing: false,
             },
             generic: GenericTunnelOptions {
                 // Enable IPv6 be default on Android
                 enable_ipv6: cfg!(target_os = "android"),
+                excluded_subnets: vec![],
+                forwarded_ports: vec![],
+                shared_interfaces: vec![],
             },
         }
     }
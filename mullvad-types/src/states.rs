@@ -4,7 +4,7 @@ use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
 use talpid_types::{
     net::TunnelEndpoint,
-    tunnel::{ActionAfterDisconnect, ErrorState},
+    tunnel::{ActionAfterDisconnect, ConnectionQuality, ErrorState},
 };
 
 /// Represents the state the client strives towards.
@@ -32,6 +32,9 @@ pub enum TunnelState {
     Connected {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
+        /// Latest measured quality of the tunnel, if the tunnel type supports measuring it.
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        quality: Option<ConnectionQuality>,
     },
     Disconnecting(ActionAfterDisconnect),
     Error(ErrorState),
@@ -1,4 +1,5 @@
 use std::{net::IpAddr, path::Path};
+use talpid_types::dns::SplitDnsRule;
 
 /// Stub error type for DNS errors on Android.
 #[derive(Debug, err_derive::Error)]
@@ -14,10 +15,19 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(DnsMonitor)
     }
 
-    fn set(&mut self, _interface: &str, _servers: &[IpAddr]) -> Result<(), Self::Error> {
+    fn set(
+        &mut self,
+        _interface: &str,
+        _servers: &[IpAddr],
+        _split_dns: &[SplitDnsRule],
+    ) -> Result<(), Self::Error> {
         Ok(())
     }
 
+    fn supports_split_dns(&self) -> bool {
+        false
+    }
+
     fn reset(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -0,0 +1,558 @@
+//! A test of whether DNS queries leak outside the tunnel while connected.
+//!
+//! Unique names under the domain of a checker are looked up through the system resolver. The
+//! authoritative server of that domain records which resolvers asked for the names, and the
+//! checker is then asked which ones it saw. Queries are also sent straight to port 53 of resolvers
+//! outside the tunnel, from every network interface except the tunnel, which the firewall should
+//! drop.
+
+use super::stub::{encrypted, message};
+use nix::{ifaddrs::getifaddrs, net::if_::InterfaceFlags, sys::socket::SockAddr};
+use openssl::ssl::{SslConnector, SslMethod};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    os::unix::io::AsRawFd,
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::{
+    dns::{
+        DirectQueryResult, DirectQueryTest, DnsLeakCheckerEndpoint, DnsLeakReport, LeakTestResolver,
+    },
+    ErrorExt,
+};
+
+/// How many unique names are looked up.
+const LOOKUP_COUNT: usize = 6;
+/// How long the checker is given to record the last lookups before it is asked about them.
+const CHECKER_DELAY: Duration = Duration::from_secs(1);
+/// How long to wait for a response to a direct query before it is considered blocked.
+const DIRECT_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const DNS_PORT: u16 = 53;
+const HTTPS_PORT: u16 = 443;
+
+/// Public resolvers that direct queries are sent to.
+pub const DEFAULT_DIRECT_SERVERS: [IpAddr; 3] = [
+    IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+    IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9)),
+    IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111)),
+];
+
+/// Errors that keep the DNS leak test from running.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The leak test can only tell anything about a connected tunnel.
+    #[error(display = "The DNS leak test can only be run while connected")]
+    NotConnected,
+
+    /// The checker URL is not one that can be queried.
+    #[error(display = "The checker URL must start with https:// - {}", _0)]
+    InvalidCheckerUrl(String),
+
+    /// Unable to set up the TLS context used to ask the checker.
+    #[error(display = "Failed to initialize TLS for the leak checker")]
+    InitTls(#[error(source)] openssl::error::ErrorStack),
+
+    /// Unable to ask the checker which resolvers it saw.
+    #[error(display = "Failed to ask the leak checker which resolvers it saw")]
+    QueryChecker(#[error(source)] io::Error),
+
+    /// The checker responded with something else than a list of resolvers.
+    #[error(display = "The response of the leak checker could not be parsed")]
+    ParseCheckerResponse(#[error(source)] serde_json::Error),
+}
+
+/// Tells which resolvers looked up names under its domain. Replaceable so that the leak test can
+/// be run against a local stand-in.
+pub trait LeakChecker {
+    /// Returns the domain that the unique names are made under.
+    fn domain(&self) -> &str;
+
+    /// Returns the resolvers that looked up the name made from `id`.
+    fn resolvers(&self, id: &str) -> Result<Vec<LeakTestResolver>, Error>;
+}
+
+/// A checker that is asked over HTTPS, such as the one of am.i.mullvad.net.
+pub struct HttpLeakChecker {
+    domain: String,
+    hostname: String,
+    port: u16,
+    path: String,
+    connector: SslConnector,
+}
+
+impl HttpLeakChecker {
+    /// Creates a checker for the endpoint. Fails if its URL is not an HTTPS URL, or if TLS can
+    /// not be set up.
+    pub fn new(endpoint: &DnsLeakCheckerEndpoint) -> Result<Self, Error> {
+        let (hostname, port, path) = parse_https_url(&endpoint.url)
+            .ok_or_else(|| Error::InvalidCheckerUrl(endpoint.url.clone()))?;
+        let connector = SslConnector::builder(SslMethod::tls())
+            .map_err(Error::InitTls)?
+            .build();
+        Ok(HttpLeakChecker {
+            domain: endpoint.domain.trim_matches('.').to_owned(),
+            hostname,
+            port,
+            path,
+            connector,
+        })
+    }
+}
+
+impl LeakChecker for HttpLeakChecker {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn resolvers(&self, id: &str) -> Result<Vec<LeakTestResolver>, Error> {
+        let path = format!("{}{}", self.path, id);
+        let body = encrypted::get(&self.connector, &self.hostname, self.port, &path)
+            .map_err(Error::QueryChecker)?;
+        parse_checker_response(&body)
+    }
+}
+
+/// Splits an HTTPS URL into its host, port and path.
+fn parse_https_url(url: &str) -> Option<(String, u16, String)> {
+    const SCHEME: &str = "https://";
+    if !url.starts_with(SCHEME) {
+        return None;
+    }
+    let rest = &url[SCHEME.len()..];
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let (hostname, port) = match authority.rfind(':') {
+        Some(index) if !authority.ends_with(']') => {
+            (&authority[..index], authority[index + 1..].parse().ok()?)
+        }
+        _ => (authority, HTTPS_PORT),
+    };
+    if hostname.is_empty() {
+        return None;
+    }
+    Some((hostname.to_owned(), port, path.to_owned()))
+}
+
+/// A resolver as the checker describes it.
+#[derive(Deserialize)]
+struct CheckerResolver {
+    ip: IpAddr,
+    #[serde(default)]
+    mullvad_dns: bool,
+    #[serde(default)]
+    mullvad_dns_hostname: Option<String>,
+}
+
+/// Parses the JSON array of resolvers that the checker returns. Resolvers that are listed more
+/// than once are only returned once.
+fn parse_checker_response(body: &[u8]) -> Result<Vec<LeakTestResolver>, Error> {
+    let resolvers: Vec<CheckerResolver> =
+        serde_json::from_slice(body).map_err(Error::ParseCheckerResponse)?;
+    let mut unique: Vec<LeakTestResolver> = Vec::new();
+    for resolver in resolvers {
+        if !unique.iter().any(|seen| seen.address == resolver.ip) {
+            unique.push(LeakTestResolver {
+                address: resolver.ip,
+                trusted: resolver.mullvad_dns,
+                hostname: resolver
+                    .mullvad_dns_hostname
+                    .filter(|hostname| !hostname.is_empty()),
+            });
+        }
+    }
+    Ok(unique)
+}
+
+/// Runs the leak test through the system resolver. `direct_servers` are the resolvers outside
+/// the tunnel that queries are sent straight to.
+pub fn run(
+    checker: &dyn LeakChecker,
+    tunnel_interface: &str,
+    direct_servers: &[IpAddr],
+) -> DnsLeakReport {
+    run_with_lookup(checker, &system_lookup, tunnel_interface, direct_servers)
+}
+
+/// Looks up the name through the system resolver. The answer does not matter, only which
+/// resolvers the query passes through.
+fn system_lookup(name: &str) {
+    let _ = (name, 0).to_socket_addrs();
+}
+
+fn run_with_lookup(
+    checker: &dyn LeakChecker,
+    lookup: &dyn Fn(&str),
+    tunnel_interface: &str,
+    direct_servers: &[IpAddr],
+) -> DnsLeakReport {
+    let ids: Vec<String> = (0..LOOKUP_COUNT)
+        .map(|_| uuid::Uuid::new_v4().to_string())
+        .collect();
+    for id in &ids {
+        let name = format!("{}.{}", id, checker.domain());
+        log::debug!("DNS leak test looking up {}", name);
+        lookup(&name);
+    }
+    thread::sleep(CHECKER_DELAY);
+
+    let mut resolvers: Vec<LeakTestResolver> = Vec::new();
+    let mut checker_error = None;
+    for id in &ids {
+        match checker.resolvers(id) {
+            Ok(seen) => {
+                for resolver in seen {
+                    if !resolvers.contains(&resolver) {
+                        resolvers.push(resolver);
+                    }
+                }
+            }
+            Err(error) => {
+                checker_error = Some(error.display_chain());
+                break;
+            }
+        }
+    }
+
+    let direct_name = format!("{}.{}", uuid::Uuid::new_v4(), checker.domain());
+    DnsLeakReport {
+        tunnel_interface: tunnel_interface.to_owned(),
+        checker_domain: checker.domain().to_owned(),
+        queries: ids.len(),
+        resolvers,
+        checker_error,
+        direct_queries: direct_queries(tunnel_interface, direct_servers, &direct_name),
+    }
+}
+
+/// Sends a query for `name` to each server from each interface outside the tunnel that has an
+/// address of the same family.
+fn direct_queries(tunnel_interface: &str, servers: &[IpAddr], name: &str) -> Vec<DirectQueryTest> {
+    let interfaces = match outside_interfaces(tunnel_interface) {
+        Ok(interfaces) => interfaces,
+        Err(error) => {
+            let reason = format!("Failed to list the network interfaces: {}", error);
+            return servers
+                .iter()
+                .map(|server| DirectQueryTest {
+                    server: *server,
+                    interface: "any".to_owned(),
+                    result: DirectQueryResult::Failed {
+                        reason: reason.clone(),
+                    },
+                })
+                .collect();
+        }
+    };
+
+    let mut tests = Vec::new();
+    for server in servers {
+        for (interface, (has_ipv4, has_ipv6)) in &interfaces {
+            if (server.is_ipv4() && !has_ipv4) || (server.is_ipv6() && !has_ipv6) {
+                continue;
+            }
+            let result = direct_query(SocketAddr::new(*server, DNS_PORT), interface, name);
+            log::debug!(
+                "Direct DNS query to {} from {}: {}",
+                server,
+                interface,
+                result
+            );
+            tests.push(DirectQueryTest {
+                server: *server,
+                interface: interface.clone(),
+                result,
+            });
+        }
+    }
+    tests
+}
+
+/// Returns the interfaces that are up, except loopback and the tunnel, along with whether they
+/// have an IPv4 address and a global IPv6 address.
+fn outside_interfaces(tunnel_interface: &str) -> nix::Result<BTreeMap<String, (bool, bool)>> {
+    let mut interfaces = BTreeMap::new();
+    for interface_address in getifaddrs()? {
+        if interface_address.interface_name == tunnel_interface
+            || interface_address
+                .flags
+                .contains(InterfaceFlags::IFF_LOOPBACK)
+            || !interface_address.flags.contains(InterfaceFlags::IFF_UP)
+        {
+            continue;
+        }
+        let address = match &interface_address.address {
+            Some(SockAddr::Inet(address)) => address.ip().to_std(),
+            _ => continue,
+        };
+        let families = interfaces
+            .entry(interface_address.interface_name)
+            .or_insert((false, false));
+        match address {
+            IpAddr::V4(_) => families.0 = true,
+            // Link-local addresses can not reach resolvers on other networks
+            IpAddr::V6(address) if address.segments()[0] & 0xffc0 != 0xfe80 => families.1 = true,
+            IpAddr::V6(_) => (),
+        }
+    }
+    Ok(interfaces)
+}
+
+fn direct_query(server: SocketAddr, interface: &str, name: &str) -> DirectQueryResult {
+    match send_direct_query(server, interface, name) {
+        Ok(true) => DirectQueryResult::Answered,
+        Ok(false) => DirectQueryResult::Blocked,
+        // Packets that the firewall drops on the way out fail to be sent with EPERM
+        Err(ref error) if error.kind() == io::ErrorKind::PermissionDenied => {
+            DirectQueryResult::Blocked
+        }
+        Err(ref error)
+            if error.raw_os_error() == Some(libc::ENETUNREACH)
+                || error.raw_os_error() == Some(libc::EHOSTUNREACH) =>
+        {
+            DirectQueryResult::NoRoute
+        }
+        Err(error) => DirectQueryResult::Failed {
+            reason: error.to_string(),
+        },
+    }
+}
+
+/// Sends a query from the interface and returns whether anything answered it before the timeout.
+fn send_direct_query(server: SocketAddr, interface: &str, name: &str) -> io::Result<bool> {
+    let unspecified = match server {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    bind_to_device(&socket, interface)?;
+
+    let id = (std::process::id() & 0xffff) as u16;
+    let query = message::query(id, name, message::TYPE_A).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "The name has a too long label")
+    })?;
+    socket.send_to(&query, server)?;
+
+    let deadline = Instant::now() + DIRECT_QUERY_TIMEOUT;
+    let mut buffer = [0u8; message::DEFAULT_UDP_PAYLOAD_SIZE];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((length, source)) if source == server => {
+                let is_response = message::parse_response(&buffer[..length])
+                    .map(|response| response.id == id)
+                    .unwrap_or(false);
+                if is_response {
+                    return Ok(true);
+                }
+            }
+            Ok(_) => (),
+            // The server host rejected the query, so it left the device
+            Err(ref error) if error.kind() == io::ErrorKind::ConnectionRefused => return Ok(true),
+            Err(ref error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Ok(false)
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Makes the socket send its packets out through the interface, whatever the routes say.
+fn bind_to_device(socket: &UdpSocket, interface: &str) -> io::Result<()> {
+    let interface = CString::new(interface)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let interface = interface.as_bytes_with_nul();
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::{collections::HashMap, sync::Arc};
+    use talpid_types::dns::DnsLeakVerdict;
+
+    /// A stand-in for the authoritative server and the checker of a leak test domain, listening
+    /// on loopback. It answers every query with NXDOMAIN, and remembers where they came from.
+    struct LocalChecker {
+        address: SocketAddr,
+        seen: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+        trusted: Vec<IpAddr>,
+    }
+
+    impl LocalChecker {
+        fn start(trusted: Vec<IpAddr>) -> Self {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let address = socket.local_addr().unwrap();
+            let seen = Arc::new(Mutex::new(HashMap::<String, Vec<IpAddr>>::new()));
+            let thread_seen = seen.clone();
+            thread::spawn(move || {
+                let mut buffer = [0u8; 512];
+                while let Ok((length, source)) = socket.recv_from(&mut buffer) {
+                    if let Some(query) = message::parse_query(&buffer[..length]) {
+                        thread_seen
+                            .lock()
+                            .entry(query.question.name.clone())
+                            .or_default()
+                            .push(source.ip());
+                        let _ = socket.send_to(&message::nxdomain(&query), source);
+                    }
+                }
+            });
+            LocalChecker {
+                address,
+                seen,
+                trusted,
+            }
+        }
+
+        /// Looks up names by sending queries straight to the stand-in, like a resolver would.
+        fn lookup(&self, name: &str) {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            let query = message::query(1, name, message::TYPE_A).unwrap();
+            socket.send_to(&query, self.address).unwrap();
+            let mut buffer = [0u8; 512];
+            socket.recv_from(&mut buffer).unwrap();
+        }
+    }
+
+    impl LeakChecker for LocalChecker {
+        fn domain(&self) -> &str {
+            "leaktest.example"
+        }
+
+        fn resolvers(&self, id: &str) -> Result<Vec<LeakTestResolver>, Error> {
+            let name = format!("{}.{}", id, self.domain());
+            Ok(self
+                .seen
+                .lock()
+                .get(&name)
+                .into_iter()
+                .flatten()
+                .map(|address| LeakTestResolver {
+                    address: *address,
+                    trusted: self.trusted.contains(address),
+                    hostname: None,
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_trusted_resolver() {
+        let checker = LocalChecker::start(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        let report = run_with_lookup(&checker, &|name| checker.lookup(name), "wg-mullvad", &[]);
+
+        assert_eq!(report.queries, LOOKUP_COUNT);
+        assert_eq!(checker.seen.lock().len(), LOOKUP_COUNT);
+        assert_eq!(
+            report.resolvers,
+            vec![LeakTestResolver {
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                trusted: true,
+                hostname: None,
+            }]
+        );
+        assert_eq!(report.checker_error, None);
+        // Nothing shows that the firewall blocks direct queries
+        assert_eq!(report.verdict(), DnsLeakVerdict::Inconclusive);
+    }
+
+    #[test]
+    fn test_untrusted_resolver() {
+        let checker = LocalChecker::start(vec![]);
+        let report = run_with_lookup(&checker, &|name| checker.lookup(name), "wg-mullvad", &[]);
+
+        assert_eq!(report.resolvers.len(), 1);
+        assert!(!report.resolvers[0].trusted);
+        assert_eq!(report.verdict(), DnsLeakVerdict::Leak);
+    }
+
+    #[test]
+    fn test_direct_query() {
+        let checker = LocalChecker::start(vec![]);
+        assert_eq!(
+            direct_query(checker.address, "lo", "answered.leaktest.example"),
+            DirectQueryResult::Answered
+        );
+
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            direct_query(
+                silent.local_addr().unwrap(),
+                "lo",
+                "dropped.leaktest.example"
+            ),
+            DirectQueryResult::Blocked
+        );
+    }
+
+    #[test]
+    fn test_parse_checker_response() {
+        let body = br#"[
+            {"ip": "193.138.218.74", "mullvad_dns": true, "mullvad_dns_hostname": "se-got-dns-001"},
+            {"ip": "193.138.218.74", "mullvad_dns": true, "mullvad_dns_hostname": "se-got-dns-001"},
+            {"ip": "2001:db8::53", "country": "Sweden", "mullvad_dns": false}
+        ]"#;
+        assert_eq!(
+            parse_checker_response(body).unwrap(),
+            vec![
+                LeakTestResolver {
+                    address: "193.138.218.74".parse().unwrap(),
+                    trusted: true,
+                    hostname: Some("se-got-dns-001".to_owned()),
+                },
+                LeakTestResolver {
+                    address: "2001:db8::53".parse().unwrap(),
+                    trusted: false,
+                    hostname: None,
+                },
+            ]
+        );
+        assert!(parse_checker_response(b"{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_https_url() {
+        assert_eq!(
+            parse_https_url("https://am.i.mullvad.net/dnsleak/"),
+            Some(("am.i.mullvad.net".to_owned(), 443, "/dnsleak/".to_owned()))
+        );
+        assert_eq!(
+            parse_https_url("https://checker.example:8443"),
+            Some(("checker.example".to_owned(), 8443, "/".to_owned()))
+        );
+        assert_eq!(parse_https_url("http://checker.example/"), None);
+        assert_eq!(parse_https_url("https:///path"), None);
+    }
+}
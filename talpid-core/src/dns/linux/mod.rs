@@ -1,13 +1,26 @@
 mod network_manager;
 mod resolvconf;
+mod state;
 mod static_resolv_conf;
 mod systemd_resolved;
 
 use self::{
-    network_manager::NetworkManager, resolvconf::Resolvconf, static_resolv_conf::StaticResolvConf,
+    network_manager::NetworkManager,
+    resolvconf::Resolvconf,
+    state::{DnsState, StateFile},
+    static_resolv_conf::StaticResolvConf,
     systemd_resolved::SystemdResolved,
 };
-use std::{env, fmt, net::IpAddr, path::Path};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::{env, fmt, fs, net::IpAddr, path::Path};
+use talpid_types::{
+    dns::{
+        AppliedDnsConfig, DnsManager, DnsManagerDiagnostics, DnsManagerSelection, DnsSystemState,
+        RejectedDnsManager, SplitDnsRule,
+    },
+    ErrorExt,
+};
 
 
 const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
@@ -36,36 +49,300 @@ pub enum Error {
     /// No suitable DNS monitor implementation detected
     #[error(display = "No suitable DNS monitor implementation detected")]
     NoDnsMonitor,
+
+    /// Failed to read the DNS servers from /etc/resolv.conf
+    #[error(display = "Failed to read the DNS servers from {}", RESOLV_CONF_PATH)]
+    ReadResolvConf(#[error(source)] std::io::Error),
+
+    /// Failed to parse /etc/resolv.conf
+    #[error(display = "Failed to parse {}", RESOLV_CONF_PATH)]
+    ParseResolvConf(#[error(source)] resolv_conf::ParseError),
+
+    /// Failed to access the file that holds the original DNS settings
+    #[error(display = "Failed to access the DNS state file")]
+    StateFile(#[error(source)] state::Error),
+}
+
+impl Error {
+    /// Returns the name of the DNS manager that failed.
+    pub fn backend_name(&self) -> &'static str {
+        match self {
+            Error::SystemdResolved(_) => "systemd-resolved",
+            Error::NetworkManager(_) => "NetworkManager",
+            Error::Resolvconf(_) => "resolvconf",
+            Error::StaticResolvConf(_) => RESOLV_CONF_PATH,
+            Error::NoDnsMonitor => "none",
+            Error::ReadResolvConf(_) | Error::ParseResolvConf(_) => RESOLV_CONF_PATH,
+            Error::StateFile(_) => "state file",
+        }
+    }
+}
+
+lazy_static! {
+    /// How the DNS manager that DNS was last set through was chosen, used for diagnostics.
+    static ref LAST_DETECTION: Mutex<Option<Detection>> = Mutex::new(None);
+    /// The DNS config that is currently set, used for diagnostics.
+    static ref APPLIED_CONFIG: Mutex<Option<AppliedDnsConfig>> = Mutex::new(None);
+}
+
+/// Which DNS manager was chosen, why, and why the ones tried before it can not be used.
+#[derive(Clone)]
+struct Detection {
+    manager: Option<DnsManager>,
+    selection: DnsManagerSelection,
+    rejected: Vec<RejectedDnsManager>,
 }
 
 pub struct DnsMonitor {
     inner: Option<DnsMonitorHolder>,
+    /// The DNS manager forced by the settings, if any.
+    manager_override: Option<DnsManager>,
+    /// Holds the original DNS settings while DNS is set.
+    state_file: StateFile,
 }
 
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(_cache_dir: impl AsRef<Path>) -> Result<Self> {
-        Ok(DnsMonitor { inner: None })
+    fn new(cache_dir: impl AsRef<Path>) -> Result<Self> {
+        if let Err(error) = restore_leftover_state(cache_dir.as_ref(), Path::new("/")) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to restore DNS settings left behind")
+            );
+        }
+
+        Ok(DnsMonitor {
+            inner: None,
+            manager_override: None,
+            state_file: StateFile::new(cache_dir.as_ref()),
+        })
     }
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &[SplitDnsRule],
+    ) -> Result<()> {
         self.reset()?;
         // Creating a new DNS monitor for each set, in case the system changed how it manages DNS.
-        let mut inner = DnsMonitorHolder::new()?;
-        inner.set(interface, servers)?;
+        let mut inner = DnsMonitorHolder::new(self.manager_override)?;
+        inner.set(interface, servers, split_dns, &self.state_file)?;
+        *APPLIED_CONFIG.lock() = Some(AppliedDnsConfig {
+            manager: inner.manager(),
+            interface: interface.to_owned(),
+            servers: servers.to_vec(),
+            split_dns: split_dns.to_vec(),
+        });
         self.inner = Some(inner);
         Ok(())
     }
 
+    /// Only systemd-resolved can route domains to other servers. The DNS manager is detected
+    /// anew since the next `set` detects it again too.
+    fn supports_split_dns(&self) -> bool {
+        match forced_manager(self.manager_override) {
+            Some((manager, _)) => manager == DnsManager::SystemdResolved,
+            None => SystemdResolved::new().is_ok(),
+        }
+    }
+
     fn reset(&mut self) -> Result<()> {
         if let Some(mut inner) = self.inner.take() {
+            *APPLIED_CONFIG.lock() = None;
             inner.reset()?;
+            self.state_file.remove()?;
         }
         Ok(())
     }
 }
 
+impl DnsMonitor {
+    /// Forces the DNS servers to be set through the given DNS manager, unless the
+    /// `TALPID_DNS_MODULE` environment variable forces another one. The manager is detected if
+    /// `None`. Takes effect the next time DNS is set. Returns true if it changed.
+    pub fn set_manager(&mut self, manager: Option<DnsManager>) -> bool {
+        if self.manager_override != manager {
+            self.manager_override = manager;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Restores the DNS settings saved by a daemon that was stopped without resetting DNS, and removes
+/// the state file. `/etc/resolv.conf` is taken relative to `root`. Returns true if there was
+/// anything to restore.
+pub fn restore_leftover_state(cache_dir: &Path, root: &Path) -> Result<bool> {
+    let state_file = StateFile::new(cache_dir);
+    let state = match state_file.load() {
+        Ok(Some(state)) => state,
+        Ok(None) => return Ok(false),
+        Err(error @ state::Error::ParseStateFile(_)) => {
+            // It can never be restored, so keep it from failing every start
+            state_file.remove()?;
+            return Err(error.into());
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    log::info!("Restoring DNS settings left behind by the previous daemon");
+    match state {
+        DnsState::StaticFile { resolv_conf } => static_resolv_conf::restore(root, &resolv_conf)?,
+        DnsState::Resolvconf { record_names } => Resolvconf::restore(record_names)?,
+        DnsState::NetworkManager => NetworkManager::restore()?,
+        DnsState::SystemdResolved {
+            interface,
+            split_dns_links,
+        } => SystemdResolved::restore(&interface, split_dns_links)?,
+    }
+    state_file.remove()?;
+    Ok(true)
+}
+
+/// Returns the DNS manager forced by the `TALPID_DNS_MODULE` environment variable, or else the
+/// one forced by the settings.
+fn forced_manager(
+    manager_override: Option<DnsManager>,
+) -> Option<(DnsManager, DnsManagerSelection)> {
+    let env_manager = env::var("TALPID_DNS_MODULE")
+        .ok()
+        .and_then(|value| value.parse().ok());
+    match (env_manager, manager_override) {
+        (Some(manager), _) => Some((manager, DnsManagerSelection::Environment)),
+        (None, Some(manager)) => Some((manager, DnsManagerSelection::Settings)),
+        (None, None) => None,
+    }
+}
+
+/// Chooses the DNS manager and creates what `create` returns for it. A forced manager is used
+/// even if it fails. Otherwise the first manager that `create` succeeds for is used.
+fn detect<T>(
+    manager_override: Option<DnsManager>,
+    create: impl Fn(DnsManager) -> Result<T>,
+) -> (Result<T>, Detection) {
+    if let Some((manager, selection)) = forced_manager(manager_override) {
+        let result = create(manager);
+        let detection = Detection {
+            manager: if result.is_ok() { Some(manager) } else { None },
+            selection,
+            rejected: match &result {
+                Ok(_) => vec![],
+                Err(error) => vec![RejectedDnsManager {
+                    manager,
+                    reason: error.display_chain(),
+                }],
+            },
+        };
+        return (result, detection);
+    }
+
+    let mut rejected = Vec::new();
+    for &manager in &DnsManager::ALL {
+        match create(manager) {
+            Ok(value) => {
+                let detection = Detection {
+                    manager: Some(manager),
+                    selection: DnsManagerSelection::Detected,
+                    rejected,
+                };
+                return (Ok(value), detection);
+            }
+            Err(error) => rejected.push(RejectedDnsManager {
+                manager,
+                reason: error.display_chain(),
+            }),
+        }
+    }
+    let detection = Detection {
+        manager: None,
+        selection: DnsManagerSelection::Detected,
+        rejected,
+    };
+    (Err(Error::NoDnsMonitor), detection)
+}
+
+/// Checks whether DNS can be set through the DNS manager, without changing any DNS settings.
+fn probe(manager: DnsManager) -> Result<()> {
+    match manager {
+        DnsManager::SystemdResolved => SystemdResolved::new().map(|_| ())?,
+        DnsManager::NetworkManager => NetworkManager::new().map(|_| ())?,
+        DnsManager::Resolvconf => Resolvconf::new().map(|_| ())?,
+        // Creating it restores any backup of /etc/resolv.conf, which may be in use. Writing the
+        // file directly always works, as far as can be told without changing it.
+        DnsManager::StaticFile => (),
+    }
+    Ok(())
+}
+
+/// Returns which DNS manager is used and why, what was set through it, and whether the system
+/// still uses it. While nothing is set, reports the DNS manager that would be used.
+pub fn diagnostics(manager_override: Option<DnsManager>) -> DnsManagerDiagnostics {
+    let applied = APPLIED_CONFIG.lock().clone();
+    let detection = match applied {
+        Some(_) => LAST_DETECTION.lock().clone(),
+        None => None,
+    }
+    .unwrap_or_else(|| detect(manager_override, probe).1);
+    let system_state = match &applied {
+        Some(applied) => system_state(applied),
+        None => DnsSystemState::NotSet,
+    };
+
+    DnsManagerDiagnostics {
+        manager: detection.manager,
+        selection: detection.selection,
+        rejected: detection.rejected,
+        applied,
+        system_state,
+    }
+}
+
+/// Compares the DNS servers that the system uses with the ones that were set.
+fn system_state(applied: &AppliedDnsConfig) -> DnsSystemState {
+    let servers = match applied.manager {
+        DnsManager::SystemdResolved => SystemdResolved::new()
+            .and_then(|systemd_resolved| systemd_resolved.link_dns_servers(&applied.interface))
+            .map_err(Error::SystemdResolved),
+        DnsManager::NetworkManager | DnsManager::Resolvconf | DnsManager::StaticFile => {
+            resolv_conf_servers()
+        }
+    };
+    match servers {
+        Ok(servers) => {
+            let matches = match applied.manager {
+                // resolvconf merges the records of all interfaces, with the tunnel first
+                DnsManager::Resolvconf => servers.starts_with(&applied.servers),
+                _ => servers == applied.servers,
+            };
+            if matches {
+                DnsSystemState::Matches
+            } else {
+                DnsSystemState::Differs { servers }
+            }
+        }
+        Err(error) => DnsSystemState::Unknown {
+            reason: error.display_chain(),
+        },
+    }
+}
+
+/// Returns the name servers in /etc/resolv.conf.
+fn resolv_conf_servers() -> Result<Vec<IpAddr>> {
+    let contents = fs::read_to_string(RESOLV_CONF_PATH).map_err(Error::ReadResolvConf)?;
+    let config = resolv_conf::Config::parse(contents).map_err(Error::ParseResolvConf)?;
+    Ok(config
+        .nameservers
+        .into_iter()
+        .map(|nameserver| match nameserver {
+            resolv_conf::ScopedIp::V4(address) => IpAddr::V4(address),
+            resolv_conf::ScopedIp::V6(address, _) => IpAddr::V6(address),
+        })
+        .collect())
+}
+
 pub enum DnsMonitorHolder {
     SystemdResolved(SystemdResolved),
     NetworkManager(NetworkManager),
@@ -87,40 +364,64 @@ impl fmt::Display for DnsMonitorHolder {
 }
 
 impl DnsMonitorHolder {
-    fn new() -> Result<Self> {
-        let dns_module = env::var_os("TALPID_DNS_MODULE");
-
-        let manager = match dns_module.as_ref().and_then(|value| value.to_str()) {
-            Some("static-file") => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?),
-            Some("resolvconf") => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
-            Some("systemd") => DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?),
-            Some("network-manager") => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
-            Some(_) | None => Self::with_detected_dns_manager()?,
-        };
+    fn new(manager_override: Option<DnsManager>) -> Result<Self> {
+        let (result, detection) = detect(manager_override, Self::with_manager);
+        for rejected in &detection.rejected {
+            log::debug!(
+                "Not managing DNS via {}: {}",
+                rejected.manager,
+                rejected.reason
+            );
+        }
+        *LAST_DETECTION.lock() = Some(detection);
+
+        let manager = result?;
         log::debug!("Managing DNS via {}", manager);
         Ok(manager)
     }
 
-    fn with_detected_dns_manager() -> Result<Self> {
-        SystemdResolved::new()
-            .map(DnsMonitorHolder::SystemdResolved)
-            .or_else(|_| NetworkManager::new().map(DnsMonitorHolder::NetworkManager))
-            .or_else(|_| Resolvconf::new().map(DnsMonitorHolder::Resolvconf))
-            .or_else(|_| StaticResolvConf::new().map(DnsMonitorHolder::StaticResolvConf))
-            .map_err(|_| Error::NoDnsMonitor)
+    fn with_manager(manager: DnsManager) -> Result<Self> {
+        Ok(match manager {
+            DnsManager::SystemdResolved => {
+                DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?)
+            }
+            DnsManager::NetworkManager => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
+            DnsManager::Resolvconf => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
+            DnsManager::StaticFile => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?),
+        })
+    }
+
+    fn manager(&self) -> DnsManager {
+        match self {
+            DnsMonitorHolder::SystemdResolved(..) => DnsManager::SystemdResolved,
+            DnsMonitorHolder::NetworkManager(..) => DnsManager::NetworkManager,
+            DnsMonitorHolder::Resolvconf(..) => DnsManager::Resolvconf,
+            DnsMonitorHolder::StaticResolvConf(..) => DnsManager::StaticFile,
+        }
     }
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+    /// Sets the DNS servers. The split DNS rules are only applied by systemd-resolved, and are
+    /// emulated by the local DNS resolver for the other DNS managers. What is needed to restore
+    /// the original DNS settings is saved to the state file before anything is changed.
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &[SplitDnsRule],
+        state_file: &StateFile,
+    ) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
-            Resolvconf(ref mut resolvconf) => resolvconf.set_dns(interface, servers)?,
+            Resolvconf(ref mut resolvconf) => resolvconf.set_dns(interface, servers, state_file)?,
             StaticResolvConf(ref mut static_resolv_conf) => {
-                static_resolv_conf.set_dns(servers.to_vec())?
+                static_resolv_conf.set_dns(servers.to_vec(), state_file)?
             }
             SystemdResolved(ref mut systemd_resolved) => {
-                systemd_resolved.set_dns(interface, &servers)?
+                systemd_resolved.set_dns(interface, &servers, split_dns, state_file)?
+            }
+            NetworkManager(ref mut network_manager) => {
+                network_manager.set_dns(servers, state_file)?
             }
-            NetworkManager(ref mut network_manager) => network_manager.set_dns(servers)?,
         }
         Ok(())
     }
@@ -136,3 +437,58 @@ impl DnsMonitorHolder {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_restore_without_leftover_state() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        assert!(!restore_leftover_state(cache_dir.path(), root.path()).unwrap());
+        assert!(!root.path().join("etc/resolv.conf").exists());
+    }
+
+    #[test]
+    fn test_restore_leftover_resolv_conf() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join("etc/resolv.conf"),
+            "nameserver 10.64.0.1\n",
+        )
+        .unwrap();
+
+        let original = "nameserver 192.168.1.1\nsearch lan\n";
+        let state_file = StateFile::new(cache_dir.path());
+        state_file
+            .save(&DnsState::StaticFile {
+                resolv_conf: original.to_owned(),
+            })
+            .unwrap();
+
+        assert!(restore_leftover_state(cache_dir.path(), root.path()).unwrap());
+        assert_eq!(
+            fs::read_to_string(root.path().join("etc/resolv.conf")).unwrap(),
+            original
+        );
+        assert_eq!(state_file.load().unwrap(), None);
+        assert!(!restore_leftover_state(cache_dir.path(), root.path()).unwrap());
+    }
+
+    #[test]
+    fn test_restore_corrupt_state_file() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        fs::write(cache_dir.path().join("dns-state-backup.json"), "{ not json").unwrap();
+
+        match restore_leftover_state(cache_dir.path(), root.path()) {
+            Err(Error::StateFile(state::Error::ParseStateFile(_))) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        // The state file can not be restored, so it is removed to not fail again
+        assert!(!restore_leftover_state(cache_dir.path(), root.path()).unwrap());
+    }
+}
@@ -1,3 +1,4 @@
+use super::state::{self, DnsState, StateFile};
 use dbus::{
     arg::{RefArg, Variant},
     stdintf::*,
@@ -30,6 +31,9 @@ pub enum Error {
 
     #[error(display = "DNS is managed by systemd-resolved - NM can't enforce DNS globally")]
     SystemdResolved,
+
+    #[error(display = "Failed to save that the global DNS configuration is set")]
+    SaveState(#[error(source)] state::Error),
 }
 
 const NM_BUS: &str = "org.freedesktop.NetworkManager";
@@ -57,6 +61,13 @@ impl NetworkManager {
         Ok(manager)
     }
 
+    /// Clears the global DNS configuration set by a daemon that did not reset DNS.
+    pub fn restore() -> Result<()> {
+        let dbus_connection =
+            dbus::Connection::get_private(BusType::System).map_err(Error::Dbus)?;
+        NetworkManager { dbus_connection }.reset()
+    }
+
     fn ensure_network_manager_exists(&self) -> Result<()> {
         let _: Box<dyn RefArg> = self
             .as_manager()
@@ -107,7 +118,10 @@ impl NetworkManager {
             .with_path(NM_BUS, NM_OBJECT_PATH, RPC_TIMEOUT_MS)
     }
 
-    pub fn set_dns(&mut self, servers: &[IpAddr]) -> Result<()> {
+    pub fn set_dns(&mut self, servers: &[IpAddr], state_file: &StateFile) -> Result<()> {
+        state_file
+            .save(&DnsState::NetworkManager)
+            .map_err(Error::SaveState)?;
         self.set_global_dns(create_global_settings(servers))
     }
 
@@ -1,3 +1,4 @@
+use super::state::{self, DnsState, StateFile};
 use std::{
     collections::HashSet,
     ffi::OsStr,
@@ -32,6 +33,9 @@ pub enum Error {
 
     #[error(display = "Current /etc/resolv.conf is not generated by resolvconf")]
     ResolvconfNotInUseError,
+
+    #[error(display = "Failed to save the names of the added resolvconf records")]
+    SaveState(#[error(source)] state::Error),
 }
 
 pub struct Resolvconf {
@@ -67,6 +71,16 @@ impl Resolvconf {
         })
     }
 
+    /// Deletes the records added by a daemon that did not reset DNS.
+    pub fn restore(record_names: Vec<String>) -> Result<()> {
+        let resolvconf_path = which("resolvconf").map_err(|_| Error::NoResolvconf)?;
+        Resolvconf {
+            record_names: record_names.into_iter().collect(),
+            resolvconf: resolvconf_path,
+        }
+        .reset()
+    }
+
     fn resolvconf_is_resolved_symlink(resolvconf_path: &Path) -> bool {
         fs::read_link(resolvconf_path)
             .map(|resolvconf_target| {
@@ -75,7 +89,12 @@ impl Resolvconf {
             .unwrap_or_else(|_| false)
     }
 
-    pub fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+    pub fn set_dns(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        state_file: &StateFile,
+    ) -> Result<()> {
         let record_name = format!("{}.mullvad", interface);
         let mut record_contents = String::new();
 
@@ -85,6 +104,14 @@ impl Resolvconf {
             record_contents.push('\n');
         }
 
+        let mut record_names: Vec<String> = self.record_names.iter().cloned().collect();
+        if !record_names.contains(&record_name) {
+            record_names.push(record_name.clone());
+        }
+        state_file
+            .save(&DnsState::Resolvconf { record_names })
+            .map_err(Error::SaveState)?;
+
         let output = duct::cmd!(&self.resolvconf, "-a", &record_name)
             .stdin_bytes(record_contents)
             .stderr_capture()
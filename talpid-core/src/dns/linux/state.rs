@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const DNS_STATE_FILENAME: &str = "dns-state-backup.json";
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to read the DNS state file")]
+    ReadStateFile(#[error(source)] io::Error),
+
+    #[error(display = "The DNS state file could not be parsed")]
+    ParseStateFile(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to write the DNS state file")]
+    WriteStateFile(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove the DNS state file")]
+    RemoveStateFile(#[error(source)] io::Error),
+}
+
+/// What a DNS manager changed, and what is needed to restore the original DNS settings if the
+/// daemon stops without resetting them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsState {
+    /// `/etc/resolv.conf` was overwritten. Holds its original contents.
+    StaticFile { resolv_conf: String },
+    /// Records were added with `resolvconf`.
+    Resolvconf { record_names: Vec<String> },
+    /// The global DNS configuration of NetworkManager was set. It is cleared on restore.
+    NetworkManager,
+    /// The DNS servers of the tunnel interface were set in systemd-resolved, and split DNS
+    /// servers and routing domains were added to other links.
+    SystemdResolved {
+        interface: String,
+        split_dns_links: Vec<LinkState>,
+    },
+}
+
+/// The settings that a systemd-resolved link had before split DNS was added to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkState {
+    pub interface: String,
+    pub servers: Vec<(i32, Vec<u8>)>,
+    pub domains: Vec<(String, bool)>,
+}
+
+/// A file in the cache directory that holds the [`DnsState`] while DNS is set.
+#[derive(Debug, Clone)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(cache_dir: &Path) -> Self {
+        StateFile {
+            path: cache_dir.join(DNS_STATE_FILENAME),
+        }
+    }
+
+    /// Writes the state. The file is replaced by a rename, so that a crash never leaves a partly
+    /// written file behind.
+    pub fn save(&self, state: &DnsState) -> Result<()> {
+        let contents = serde_json::to_vec(state).map_err(|e| Error::WriteStateFile(e.into()))?;
+        let temp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&temp_path).map_err(Error::WriteStateFile)?;
+        file.write_all(&contents)
+            .and_then(|()| file.sync_all())
+            .map_err(Error::WriteStateFile)?;
+        fs::rename(&temp_path, &self.path).map_err(Error::WriteStateFile)
+    }
+
+    /// Returns the saved state, or `None` if there is no state file.
+    pub fn load(&self) -> Result<Option<DnsState>> {
+        match fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map(Some)
+                .map_err(Error::ParseStateFile),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(Error::ReadStateFile(error)),
+        }
+    }
+
+    /// Removes the state file, if there is one.
+    pub fn remove(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(Error::RemoveStateFile(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let state_file = StateFile::new(cache_dir.path());
+        assert_eq!(state_file.load().unwrap(), None);
+
+        let state = DnsState::SystemdResolved {
+            interface: "wg-mullvad".to_owned(),
+            split_dns_links: vec![LinkState {
+                interface: "eth0".to_owned(),
+                servers: vec![(2, vec![192, 168, 1, 1])],
+                domains: vec![("lan".to_owned(), false)],
+            }],
+        };
+        state_file.save(&state).unwrap();
+        assert_eq!(state_file.load().unwrap(), Some(state));
+
+        let state = DnsState::NetworkManager;
+        state_file.save(&state).unwrap();
+        assert_eq!(state_file.load().unwrap(), Some(state));
+        assert_eq!(
+            fs::read_dir(cache_dir.path()).unwrap().count(),
+            1,
+            "The temporary file should have been renamed"
+        );
+
+        state_file.remove().unwrap();
+        assert_eq!(state_file.load().unwrap(), None);
+        state_file.remove().unwrap();
+    }
+}
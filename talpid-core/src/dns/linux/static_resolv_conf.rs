@@ -1,10 +1,14 @@
-use super::RESOLV_CONF_PATH;
+use super::{
+    state::{self, DnsState, StateFile},
+    RESOLV_CONF_PATH,
+};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use parking_lot::Mutex;
 use resolv_conf::{Config, ScopedIp};
 use std::{
     fs, io,
     net::IpAddr,
+    path::Path,
     sync::{mpsc, Arc},
     thread,
 };
@@ -31,6 +35,12 @@ pub enum Error {
 
     #[error(display = "Failed to remove stale resolv.conf backup at {}", _0)]
     RemoveBackup(&'static str, #[error(source)] io::Error),
+
+    #[error(
+        display = "Failed to save the original contents of {}",
+        RESOLV_CONF_PATH
+    )]
+    SaveState(#[error(source)] state::Error),
 }
 
 pub struct StaticResolvConf {
@@ -40,6 +50,7 @@ pub struct StaticResolvConf {
 
 impl StaticResolvConf {
     pub fn new() -> Result<Self> {
+        // Older versions kept the original resolv.conf next to it instead of in the state file
         restore_from_backup()?;
 
         let state = Arc::new(Mutex::new(None));
@@ -51,21 +62,22 @@ impl StaticResolvConf {
         })
     }
 
-    pub fn set_dns(&mut self, servers: Vec<IpAddr>) -> Result<()> {
+    pub fn set_dns(&mut self, servers: Vec<IpAddr>, state_file: &StateFile) -> Result<()> {
         let mut state = self.state.lock();
         let new_state = match state.take() {
             None => {
-                let backup = read_config()?;
-                write_backup(&backup)?;
-
-                State {
-                    backup,
+                let new_state = State {
+                    backup: read_config()?,
                     desired_dns: servers,
-                }
+                    state_file: state_file.clone(),
+                };
+                new_state.save_backup()?;
+                new_state
             }
             Some(previous_state) => State {
                 backup: previous_state.backup,
                 desired_dns: servers,
+                state_file: previous_state.state_file,
             },
         };
 
@@ -79,7 +91,6 @@ impl StaticResolvConf {
     pub fn reset(&mut self) -> Result<()> {
         if let Some(state) = self.state.lock().take() {
             write_config(&state.backup)?;
-            let _ = fs::remove_file(RESOLV_CONF_BACKUP_PATH);
         }
 
         Ok(())
@@ -89,9 +100,20 @@ impl StaticResolvConf {
 struct State {
     backup: Config,
     desired_dns: Vec<IpAddr>,
+    state_file: StateFile,
 }
 
 impl State {
+    /// Saves the original config, so that it can be restored if the daemon stops without
+    /// resetting DNS.
+    fn save_backup(&self) -> Result<()> {
+        self.state_file
+            .save(&DnsState::StaticFile {
+                resolv_conf: self.backup.to_string(),
+            })
+            .map_err(Error::SaveState)
+    }
+
     fn desired_config(&self) -> Config {
         let mut config = self.backup.clone();
 
@@ -155,6 +177,7 @@ impl DnsWatcher {
 
             if new_config.nameservers != desired_nameservers {
                 state.backup = new_config.clone();
+                state.save_backup()?;
                 new_config.nameservers = desired_nameservers;
 
                 write_config(&new_config)
@@ -163,7 +186,7 @@ impl DnsWatcher {
                 new_config.nameservers.append(&mut state.backup.nameservers);
                 state.backup = new_config;
 
-                write_backup(&state.backup)
+                state.save_backup()
             }
         } else {
             Ok(())
@@ -188,9 +211,11 @@ fn write_config(config: &Config) -> Result<()> {
         .map_err(|e| Error::WriteResolvConf(RESOLV_CONF_PATH, e))
 }
 
-fn write_backup(backup: &Config) -> Result<()> {
-    fs::write(RESOLV_CONF_BACKUP_PATH, backup.to_string().as_bytes())
-        .map_err(|e| Error::WriteResolvConf(RESOLV_CONF_BACKUP_PATH, e))
+/// Writes back the original contents of resolv.conf, saved by a daemon that did not reset DNS.
+/// The path of resolv.conf is taken relative to `root`.
+pub fn restore(root: &Path, resolv_conf: &str) -> Result<()> {
+    let path = root.join(RESOLV_CONF_PATH.trim_start_matches('/'));
+    fs::write(path, resolv_conf).map_err(|e| Error::WriteResolvConf(RESOLV_CONF_PATH, e))
 }
 
 fn restore_from_backup() -> Result<()> {
@@ -1,4 +1,7 @@
-use super::RESOLV_CONF_PATH;
+use super::{
+    state::{self, DnsState, LinkState, StateFile},
+    RESOLV_CONF_PATH,
+};
 use crate::linux::iface_index;
 use dbus::{
     arg::RefArg, stdintf::*, BusType, Interface, Member, Message, MessageItem, MessageItemArray,
@@ -6,12 +9,14 @@ use dbus::{
 };
 use lazy_static::lazy_static;
 use libc::{AF_INET, AF_INET6};
+use nix::{ifaddrs::getifaddrs, sys::socket::SockAddr};
 use std::{
+    collections::BTreeMap,
     fs, io,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     path::Path,
 };
-use talpid_types::ErrorExt as _;
+use talpid_types::{dns::SplitDnsRule, ErrorExt as _};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -48,11 +53,23 @@ pub enum Error {
     #[error(display = "Failed to revert DNS settings of interface: {}", _0)]
     RevertDnsError(String, #[error(source)] dbus::Error),
 
+    #[error(display = "Failed to read DNS settings of interface: {}", _0)]
+    ReadLinkSettingsError(String, #[error(source)] dbus::Error),
+
+    #[error(display = "Failed to route split DNS domains to interface: {}", _0)]
+    SetSplitDnsError(String, #[error(source)] dbus::Error),
+
+    #[error(display = "Failed to restore DNS settings of interface: {}", _0)]
+    RestoreSplitDnsError(String, #[error(source)] dbus::Error),
+
     #[error(display = "Failed to perform RPC call on D-Bus")]
     DBusRpcError(#[error(source)] dbus::Error),
 
     #[error(display = "Failed to match the returned D-Bus object with expected type")]
     MatchDBusTypeError(#[error(source)] dbus::arg::TypeMismatchError),
+
+    #[error(display = "Failed to save the DNS settings that are about to be changed")]
+    SaveState(#[error(source)] state::Error),
 }
 
 lazy_static! {
@@ -78,9 +95,24 @@ lazy_static! {
     static ref REVERT_METHOD: Member<'static> = Member::from_slice(b"Revert").unwrap();
 }
 
+/// The DNS servers of a link, as they are passed over D-Bus.
+type LinkServers = Vec<(i32, Vec<u8>)>;
+/// The search and routing domains of a link, as they are passed over D-Bus.
+type LinkDomains = Vec<(String, bool)>;
+
 pub struct SystemdResolved {
     dbus_connection: dbus::Connection,
     interface_link: Option<(String, dbus::Path<'static>)>,
+    split_dns_links: Vec<SplitDnsLink>,
+}
+
+/// A link that split DNS servers and routing domains were added to, and the settings it had
+/// before, which are restored on reset.
+struct SplitDnsLink {
+    interface_name: String,
+    link_object_path: dbus::Path<'static>,
+    servers: LinkServers,
+    domains: LinkDomains,
 }
 
 impl SystemdResolved {
@@ -90,6 +122,7 @@ impl SystemdResolved {
         let systemd_resolved = SystemdResolved {
             dbus_connection,
             interface_link: None,
+            split_dns_links: Vec::new(),
         };
 
         systemd_resolved.ensure_resolved_exists()?;
@@ -99,6 +132,49 @@ impl SystemdResolved {
         Ok(systemd_resolved)
     }
 
+    /// Reverts the DNS settings of the tunnel interface and restores the links that split DNS
+    /// was added to, as saved by a daemon that did not reset DNS. Interfaces that no longer exist
+    /// are skipped.
+    pub fn restore(interface_name: &str, links: Vec<LinkState>) -> Result<()> {
+        let dbus_connection =
+            dbus::Connection::get_private(BusType::System).map_err(Error::ConnectDBus)?;
+        let mut systemd_resolved = SystemdResolved {
+            dbus_connection,
+            interface_link: None,
+            split_dns_links: Vec::new(),
+        };
+
+        systemd_resolved.interface_link = systemd_resolved
+            .fetch_existing_link(interface_name)?
+            .map(|link_object_path| (interface_name.to_owned(), link_object_path));
+        for link in links {
+            if let Some(link_object_path) = systemd_resolved.fetch_existing_link(&link.interface)? {
+                systemd_resolved.split_dns_links.push(SplitDnsLink {
+                    interface_name: link.interface,
+                    link_object_path,
+                    servers: link.servers,
+                    domains: link.domains,
+                });
+            }
+        }
+        systemd_resolved.reset()
+    }
+
+    /// Returns the link of the interface, or `None` if there is no such interface.
+    fn fetch_existing_link(&self, interface_name: &str) -> Result<Option<dbus::Path<'static>>> {
+        match self.fetch_link(interface_name) {
+            Ok(link_object_path) => Ok(Some(link_object_path)),
+            Err(Error::InvalidInterfaceName(_)) => {
+                log::info!(
+                    "Not restoring DNS of interface {} because it no longer exists",
+                    interface_name
+                );
+                Ok(None)
+            }
+            Err(error) => Err(Error::GetLinkError(Box::new(error))),
+        }
+    }
+
     fn ensure_resolved_exists(&self) -> Result<()> {
         let _: Box<dyn RefArg> = self
             .as_manager_object()
@@ -170,7 +246,13 @@ impl SystemdResolved {
             .with_path(RESOLVED_BUS, link_object_path, RPC_TIMEOUT_MS)
     }
 
-    pub fn set_dns(&mut self, interface_name: &str, servers: &[IpAddr]) -> Result<()> {
+    pub fn set_dns(
+        &mut self,
+        interface_name: &str,
+        servers: &[IpAddr],
+        split_dns: &[SplitDnsRule],
+        state_file: &StateFile,
+    ) -> Result<()> {
         let link_object_path = self
             .fetch_link(interface_name)
             .map_err(|e| Error::GetLinkError(Box::new(e)))?;
@@ -181,12 +263,167 @@ impl SystemdResolved {
             );
         }
 
+        self.save_state(interface_name, state_file)?;
         self.set_link_dns(&link_object_path, servers)?;
         self.interface_link = Some((interface_name.to_string(), link_object_path));
 
+        self.set_split_dns(interface_name, split_dns, state_file)
+    }
+
+    /// Saves what is needed to undo the changes to the links, in case the daemon stops without
+    /// resetting them.
+    fn save_state(&self, interface_name: &str, state_file: &StateFile) -> Result<()> {
+        state_file
+            .save(&DnsState::SystemdResolved {
+                interface: interface_name.to_owned(),
+                split_dns_links: self
+                    .split_dns_links
+                    .iter()
+                    .map(|link| LinkState {
+                        interface: link.interface_name.clone(),
+                        servers: link.servers.clone(),
+                        domains: link.domains.clone(),
+                    })
+                    .collect(),
+            })
+            .map_err(Error::SaveState)
+    }
+
+    /// Routes the domains of the rules to their servers. systemd-resolved can only route domains
+    /// to links, and sends queries out on the link whose servers it uses, so the servers and
+    /// routing domains of each rule are added to the link that the servers are reached through.
+    /// The servers are put first, since systemd-resolved keeps using the first server that
+    /// answers. The previous settings of the links are restored on reset.
+    fn set_split_dns(
+        &mut self,
+        tunnel_interface: &str,
+        rules: &[SplitDnsRule],
+        state_file: &StateFile,
+    ) -> Result<()> {
+        let mut links: BTreeMap<String, (Vec<IpAddr>, Vec<&str>)> = BTreeMap::new();
+        for rule in rules {
+            for server in &rule.servers {
+                match outgoing_interface(*server) {
+                    Ok(ref interface) if interface == tunnel_interface => log::warn!(
+                        "Not using split DNS server {} for {} since it is routed through the \
+                         tunnel",
+                        server,
+                        rule.domain
+                    ),
+                    Ok(interface) => {
+                        let (servers, domains) = links.entry(interface).or_default();
+                        if !servers.contains(server) {
+                            servers.push(*server);
+                        }
+                        if !domains.contains(&rule.domain.as_str()) {
+                            domains.push(&rule.domain);
+                        }
+                    }
+                    Err(error) => log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Unable to find the interface that split DNS server {} is reached \
+                             through",
+                            server
+                        ))
+                    ),
+                }
+            }
+        }
+
+        for (interface_name, (servers, domains)) in links {
+            let link_object_path = self
+                .fetch_link(&interface_name)
+                .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+            let link = self.as_link_object(link_object_path.clone());
+            let previous_servers: LinkServers = link
+                .get(&LINK_INTERFACE, "DNS")
+                .map_err(|e| Error::ReadLinkSettingsError(interface_name.clone(), e))?;
+            let previous_domains: LinkDomains = link
+                .get(&LINK_INTERFACE, "Domains")
+                .map_err(|e| Error::ReadLinkSettingsError(interface_name.clone(), e))?;
+
+            let mut new_servers: LinkServers = servers.iter().map(address_to_tuple).collect();
+            for server in &previous_servers {
+                if !new_servers.contains(server) {
+                    new_servers.push(server.clone());
+                }
+            }
+            let mut new_domains = previous_domains.clone();
+            for domain in domains {
+                let routing_domain = (domain.to_owned(), true);
+                if !new_domains.contains(&routing_domain) {
+                    new_domains.push(routing_domain);
+                }
+            }
+
+            log::debug!(
+                "Routing DNS queries for {} to interface {}",
+                new_domains
+                    .iter()
+                    .filter(|domain| !previous_domains.contains(domain))
+                    .map(|(domain, _)| domain.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                interface_name
+            );
+            // Remember the previous settings first, so that a partial change is also restored
+            self.split_dns_links.push(SplitDnsLink {
+                interface_name: interface_name.clone(),
+                link_object_path: link_object_path.clone(),
+                servers: previous_servers,
+                domains: previous_domains,
+            });
+            self.save_state(tunnel_interface, state_file)?;
+            self.set_link_settings(&link_object_path, &new_servers, &new_domains)
+                .map_err(|e| Error::SetSplitDnsError(interface_name, e))?;
+        }
         Ok(())
     }
 
+    fn set_link_settings(
+        &self,
+        link_object_path: &dbus::Path<'static>,
+        servers: &[(i32, Vec<u8>)],
+        domains: &[(String, bool)],
+    ) -> std::result::Result<(), dbus::Error> {
+        let set_servers = Message::new_method_call(
+            RESOLVED_BUS,
+            link_object_path as &str,
+            &LINK_INTERFACE as &str,
+            &SET_DNS_METHOD as &str,
+        )
+        .expect("failed to construct a new dbus message")
+        .append1(servers);
+        self.dbus_connection
+            .send_with_reply_and_block(set_servers, RPC_TIMEOUT_MS)
+            .and_then(|mut reply| reply.as_result().map(|_| ()))?;
+
+        let set_domains = Message::new_method_call(
+            RESOLVED_BUS,
+            link_object_path as &str,
+            &LINK_INTERFACE as &str,
+            &SET_DOMAINS_METHOD as &str,
+        )
+        .expect("failed to construct a new dbus message")
+        .append1(domains);
+        self.dbus_connection
+            .send_with_reply_and_block(set_domains, RPC_TIMEOUT_MS)
+            .and_then(|mut reply| reply.as_result().map(|_| ()))
+    }
+
+    /// Returns the DNS servers that systemd-resolved currently uses for the interface.
+    pub fn link_dns_servers(&self, interface_name: &str) -> Result<Vec<IpAddr>> {
+        let link_object_path = self
+            .fetch_link(interface_name)
+            .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+        let servers: LinkServers = self
+            .as_link_object(link_object_path)
+            .get(&LINK_INTERFACE, "DNS")
+            .map_err(|e| Error::ReadLinkSettingsError(interface_name.to_owned(), e))?;
+        Ok(servers.iter().filter_map(tuple_to_address).collect())
+    }
+
     fn fetch_link(&self, interface_name: &str) -> Result<dbus::Path<'static>> {
         let interface_index = iface_index(interface_name).map_err(Error::InvalidInterfaceName)?;
 
@@ -236,13 +473,37 @@ impl SystemdResolved {
     }
 
     pub fn reset(&mut self) -> Result<()> {
-        if let Some((interface_name, link_object_path)) = self.interface_link.take() {
+        let split_dns_result = self.reset_split_dns();
+        let result = if let Some((interface_name, link_object_path)) = self.interface_link.take() {
             self.revert_link(link_object_path, &interface_name)
                 .map_err(|e| Error::RevertDnsError(interface_name.to_owned(), e))
         } else {
             log::trace!("No DNS settings to reset");
             Ok(())
+        };
+        split_dns_result.and(result)
+    }
+
+    /// Restores the settings of the links that split DNS rules were added to. Links that no
+    /// longer exist are skipped.
+    fn reset_split_dns(&mut self) -> Result<()> {
+        for link in std::mem::replace(&mut self.split_dns_links, Vec::new()) {
+            match self.set_link_settings(&link.link_object_path, &link.servers, &link.domains) {
+                Ok(()) => (),
+                Err(ref error)
+                    if error.name() == Some("org.freedesktop.DBus.Error.UnknownObject") =>
+                {
+                    log::info!(
+                        "Not restoring DNS of interface {} because it no longer exists",
+                        link.interface_name
+                    );
+                }
+                Err(error) => {
+                    return Err(Error::RestoreSplitDnsError(link.interface_name, error));
+                }
+            }
         }
+        Ok(())
     }
 
     fn revert_link(
@@ -269,6 +530,54 @@ impl SystemdResolved {
     }
 }
 
+/// Returns the name of the interface that packets to the address are routed through, which is
+/// the interface of the source address that the kernel picks for them.
+fn outgoing_interface(address: IpAddr) -> io::Result<String> {
+    let unspecified = match address {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    // Connecting a UDP socket only looks up the route, nothing is sent
+    socket.connect(SocketAddr::new(address, 53))?;
+    let source_address = socket.local_addr()?.ip();
+
+    getifaddrs()
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?
+        .find(|interface_address| match &interface_address.address {
+            Some(SockAddr::Inet(inet_address)) => inet_address.ip().to_std() == source_address,
+            _ => false,
+        })
+        .map(|interface_address| interface_address.interface_name)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No interface has the address {}", source_address),
+            )
+        })
+}
+
+fn address_to_tuple(address: &IpAddr) -> (i32, Vec<u8>) {
+    match address {
+        IpAddr::V4(ipv4_address) => (AF_INET, ipv4_address.octets().to_vec()),
+        IpAddr::V6(ipv6_address) => (AF_INET6, ipv6_address.octets().to_vec()),
+    }
+}
+
+fn tuple_to_address((family, octets): &(i32, Vec<u8>)) -> Option<IpAddr> {
+    match *family {
+        AF_INET if octets.len() == 4 => Some(IpAddr::V4(Ipv4Addr::new(
+            octets[0], octets[1], octets[2], octets[3],
+        ))),
+        AF_INET6 if octets.len() == 16 => {
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(octets);
+            Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+        }
+        _ => None,
+    }
+}
+
 fn build_addresses_argument(addresses: &[IpAddr]) -> MessageItem {
     let addresses = addresses.iter().map(ip_address_to_message_item).collect();
 
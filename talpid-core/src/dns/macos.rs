@@ -20,6 +20,7 @@ use system_configuration::{
     dynamic_store::{SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext},
     sys::schema_definitions::kSCPropNetDNSServerAddresses,
 };
+use talpid_types::dns::SplitDnsRule;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -149,7 +150,12 @@ impl super::DnsMonitorT for DnsMonitor {
         })
     }
 
-    fn set(&mut self, _interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(
+        &mut self,
+        _interface: &str,
+        servers: &[IpAddr],
+        _split_dns: &[SplitDnsRule],
+    ) -> Result<()> {
         let servers: Vec<DnsServer> = servers.iter().map(|ip| ip.to_string()).collect();
         let settings = DnsSettings::from_server_addresses(&servers);
         let mut state_lock = self.state.lock();
@@ -183,6 +189,10 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(())
     }
 
+    fn supports_split_dns(&self) -> bool {
+        false
+    }
+
     fn reset(&mut self) -> Result<()> {
         let mut state_lock = self.state.lock();
         if let Some(state) = state_lock.take() {
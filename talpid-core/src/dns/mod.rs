@@ -1,4 +1,14 @@
 use std::{net::IpAddr, path::Path};
+#[cfg(target_os = "linux")]
+use talpid_types::dns::{DnsManager, DnsManagerDiagnostics};
+use talpid_types::{dns::SplitDnsRule, tunnel::ErrorDetails};
+
+/// A local DNS resolver that the system DNS can be pointed at.
+pub mod stub;
+
+/// A test of whether DNS queries leak outside the tunnel.
+#[cfg(target_os = "linux")]
+pub mod leak_test;
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -18,6 +28,34 @@ mod imp;
 
 pub use self::imp::Error;
 
+/// Describes a DNS error, so that it can be reported along with the error state cause.
+pub fn error_details(error: &Error) -> ErrorDetails {
+    #[cfg(target_os = "linux")]
+    let backend = error.backend_name();
+    #[cfg(target_os = "macos")]
+    let backend = "SystemConfiguration";
+    #[cfg(windows)]
+    let backend = "WinDns";
+    #[cfg(target_os = "android")]
+    let backend = "android";
+    ErrorDetails::from_error(backend, error)
+}
+
+/// Returns which DNS manager the system DNS is set through and why, what was set through it, and
+/// whether the system still uses it. `manager_override` is the DNS manager forced by the
+/// settings, which is reported as the one that would be used while nothing is set.
+#[cfg(target_os = "linux")]
+pub fn manager_diagnostics(manager_override: Option<DnsManager>) -> DnsManagerDiagnostics {
+    imp::diagnostics(manager_override)
+}
+
+/// Restores the original DNS settings if a daemon that used `cache_dir` was stopped without
+/// resetting DNS. Returns true if there was anything to restore.
+#[cfg(target_os = "linux")]
+pub fn restore_leftover_state(cache_dir: impl AsRef<Path>) -> Result<bool, Error> {
+    imp::restore_leftover_state(cache_dir.as_ref(), Path::new("/"))
+}
+
 /// Sets and monitors system DNS settings. Makes sure the desired DNS servers are being used.
 pub struct DnsMonitor {
     inner: imp::DnsMonitor,
@@ -31,8 +69,14 @@ impl DnsMonitor {
         })
     }
 
-    /// Set DNS to the given servers. And start monitoring the system for changes.
-    pub fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
+    /// Set DNS to the given servers. And start monitoring the system for changes. The split DNS
+    /// rules are only applied if [`supports_split_dns`](Self::supports_split_dns) returns true.
+    pub fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &[SplitDnsRule],
+    ) -> Result<(), Error> {
         log::info!(
             "Setting DNS servers to {}",
             servers
@@ -41,7 +85,13 @@ impl DnsMonitor {
                 .collect::<Vec<String>>()
                 .join(", ")
         );
-        self.inner.set(interface, servers)
+        self.inner.set(interface, servers, split_dns)
+    }
+
+    /// Returns true if the system DNS manager can send queries for some domains to other servers
+    /// by itself. Otherwise split DNS has to be emulated by the local DNS resolver.
+    pub fn supports_split_dns(&self) -> bool {
+        self.inner.supports_split_dns()
     }
 
     /// Reset system DNS settings to what it was before being set by this instance.
@@ -49,6 +99,61 @@ impl DnsMonitor {
         log::info!("Resetting DNS");
         self.inner.reset()
     }
+
+    /// Forces DNS to be set through the given DNS manager instead of the detected one. Takes
+    /// effect the next time DNS is set. Returns true if it changed.
+    #[cfg(target_os = "linux")]
+    pub fn set_manager(&mut self, manager: Option<DnsManager>) -> bool {
+        self.inner.set_manager(manager)
+    }
+}
+
+/// Something that can set and reset the system DNS. Implemented by [`DnsMonitor`], and used by
+/// the tunnel state machine so that the system DNS manager can be replaced.
+pub trait DnsBackend {
+    /// Set DNS to the given servers, and route the domains of the split DNS rules to their
+    /// servers if supported.
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &[SplitDnsRule],
+    ) -> Result<(), Error>;
+
+    /// Returns true if split DNS rules passed to `set` are applied.
+    fn supports_split_dns(&self) -> bool;
+
+    /// Reset system DNS settings to what it was before being set.
+    fn reset(&mut self) -> Result<(), Error>;
+
+    /// Forces DNS to be set through the given DNS manager the next time it is set, or detects
+    /// the manager if `None`. Returns true if it changed.
+    #[cfg(target_os = "linux")]
+    fn set_manager(&mut self, manager: Option<DnsManager>) -> bool;
+}
+
+impl DnsBackend for DnsMonitor {
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &[SplitDnsRule],
+    ) -> Result<(), Error> {
+        DnsMonitor::set(self, interface, servers, split_dns)
+    }
+
+    fn supports_split_dns(&self) -> bool {
+        DnsMonitor::supports_split_dns(self)
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        DnsMonitor::reset(self)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_manager(&mut self, manager: Option<DnsManager>) -> bool {
+        DnsMonitor::set_manager(self, manager)
+    }
 }
 
 trait DnsMonitorT: Sized {
@@ -56,7 +161,14 @@ trait DnsMonitorT: Sized {
 
     fn new(cache_dir: impl AsRef<Path>) -> Result<Self, Self::Error>;
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &[SplitDnsRule],
+    ) -> Result<(), Self::Error>;
+
+    fn supports_split_dns(&self) -> bool;
 
     fn reset(&mut self) -> Result<(), Self::Error>;
 }
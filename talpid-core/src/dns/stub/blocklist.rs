@@ -0,0 +1,253 @@
+//! Lists of domains that the stub resolver does not resolve.
+//!
+//! The lists are read from files in either the hosts format, where each line is an address
+//! followed by names, or as plain lists of domains with one per line. Anything after a `#` is a
+//! comment. A listed domain blocks all of its subdomains too.
+
+use parking_lot::RwLock;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use talpid_types::dns::{BlockedResponse, BlocklistCategory, BlocklistStats};
+
+/// Names that hosts files map to local addresses, which must never be blocked.
+const LOCAL_HOST_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+/// Returns the name of the file that the list of the given category is stored in.
+pub fn file_name(category: BlocklistCategory) -> String {
+    format!("dns-blocklist-{}.txt", category.name())
+}
+
+/// Returns the path that a list of the given category that was downloaded from the API is cached
+/// at.
+pub fn cache_path(category: BlocklistCategory, cache_dir: &Path) -> PathBuf {
+    cache_dir.join(file_name(category))
+}
+
+/// The domains of one category.
+#[derive(Debug, Clone)]
+pub struct Blocklist {
+    category: BlocklistCategory,
+    domains: HashSet<String>,
+}
+
+impl Blocklist {
+    /// Parses a list in the hosts or domain list format.
+    pub fn parse(category: BlocklistCategory, contents: &str) -> Self {
+        let mut domains = HashSet::new();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace().peekable();
+            // Lines in hosts files start with the address that the names resolve to
+            if let Some(Ok(_)) = fields.peek().map(|field| field.parse::<IpAddr>()) {
+                fields.next();
+            }
+            for field in fields {
+                let domain = field.trim_end_matches('.').to_ascii_lowercase();
+                if !domain.is_empty() && !LOCAL_HOST_NAMES.contains(&domain.as_str()) {
+                    domains.insert(domain);
+                }
+            }
+        }
+        Blocklist { category, domains }
+    }
+
+    /// Loads the list of a category from the cache directory, where lists downloaded from the
+    /// API are stored, or otherwise from the list shipped in the resource directory.
+    pub fn load(
+        category: BlocklistCategory,
+        resource_dir: &Path,
+        cache_dir: &Path,
+    ) -> io::Result<Self> {
+        let contents = match fs::read_to_string(cache_path(category, cache_dir)) {
+            Ok(contents) => contents,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                fs::read_to_string(resource_dir.join(file_name(category)))?
+            }
+            Err(error) => return Err(error),
+        };
+        Ok(Self::parse(category, &contents))
+    }
+
+    /// The category that the domains belong to.
+    pub fn category(&self) -> BlocklistCategory {
+        self.category
+    }
+
+    /// Returns the number of domains on the list.
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Returns true if the list has no domains.
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Returns true if the name or any of its parent domains is on the list.
+    pub fn contains(&self, name: &str) -> bool {
+        let mut domain = name.trim_end_matches('.');
+        loop {
+            if self.domains.contains(domain) {
+                return true;
+            }
+            match domain.find('.') {
+                Some(index) => domain = &domain[index + 1..],
+                None => return false,
+            }
+        }
+    }
+}
+
+/// The blocklists in use, shared between the stub resolver, which checks queries against them,
+/// and whoever wants to know how many queries they have blocked.
+#[derive(Clone, Default)]
+pub struct Blocklists {
+    inner: Arc<RwLock<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    lists: Vec<Blocklist>,
+    response: BlockedResponse,
+    /// Kept when the lists are replaced, so that the counts are not lost when lists are updated.
+    hits: HashMap<BlocklistCategory, AtomicU64>,
+}
+
+impl Blocklists {
+    /// Creates blocklists that block the domains of the given lists, with no blocked queries
+    /// counted yet.
+    pub fn new(lists: Vec<Blocklist>, response: BlockedResponse) -> Self {
+        let blocklists = Self::default();
+        blocklists.set(lists, response);
+        blocklists
+    }
+
+    /// Replaces the lists in use and how blocked names are answered.
+    pub fn set(&self, lists: Vec<Blocklist>, response: BlockedResponse) {
+        let mut inner = self.inner.write();
+        for list in &lists {
+            inner.hits.entry(list.category).or_default();
+        }
+        inner.lists = lists;
+        inner.response = response;
+    }
+
+    /// Returns true if any category is blocked.
+    pub fn is_enabled(&self) -> bool {
+        !self.inner.read().lists.is_empty()
+    }
+
+    /// Returns how the name should be answered if it is blocked, and counts the blocked query.
+    pub fn check(&self, name: &str) -> Option<BlockedResponse> {
+        let inner = self.inner.read();
+        let list = inner.lists.iter().find(|list| list.contains(name))?;
+        if let Some(hits) = inner.hits.get(&list.category) {
+            hits.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(inner.response)
+    }
+
+    /// Returns the number of domains and blocked queries of each list in use.
+    pub fn stats(&self) -> Vec<BlocklistStats> {
+        let inner = self.inner.read();
+        inner
+            .lists
+            .iter()
+            .map(|list| BlocklistStats {
+                category: list.category,
+                domains: list.len(),
+                blocked_queries: inner
+                    .hits
+                    .get(&list.category)
+                    .map(|hits| hits.load(Ordering::Relaxed))
+                    .unwrap_or(0),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hosts_and_domain_lists() {
+        let list = Blocklist::parse(
+            BlocklistCategory::Ads,
+            "# Ads\n\
+             127.0.0.1 localhost\n\
+             0.0.0.0 ads.example tracker.example # inline comment\n\
+             ::1 ip6-localhost\n\
+             Banner.Example.\n\
+             \n",
+        );
+        assert_eq!(list.len(), 3);
+        assert!(list.contains("ads.example"));
+        assert!(list.contains("tracker.example"));
+        assert!(list.contains("banner.example"));
+        assert!(!list.contains("localhost"));
+    }
+
+    #[test]
+    fn test_blocks_subdomains() {
+        let list = Blocklist::parse(BlocklistCategory::Malware, "bad.example\n");
+        assert!(list.contains("bad.example"));
+        assert!(list.contains("cdn.bad.example."));
+        assert!(!list.contains("example"));
+        assert!(!list.contains("notbad.example"));
+    }
+
+    #[test]
+    fn test_counts_hits_across_updates() {
+        let blocklists = Blocklists::new(
+            vec![Blocklist::parse(BlocklistCategory::Trackers, "t.example")],
+            BlockedResponse::Nxdomain,
+        );
+        assert_eq!(
+            blocklists.check("x.t.example"),
+            Some(BlockedResponse::Nxdomain)
+        );
+        assert_eq!(blocklists.check("other.example"), None);
+
+        blocklists.set(
+            vec![Blocklist::parse(
+                BlocklistCategory::Trackers,
+                "t.example\nu.example",
+            )],
+            BlockedResponse::Unspecified,
+        );
+        assert_eq!(
+            blocklists.check("u.example"),
+            Some(BlockedResponse::Unspecified)
+        );
+        assert_eq!(
+            blocklists.stats(),
+            vec![BlocklistStats {
+                category: BlocklistCategory::Trackers,
+                domains: 2,
+                blocked_queries: 2,
+            }]
+        );
+    }
+}
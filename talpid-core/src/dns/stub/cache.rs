@@ -0,0 +1,227 @@
+use super::message::{self, Query, Question, Response, RCODE_NOERROR, RCODE_NXDOMAIN};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+
+/// Responses are not cached for longer than this, whatever their TTL is.
+const MAX_TTL: u32 = 24 * 60 * 60;
+/// How long to cache negative responses without records, which do not say for how long the name
+/// does not exist.
+const NEGATIVE_TTL: u32 = 60;
+
+/// Caches responses by their question until their records expire.
+pub struct Cache {
+    entries: HashMap<Question, Entry>,
+    capacity: usize,
+}
+
+struct Entry {
+    response: Vec<u8>,
+    ttl_offsets: Vec<usize>,
+    inserted: Instant,
+    expires: Instant,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Self {
+        Cache {
+            entries: HashMap::new(),
+            capacity,
+        }
+    }
+
+    /// Returns the cached response to `query`, with the ID and question of the query and the
+    /// TTLs reduced by the time it has been cached.
+    pub fn get(&mut self, query: &Query<'_>, now: Instant) -> Option<Vec<u8>> {
+        let entry = self.entries.get(&query.question)?;
+        if entry.expires <= now {
+            self.entries.remove(&query.question);
+            return None;
+        }
+
+        let mut response = entry.response.clone();
+        message::set_id(&mut response, query.id);
+        // The question only differs in the case of the name, so it has the same length
+        let question_end = message::HEADER_LEN + query.question_bytes.len();
+        response[message::HEADER_LEN..question_end].copy_from_slice(query.question_bytes);
+
+        let elapsed =
+            u32::try_from(now.duration_since(entry.inserted).as_secs()).unwrap_or(MAX_TTL);
+        for offset in &entry.ttl_offsets {
+            let ttl = message::read_u32(&response, *offset).unwrap_or(0);
+            response[*offset..*offset + 4]
+                .copy_from_slice(&ttl.saturating_sub(elapsed).to_be_bytes());
+        }
+        Some(response)
+    }
+
+    /// Caches `response` if it answers `question` and its records do not expire immediately.
+    pub fn insert(
+        &mut self,
+        question: Question,
+        response: Vec<u8>,
+        parsed: Response,
+        now: Instant,
+    ) {
+        if parsed.truncated
+            || (parsed.rcode != RCODE_NOERROR && parsed.rcode != RCODE_NXDOMAIN)
+            || parsed.question.as_ref() != Some(&question)
+        {
+            return;
+        }
+        let ttl = parsed
+            .min_ttl(&response)
+            .unwrap_or(NEGATIVE_TTL)
+            .min(MAX_TTL);
+        if ttl == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&question) {
+            self.entries.retain(|_, entry| entry.expires > now);
+            if self.entries.len() >= self.capacity {
+                self.remove_first_to_expire();
+            }
+        }
+        self.entries.insert(
+            question,
+            Entry {
+                response,
+                ttl_offsets: parsed.ttl_offsets,
+                inserted: now,
+                expires: now + Duration::from_secs(u64::from(ttl)),
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn remove_first_to_expire(&mut self) {
+        let first = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.expires)
+            .map(|(question, _)| question.clone());
+        if let Some(question) = first {
+            self.entries.remove(&question);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dns::stub::message::{
+        parse_query, parse_response,
+        test::{query, response},
+        RCODE_SERVFAIL,
+    };
+
+    fn insert(cache: &mut Cache, message: &[u8], response: Vec<u8>, now: Instant) {
+        let question = parse_query(message).unwrap().question;
+        let parsed = parse_response(&response).unwrap();
+        cache.insert(question, response, parsed, now);
+    }
+
+    #[test]
+    fn test_serves_cached_response_with_reduced_ttl() {
+        let mut cache = Cache::new(10);
+        let now = Instant::now();
+        let first_query = query(1, "example.com");
+        insert(
+            &mut cache,
+            &first_query,
+            response(&first_query, RCODE_NOERROR, &[300]),
+            now,
+        );
+
+        let second_query = query(2, "EXAMPLE.com");
+        let cached = cache
+            .get(
+                &parse_query(&second_query).unwrap(),
+                now + Duration::from_secs(100),
+            )
+            .unwrap();
+        let parsed = parse_response(&cached).unwrap();
+        assert_eq!(parsed.id, 2);
+        // The question is repeated as the client sent it
+        assert_eq!(
+            &cached[message::HEADER_LEN..second_query.len()],
+            &second_query[message::HEADER_LEN..]
+        );
+        assert_eq!(parsed.min_ttl(&cached), Some(200));
+    }
+
+    #[test]
+    fn test_expires_responses() {
+        let mut cache = Cache::new(10);
+        let now = Instant::now();
+        let message = query(1, "example.com");
+        insert(
+            &mut cache,
+            &message,
+            response(&message, RCODE_NOERROR, &[60, 30]),
+            now,
+        );
+
+        let query = parse_query(&message).unwrap();
+        assert!(cache.get(&query, now + Duration::from_secs(29)).is_some());
+        assert!(cache.get(&query, now + Duration::from_secs(30)).is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_does_not_cache_failures_or_zero_ttls() {
+        let mut cache = Cache::new(10);
+        let now = Instant::now();
+        let message = query(1, "example.com");
+        insert(
+            &mut cache,
+            &message,
+            response(&message, RCODE_SERVFAIL, &[]),
+            now,
+        );
+        insert(
+            &mut cache,
+            &message,
+            response(&message, RCODE_NOERROR, &[0]),
+            now,
+        );
+        assert_eq!(cache.len(), 0);
+
+        insert(
+            &mut cache,
+            &message,
+            response(&message, RCODE_NXDOMAIN, &[]),
+            now,
+        );
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_evicts_first_to_expire_when_full() {
+        let mut cache = Cache::new(2);
+        let now = Instant::now();
+        for (name, ttl) in &[("a.example", 100), ("b.example", 50), ("c.example", 200)] {
+            let message = query(1, name);
+            insert(
+                &mut cache,
+                &message,
+                response(&message, RCODE_NOERROR, &[*ttl]),
+                now,
+            );
+        }
+
+        assert_eq!(cache.len(), 2);
+        let b_query = query(1, "b.example");
+        assert!(cache.get(&parse_query(&b_query).unwrap(), now).is_none());
+    }
+}
@@ -0,0 +1,310 @@
+//! Forwarding of queries to DNS-over-TLS (RFC 7858) and DNS-over-HTTPS (RFC 8484) servers.
+//!
+//! Every query is sent over a new connection, which is closed once the response has been read.
+//! The cache of the stub resolver keeps the number of connections down.
+
+use super::{read_tcp_message, write_tcp_message, MAX_MESSAGE_SIZE, UPSTREAM_TIMEOUT};
+use openssl::ssl::SslConnector;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+};
+use talpid_types::dns::{EncryptedDnsProtocol, EncryptedDnsServer};
+
+/// The media type of DNS messages sent over HTTPS.
+const DNS_MESSAGE_MEDIA_TYPE: &str = "application/dns-message";
+/// The path that queries are posted to if the server does not specify one.
+const DEFAULT_PATH: &str = "/dns-query";
+/// The longest status or header line that is accepted in an HTTP response.
+const MAX_HTTP_LINE_LENGTH: u64 = 8192;
+
+/// Sends the query to the server at the given address, and returns its response. The TLS
+/// handshake fails unless the server has a certificate for its hostname that the system trusts.
+pub fn forward(
+    connector: &SslConnector,
+    server: &EncryptedDnsServer,
+    address: IpAddr,
+    message: &[u8],
+) -> io::Result<Vec<u8>> {
+    let address = SocketAddr::new(address, server.protocol.port());
+    let stream = TcpStream::connect_timeout(&address, UPSTREAM_TIMEOUT)?;
+    stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+    let mut stream = connector
+        .connect(&server.hostname, stream)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))?;
+
+    match server.protocol {
+        EncryptedDnsProtocol::Tls => {
+            write_tcp_message(&mut stream, message)?;
+            read_tcp_message(&mut stream)
+        }
+        EncryptedDnsProtocol::Https => {
+            post_query(&mut stream, &server.hostname, &server.path, message)
+        }
+    }
+}
+
+/// Posts the query over HTTP/1.1 and returns the body of the response.
+fn post_query<S: Read + Write>(
+    stream: &mut S,
+    hostname: &str,
+    path: &str,
+    message: &[u8],
+) -> io::Result<Vec<u8>> {
+    let path = if path.is_empty() { DEFAULT_PATH } else { path };
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Accept: {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n",
+        path,
+        hostname,
+        DNS_MESSAGE_MEDIA_TYPE,
+        DNS_MESSAGE_MEDIA_TYPE,
+        message.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(message);
+    stream.write_all(&request)?;
+    stream.flush()?;
+    read_http_response(stream)
+}
+
+/// Sends a GET request over HTTPS to the host, and returns the body of the response. Used by the
+/// DNS leak test to ask its checker which resolvers it saw.
+pub fn get(connector: &SslConnector, hostname: &str, port: u16, path: &str) -> io::Result<Vec<u8>> {
+    let address = (hostname, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The host has no addresses"))?;
+    let stream = TcpStream::connect_timeout(&address, UPSTREAM_TIMEOUT)?;
+    stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+    let mut stream = connector
+        .connect(hostname, stream)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))?;
+    send_get(&mut stream, hostname, path)
+}
+
+fn send_get<S: Read + Write>(stream: &mut S, hostname: &str, path: &str) -> io::Result<Vec<u8>> {
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Accept: application/json\r\n\
+         Connection: close\r\n\
+         \r\n",
+        path, hostname
+    );
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    read_http_response(stream)
+}
+
+/// Reads an HTTP response and returns its body, which may be sent with a length, in chunks, or
+/// until the connection is closed.
+fn read_http_response(stream: impl Read) -> io::Result<Vec<u8>> {
+    let mut reader = BufReader::new(stream);
+    let status_line = read_http_line(&mut reader)?;
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(invalid_data(format!(
+            "Unexpected HTTP status: {}",
+            status_line
+        )));
+    }
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let line = read_http_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            let length = value
+                .parse::<usize>()
+                .map_err(|_| invalid_data(format!("Invalid content length: {}", value)))?;
+            content_length = Some(length);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    if chunked {
+        return read_chunked_body(&mut reader);
+    }
+    let body = match content_length {
+        Some(length) if length > MAX_MESSAGE_SIZE => {
+            return Err(invalid_data("The HTTP response is too large"));
+        }
+        Some(length) => {
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body)?;
+            body
+        }
+        None => {
+            let mut body = Vec::new();
+            reader
+                .take(MAX_MESSAGE_SIZE as u64 + 1)
+                .read_to_end(&mut body)?;
+            body
+        }
+    };
+    if body.len() > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("The HTTP response is too large"));
+    }
+    Ok(body)
+}
+
+fn read_chunked_body(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_http_line(reader)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_data(format!("Invalid chunk size: {}", size)))?;
+        if size == 0 {
+            // Skip the trailer
+            while !read_http_line(reader)?.is_empty() {}
+            return Ok(body);
+        }
+        if body.len() + size > MAX_MESSAGE_SIZE {
+            return Err(invalid_data("The HTTP response is too large"));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        // Every chunk ends with an empty line
+        read_http_line(reader)?;
+    }
+}
+
+/// Reads a line and strips the line ending.
+fn read_http_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    let length = reader
+        .by_ref()
+        .take(MAX_HTTP_LINE_LENGTH)
+        .read_line(&mut line)?;
+    if length == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    if !line.ends_with('\n') {
+        return Err(invalid_data("The HTTP response has a too long line"));
+    }
+    let trimmed_length = line.trim_end_matches(&['\r', '\n'][..]).len();
+    line.truncate(trimmed_length);
+    Ok(line)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A connection that has already received `input`, and records what is written to it.
+    struct MockStream {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &[u8]) -> Self {
+            MockStream {
+                input: io::Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_post_query() {
+        let mut stream = MockStream::new(
+            b"HTTP/1.1 200 OK\r\n\
+              content-type: application/dns-message\r\n\
+              Content-Length: 3\r\n\
+              \r\n\
+              abc",
+        );
+        let response = post_query(&mut stream, "dns.example", "", b"query").unwrap();
+        assert_eq!(response, b"abc");
+        assert_eq!(
+            String::from_utf8(stream.output).unwrap(),
+            "POST /dns-query HTTP/1.1\r\n\
+             Host: dns.example\r\n\
+             Accept: application/dns-message\r\n\
+             Content-Type: application/dns-message\r\n\
+             Content-Length: 5\r\n\
+             Connection: close\r\n\
+             \r\n\
+             query"
+        );
+    }
+
+    #[test]
+    fn test_get() {
+        let mut stream = MockStream::new(
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Length: 2\r\n\
+              \r\n\
+              []",
+        );
+        let response = send_get(&mut stream, "checker.example", "/dnsleak/id").unwrap();
+        assert_eq!(response, b"[]");
+        assert_eq!(
+            String::from_utf8(stream.output).unwrap(),
+            "GET /dnsleak/id HTTP/1.1\r\n\
+             Host: checker.example\r\n\
+             Accept: application/json\r\n\
+             Connection: close\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let response = read_http_response(
+            &b"HTTP/1.1 200 OK\r\n\
+               Transfer-Encoding: chunked\r\n\
+               \r\n\
+               2;ext=1\r\nab\r\n\
+               1\r\nc\r\n\
+               0\r\n\
+               \r\n"[..],
+        )
+        .unwrap();
+        assert_eq!(response, b"abc");
+    }
+
+    #[test]
+    fn test_error_status() {
+        let error =
+            read_http_response(&b"HTTP/1.1 415 Unsupported Media Type\r\n\r\n"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,382 @@
+//! Just enough parsing of DNS messages to forward queries and cache the responses. Messages that
+//! can not be parsed are still forwarded, but are never cached. Queries can also be built, for
+//! the DNS leak test.
+
+use std::convert::TryFrom;
+
+pub const HEADER_LEN: usize = 12;
+/// The largest UDP response that a client accepts if it does not say otherwise with EDNS.
+pub const DEFAULT_UDP_PAYLOAD_SIZE: usize = 512;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+const OPCODE_MASK: u16 = 0x7800;
+const RCODE_MASK: u16 = 0x000f;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_SERVFAIL: u8 = 2;
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const TYPE_OPT: u16 = 41;
+pub const CLASS_IN: u16 = 1;
+
+const POINTER_MASK: u8 = 0xc0;
+/// Limits how many compression pointers are followed in a name, so that loops end.
+const MAX_POINTERS: usize = 16;
+
+/// The question of a query, which is what responses are cached by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Question {
+    /// The queried name in lowercase, with the labels separated by dots.
+    pub name: String,
+    pub record_type: u16,
+    pub class: u16,
+}
+
+/// A standard query with a single question.
+#[derive(Debug)]
+pub struct Query<'a> {
+    pub id: u16,
+    pub question: Question,
+    /// The question section as sent by the client, which responses must repeat exactly.
+    pub question_bytes: &'a [u8],
+    /// The largest UDP response that the client accepts.
+    pub udp_payload_size: usize,
+}
+
+/// A response, along with where the TTLs of its records are.
+#[derive(Debug)]
+pub struct Response {
+    pub id: u16,
+    pub rcode: u8,
+    pub truncated: bool,
+    pub question: Option<Question>,
+    /// Offsets of the TTL fields of all records, except the EDNS pseudo-record.
+    pub ttl_offsets: Vec<usize>,
+}
+
+impl Response {
+    /// Returns the lowest TTL of the records in the response, if it has any.
+    pub fn min_ttl(&self, message: &[u8]) -> Option<u32> {
+        self.ttl_offsets
+            .iter()
+            .map(|offset| read_u32(message, *offset).unwrap_or(0))
+            .min()
+    }
+}
+
+/// Parses a standard query with a single question. Returns `None` for anything else.
+pub fn parse_query(message: &[u8]) -> Option<Query<'_>> {
+    let flags = read_u16(message, 2)?;
+    if flags & (FLAG_RESPONSE | OPCODE_MASK) != 0 || read_u16(message, 4)? != 1 {
+        return None;
+    }
+    let (question, question_end) = parse_question(message, HEADER_LEN)?;
+
+    // The answer and authority sections are empty in queries
+    let mut offset = question_end;
+    let mut udp_payload_size = DEFAULT_UDP_PAYLOAD_SIZE;
+    for _ in 0..read_u16(message, 10)? {
+        let record = parse_record_header(message, offset)?;
+        if record.record_type == TYPE_OPT {
+            udp_payload_size = usize::from(record.class).max(DEFAULT_UDP_PAYLOAD_SIZE);
+        }
+        offset = record.end;
+    }
+
+    Some(Query {
+        id: read_u16(message, 0)?,
+        question,
+        question_bytes: &message[HEADER_LEN..question_end],
+        udp_payload_size,
+    })
+}
+
+/// Parses a response. Returns `None` if it is malformed.
+pub fn parse_response(message: &[u8]) -> Option<Response> {
+    let flags = read_u16(message, 2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return None;
+    }
+    let question_count = read_u16(message, 4)?;
+    let record_count = u32::from(read_u16(message, 6)?)
+        + u32::from(read_u16(message, 8)?)
+        + u32::from(read_u16(message, 10)?);
+
+    let mut offset = HEADER_LEN;
+    let mut question = None;
+    for _ in 0..question_count {
+        let (parsed_question, end) = parse_question(message, offset)?;
+        question = Some(parsed_question);
+        offset = end;
+    }
+    let mut ttl_offsets = Vec::new();
+    for _ in 0..record_count {
+        let record = parse_record_header(message, offset)?;
+        if record.record_type != TYPE_OPT {
+            ttl_offsets.push(record.ttl_offset);
+        }
+        offset = record.end;
+    }
+
+    Some(Response {
+        id: read_u16(message, 0)?,
+        rcode: (flags & RCODE_MASK) as u8,
+        truncated: flags & FLAG_TRUNCATED != 0,
+        question: if question_count == 1 { question } else { None },
+        ttl_offsets,
+    })
+}
+
+/// Returns a SERVFAIL response to the given query, which tells the client that the name could
+/// not be resolved.
+pub fn servfail(query: &Query<'_>) -> Vec<u8> {
+    empty_response(query, u16::from(RCODE_SERVFAIL))
+}
+
+/// Returns a truncated response to the given query, which tells the client to retry over TCP.
+pub fn truncated(query: &Query<'_>) -> Vec<u8> {
+    empty_response(query, FLAG_TRUNCATED)
+}
+
+/// Returns a response to the given query which says that the name does not exist.
+pub fn nxdomain(query: &Query<'_>) -> Vec<u8> {
+    empty_response(query, u16::from(RCODE_NXDOMAIN))
+}
+
+/// Returns a response to the given query with the unspecified address, `0.0.0.0` or `::`, if it
+/// asks for an address. Other queries are answered without records.
+pub fn unspecified_address(query: &Query<'_>, ttl: u32) -> Vec<u8> {
+    let mut response = empty_response(query, u16::from(RCODE_NOERROR));
+    let address: &[u8] = match (query.question.record_type, query.question.class) {
+        (TYPE_A, CLASS_IN) => &[0; 4],
+        (TYPE_AAAA, CLASS_IN) => &[0; 16],
+        _ => return response,
+    };
+    response[6..8].copy_from_slice(&1u16.to_be_bytes());
+    // The name of the record points to the name in the question
+    response.extend_from_slice(&[POINTER_MASK, HEADER_LEN as u8]);
+    response.extend_from_slice(&query.question.record_type.to_be_bytes());
+    response.extend_from_slice(&query.question.class.to_be_bytes());
+    response.extend_from_slice(&ttl.to_be_bytes());
+    response.extend_from_slice(&(address.len() as u16).to_be_bytes());
+    response.extend_from_slice(address);
+    response
+}
+
+/// Returns a standard query for the name, which asks for recursion. Returns `None` if the name
+/// has a label that is too long.
+pub fn query(id: u16, name: &str, record_type: u16) -> Option<Vec<u8>> {
+    let mut message = Vec::new();
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        message.push(
+            u8::try_from(label.len())
+                .ok()
+                .filter(|length| *length < 64)?,
+        );
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&record_type.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Some(message)
+}
+
+fn empty_response(query: &Query<'_>, flags: u16) -> Vec<u8> {
+    let mut response = Vec::with_capacity(HEADER_LEN + query.question_bytes.len());
+    response.extend_from_slice(&query.id.to_be_bytes());
+    let flags = flags | FLAG_RESPONSE | FLAG_RECURSION_DESIRED | FLAG_RECURSION_AVAILABLE;
+    response.extend_from_slice(&flags.to_be_bytes());
+    response.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    response.extend_from_slice(query.question_bytes);
+    response
+}
+
+pub fn set_id(message: &mut [u8], id: u16) {
+    message[0..2].copy_from_slice(&id.to_be_bytes());
+}
+
+pub fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn read_u32(message: &[u8], offset: usize) -> Option<u32> {
+    let bytes = message.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn parse_question(message: &[u8], offset: usize) -> Option<(Question, usize)> {
+    let (name, name_end) = parse_name(message, offset)?;
+    let question = Question {
+        name,
+        record_type: read_u16(message, name_end)?,
+        class: read_u16(message, name_end + 2)?,
+    };
+    Some((question, name_end + 4))
+}
+
+struct RecordHeader {
+    record_type: u16,
+    class: u16,
+    ttl_offset: usize,
+    /// Where the record, including its data, ends.
+    end: usize,
+}
+
+fn parse_record_header(message: &[u8], offset: usize) -> Option<RecordHeader> {
+    let (_, name_end) = parse_name(message, offset)?;
+    let data_length = usize::from(read_u16(message, name_end + 8)?);
+    let end = name_end + 10 + data_length;
+    if end > message.len() {
+        return None;
+    }
+    Some(RecordHeader {
+        record_type: read_u16(message, name_end)?,
+        class: read_u16(message, name_end + 2)?,
+        ttl_offset: name_end + 4,
+        end,
+    })
+}
+
+/// Parses the name at `offset`. Returns the name in lowercase, and where it ends in the message,
+/// which is after the first compression pointer if it has one.
+fn parse_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *message.get(offset)?;
+        if length & POINTER_MASK == POINTER_MASK {
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return None;
+            }
+            let pointer = read_u16(message, offset)? & !(u16::from(POINTER_MASK) << 8);
+            end.get_or_insert(offset + 2);
+            offset = usize::from(pointer);
+        } else if length & POINTER_MASK != 0 {
+            // Extended label types are not in use
+            return None;
+        } else if length == 0 {
+            let end = end.unwrap_or(offset + 1);
+            return Some((labels.join("."), end));
+        } else {
+            let label = message.get(offset + 1..offset + 1 + usize::from(length))?;
+            labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+            offset += 1 + usize::from(length);
+        }
+    }
+}
+
+/// Builders of the messages that clients and servers send.
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::convert::TryFrom;
+
+    pub fn query(id: u16, name: &str) -> Vec<u8> {
+        super::query(id, name, TYPE_A).unwrap()
+    }
+
+    /// Returns a response to `query` with an A record for each TTL. The records refer to the
+    /// question name with a compression pointer.
+    pub fn response(query: &[u8], rcode: u8, ttls: &[u32]) -> Vec<u8> {
+        let question_end = parse_question(query, HEADER_LEN).unwrap().1;
+        let mut message = query[..question_end].to_vec();
+        let flags = FLAG_RESPONSE | FLAG_RECURSION_DESIRED | FLAG_RECURSION_AVAILABLE;
+        message[2..4].copy_from_slice(&(flags | u16::from(rcode)).to_be_bytes());
+        message[6..8].copy_from_slice(&u16::try_from(ttls.len()).unwrap().to_be_bytes());
+        message[10..12].copy_from_slice(&[0, 0]);
+        for (index, ttl) in ttls.iter().enumerate() {
+            message.extend_from_slice(&[POINTER_MASK, HEADER_LEN as u8]);
+            message.extend_from_slice(&TYPE_A.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&ttl.to_be_bytes());
+            message.extend_from_slice(&4u16.to_be_bytes());
+            message.extend_from_slice(&[192, 0, 2, index as u8]);
+        }
+        message
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let message = query(0x1234, "Example.COM");
+        let query = parse_query(&message).unwrap();
+        assert_eq!(query.id, 0x1234);
+        assert_eq!(
+            query.question,
+            Question {
+                name: "example.com".to_owned(),
+                record_type: TYPE_A,
+                class: CLASS_IN,
+            }
+        );
+        assert_eq!(query.question_bytes, &message[HEADER_LEN..]);
+        assert_eq!(query.udp_payload_size, DEFAULT_UDP_PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn test_parse_query_with_edns() {
+        let mut message = query(1, "example.com");
+        message[10..12].copy_from_slice(&1u16.to_be_bytes());
+        // Root name, OPT, 4096 byte payload size, no extended flags and no options
+        message.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(parse_query(&message).unwrap().udp_payload_size, 4096);
+    }
+
+    #[test]
+    fn test_parse_response_with_compressed_names() {
+        let message = response(&query(7, "example.com"), RCODE_NOERROR, &[300, 60]);
+        let response = parse_response(&message).unwrap();
+        assert_eq!(response.id, 7);
+        assert_eq!(response.rcode, RCODE_NOERROR);
+        assert!(!response.truncated);
+        assert_eq!(response.question.as_ref().unwrap().name, "example.com");
+        assert_eq!(response.ttl_offsets.len(), 2);
+        assert_eq!(response.min_ttl(&message), Some(60));
+    }
+
+    #[test]
+    fn test_rejects_pointer_loops() {
+        let mut message = query(1, "example.com");
+        message.truncate(HEADER_LEN);
+        message.extend_from_slice(&[POINTER_MASK, HEADER_LEN as u8, 0, 1, 0, 1]);
+        assert!(parse_query(&message).is_none());
+    }
+
+    #[test]
+    fn test_rejects_truncated_records() {
+        let mut message = response(&query(1, "example.com"), RCODE_NOERROR, &[60]);
+        message.pop();
+        assert!(parse_response(&message).is_none());
+    }
+
+    #[test]
+    fn test_servfail() {
+        let message = query(42, "example.com");
+        let response = servfail(&parse_query(&message).unwrap());
+        let parsed = parse_response(&response).unwrap();
+        assert_eq!(parsed.id, 42);
+        assert_eq!(parsed.rcode, RCODE_SERVFAIL);
+        assert_eq!(parsed.question.unwrap().name, "example.com");
+    }
+
+    #[test]
+    fn test_unspecified_address() {
+        let message = query(8, "ads.example");
+        let response = unspecified_address(&parse_query(&message).unwrap(), 60);
+        let parsed = parse_response(&response).unwrap();
+        assert_eq!(parsed.id, 8);
+        assert_eq!(parsed.rcode, RCODE_NOERROR);
+        assert_eq!(parsed.min_ttl(&response), Some(60));
+        assert_eq!(&response[response.len() - 4..], &[0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,677 @@
+//! A DNS stub resolver that listens on a loopback address and forwards the queries it receives
+//! to the upstream resolvers, such as the DNS server of the relay, caching the responses. Queries
+//! can also be forwarded to a DNS-over-HTTPS or DNS-over-TLS server. Queries for the domains of
+//! split DNS rules are forwarded to the resolvers of the rule instead.
+//!
+//! The system DNS is pointed at the stub with the [`DnsMonitor`](super::DnsMonitor), so that
+//! every query passes through one place no matter which DNS manager the system uses.
+
+pub use self::blocklist::{Blocklist, Blocklists};
+
+use self::cache::Cache;
+use openssl::ssl::{SslConnector, SslMethod};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::dns::{BlockedResponse, EncryptedDnsServer, SplitDnsRule};
+
+pub mod blocklist;
+mod cache;
+pub(super) mod encrypted;
+pub(super) mod message;
+
+/// The address that the stub resolver listens on by default. It is not the usual `127.0.0.1` or
+/// `127.0.0.53` so that it does not collide with other resolvers on the system.
+pub const DEFAULT_LISTEN_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 153)), 53);
+
+/// How many queries over UDP can be resolved at the same time.
+const UDP_WORKERS: usize = 4;
+/// How many responses are cached at most.
+const CACHE_SIZE: usize = 4096;
+/// The TTL of the addresses that blocked names are answered with.
+const BLOCKED_TTL: u32 = 60;
+/// How long to wait for each upstream resolver before trying the next one.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often the UDP workers check whether the resolver has been stopped.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a TCP client can stay idle before the connection is closed.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The largest DNS message, which is limited by the length prefix used over TCP.
+const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// Errors that can happen when starting the stub resolver.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Unable to listen for queries over UDP.
+    #[error(display = "Failed to bind UDP socket for the stub resolver")]
+    BindUdpSocket(#[error(source)] io::Error),
+
+    /// Unable to listen for queries over TCP.
+    #[error(display = "Failed to bind TCP socket for the stub resolver")]
+    BindTcpSocket(#[error(source)] io::Error),
+
+    /// Unable to configure a listening socket.
+    #[error(display = "Failed to configure the stub resolver sockets")]
+    ConfigureSocket(#[error(source)] io::Error),
+
+    /// Unable to start a thread that serves queries.
+    #[error(display = "Failed to spawn a stub resolver thread")]
+    SpawnThread(#[error(source)] io::Error),
+
+    /// Unable to set up the TLS context used for encrypted upstream resolvers.
+    #[error(display = "Failed to initialize TLS for encrypted DNS")]
+    InitTls(#[error(source)] openssl::error::ErrorStack),
+}
+
+/// Where the stub resolver forwards queries to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Upstream {
+    /// Unencrypted resolvers, in order of preference. Queries are forwarded over the transport
+    /// they were received on.
+    Plain(Vec<SocketAddr>),
+    /// A DNS-over-HTTPS or DNS-over-TLS server, whose certificate is verified against the
+    /// certificates trusted by the system.
+    Encrypted(EncryptedDnsServer),
+}
+
+/// How many queries the stub resolver has handled, and how.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Stats {
+    /// Queries received from clients.
+    pub queries: u64,
+    /// Queries answered from the cache.
+    pub cache_hits: u64,
+    /// Queries for names on a blocklist.
+    pub blocked: u64,
+    /// Queries that no upstream resolver answered.
+    pub upstream_failures: u64,
+    /// Responses currently in the cache.
+    pub cached_responses: usize,
+}
+
+/// A running stub resolver. It is stopped when dropped.
+pub struct StubResolver {
+    resolver: Arc<Resolver>,
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl StubResolver {
+    /// Starts a stub resolver that listens on `listen_addr` over both UDP and TCP, and forwards
+    /// queries to the `upstream` resolvers. Queries for names on the `blocklists` are answered
+    /// without being forwarded.
+    pub fn start(
+        listen_addr: SocketAddr,
+        upstream: Upstream,
+        blocklists: Blocklists,
+    ) -> Result<Self, Error> {
+        let tls = SslConnector::builder(SslMethod::tls())
+            .map_err(Error::InitTls)?
+            .build();
+        let udp_socket = UdpSocket::bind(listen_addr).map_err(Error::BindUdpSocket)?;
+        // Listen on the same port over TCP, which is only known here if it was picked by the OS
+        let local_addr = udp_socket.local_addr().map_err(Error::BindUdpSocket)?;
+        let tcp_listener = TcpListener::bind(local_addr).map_err(Error::BindTcpSocket)?;
+        udp_socket
+            .set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))
+            .map_err(Error::ConfigureSocket)?;
+
+        let mut stub = StubResolver {
+            resolver: Arc::new(Resolver::new(upstream, tls, blocklists)),
+            local_addr,
+            stop: Arc::new(AtomicBool::new(false)),
+            threads: Vec::with_capacity(UDP_WORKERS + 1),
+        };
+        for worker in 0..UDP_WORKERS {
+            let socket = udp_socket.try_clone().map_err(Error::ConfigureSocket)?;
+            let resolver = stub.resolver.clone();
+            let stop = stub.stop.clone();
+            stub.spawn(format!("dns-stub-udp-{}", worker), move || {
+                serve_udp(&socket, &resolver, &stop)
+            })?;
+        }
+        let resolver = stub.resolver.clone();
+        let stop = stub.stop.clone();
+        stub.spawn("dns-stub-tcp".to_owned(), move || {
+            serve_tcp(&tcp_listener, &resolver, &stop)
+        })?;
+
+        log::debug!("Started DNS stub resolver on {}", local_addr);
+        Ok(stub)
+    }
+
+    fn spawn(&mut self, name: String, f: impl FnOnce() + Send + 'static) -> Result<(), Error> {
+        let handle = thread::Builder::new()
+            .name(name)
+            .spawn(f)
+            .map_err(Error::SpawnThread)?;
+        self.threads.push(handle);
+        Ok(())
+    }
+
+    /// Returns the address that the resolver listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Changes the resolvers that queries are forwarded to. The cache is cleared if they differ
+    /// from the current ones, since they may not give the same answers.
+    pub fn set_upstream(&self, upstream: Upstream) {
+        let mut current = self.resolver.upstream.write();
+        if *current != upstream {
+            *current = upstream;
+            self.resolver.cache.lock().clear();
+        }
+    }
+
+    /// Changes which domains are forwarded to other resolvers than the upstream ones. The cache
+    /// is cleared if the rules differ from the current ones.
+    pub fn set_split_dns(&self, rules: Vec<SplitDnsRule>) {
+        let mut current = self.resolver.split_dns.write();
+        if *current != rules {
+            *current = rules;
+            self.resolver.cache.lock().clear();
+        }
+    }
+
+    /// Returns how many queries have been handled since the resolver was started.
+    pub fn stats(&self) -> Stats {
+        let counters = &self.resolver.counters;
+        Stats {
+            queries: counters.queries.load(Ordering::Relaxed),
+            cache_hits: counters.cache_hits.load(Ordering::Relaxed),
+            blocked: counters.blocked.load(Ordering::Relaxed),
+            upstream_failures: counters.upstream_failures.load(Ordering::Relaxed),
+            cached_responses: self.resolver.cache.lock().len(),
+        }
+    }
+}
+
+impl Drop for StubResolver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the TCP listener, which can not time out
+        let _ = TcpStream::connect_timeout(&self.local_addr, SHUTDOWN_POLL_INTERVAL);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        log::debug!("Stopped DNS stub resolver: {:?}", self.stats());
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    queries: AtomicU64,
+    cache_hits: AtomicU64,
+    blocked: AtomicU64,
+    upstream_failures: AtomicU64,
+}
+
+/// The state shared by the threads that serve queries.
+struct Resolver {
+    upstream: RwLock<Upstream>,
+    split_dns: RwLock<Vec<SplitDnsRule>>,
+    tls: SslConnector,
+    cache: Mutex<Cache>,
+    blocklists: Blocklists,
+    counters: Counters,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl Resolver {
+    fn new(upstream: Upstream, tls: SslConnector, blocklists: Blocklists) -> Self {
+        Resolver {
+            upstream: RwLock::new(upstream),
+            split_dns: RwLock::new(Vec::new()),
+            tls,
+            cache: Mutex::new(Cache::new(CACHE_SIZE)),
+            blocklists,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Returns the response to a query that a client sent over the given transport, or `None`
+    /// if there is nothing to answer.
+    fn resolve(&self, message: &[u8], transport: Transport) -> Option<Vec<u8>> {
+        self.counters.queries.fetch_add(1, Ordering::Relaxed);
+        let query = message::parse_query(message);
+
+        if let Some(query) = &query {
+            if let Some(blocked_response) = self.blocklists.check(&query.question.name) {
+                self.counters.blocked.fetch_add(1, Ordering::Relaxed);
+                return Some(match blocked_response {
+                    BlockedResponse::Nxdomain => message::nxdomain(query),
+                    BlockedResponse::Unspecified => {
+                        message::unspecified_address(query, BLOCKED_TTL)
+                    }
+                });
+            }
+        }
+
+        let response = match &query {
+            Some(query) => self.cache.lock().get(query, Instant::now()),
+            None => None,
+        };
+        let response = match response {
+            Some(response) => {
+                self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+                response
+            }
+            None => match self.forward(message, query.as_ref(), transport) {
+                Some(response) => {
+                    if let Some(query) = &query {
+                        let parsed = message::parse_response(&response)
+                            .filter(|parsed| parsed.id == query.id);
+                        if let Some(parsed) = parsed {
+                            self.cache.lock().insert(
+                                query.question.clone(),
+                                response.clone(),
+                                parsed,
+                                Instant::now(),
+                            );
+                        }
+                    }
+                    response
+                }
+                None => {
+                    self.counters
+                        .upstream_failures
+                        .fetch_add(1, Ordering::Relaxed);
+                    return query.as_ref().map(message::servfail);
+                }
+            },
+        };
+
+        match &query {
+            Some(query)
+                if transport == Transport::Udp && response.len() > query.udp_payload_size =>
+            {
+                Some(message::truncated(query))
+            }
+            _ => Some(response),
+        }
+    }
+
+    /// Sends the query to each upstream resolver, or each address of the encrypted resolver,
+    /// until one of them responds. Queries for the domain of a split DNS rule are sent to the
+    /// resolvers of the rule.
+    fn forward(
+        &self,
+        message: &[u8],
+        query: Option<&message::Query<'_>>,
+        transport: Transport,
+    ) -> Option<Vec<u8>> {
+        let split_dns_servers = query.and_then(|query| {
+            self.split_dns
+                .read()
+                .iter()
+                .find(|rule| rule.matches(&query.question.name))
+                .map(|rule| rule.servers.clone())
+        });
+        let upstream = match split_dns_servers {
+            Some(servers) => Upstream::Plain(
+                servers
+                    .into_iter()
+                    .map(|server| SocketAddr::new(server, 53))
+                    .collect(),
+            ),
+            None => self.upstream.read().clone(),
+        };
+        match upstream {
+            Upstream::Plain(servers) => {
+                for server in servers {
+                    let result = match transport {
+                        Transport::Udp => forward_udp(message, server),
+                        Transport::Tcp => forward_tcp(message, server),
+                    };
+                    match result {
+                        Ok(response) => return Some(response),
+                        Err(error) => {
+                            log::debug!("No response from DNS server {}: {}", server, error)
+                        }
+                    }
+                }
+            }
+            Upstream::Encrypted(server) => {
+                for address in &server.bootstrap_addresses {
+                    match encrypted::forward(&self.tls, &server, *address, message) {
+                        Ok(response) => return Some(response),
+                        Err(error) => log::debug!(
+                            "No response from DNS server {} at {}: {}",
+                            server.hostname,
+                            address,
+                            error
+                        ),
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+fn serve_udp(socket: &UdpSocket, resolver: &Resolver, stop: &AtomicBool) {
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+    while !stop.load(Ordering::SeqCst) {
+        let (length, client) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(ref error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue;
+            }
+            Err(error) => {
+                log::error!("Failed to receive DNS query: {}", error);
+                continue;
+            }
+        };
+        if let Some(response) = resolver.resolve(&buffer[..length], Transport::Udp) {
+            if let Err(error) = socket.send_to(&response, client) {
+                log::debug!("Failed to send DNS response to {}: {}", client, error);
+            }
+        }
+    }
+}
+
+fn serve_tcp(listener: &TcpListener, resolver: &Arc<Resolver>, stop: &AtomicBool) {
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                log::error!("Failed to accept DNS client: {}", error);
+                continue;
+            }
+        };
+        let resolver = resolver.clone();
+        let result = thread::Builder::new()
+            .name("dns-stub-tcp-client".to_owned())
+            .spawn(move || {
+                if let Err(error) = serve_tcp_client(stream, &resolver) {
+                    log::debug!("DNS client connection failed: {}", error);
+                }
+            });
+        if let Err(error) = result {
+            log::error!("Failed to spawn thread for DNS client: {}", error);
+        }
+    }
+}
+
+fn serve_tcp_client(mut stream: TcpStream, resolver: &Resolver) -> io::Result<()> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    loop {
+        let message = match read_tcp_message(&mut stream) {
+            Ok(message) => message,
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        match resolver.resolve(&message, Transport::Tcp) {
+            Some(response) => write_tcp_message(&mut stream, &response)?,
+            None => return stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+fn forward_udp(message: &[u8], server: SocketAddr) -> io::Result<Vec<u8>> {
+    let unspecified = match server {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    socket.connect(server)?;
+    socket.send(message)?;
+
+    let deadline = Instant::now() + UPSTREAM_TIMEOUT;
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let length = socket.recv(&mut buffer)?;
+        // Ignore stray responses to earlier queries
+        if length >= 2 && message.len() >= 2 && buffer[..2] == message[..2] {
+            buffer.truncate(length);
+            return Ok(buffer);
+        }
+    }
+}
+
+fn forward_tcp(message: &[u8], server: SocketAddr) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&server, UPSTREAM_TIMEOUT)?;
+    stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+    write_tcp_message(&mut stream, message)?;
+    read_tcp_message(&mut stream)
+}
+
+/// Reads a message that is prefixed by its length, as messages are sent over TCP.
+fn read_tcp_message(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length)?;
+    let mut message = vec![0u8; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn write_tcp_message(stream: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    if message.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "DNS message is too large",
+        ));
+    }
+    let mut framed = Vec::with_capacity(2 + message.len());
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        message::{
+            test::{query, response},
+            RCODE_NOERROR, RCODE_NXDOMAIN, RCODE_SERVFAIL,
+        },
+        *,
+    };
+    use std::sync::atomic::AtomicUsize;
+    use talpid_types::dns::BlocklistCategory;
+
+    /// An upstream resolver that answers every query with an A record with the given TTL, and
+    /// counts the queries it gets.
+    struct FakeUpstream {
+        addr: SocketAddr,
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl FakeUpstream {
+        fn start(ttl: u32) -> Self {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = socket.local_addr().unwrap();
+            let queries = Arc::new(AtomicUsize::new(0));
+            let counter = queries.clone();
+            thread::spawn(move || {
+                let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+                while let Ok((length, client)) = socket.recv_from(&mut buffer) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let reply = response(&buffer[..length], RCODE_NOERROR, &[ttl]);
+                    let _ = socket.send_to(&reply, client);
+                }
+            });
+            FakeUpstream { addr, queries }
+        }
+
+        fn queries(&self) -> usize {
+            self.queries.load(Ordering::SeqCst)
+        }
+    }
+
+    fn start_stub(upstream: Vec<SocketAddr>) -> StubResolver {
+        start_stub_with_blocklists(upstream, Blocklists::default())
+    }
+
+    fn start_stub_with_blocklists(
+        upstream: Vec<SocketAddr>,
+        blocklists: Blocklists,
+    ) -> StubResolver {
+        StubResolver::start(
+            "127.0.0.1:0".parse().unwrap(),
+            Upstream::Plain(upstream),
+            blocklists,
+        )
+        .unwrap()
+    }
+
+    fn ask(stub: &StubResolver, message: &[u8]) -> Vec<u8> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        socket.send_to(message, stub.local_addr()).unwrap();
+        let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+        let length = socket.recv(&mut buffer).unwrap();
+        buffer.truncate(length);
+        buffer
+    }
+
+    #[test]
+    fn test_forwards_and_caches() {
+        let upstream = FakeUpstream::start(300);
+        let stub = start_stub(vec![upstream.addr]);
+
+        let first = message::parse_response(&ask(&stub, &query(1, "example.com"))).unwrap();
+        assert_eq!(first.id, 1);
+        assert_eq!(first.rcode, RCODE_NOERROR);
+        let second = message::parse_response(&ask(&stub, &query(2, "example.com"))).unwrap();
+        assert_eq!(second.id, 2);
+
+        assert_eq!(upstream.queries(), 1);
+        assert_eq!(
+            stub.stats(),
+            Stats {
+                queries: 2,
+                cache_hits: 1,
+                blocked: 0,
+                upstream_failures: 0,
+                cached_responses: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_answers_over_tcp() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let message = read_tcp_message(&mut stream).unwrap();
+            let reply = response(&message, RCODE_NOERROR, &[60]);
+            write_tcp_message(&mut stream, &reply).unwrap();
+        });
+        let stub = start_stub(vec![upstream_addr]);
+
+        let mut stream = TcpStream::connect(stub.local_addr()).unwrap();
+        write_tcp_message(&mut stream, &query(3, "example.com")).unwrap();
+        let reply = message::parse_response(&read_tcp_message(&mut stream).unwrap()).unwrap();
+        assert_eq!(reply.id, 3);
+        assert_eq!(reply.ttl_offsets.len(), 1);
+    }
+
+    #[test]
+    fn test_falls_back_to_next_upstream() {
+        // Nothing listens on this address, so the query is refused or times out
+        let unreachable = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let upstream = FakeUpstream::start(300);
+        let stub = start_stub(vec![unreachable, upstream.addr]);
+
+        let reply = message::parse_response(&ask(&stub, &query(4, "example.com"))).unwrap();
+        assert_eq!(reply.rcode, RCODE_NOERROR);
+        assert_eq!(upstream.queries(), 1);
+    }
+
+    #[test]
+    fn test_servfail_without_upstream() {
+        let stub = start_stub(vec![]);
+        let reply = message::parse_response(&ask(&stub, &query(5, "example.com"))).unwrap();
+        assert_eq!(reply.id, 5);
+        assert_eq!(reply.rcode, RCODE_SERVFAIL);
+        assert_eq!(stub.stats().upstream_failures, 1);
+    }
+
+    #[test]
+    fn test_changing_upstream_clears_cache() {
+        let first_upstream = FakeUpstream::start(300);
+        let second_upstream = FakeUpstream::start(300);
+        let stub = start_stub(vec![first_upstream.addr]);
+
+        ask(&stub, &query(6, "example.com"));
+        stub.set_upstream(Upstream::Plain(vec![second_upstream.addr]));
+        ask(&stub, &query(7, "example.com"));
+
+        assert_eq!(first_upstream.queries(), 1);
+        assert_eq!(second_upstream.queries(), 1);
+    }
+
+    #[test]
+    fn test_answers_blocked_names_without_forwarding() {
+        let upstream = FakeUpstream::start(300);
+        let blocklists = Blocklists::new(
+            vec![Blocklist::parse(
+                BlocklistCategory::Ads,
+                "0.0.0.0 ads.example",
+            )],
+            BlockedResponse::Nxdomain,
+        );
+        let stub = start_stub_with_blocklists(vec![upstream.addr], blocklists.clone());
+
+        let reply = message::parse_response(&ask(&stub, &query(9, "banner.ads.example"))).unwrap();
+        assert_eq!(reply.id, 9);
+        assert_eq!(reply.rcode, RCODE_NXDOMAIN);
+        assert_eq!(upstream.queries(), 0);
+        assert_eq!(stub.stats().blocked, 1);
+        assert_eq!(blocklists.stats()[0].blocked_queries, 1);
+    }
+
+    #[test]
+    fn test_forwards_split_dns_domains_elsewhere() {
+        let upstream = FakeUpstream::start(300);
+        let stub = start_stub(vec![upstream.addr]);
+        stub.set_split_dns(vec![SplitDnsRule {
+            domain: "corp.example".to_owned(),
+            servers: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        }]);
+
+        ask(&stub, &query(10, "intranet.corp.example"));
+        ask(&stub, &query(11, "corp.example"));
+        assert_eq!(upstream.queries(), 0);
+
+        ask(&stub, &query(12, "notcorp.example"));
+        assert_eq!(upstream.queries(), 1);
+    }
+}
@@ -2,6 +2,7 @@ use crate::logging::windows::{log_sink, LogSink};
 
 use log::{error, trace};
 use std::{net::IpAddr, path::Path};
+use talpid_types::dns::SplitDnsRule;
 use widestring::WideCString;
 
 mod system_state;
@@ -44,7 +45,12 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(DnsMonitor {})
     }
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        _split_dns: &[SplitDnsRule],
+    ) -> Result<(), Error> {
         let ipv4 = servers
             .iter()
             .filter(|ip| ip.is_ipv4())
@@ -80,6 +86,10 @@ impl super::DnsMonitorT for DnsMonitor {
         }
     }
 
+    fn supports_split_dns(&self) -> bool {
+        false
+    }
+
     fn reset(&mut self) -> Result<(), Error> {
         Ok(())
     }
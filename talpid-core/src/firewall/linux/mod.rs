@@ -0,0 +1,507 @@
+use self::ruleset::{Chain, Direction, End, Match, Ruleset, Statement};
+use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use ipnetwork::IpNetwork;
+use lazy_static::lazy_static;
+use libc;
+use nftnl::{
+    self,
+    expr::{Payload, Verdict},
+    nft_expr, table, Batch, FinalizedBatch, ProtoFamily, Rule, Table,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{CStr, CString},
+    io,
+    net::IpAddr,
+};
+use talpid_types::{firewall::RuleStats, net::TransportProtocol};
+
+mod netlink;
+mod policy;
+mod ruleset;
+mod stats;
+mod watchdog;
+
+pub use self::watchdog::spawn_watchdog;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can happen when interacting with Linux netfilter.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Unable to open netlink socket to netfilter.
+    #[error(display = "Unable to open netlink socket to netfilter")]
+    NetlinkOpenError(#[error(source)] io::Error),
+
+    /// Unable to subscribe to netfilter ruleset changes.
+    #[error(display = "Unable to subscribe to netfilter ruleset changes")]
+    NetlinkSubscribeError(#[error(source)] io::Error),
+
+    /// Unable to send netlink command to netfilter.
+    #[error(display = "Unable to send netlink command to netfilter")]
+    NetlinkSendError(#[error(source)] io::Error),
+
+    /// Error while reading from netlink socket.
+    #[error(display = "Error while reading from netlink socket")]
+    NetlinkRecvError(#[error(source)] io::Error),
+
+    /// Error while processing an incoming netlink message.
+    #[error(display = "Error while processing an incoming netlink message")]
+    ProcessNetlinkError(#[error(source)] io::Error),
+
+    /// Failed to verify that our tables are set. Probably means that
+    /// it's the host that does not support nftables properly.
+    #[error(display = "Failed to set firewall rules")]
+    NetfilterTableNotSetError,
+
+    /// Unable to translate network interface name into index.
+    #[error(
+        display = "Unable to translate network interface name \"{}\" into index",
+        _0
+    )]
+    LookupIfaceIndexError(String, #[error(source)] crate::linux::IfaceIndexLookupError),
+
+    /// The name of a network interface contains a nul byte.
+    #[error(display = "Invalid network interface name \"{}\"", _0)]
+    InvalidIfaceName(String),
+
+    /// The rules in netfilter are not the ones that were applied.
+    #[error(
+        display = "The rules in the {} chain differ from the applied policy",
+        _0
+    )]
+    RulesetMismatch(&'static str),
+}
+
+lazy_static! {
+    /// TODO(linus): This crate is not supposed to be Mullvad-aware. So at some point this should be
+    /// replaced by allowing the table name to be configured from the public API of this crate.
+    static ref TABLE_NAME: CString = CString::new("mullvad").unwrap();
+
+    /// The nflog group that dropped packets are logged to, if set. Useful for seeing what is
+    /// blocked, with for example `tcpdump -i nflog:<group>`.
+    static ref LOG_GROUP: Option<u16> = env::var("TALPID_FIREWALL_LOG_GROUP")
+        .ok()
+        .and_then(|group| group.parse().ok());
+
+    /// The ruleset that was last applied, used to tell which rule each counter belongs to.
+    static ref APPLIED_RULESET: Mutex<Option<Ruleset>> = Mutex::new(None);
+}
+
+/// The Linux implementation for the firewall and DNS.
+pub struct Firewall {
+    table_name: CString,
+}
+
+impl FirewallT for Firewall {
+    type Error = Error;
+
+    fn new(_args: FirewallArguments) -> Result<Self> {
+        Ok(Firewall {
+            table_name: TABLE_NAME.clone(),
+        })
+    }
+
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        // Counted, so that the counters can be read with `rule_stats`
+        let ruleset = policy::policy_ruleset(
+            &self.table_name.to_string_lossy(),
+            &policy,
+            true,
+            *LOG_GROUP,
+        );
+        let table = Table::new(&self.table_name, ProtoFamily::Inet);
+        let batch = ruleset_batch(&table, &ruleset)?;
+        {
+            // Held while the rules are replaced, so that the watchdog does not compare the new
+            // table against the previous ruleset.
+            let mut applied_ruleset = APPLIED_RULESET.lock();
+            self.send_and_process(&batch)?;
+            *applied_ruleset = Some(ruleset);
+        }
+        self.verify_tables(&[&TABLE_NAME])
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        let table = Table::new(&self.table_name, ProtoFamily::Inet);
+        let batch = {
+            let mut batch = Batch::new();
+            // Our batch will add and remove the table even though the goal is just to remove it.
+            // This because only removing it throws a strange error if the table does not exist.
+            batch.add(&table, nftnl::MsgType::Add);
+            batch.add(&table, nftnl::MsgType::Del);
+            batch.finalize()
+        };
+
+        log::debug!("Removing table and chain from netfilter");
+        let mut applied_ruleset = APPLIED_RULESET.lock();
+        *applied_ruleset = None;
+        self.send_and_process(&batch)
+    }
+}
+
+impl Firewall {
+    fn send_and_process(&self, batch: &FinalizedBatch) -> Result<()> {
+        let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
+        socket.send_all(batch).map_err(Error::NetlinkSendError)?;
+
+        let portid = socket.portid();
+        let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+
+        let seq = 0;
+        while let Some(message) = Self::socket_recv(&socket, &mut buffer[..])? {
+            match mnl::cb_run(message, seq, portid).map_err(Error::ProcessNetlinkError)? {
+                mnl::CbResult::Stop => {
+                    log::trace!("cb_run STOP");
+                    break;
+                }
+                mnl::CbResult::Ok => log::trace!("cb_run OK"),
+            };
+        }
+        Ok(())
+    }
+
+    fn verify_tables(&self, expected_tables: &[&CStr]) -> Result<()> {
+        let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
+        let portid = socket.portid();
+        let seq = 0;
+
+        let get_tables_msg = table::get_tables_nlmsg(seq);
+        socket
+            .send(&get_tables_msg)
+            .map_err(Error::NetlinkSendError)?;
+
+        let mut table_set = std::collections::HashSet::new();
+        let mut msg_buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+
+        while let Some(message) = Self::socket_recv(&socket, &mut msg_buffer)? {
+            match mnl::cb_run2(message, seq, portid, table::get_tables_cb, &mut table_set)
+                .map_err(Error::ProcessNetlinkError)?
+            {
+                mnl::CbResult::Stop => {
+                    log::trace!("cb_run STOP");
+                    break;
+                }
+                mnl::CbResult::Ok => log::trace!("cb_run OK"),
+            }
+        }
+
+        for expected_table in expected_tables {
+            if !table_set.contains(*expected_table) {
+                log::error!(
+                    "Expected '{}' netfilter table to be set, but it is not",
+                    expected_table.to_string_lossy()
+                );
+                return Err(Error::NetfilterTableNotSetError);
+            }
+        }
+        Ok(())
+    }
+
+    fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>> {
+        let ret = socket.recv(buf).map_err(Error::NetlinkRecvError)?;
+        log::trace!("Read {} bytes from netlink", ret);
+        if ret > 0 {
+            Ok(Some(&buf[..ret]))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Renders the rules that the given policy results in as an `nft -f` script, without applying
+/// them.
+pub fn render_policy(policy: &FirewallPolicy) -> String {
+    policy::policy_ruleset(&TABLE_NAME.to_string_lossy(), policy, true, *LOG_GROUP).to_string()
+}
+
+/// Renders the rules that keep traffic blocked before the daemon has started as an `nft -f`
+/// script.
+pub fn render_boot_ruleset() -> String {
+    policy::boot_ruleset(&TABLE_NAME.to_string_lossy()).to_string()
+}
+
+/// Returns how many packets and bytes each rule of the applied policy has accepted or dropped.
+/// Returns nothing if no policy is applied.
+pub fn rule_stats() -> Result<Vec<RuleStats>> {
+    let applied_ruleset = APPLIED_RULESET.lock().clone();
+    match applied_ruleset {
+        Some(ruleset) => stats::rule_stats(&TABLE_NAME, &ruleset),
+        None => Ok(vec![]),
+    }
+}
+
+/// Returns whether the table in netfilter still holds the rules of the applied policy. Always
+/// true if no policy is applied.
+fn applied_ruleset_intact() -> Result<bool> {
+    match &*APPLIED_RULESET.lock() {
+        Some(ruleset) => stats::table_matches(&TABLE_NAME, ruleset),
+        None => Ok(true),
+    }
+}
+
+/// Creates a message batch that replaces the table with one holding the chains and rules of the
+/// ruleset.
+fn ruleset_batch(table: &Table, ruleset: &Ruleset) -> Result<FinalizedBatch> {
+    let mut batch = Batch::new();
+    // A little dance that will make sure the table exists, but is cleared.
+    batch.add(table, nftnl::MsgType::Add);
+    batch.add(table, nftnl::MsgType::Del);
+    batch.add(table, nftnl::MsgType::Add);
+
+    let chain_names = ruleset
+        .chains()
+        .map(|chain| (chain, CString::new(chain.name()).unwrap()))
+        .collect::<HashMap<_, _>>();
+    let chains = ruleset
+        .chains()
+        .map(|chain| (chain, nftnl_chain(chain, &chain_names[&chain], table)))
+        .collect::<HashMap<_, _>>();
+    for chain in ruleset.chains() {
+        batch.add(&chains[&chain], nftnl::MsgType::Add);
+    }
+
+    for rule in &ruleset.rules {
+        let mut nftnl_rule = Rule::new(&chains[&rule.chain]);
+        for rule_match in &rule.matches {
+            add_match(&mut nftnl_rule, rule_match)?;
+        }
+        for statement in &rule.statements {
+            add_statement(&mut nftnl_rule, statement);
+        }
+        batch.add(&nftnl_rule, nftnl::MsgType::Add);
+    }
+    Ok(batch.finalize())
+}
+
+fn nftnl_chain<'a>(chain: Chain, name: &CString, table: &'a Table) -> nftnl::Chain<'a> {
+    let mut nftnl_chain = nftnl::Chain::new(name, table);
+    nftnl_chain.set_type(match chain {
+        Chain::Out | Chain::In | Chain::Forward | Chain::Prerouting => nftnl::ChainType::Filter,
+        Chain::Mangle => nftnl::ChainType::Route,
+        Chain::Nat => nftnl::ChainType::Nat,
+    });
+    let hook = match chain {
+        Chain::Out | Chain::Mangle => nftnl::Hook::Out,
+        Chain::In => nftnl::Hook::In,
+        Chain::Forward => nftnl::Hook::Forward,
+        Chain::Prerouting => nftnl::Hook::PreRouting,
+        Chain::Nat => nftnl::Hook::PostRouting,
+    };
+    nftnl_chain.set_hook(hook, chain.priority());
+    nftnl_chain.set_policy(if chain.drops_by_default() {
+        nftnl::Policy::Drop
+    } else {
+        nftnl::Policy::Accept
+    });
+    nftnl_chain
+}
+
+fn add_match(rule: &mut Rule<'_>, rule_match: &Match) -> Result<()> {
+    match rule_match {
+        Match::Iface {
+            direction,
+            name,
+            negate,
+        } => check_iface(rule, *direction, name, *negate)?,
+        Match::IfaceName { direction, name } => check_iface_name(rule, *direction, name)?,
+        Match::Ip(end, ip) => check_ip(rule, *end, *ip),
+        Match::Net(end, net) => check_net(rule, *end, *net),
+        Match::Port(protocol, end, port) => check_port(rule, *protocol, *end, *port),
+        Match::PortRange(protocol, end, start, last) => {
+            check_port_range(rule, *protocol, *end, *start, *last)
+        }
+        Match::L4Proto(protocol) => {
+            rule.add_expr(&nft_expr!(meta l4proto));
+            rule.add_expr(&nft_expr!(cmp == *protocol));
+        }
+        Match::Icmpv6 { icmp_type, code } => {
+            rule.add_expr(&Payload::Transport(
+                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
+            ));
+            rule.add_expr(&nft_expr!(cmp == *icmp_type));
+            rule.add_expr(&Payload::Transport(
+                nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
+            ));
+            rule.add_expr(&nft_expr!(cmp == *code));
+        }
+        Match::Cgroup(class_id) => {
+            rule.add_expr(&nft_expr!(meta cgroup));
+            rule.add_expr(&nft_expr!(cmp == *class_id));
+        }
+        Match::MetaMark(mark) => {
+            rule.add_expr(&nft_expr!(meta mark));
+            rule.add_expr(&nft_expr!(cmp == *mark));
+        }
+        Match::CtMark(mark) => {
+            rule.add_expr(&nft_expr!(ct mark));
+            rule.add_expr(&nft_expr!(cmp == *mark));
+        }
+        Match::CtStateEstablished => check_ct_state(rule, nftnl::expr::ct::States::ESTABLISHED),
+        Match::CtStateEstablishedOrRelated => check_ct_state(
+            rule,
+            nftnl::expr::ct::States::ESTABLISHED | nftnl::expr::ct::States::RELATED,
+        ),
+    }
+    Ok(())
+}
+
+fn add_statement(rule: &mut Rule<'_>, statement: &Statement) {
+    match statement {
+        Statement::Counter => rule.add_expr(&nft_expr!(counter)),
+        Statement::Log(group) => rule.add_expr(&LogGroup(*group)),
+        Statement::SetCtMark(mark) => {
+            rule.add_expr(&nft_expr!(immediate data *mark));
+            rule.add_expr(&nft_expr!(ct mark set));
+        }
+        Statement::SetMetaMark(mark) => {
+            rule.add_expr(&nft_expr!(immediate data *mark));
+            rule.add_expr(&nft_expr!(meta mark set));
+        }
+        Statement::Masquerade => rule.add_expr(&nft_expr!(masquerade)),
+        Statement::Accept => rule.add_expr(&Verdict::Accept),
+        Statement::Drop => rule.add_expr(&Verdict::Drop),
+    }
+}
+
+/// The `log` expression, which nftnl does not provide. Sends the packet to an nflog group.
+struct LogGroup(u16);
+
+impl nftnl::expr::Expression for LogGroup {
+    fn to_expr(&self, _rule: &Rule<'_>) -> *mut nftnl::nftnl_sys::nftnl_expr {
+        use nftnl::nftnl_sys as sys;
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(b"log\0" as *const _ as *const libc::c_char);
+            if expr.is_null() {
+                panic!("Failed to allocate log expression");
+            }
+            sys::nftnl_expr_set_u16(expr, sys::NFTNL_EXPR_LOG_GROUP as u16, self.0);
+            expr
+        }
+    }
+}
+
+fn check_iface(rule: &mut Rule<'_>, direction: Direction, iface: &str, negate: bool) -> Result<()> {
+    let iface_index = crate::linux::iface_index(iface)
+        .map_err(|e| Error::LookupIfaceIndexError(iface.to_owned(), e))?;
+    rule.add_expr(&match direction {
+        Direction::In => nft_expr!(meta iif),
+        Direction::Out => nft_expr!(meta oif),
+    });
+    if negate {
+        rule.add_expr(&nft_expr!(cmp != iface_index));
+    } else {
+        rule.add_expr(&nft_expr!(cmp == iface_index));
+    }
+    Ok(())
+}
+
+fn check_iface_name(rule: &mut Rule<'_>, direction: Direction, iface: &str) -> Result<()> {
+    let iface_name = CString::new(iface).map_err(|_| Error::InvalidIfaceName(iface.to_owned()))?;
+    rule.add_expr(&match direction {
+        Direction::In => nft_expr!(meta iifname),
+        Direction::Out => nft_expr!(meta oifname),
+    });
+    rule.add_expr(&nft_expr!(cmp == iface_name.as_c_str()));
+    Ok(())
+}
+
+fn check_net(rule: &mut Rule<'_>, end: End, net: impl Into<IpNetwork>) {
+    let net = net.into();
+    // Must check network layer protocol before loading network layer payload
+    check_l3proto(rule, net.ip());
+
+    rule.add_expr(&match (net, end) {
+        (IpNetwork::V4(_), End::Src) => nft_expr!(payload ipv4 saddr),
+        (IpNetwork::V4(_), End::Dst) => nft_expr!(payload ipv4 daddr),
+        (IpNetwork::V6(_), End::Src) => nft_expr!(payload ipv6 saddr),
+        (IpNetwork::V6(_), End::Dst) => nft_expr!(payload ipv6 daddr),
+    });
+    match net {
+        IpNetwork::V4(_) => rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor 0u32)),
+        IpNetwork::V6(_) => rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor &[0u16; 8][..])),
+    };
+    rule.add_expr(&nft_expr!(cmp == net.ip()));
+}
+
+fn check_ip(rule: &mut Rule<'_>, end: End, ip: impl Into<IpAddr>) {
+    let ip = ip.into();
+    // Must check network layer protocol before loading network layer payload
+    check_l3proto(rule, ip);
+
+    rule.add_expr(&match (ip, end) {
+        (IpAddr::V4(..), End::Src) => nft_expr!(payload ipv4 saddr),
+        (IpAddr::V4(..), End::Dst) => nft_expr!(payload ipv4 daddr),
+        (IpAddr::V6(..), End::Src) => nft_expr!(payload ipv6 saddr),
+        (IpAddr::V6(..), End::Dst) => nft_expr!(payload ipv6 daddr),
+    });
+    match ip {
+        IpAddr::V4(addr) => rule.add_expr(&nft_expr!(cmp == addr)),
+        IpAddr::V6(addr) => rule.add_expr(&nft_expr!(cmp == addr)),
+    }
+}
+
+fn check_port(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, port: u16) {
+    load_port(rule, protocol, end);
+    rule.add_expr(&nft_expr!(cmp == port.to_be()));
+}
+
+/// Ports are compared in network byte order, which orders them correctly since the kernel
+/// compares the bytes in memory order.
+fn check_port_range(
+    rule: &mut Rule<'_>,
+    protocol: TransportProtocol,
+    end: End,
+    start: u16,
+    last: u16,
+) {
+    load_port(rule, protocol, end);
+    rule.add_expr(&nft_expr!(cmp >= start.to_be()));
+    rule.add_expr(&nft_expr!(cmp <= last.to_be()));
+}
+
+fn load_port(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End) {
+    // Must check transport layer protocol before loading transport layer payload
+    check_l4proto(rule, protocol);
+
+    rule.add_expr(&match (protocol, end) {
+        (TransportProtocol::Udp, End::Src) => nft_expr!(payload udp sport),
+        (TransportProtocol::Udp, End::Dst) => nft_expr!(payload udp dport),
+        (TransportProtocol::Tcp, End::Src) => nft_expr!(payload tcp sport),
+        (TransportProtocol::Tcp, End::Dst) => nft_expr!(payload tcp dport),
+    });
+}
+
+fn check_ct_state(rule: &mut Rule<'_>, allowed_states: nftnl::expr::ct::States) {
+    rule.add_expr(&nft_expr!(ct state));
+    let allowed_states = allowed_states.bits();
+    rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32));
+    rule.add_expr(&nft_expr!(cmp != 0u32));
+}
+
+fn check_l3proto(rule: &mut Rule<'_>, ip: IpAddr) {
+    rule.add_expr(&nft_expr!(meta nfproto));
+    rule.add_expr(&nft_expr!(cmp == l3proto(ip)));
+}
+
+fn l3proto(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::NFPROTO_IPV4 as u8,
+        IpAddr::V6(_) => libc::NFPROTO_IPV6 as u8,
+    }
+}
+
+fn check_l4proto(rule: &mut Rule<'_>, protocol: TransportProtocol) {
+    rule.add_expr(&nft_expr!(meta l4proto));
+    rule.add_expr(&nft_expr!(cmp == l4proto(protocol)));
+}
+
+fn l4proto(protocol: TransportProtocol) -> u8 {
+    match protocol {
+        TransportProtocol::Udp => libc::IPPROTO_UDP as u8,
+        TransportProtocol::Tcp => libc::IPPROTO_TCP as u8,
+    }
+}
@@ -0,0 +1,91 @@
+//! Helpers for reading nftables netlink messages that nftnl does not parse.
+
+use std::ffi::CStr;
+
+pub const NFNL_SUBSYS_NFTABLES: u16 = 10;
+pub const NFPROTO_INET: u8 = 1;
+pub const NLA_TYPE_MASK: u16 = 0x3fff;
+
+pub const NLMSG_HEADER_LEN: usize = 16;
+pub const NFGENMSG_LEN: usize = 4;
+pub const NLA_HEADER_LEN: usize = 4;
+
+/// Iterates over the type and value of each attribute in a netlink attribute stream. Stops at the
+/// first malformed attribute.
+pub struct Attributes<'a>(&'a [u8]);
+
+impl<'a> Attributes<'a> {
+    pub fn new(attributes: &'a [u8]) -> Self {
+        Attributes(attributes)
+    }
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.len() < NLA_HEADER_LEN {
+            return None;
+        }
+        let length = read_u16(&self.0[0..2]) as usize;
+        if length < NLA_HEADER_LEN || length > self.0.len() {
+            self.0 = &[];
+            return None;
+        }
+        let attribute_type = read_u16(&self.0[2..4]) & NLA_TYPE_MASK;
+        let value = &self.0[NLA_HEADER_LEN..length];
+        self.0 = &self.0[align(length).min(self.0.len())..];
+        Some((attribute_type, value))
+    }
+}
+
+pub fn find_attribute(attributes: &[u8], attribute_type: u16) -> Option<&[u8]> {
+    Attributes::new(attributes)
+        .find(|(found_type, _)| *found_type == attribute_type)
+        .map(|(_, value)| value)
+}
+
+pub fn parse_string(value: &[u8]) -> Option<&str> {
+    CStr::from_bytes_with_nul(value)
+        .ok()
+        .and_then(|value| value.to_str().ok())
+}
+
+pub fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+pub fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_ne_bytes([bytes[0], bytes[1]])
+}
+
+pub fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Builders for the messages that the kernel sends, used to test the parsers.
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub fn attribute(attribute_type: u16, value: &[u8]) -> Vec<u8> {
+        let mut attribute = Vec::new();
+        attribute.extend_from_slice(&((NLA_HEADER_LEN + value.len()) as u16).to_ne_bytes());
+        attribute.extend_from_slice(&attribute_type.to_ne_bytes());
+        attribute.extend_from_slice(value);
+        attribute.resize(align(attribute.len()), 0);
+        attribute
+    }
+
+    pub fn message(message: u16, family: u8, attributes: &[Vec<u8>]) -> Vec<u8> {
+        let payload = attributes.concat();
+        let length = NLMSG_HEADER_LEN + NFGENMSG_LEN + payload.len();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(length as u32).to_ne_bytes());
+        bytes.extend_from_slice(&((NFNL_SUBSYS_NFTABLES << 8) | message).to_ne_bytes());
+        bytes.extend_from_slice(&[0; 10]);
+        bytes.extend_from_slice(&[family, 0, 0, 0]);
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+}
@@ -0,0 +1,1058 @@
+use super::ruleset::{Chain, Direction, End, Match, Rule, Ruleset, Statement};
+use crate::{
+    firewall::{
+        allowed_lan_nets, FirewallPolicy, ALLOWED_LAN_MULTICAST_NETS, DHCPV4_CLIENT_PORT,
+        DHCPV4_SERVER_PORT, DHCPV6_CLIENT_PORT, DHCPV6_SERVER_ADDRS, DHCPV6_SERVER_PORT,
+        IPV6_LINK_LOCAL, ROUTER_SOLICITATION_OUT_DST_ADDR,
+    },
+    routing::TUNNEL_FWMARK,
+    split_tunnel, tunnel,
+};
+use ipnetwork::IpNetwork;
+use std::net::{IpAddr, Ipv4Addr};
+use talpid_types::{
+    dns::EncryptedDnsProtocol,
+    net::{AllowedEndpoint, Endpoint, ForwardedPort, LanNetworks, TransportProtocol},
+};
+
+const LOOPBACK_IFACE_NAME: &str = "lo";
+const DNS_PORT: u16 = 53;
+
+/// Returns every firewall rule needed to satisfy the given policy.
+pub fn policy_ruleset(
+    table_name: &str,
+    policy: &FirewallPolicy,
+    add_counters: bool,
+    log_group: Option<u16>,
+) -> Ruleset {
+    let mut builder = RulesetBuilder {
+        rules: Vec::new(),
+        add_counters,
+        log_group,
+    };
+    builder.add_loopback_rules();
+    builder.add_dhcp_client_rules();
+    builder.add_policy_specific_rules(policy);
+    builder.add_final_drop_rules();
+
+    Ruleset {
+        table_name: table_name.to_owned(),
+        rules: builder.rules,
+    }
+}
+
+/// Returns the rules that keep traffic blocked before the daemon has started. Only loopback and
+/// DHCP traffic is allowed, and nothing is counted or logged, since nothing reads it at boot.
+pub fn boot_ruleset(table_name: &str) -> Ruleset {
+    let mut builder = RulesetBuilder {
+        rules: Vec::new(),
+        add_counters: false,
+        log_group: None,
+    };
+    builder.add_loopback_rules();
+    builder.add_dhcp_client_rules();
+
+    Ruleset {
+        table_name: table_name.to_owned(),
+        rules: builder.rules,
+    }
+}
+
+struct RulesetBuilder {
+    rules: Vec<Rule>,
+    /// Whether rules that accept or drop packets should count them.
+    add_counters: bool,
+    /// The nflog group that dropped packets are logged to, if any.
+    log_group: Option<u16>,
+}
+
+impl RulesetBuilder {
+    fn add(&mut self, chain: Chain, matches: Vec<Match>, statements: Vec<Statement>) {
+        self.rules.push(Rule {
+            chain,
+            matches,
+            statements,
+        });
+    }
+
+    fn add_verdict_rule(&mut self, chain: Chain, matches: Vec<Match>, verdict: Statement) {
+        let mut statements = Vec::new();
+        if self.add_counters {
+            statements.push(Statement::Counter);
+        }
+        if let (Statement::Drop, Some(group)) = (&verdict, self.log_group) {
+            statements.push(Statement::Log(group));
+        }
+        statements.push(verdict);
+        self.add(chain, matches, statements);
+    }
+
+    /// Ends the chains that drop by default with a rule that drops everything, so that packets
+    /// that no other rule matched are counted and logged too. The chain policy already drops
+    /// them, so the rules are only needed for that.
+    fn add_final_drop_rules(&mut self) {
+        if !self.add_counters && self.log_group.is_none() {
+            return;
+        }
+        for chain in Chain::ALL.iter().filter(|chain| chain.drops_by_default()) {
+            self.add_verdict_rule(*chain, vec![], Statement::Drop);
+        }
+    }
+
+    fn add_loopback_rules(&mut self) {
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![iface(Direction::Out, LOOPBACK_IFACE_NAME)],
+            Statement::Accept,
+        );
+        self.add_verdict_rule(
+            Chain::In,
+            vec![iface(Direction::In, LOOPBACK_IFACE_NAME)],
+            Statement::Accept,
+        );
+    }
+
+    /// Marks traffic from the split tunnel cgroup, and replies to it, so that it can be routed
+    /// outside the tunnel. The source address of such traffic may still be the tunnel IP, since
+    /// the route was picked before the packet was marked, so it is translated when leaving.
+    fn add_split_tunnel_mark_rules(&mut self) {
+        self.add(
+            Chain::Mangle,
+            vec![Match::Cgroup(split_tunnel::NET_CLS_CLASSID)],
+            vec![
+                Statement::SetCtMark(split_tunnel::MARK),
+                Statement::SetMetaMark(split_tunnel::MARK),
+            ],
+        );
+        self.add(
+            Chain::Prerouting,
+            vec![Match::CtMark(split_tunnel::MARK)],
+            vec![Statement::SetMetaMark(split_tunnel::MARK)],
+        );
+        self.add(
+            Chain::Nat,
+            vec![
+                Match::CtMark(split_tunnel::MARK),
+                not_iface(Direction::Out, LOOPBACK_IFACE_NAME),
+            ],
+            vec![Statement::Masquerade],
+        );
+    }
+
+    /// Lets excluded traffic pass. The routing rules make sure that it never enters the tunnel,
+    /// but should it be routed there anyway, it is dropped rather than sent through the tunnel.
+    fn add_allow_split_tunnel_rules(&mut self, tunnel_interface: Option<&str>) {
+        if let Some(interface) = tunnel_interface {
+            self.add_verdict_rule(
+                Chain::Out,
+                vec![
+                    Match::MetaMark(split_tunnel::MARK),
+                    iface(Direction::Out, interface),
+                ],
+                Statement::Drop,
+            );
+            self.add_verdict_rule(
+                Chain::In,
+                vec![
+                    Match::CtMark(split_tunnel::MARK),
+                    iface(Direction::In, interface),
+                ],
+                Statement::Drop,
+            );
+        }
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![Match::MetaMark(split_tunnel::MARK)],
+            Statement::Accept,
+        );
+        self.add_verdict_rule(
+            Chain::In,
+            vec![Match::CtMark(split_tunnel::MARK)],
+            Statement::Accept,
+        );
+    }
+
+    fn add_dhcp_client_rules(&mut self) {
+        use self::TransportProtocol::Udp;
+        // Outgoing DHCPv4 request
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![
+                Match::Port(Udp, End::Src, DHCPV4_CLIENT_PORT),
+                Match::Ip(End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST)),
+                Match::Port(Udp, End::Dst, DHCPV4_SERVER_PORT),
+            ],
+            Statement::Accept,
+        );
+        // Incoming DHCPv4 response
+        self.add_verdict_rule(
+            Chain::In,
+            vec![
+                Match::Port(Udp, End::Src, DHCPV4_SERVER_PORT),
+                Match::Port(Udp, End::Dst, DHCPV4_CLIENT_PORT),
+            ],
+            Statement::Accept,
+        );
+
+        for dhcpv6_server in &*DHCPV6_SERVER_ADDRS {
+            self.add_verdict_rule(
+                Chain::Out,
+                vec![
+                    Match::Net(End::Src, (*IPV6_LINK_LOCAL).into()),
+                    Match::Port(Udp, End::Src, DHCPV6_CLIENT_PORT),
+                    Match::Ip(End::Dst, (*dhcpv6_server).into()),
+                    Match::Port(Udp, End::Dst, DHCPV6_SERVER_PORT),
+                ],
+                Statement::Accept,
+            );
+        }
+        self.add_verdict_rule(
+            Chain::In,
+            vec![
+                Match::Net(End::Src, (*IPV6_LINK_LOCAL).into()),
+                Match::Port(Udp, End::Src, DHCPV6_SERVER_PORT),
+                Match::Net(End::Dst, (*IPV6_LINK_LOCAL).into()),
+                Match::Port(Udp, End::Dst, DHCPV6_CLIENT_PORT),
+            ],
+            Statement::Accept,
+        );
+        // Outgoing Router solicitation (part of NDP)
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![
+                Match::Ip(End::Dst, (*ROUTER_SOLICITATION_OUT_DST_ADDR).into()),
+                Match::L4Proto(libc::IPPROTO_ICMPV6 as u8),
+                Match::Icmpv6 {
+                    icmp_type: 133,
+                    code: 0,
+                },
+            ],
+            Statement::Accept,
+        );
+        // Incoming Router advertisement (part of NDP)
+        self.add_verdict_rule(
+            Chain::In,
+            vec![
+                Match::Net(End::Src, (*IPV6_LINK_LOCAL).into()),
+                Match::L4Proto(libc::IPPROTO_ICMPV6 as u8),
+                Match::Icmpv6 {
+                    icmp_type: 134,
+                    code: 0,
+                },
+            ],
+            Statement::Accept,
+        );
+        // Incoming Redirect (part of NDP)
+        self.add_verdict_rule(
+            Chain::In,
+            vec![
+                Match::Net(End::Src, (*IPV6_LINK_LOCAL).into()),
+                Match::L4Proto(libc::IPPROTO_ICMPV6 as u8),
+                Match::Icmpv6 {
+                    icmp_type: 137,
+                    code: 0,
+                },
+            ],
+            Statement::Accept,
+        );
+    }
+
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
+        let (allow_lan, lan_networks) = match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                pingable_hosts,
+                allow_lan,
+                lan_networks,
+                excluded_subnets,
+                split_tunnel,
+                allowed_endpoints,
+            } => {
+                if *split_tunnel {
+                    self.add_split_tunnel_mark_rules();
+                    self.add_allow_split_tunnel_rules(None);
+                }
+                self.add_allow_icmp_pingable_hosts(&pingable_hosts);
+                self.add_allow_endpoint_rules(peer_endpoint);
+                self.add_allowed_endpoints_rules(allowed_endpoints);
+                // Important to block DNS after allow relay rule (so the relay can operate
+                // over port 53) but before allow LAN (so DNS does not leak to the LAN)
+                self.add_drop_dns_rule();
+                self.add_allow_excluded_subnets_rules(excluded_subnets, None);
+                (*allow_lan, lan_networks)
+            }
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                dns_servers,
+                encrypted_dns,
+                split_dns_servers,
+                allow_lan,
+                lan_networks,
+                excluded_subnets,
+                split_tunnel,
+                forwarded_ports,
+                shared_interfaces,
+            } => {
+                if *split_tunnel {
+                    self.add_split_tunnel_mark_rules();
+                    self.add_allow_split_tunnel_rules(Some(&tunnel.interface));
+                }
+                // Before the relay is allowed, since the encrypted traffic is sent to it
+                self.add_drop_tunnel_loop_rule(tunnel);
+                self.add_allow_endpoint_rules(peer_endpoint);
+                let lan_dns_servers = if *allow_lan {
+                    lan_dns_servers(tunnel, dns_servers, lan_networks)
+                } else {
+                    vec![]
+                };
+                match encrypted_dns {
+                    Some(protocol) => {
+                        self.add_allow_dns_rules(
+                            tunnel,
+                            dns_servers,
+                            &lan_dns_servers,
+                            TransportProtocol::Tcp,
+                            protocol.port(),
+                        );
+                        self.add_drop_encrypted_dns_rule();
+                    }
+                    None => {
+                        self.add_allow_dns_rules(
+                            tunnel,
+                            dns_servers,
+                            &lan_dns_servers,
+                            TransportProtocol::Udp,
+                            DNS_PORT,
+                        );
+                        self.add_allow_dns_rules(
+                            tunnel,
+                            dns_servers,
+                            &lan_dns_servers,
+                            TransportProtocol::Tcp,
+                            DNS_PORT,
+                        );
+                    }
+                }
+                self.add_allow_split_dns_rules(tunnel, split_dns_servers);
+                // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
+                // can't leak to the wrong IPs in the tunnel or on the LAN.
+                self.add_drop_dns_rule();
+                self.add_allow_excluded_subnets_rules(excluded_subnets, Some(&tunnel.interface));
+                self.add_allow_tunnel_rules(tunnel, forwarded_ports);
+                self.add_forward_tunnel_rules(tunnel, shared_interfaces);
+                if *allow_lan {
+                    self.add_block_cve_2019_14899(tunnel);
+                }
+                (*allow_lan, lan_networks)
+            }
+            FirewallPolicy::Blocked {
+                allow_lan,
+                lan_networks,
+                allowed_endpoints,
+            } => {
+                self.add_allowed_endpoints_rules(allowed_endpoints);
+                // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
+                self.add_drop_dns_rule();
+                (*allow_lan, lan_networks)
+            }
+        };
+
+        if allow_lan {
+            self.add_allow_lan_rules(lan_networks);
+        }
+    }
+
+    /// Drops the encrypted traffic of the tunnel if it is routed into the tunnel itself. The
+    /// tunnel marks it when policy routing is used, and the routing rules should send it via the
+    /// main table.
+    fn add_drop_tunnel_loop_rule(&mut self, tunnel: &tunnel::TunnelMetadata) {
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![
+                Match::MetaMark(TUNNEL_FWMARK),
+                iface(Direction::Out, &tunnel.interface),
+            ],
+            Statement::Drop,
+        );
+    }
+
+    fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        let mut in_matches = endpoint_matches(End::Src, endpoint);
+        in_matches.push(Match::CtStateEstablished);
+        self.add_verdict_rule(Chain::In, in_matches, Statement::Accept);
+
+        self.add_verdict_rule(
+            Chain::Out,
+            endpoint_matches(End::Dst, endpoint),
+            Statement::Accept,
+        );
+    }
+
+    /// Allows connections to the allowed endpoints. No tunnel interface is up in the states that
+    /// allow them, so the rules apply to all interfaces. They are added before DNS is dropped, so
+    /// that DNS servers can be allowed explicitly.
+    fn add_allowed_endpoints_rules(&mut self, allowed_endpoints: &[AllowedEndpoint]) {
+        for endpoint in allowed_endpoints {
+            self.add_verdict_rule(
+                Chain::Out,
+                vec![
+                    Match::Net(End::Dst, endpoint.network),
+                    Match::Port(endpoint.protocol, End::Dst, endpoint.port),
+                ],
+                Statement::Accept,
+            );
+            self.add_verdict_rule(
+                Chain::In,
+                vec![
+                    Match::Net(End::Src, endpoint.network),
+                    Match::Port(endpoint.protocol, End::Src, endpoint.port),
+                    Match::CtStateEstablished,
+                ],
+                Statement::Accept,
+            );
+        }
+    }
+
+    fn add_allow_icmp_pingable_hosts(&mut self, pingable_hosts: &[IpAddr]) {
+        for host in pingable_hosts {
+            let icmp_proto = match &host {
+                IpAddr::V4(_) => libc::IPPROTO_ICMP as u8,
+                IpAddr::V6(_) => libc::IPPROTO_ICMPV6 as u8,
+            };
+
+            self.add_verdict_rule(
+                Chain::Out,
+                vec![Match::Ip(End::Dst, *host), Match::L4Proto(icmp_proto)],
+                Statement::Accept,
+            );
+            self.add_verdict_rule(
+                Chain::In,
+                vec![Match::Ip(End::Src, *host), Match::L4Proto(icmp_proto)],
+                Statement::Accept,
+            );
+        }
+    }
+
+    /// Allows DNS to the given servers through the tunnel, and to the servers in `lan_servers` on
+    /// any interface, since they are reached outside the tunnel. Encrypted DNS servers are
+    /// allowed on the port of their protocol instead of port 53.
+    fn add_allow_dns_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        servers: &[IpAddr],
+        lan_servers: &[IpAddr],
+        protocol: TransportProtocol,
+        port: u16,
+    ) {
+        for server in servers {
+            self.add_allow_dns_rule(Some(&tunnel.interface), protocol, port, *server);
+        }
+        for server in lan_servers {
+            self.add_allow_dns_rule(None, protocol, port, *server);
+        }
+    }
+
+    fn add_allow_dns_rule(
+        &mut self,
+        interface: Option<&str>,
+        protocol: TransportProtocol,
+        port: u16,
+        host: IpAddr,
+    ) {
+        let mut matches = Vec::new();
+        if let Some(interface) = interface {
+            matches.push(iface(Direction::Out, interface));
+        }
+        matches.push(Match::Port(protocol, End::Dst, port));
+        matches.push(Match::Ip(End::Dst, host));
+        self.add_verdict_rule(Chain::Out, matches, Statement::Accept);
+    }
+
+    /// Allows DNS to the servers of split DNS rules outside the tunnel. Their domains are only
+    /// known to them, so they are never reached through the tunnel.
+    fn add_allow_split_dns_rules(&mut self, tunnel: &tunnel::TunnelMetadata, servers: &[IpAddr]) {
+        for server in servers {
+            for protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+                self.add_verdict_rule(
+                    Chain::Out,
+                    vec![
+                        not_iface(Direction::Out, &tunnel.interface),
+                        Match::Port(*protocol, End::Dst, DNS_PORT),
+                        Match::Ip(End::Dst, *server),
+                    ],
+                    Statement::Accept,
+                );
+            }
+        }
+    }
+
+    /// Blocks all outgoing DNS (port 53) on both TCP and UDP
+    fn add_drop_dns_rule(&mut self) {
+        self.add_drop_port_rules(DNS_PORT);
+    }
+
+    /// Blocks DNS-over-TLS to every server but the allowed one, so that no other encrypted
+    /// resolver can be used. DNS-over-HTTPS can not be told apart from other HTTPS traffic.
+    fn add_drop_encrypted_dns_rule(&mut self) {
+        self.add_drop_port_rules(EncryptedDnsProtocol::Tls.port());
+    }
+
+    fn add_drop_port_rules(&mut self, port: u16) {
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![Match::Port(TransportProtocol::Udp, End::Dst, port)],
+            Statement::Drop,
+        );
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![Match::Port(TransportProtocol::Tcp, End::Dst, port)],
+            Statement::Drop,
+        );
+    }
+
+    /// Allows traffic to and from the excluded networks, on any interface but the tunnel.
+    fn add_allow_excluded_subnets_rules(
+        &mut self,
+        excluded_subnets: &[IpNetwork],
+        tunnel_interface: Option<&str>,
+    ) {
+        for subnet in excluded_subnets {
+            let mut out_matches = Vec::new();
+            let mut in_matches = Vec::new();
+            if let Some(interface) = tunnel_interface {
+                out_matches.push(not_iface(Direction::Out, interface));
+                in_matches.push(not_iface(Direction::In, interface));
+            }
+            out_matches.push(Match::Net(End::Dst, *subnet));
+            in_matches.push(Match::Net(End::Src, *subnet));
+            self.add_verdict_rule(Chain::Out, out_matches, Statement::Accept);
+            self.add_verdict_rule(Chain::In, in_matches, Statement::Accept);
+        }
+    }
+
+    /// Allows all outgoing traffic through the tunnel. Incoming traffic is only allowed if it
+    /// belongs to a connection that was made from this device, or if it is sent to a forwarded
+    /// port.
+    fn add_allow_tunnel_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        forwarded_ports: &[ForwardedPort],
+    ) {
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![iface(Direction::Out, &tunnel.interface)],
+            Statement::Accept,
+        );
+        self.add_verdict_rule(
+            Chain::In,
+            vec![
+                iface(Direction::In, &tunnel.interface),
+                Match::CtStateEstablishedOrRelated,
+            ],
+            Statement::Accept,
+        );
+        for forwarded_port in forwarded_ports {
+            let port_match = if forwarded_port.start == forwarded_port.end {
+                Match::Port(forwarded_port.protocol, End::Dst, forwarded_port.start)
+            } else {
+                Match::PortRange(
+                    forwarded_port.protocol,
+                    End::Dst,
+                    forwarded_port.start,
+                    forwarded_port.end,
+                )
+            };
+            self.add_verdict_rule(
+                Chain::In,
+                vec![iface(Direction::In, &tunnel.interface), port_match],
+                Statement::Accept,
+            );
+        }
+    }
+
+    /// Allows forwarded traffic, such as from containers and virtual machines, to leave through
+    /// the tunnel, and replies to it to return. Traffic forwarded from the shared interfaces gets
+    /// the tunnel address as source, so that the relay accepts it. Other forwarded traffic has to
+    /// be translated by whoever set up the forwarding.
+    fn add_forward_tunnel_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        shared_interfaces: &[String],
+    ) {
+        self.add_verdict_rule(
+            Chain::Forward,
+            vec![
+                iface(Direction::In, &tunnel.interface),
+                Match::CtStateEstablishedOrRelated,
+            ],
+            Statement::Accept,
+        );
+        // Nothing may be forwarded to the LAN from the tunnel, even if LAN access is allowed.
+        self.add_verdict_rule(
+            Chain::Forward,
+            vec![iface(Direction::In, &tunnel.interface)],
+            Statement::Drop,
+        );
+        self.add_verdict_rule(
+            Chain::Forward,
+            vec![iface(Direction::Out, &tunnel.interface)],
+            Statement::Accept,
+        );
+        for shared_interface in shared_interfaces {
+            self.add(
+                Chain::Nat,
+                vec![
+                    Match::IfaceName {
+                        direction: Direction::In,
+                        name: shared_interface.clone(),
+                    },
+                    iface(Direction::Out, &tunnel.interface),
+                ],
+                vec![Statement::Masquerade],
+            );
+        }
+    }
+
+    /// Adds rules for stopping [CVE-2019-14899](https://seclists.org/oss-sec/2019/q4/122).
+    /// An attacker on the same local network as the VPN connected device could figure out
+    /// the tunnel IP the device used if the device was set to not filter reverse path (rp_filter.)
+    /// These rules stops all packets coming in to the tunnel IP. As such, these rules must come
+    /// after the rule allowing the tunnel, otherwise even the tunnel can't talk to that IP.
+    fn add_block_cve_2019_14899(&mut self, tunnel: &tunnel::TunnelMetadata) {
+        for tunnel_ip in &tunnel.ips {
+            self.add_verdict_rule(
+                Chain::In,
+                vec![Match::Ip(End::Dst, *tunnel_ip)],
+                Statement::Drop,
+            );
+        }
+    }
+
+    fn add_allow_lan_rules(&mut self, lan_networks: &LanNetworks) {
+        // LAN -> LAN
+        for net in allowed_lan_nets(lan_networks) {
+            self.add_verdict_rule(
+                Chain::Out,
+                vec![Match::Net(End::Dst, net)],
+                Statement::Accept,
+            );
+            self.add_verdict_rule(
+                Chain::In,
+                vec![Match::Net(End::Src, net)],
+                Statement::Accept,
+            );
+            // Between local networks, such as a container bridge and the LAN
+            self.add_verdict_rule(
+                Chain::Forward,
+                vec![Match::Net(End::Dst, net)],
+                Statement::Accept,
+            );
+        }
+        // LAN -> Multicast
+        for net in &*ALLOWED_LAN_MULTICAST_NETS {
+            self.add_verdict_rule(
+                Chain::Out,
+                vec![Match::Net(End::Dst, *net)],
+                Statement::Accept,
+            );
+        }
+        self.add_dhcp_server_rules();
+    }
+
+    fn add_dhcp_server_rules(&mut self) {
+        use TransportProtocol::Udp;
+        // Outgoing DHCPv4 response
+        self.add_verdict_rule(
+            Chain::Out,
+            vec![
+                Match::Port(Udp, End::Src, DHCPV4_SERVER_PORT),
+                Match::Port(Udp, End::Dst, DHCPV4_CLIENT_PORT),
+            ],
+            Statement::Accept,
+        );
+        // Incoming DHCPv4 request
+        let mut in_matches = vec![Match::Port(Udp, End::Src, DHCPV4_CLIENT_PORT)];
+        in_matches.extend(endpoint_matches(
+            End::Dst,
+            &Endpoint::new(Ipv4Addr::BROADCAST, DHCPV4_SERVER_PORT, Udp),
+        ));
+        self.add_verdict_rule(Chain::In, in_matches, Statement::Accept);
+    }
+}
+
+fn iface(direction: Direction, name: &str) -> Match {
+    Match::Iface {
+        direction,
+        name: name.to_owned(),
+        negate: false,
+    }
+}
+
+fn not_iface(direction: Direction, name: &str) -> Match {
+    Match::Iface {
+        direction,
+        name: name.to_owned(),
+        negate: true,
+    }
+}
+
+/// Returns the DNS servers that are on one of the LAN networks. The tunnel gateways are private
+/// addresses too, but are only reached through the tunnel.
+fn lan_dns_servers(
+    tunnel: &tunnel::TunnelMetadata,
+    dns_servers: &[IpAddr],
+    lan_networks: &LanNetworks,
+) -> Vec<IpAddr> {
+    let lan_nets = allowed_lan_nets(lan_networks);
+    dns_servers
+        .iter()
+        .filter(|server| {
+            **server != IpAddr::from(tunnel.ipv4_gateway)
+                && tunnel.ipv6_gateway.map(IpAddr::from) != Some(**server)
+        })
+        .filter(|server| lan_nets.iter().any(|net| net.contains(**server)))
+        .cloned()
+        .collect()
+}
+
+fn endpoint_matches(end: End, endpoint: &Endpoint) -> Vec<Match> {
+    vec![
+        Match::Ip(end, endpoint.address.ip()),
+        Match::Port(endpoint.protocol, end, endpoint.address.port()),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs, net::Ipv6Addr, path::Path};
+
+    /// Set to regenerate the golden files instead of comparing with them.
+    const UPDATE_GOLDEN_FILES_VAR: &str = "TALPID_UPDATE_GOLDEN_FILES";
+
+    fn assert_matches_golden_file(name: &str, policy: &FirewallPolicy) {
+        assert_ruleset_matches_golden_file(name, &policy_ruleset("mullvad", policy, false, None));
+    }
+
+    fn assert_ruleset_matches_golden_file(name: &str, ruleset: &Ruleset) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/firewall/linux/testdata")
+            .join(format!("{}.nft", name));
+        let rendered = ruleset.to_string();
+        if env::var(UPDATE_GOLDEN_FILES_VAR).is_ok() {
+            fs::write(&path, rendered).expect("Failed to write golden file");
+            return;
+        }
+        let expected = fs::read_to_string(&path).expect("Failed to read golden file");
+        assert!(
+            rendered == expected,
+            "Rules for {} policy differ from {}. Set {}=1 to update it if the change is \
+             intended.\nRendered:\n{}",
+            name,
+            path.display(),
+            UPDATE_GOLDEN_FILES_VAR,
+            rendered
+        );
+    }
+
+    fn relay_endpoint() -> Endpoint {
+        Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 1194, TransportProtocol::Udp)
+    }
+
+    fn excluded_subnets() -> Vec<IpNetwork> {
+        vec!["198.51.100.0/24".parse().unwrap()]
+    }
+
+    fn allowed_endpoints() -> Vec<AllowedEndpoint> {
+        vec![
+            AllowedEndpoint {
+                network: "203.0.113.10/32".parse().unwrap(),
+                port: 443,
+                protocol: TransportProtocol::Tcp,
+            },
+            AllowedEndpoint {
+                network: "2001:db8::/64".parse().unwrap(),
+                port: 53,
+                protocol: TransportProtocol::Udp,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_connecting_policy() {
+        assert_matches_golden_file(
+            "connecting",
+            &FirewallPolicy::Connecting {
+                peer_endpoint: relay_endpoint(),
+                pingable_hosts: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+                allow_lan: true,
+                lan_networks: LanNetworks::default(),
+                excluded_subnets: excluded_subnets(),
+                split_tunnel: false,
+                allowed_endpoints: allowed_endpoints(),
+            },
+        );
+    }
+
+    #[test]
+    fn test_connected_policy() {
+        assert_matches_golden_file(
+            "connected",
+            &FirewallPolicy::Connected {
+                peer_endpoint: relay_endpoint(),
+                tunnel: tunnel::TunnelMetadata {
+                    interface: "wg-mullvad".to_owned(),
+                    ips: vec![
+                        IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2)),
+                        IpAddr::V6(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 2)),
+                    ],
+                    ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                    ipv6_gateway: Some(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+                },
+                dns_servers: vec![
+                    IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+                    IpAddr::V6(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+                ],
+                encrypted_dns: None,
+                split_dns_servers: vec![],
+                allow_lan: true,
+                lan_networks: LanNetworks {
+                    extra: vec!["100.64.0.0/10".parse().unwrap()],
+                    removed_defaults: vec!["10.0.0.0/8".parse().unwrap()],
+                },
+                excluded_subnets: excluded_subnets(),
+                split_tunnel: true,
+                forwarded_ports: vec![
+                    ForwardedPort::single(TransportProtocol::Tcp, 8080),
+                    ForwardedPort {
+                        protocol: TransportProtocol::Udp,
+                        start: 50000,
+                        end: 50100,
+                    },
+                ],
+                shared_interfaces: vec!["virbr0".to_owned()],
+            },
+        );
+    }
+
+    #[test]
+    fn test_connected_custom_dns_policy() {
+        let lan_dns_server = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 53));
+        let connected_policy = |allow_lan| FirewallPolicy::Connected {
+            peer_endpoint: relay_endpoint(),
+            tunnel: tunnel::TunnelMetadata {
+                interface: "wg-mullvad".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            },
+            dns_servers: vec![IpAddr::V4(Ipv4Addr::new(198, 51, 100, 53)), lan_dns_server],
+            encrypted_dns: None,
+            split_dns_servers: vec![],
+            allow_lan,
+            lan_networks: LanNetworks::default(),
+            excluded_subnets: vec![],
+            split_tunnel: false,
+            forwarded_ports: vec![],
+            shared_interfaces: vec![],
+        };
+        assert_matches_golden_file("connected_custom_dns", &connected_policy(true));
+
+        // Without LAN access, the LAN server may only be reached through the tunnel
+        let ruleset = policy_ruleset("mullvad", &connected_policy(false), false, None);
+        assert!(ruleset.rules.iter().all(|rule| {
+            !rule.matches.contains(&Match::Ip(End::Dst, lan_dns_server))
+                || rule.matches.contains(&iface(Direction::Out, "wg-mullvad"))
+        }));
+    }
+
+    #[test]
+    fn test_connected_encrypted_dns_policy() {
+        assert_matches_golden_file(
+            "connected_encrypted_dns",
+            &FirewallPolicy::Connected {
+                peer_endpoint: relay_endpoint(),
+                tunnel: tunnel::TunnelMetadata {
+                    interface: "wg-mullvad".to_owned(),
+                    ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                    ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                    ipv6_gateway: None,
+                },
+                dns_servers: vec![IpAddr::V4(Ipv4Addr::new(198, 51, 100, 153))],
+                encrypted_dns: Some(EncryptedDnsProtocol::Tls),
+                split_dns_servers: vec![],
+                allow_lan: false,
+                lan_networks: LanNetworks::default(),
+                excluded_subnets: vec![],
+                split_tunnel: false,
+                forwarded_ports: vec![],
+                shared_interfaces: vec![],
+            },
+        );
+    }
+
+    #[test]
+    fn test_connected_split_dns_policy() {
+        assert_matches_golden_file(
+            "connected_split_dns",
+            &FirewallPolicy::Connected {
+                peer_endpoint: relay_endpoint(),
+                tunnel: tunnel::TunnelMetadata {
+                    interface: "wg-mullvad".to_owned(),
+                    ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                    ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                    ipv6_gateway: None,
+                },
+                dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+                encrypted_dns: None,
+                split_dns_servers: vec![
+                    IpAddr::V4(Ipv4Addr::new(172, 16, 8, 53)),
+                    IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53)),
+                ],
+                allow_lan: false,
+                lan_networks: LanNetworks::default(),
+                excluded_subnets: vec![],
+                split_tunnel: false,
+                forwarded_ports: vec![],
+                shared_interfaces: vec![],
+            },
+        );
+    }
+
+    #[test]
+    fn test_split_tunnel_rules() {
+        let connected_policy = |split_tunnel| FirewallPolicy::Connected {
+            peer_endpoint: relay_endpoint(),
+            tunnel: tunnel::TunnelMetadata {
+                interface: "wg-mullvad".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            },
+            dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))],
+            encrypted_dns: None,
+            split_dns_servers: vec![],
+            allow_lan: false,
+            lan_networks: LanNetworks::default(),
+            excluded_subnets: vec![],
+            split_tunnel,
+            forwarded_ports: vec![],
+            shared_interfaces: vec![],
+        };
+        let rules = policy_ruleset("mullvad", &connected_policy(true), false, None).rules;
+        let position = |chain: Chain, matches: Vec<Match>, statements: Vec<Statement>| {
+            let rule = Rule {
+                chain,
+                matches,
+                statements,
+            };
+            rules
+                .iter()
+                .position(|existing| *existing == rule)
+                .unwrap_or_else(|| panic!("Missing rule {:?}", rule))
+        };
+        let mark = split_tunnel::MARK;
+
+        // Packets from the exclusion cgroup, and replies to them, carry the mark
+        position(
+            Chain::Mangle,
+            vec![Match::Cgroup(split_tunnel::NET_CLS_CLASSID)],
+            vec![Statement::SetCtMark(mark), Statement::SetMetaMark(mark)],
+        );
+        position(
+            Chain::Prerouting,
+            vec![Match::CtMark(mark)],
+            vec![Statement::SetMetaMark(mark)],
+        );
+
+        // Marked packets are dropped in the tunnel before they can be accepted by any rule
+        let drop_out = position(
+            Chain::Out,
+            vec![Match::MetaMark(mark), iface(Direction::Out, "wg-mullvad")],
+            vec![Statement::Drop],
+        );
+        let accept_out = position(
+            Chain::Out,
+            vec![Match::MetaMark(mark)],
+            vec![Statement::Accept],
+        );
+        let accept_tunnel_out = position(
+            Chain::Out,
+            vec![iface(Direction::Out, "wg-mullvad")],
+            vec![Statement::Accept],
+        );
+        assert!(drop_out < accept_out && drop_out < accept_tunnel_out);
+
+        let drop_in = position(
+            Chain::In,
+            vec![Match::CtMark(mark), iface(Direction::In, "wg-mullvad")],
+            vec![Statement::Drop],
+        );
+        let accept_in = position(
+            Chain::In,
+            vec![Match::CtMark(mark)],
+            vec![Statement::Accept],
+        );
+        assert!(drop_in < accept_in);
+
+        // The encrypted traffic of the tunnel has a mark of its own, which is not accepted as
+        // excluded traffic, and is dropped in the tunnel before the relay is allowed
+        assert_ne!(TUNNEL_FWMARK, mark);
+        let drop_loop = position(
+            Chain::Out,
+            vec![
+                Match::MetaMark(TUNNEL_FWMARK),
+                iface(Direction::Out, "wg-mullvad"),
+            ],
+            vec![Statement::Drop],
+        );
+        let accept_relay = position(
+            Chain::Out,
+            endpoint_matches(End::Dst, &relay_endpoint()),
+            vec![Statement::Accept],
+        );
+        assert!(drop_loop < accept_relay);
+
+        // Without excluded processes, the table only has the filter chains
+        let ruleset = policy_ruleset("mullvad", &connected_policy(false), false, None);
+        assert_eq!(
+            ruleset.chains().collect::<Vec<_>>(),
+            vec![Chain::Out, Chain::In, Chain::Forward]
+        );
+        assert!(ruleset.rules.iter().all(|rule| {
+            !rule.matches.contains(&Match::MetaMark(mark))
+                && !rule.matches.contains(&Match::CtMark(mark))
+        }));
+    }
+
+    #[test]
+    fn test_blocked_policy() {
+        assert_matches_golden_file(
+            "blocked",
+            &FirewallPolicy::Blocked {
+                allow_lan: false,
+                lan_networks: LanNetworks::default(),
+                allowed_endpoints: allowed_endpoints(),
+            },
+        );
+    }
+
+    #[test]
+    fn test_counted_and_logged_blocked_policy() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            lan_networks: LanNetworks::default(),
+            allowed_endpoints: vec![],
+        };
+        assert_ruleset_matches_golden_file(
+            "blocked_counted_logged",
+            &policy_ruleset("mullvad", &policy, true, Some(3)),
+        );
+    }
+
+    #[test]
+    fn test_boot_ruleset() {
+        assert_ruleset_matches_golden_file("boot", &boot_ruleset("mullvad"));
+    }
+}
@@ -0,0 +1,299 @@
+//! An inspectable description of the netfilter rules that make up a firewall policy. It is turned
+//! into a netlink message batch when the policy is applied, and can be rendered as a script that
+//! `nft -f` accepts, so that the rules can be audited without applying them.
+
+use ipnetwork::IpNetwork;
+use std::{fmt, net::IpAddr};
+use talpid_types::net::TransportProtocol;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum End {
+    Src,
+    Dst,
+}
+
+/// The chains in the table, in the order they are created.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Chain {
+    Out,
+    In,
+    Forward,
+    Mangle,
+    Prerouting,
+    Nat,
+}
+
+/// Priority of the netfilter hooks that mark excluded traffic. Same as the `mangle` priority.
+const MANGLE_CHAIN_PRIORITY: i32 = -150;
+/// Priority of the netfilter hook that translates the source address of excluded traffic. Same as
+/// the `srcnat` priority.
+const NAT_CHAIN_PRIORITY: i32 = 100;
+
+impl Chain {
+    pub const ALL: [Chain; 6] = [
+        Chain::Out,
+        Chain::In,
+        Chain::Forward,
+        Chain::Mangle,
+        Chain::Prerouting,
+        Chain::Nat,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Chain::Out => "out",
+            Chain::In => "in",
+            Chain::Forward => "forward",
+            Chain::Mangle => "mangle",
+            Chain::Prerouting => "prerouting",
+            Chain::Nat => "nat",
+        }
+    }
+
+    /// The chain type, as written in an nft script. Route chains reroute packets whose mark was
+    /// changed by them.
+    pub fn chain_type(self) -> &'static str {
+        match self {
+            Chain::Out | Chain::In | Chain::Forward | Chain::Prerouting => "filter",
+            Chain::Mangle => "route",
+            Chain::Nat => "nat",
+        }
+    }
+
+    /// The netfilter hook, as written in an nft script.
+    pub fn hook(self) -> &'static str {
+        match self {
+            Chain::Out | Chain::Mangle => "output",
+            Chain::In => "input",
+            Chain::Forward => "forward",
+            Chain::Prerouting => "prerouting",
+            Chain::Nat => "postrouting",
+        }
+    }
+
+    pub fn priority(self) -> i32 {
+        match self {
+            Chain::Out | Chain::In | Chain::Forward => 0,
+            Chain::Mangle | Chain::Prerouting => MANGLE_CHAIN_PRIORITY,
+            Chain::Nat => NAT_CHAIN_PRIORITY,
+        }
+    }
+
+    /// Whether packets that no rule accepted are dropped.
+    pub fn drops_by_default(self) -> bool {
+        match self {
+            Chain::Out | Chain::In | Chain::Forward => true,
+            Chain::Mangle | Chain::Prerouting | Chain::Nat => false,
+        }
+    }
+}
+
+/// A condition that a packet must fulfill for a rule to apply. Matches on payloads also check the
+/// protocol of the layer the payload belongs to.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Match {
+    /// The interface the packet arrives on or leaves through, looked up by name when applied.
+    Iface {
+        direction: Direction,
+        name: String,
+        negate: bool,
+    },
+    /// The name of the interface the packet arrives on or leaves through. Unlike `Iface`, the
+    /// interface does not have to exist when the rule is added.
+    IfaceName {
+        direction: Direction,
+        name: String,
+    },
+    Ip(End, IpAddr),
+    Net(End, IpNetwork),
+    Port(TransportProtocol, End, u16),
+    /// An inclusive range of ports.
+    PortRange(TransportProtocol, End, u16, u16),
+    /// The transport layer protocol number.
+    L4Proto(u8),
+    Icmpv6 {
+        icmp_type: u8,
+        code: u8,
+    },
+    /// The net_cls class ID of the cgroup of the sending process.
+    Cgroup(u32),
+    MetaMark(u32),
+    CtMark(u32),
+    CtStateEstablished,
+    /// Packets of established connections, and packets such as ICMP errors that relate to them.
+    CtStateEstablishedOrRelated,
+}
+
+/// What a rule does with packets that it matches.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Statement {
+    Counter,
+    /// Sends the packet to the given nflog group.
+    Log(u16),
+    SetCtMark(u32),
+    SetMetaMark(u32),
+    Masquerade,
+    Accept,
+    Drop,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Rule {
+    pub chain: Chain,
+    pub matches: Vec<Match>,
+    pub statements: Vec<Statement>,
+}
+
+/// All rules of a policy, in the order they are added to the table.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Ruleset {
+    pub table_name: String,
+    pub rules: Vec<Rule>,
+}
+
+impl Ruleset {
+    /// The chains that the table is created with: those that drop packets by default, and those
+    /// that have rules. The other chains would only accept every packet.
+    pub fn chains(&self) -> impl Iterator<Item = Chain> + '_ {
+        Chain::ALL.iter().copied().filter(move |chain| {
+            chain.drops_by_default() || self.rules.iter().any(|rule| rule.chain == *chain)
+        })
+    }
+}
+
+impl fmt::Display for Ruleset {
+    /// Renders the ruleset as an nft script. Like when the policy is applied, the table is created
+    /// if needed and then replaced.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "table inet {}", self.table_name)?;
+        writeln!(f, "delete table inet {}", self.table_name)?;
+        writeln!(f)?;
+        writeln!(f, "table inet {} {{", self.table_name)?;
+        for (index, chain) in self.chains().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "\tchain {} {{", chain.name())?;
+            writeln!(
+                f,
+                "\t\ttype {} hook {} priority {}; policy {};",
+                chain.chain_type(),
+                chain.hook(),
+                chain.priority(),
+                if chain.drops_by_default() {
+                    "drop"
+                } else {
+                    "accept"
+                }
+            )?;
+            for rule in self.rules.iter().filter(|rule| rule.chain == chain) {
+                writeln!(f, "\t\t{}", rule)?;
+            }
+            writeln!(f, "\t}}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self
+            .matches
+            .iter()
+            .map(ToString::to_string)
+            .chain(self.statements.iter().map(ToString::to_string))
+            .collect::<Vec<_>>();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Match::Iface {
+                direction,
+                name,
+                negate,
+            } => write!(
+                f,
+                "{} {}\"{}\"",
+                match direction {
+                    Direction::In => "iif",
+                    Direction::Out => "oif",
+                },
+                if *negate { "!= " } else { "" },
+                name
+            ),
+            Match::IfaceName { direction, name } => write!(
+                f,
+                "{} \"{}\"",
+                match direction {
+                    Direction::In => "iifname",
+                    Direction::Out => "oifname",
+                },
+                name
+            ),
+            Match::Ip(end, ip) => write!(f, "{} {}", address_field(*ip, *end), ip),
+            Match::Net(end, net) => write!(f, "{} {}", address_field(net.ip(), *end), net),
+            Match::Port(protocol, end, port) => {
+                write!(f, "{} {}", port_field(*protocol, *end), port)
+            }
+            Match::PortRange(protocol, end, start, last) => {
+                write!(f, "{} {}-{}", port_field(*protocol, *end), start, last)
+            }
+            Match::L4Proto(protocol) => match i32::from(*protocol) {
+                libc::IPPROTO_ICMP => write!(f, "meta l4proto icmp"),
+                libc::IPPROTO_ICMPV6 => write!(f, "meta l4proto ipv6-icmp"),
+                libc::IPPROTO_TCP => write!(f, "meta l4proto tcp"),
+                libc::IPPROTO_UDP => write!(f, "meta l4proto udp"),
+                _ => write!(f, "meta l4proto {}", protocol),
+            },
+            Match::Icmpv6 { icmp_type, code } => {
+                write!(f, "icmpv6 type {} icmpv6 code {}", icmp_type, code)
+            }
+            Match::Cgroup(class_id) => write!(f, "meta cgroup {:#x}", class_id),
+            Match::MetaMark(mark) => write!(f, "meta mark {:#x}", mark),
+            Match::CtMark(mark) => write!(f, "ct mark {:#x}", mark),
+            Match::CtStateEstablished => write!(f, "ct state established"),
+            Match::CtStateEstablishedOrRelated => write!(f, "ct state established,related"),
+        }
+    }
+}
+
+fn port_field(protocol: TransportProtocol, end: End) -> &'static str {
+    match (protocol, end) {
+        (TransportProtocol::Udp, End::Src) => "udp sport",
+        (TransportProtocol::Udp, End::Dst) => "udp dport",
+        (TransportProtocol::Tcp, End::Src) => "tcp sport",
+        (TransportProtocol::Tcp, End::Dst) => "tcp dport",
+    }
+}
+
+fn address_field(ip: IpAddr, end: End) -> &'static str {
+    match (ip, end) {
+        (IpAddr::V4(_), End::Src) => "ip saddr",
+        (IpAddr::V4(_), End::Dst) => "ip daddr",
+        (IpAddr::V6(_), End::Src) => "ip6 saddr",
+        (IpAddr::V6(_), End::Dst) => "ip6 daddr",
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Counter => write!(f, "counter"),
+            Statement::Log(group) => write!(f, "log group {}", group),
+            Statement::SetCtMark(mark) => write!(f, "ct mark set {:#x}", mark),
+            Statement::SetMetaMark(mark) => write!(f, "meta mark set {:#x}", mark),
+            Statement::Masquerade => write!(f, "masquerade"),
+            Statement::Accept => write!(f, "accept"),
+            Statement::Drop => write!(f, "drop"),
+        }
+    }
+}
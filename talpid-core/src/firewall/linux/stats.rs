@@ -0,0 +1,354 @@
+//! Reads the packet counters of the rules in the table back from netfilter, so that it can be
+//! seen what the applied policy accepts and drops.
+//!
+//! The rules are dumped in the order they were added to each chain, which is the order of the
+//! rules in the applied [`Ruleset`]. That is used to tell which rule each counter belongs to.
+
+use super::{
+    netlink::{
+        align, find_attribute, parse_string, read_u16, read_u32, Attributes, NFGENMSG_LEN,
+        NFNL_SUBSYS_NFTABLES, NFPROTO_INET, NLA_HEADER_LEN, NLMSG_HEADER_LEN,
+    },
+    ruleset::{Chain, Ruleset},
+    Error, Result,
+};
+use std::{collections::HashMap, ffi::CStr};
+use talpid_types::firewall::RuleStats;
+
+const NFT_MSG_NEWRULE: u16 = 6;
+const NFT_MSG_GETRULE: u16 = 7;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+const NFTA_COUNTER_BYTES: u16 = 1;
+const NFTA_COUNTER_PACKETS: u16 = 2;
+
+const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The packets and bytes that a rule has matched.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Counter {
+    packets: u64,
+    bytes: u64,
+}
+
+/// Returns the counters of all rules in the ruleset that count packets.
+pub fn rule_stats(table_name: &CStr, ruleset: &Ruleset) -> Result<Vec<RuleStats>> {
+    let table_name = table_name.to_string_lossy();
+    let counters = dump_counters(&table_name)?;
+
+    let mut stats = Vec::new();
+    for chain in &Chain::ALL {
+        let rules = ruleset
+            .rules
+            .iter()
+            .filter(|rule| rule.chain == *chain)
+            .collect::<Vec<_>>();
+        let chain_counters = counters.get(chain.name()).map(Vec::as_slice).unwrap_or(&[]);
+        if rules.len() != chain_counters.len() {
+            return Err(Error::RulesetMismatch(chain.name()));
+        }
+        for (rule, counter) in rules.into_iter().zip(chain_counters) {
+            if let Some(counter) = counter {
+                stats.push(RuleStats {
+                    chain: chain.name().to_owned(),
+                    rule: rule.to_string(),
+                    packets: counter.packets,
+                    bytes: counter.bytes,
+                });
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Returns whether the table has the chains of the ruleset and as many rules in each of them.
+/// Returns false if the table does not exist.
+pub fn table_matches(table_name: &CStr, ruleset: &Ruleset) -> Result<bool> {
+    match dump_counters(&table_name.to_string_lossy()) {
+        Ok(counters) => Ok(rule_counts_match(ruleset, &counters)),
+        Err(Error::ProcessNetlinkError(error)) if error.raw_os_error() == Some(libc::ENOENT) => {
+            Ok(false)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn rule_counts_match(ruleset: &Ruleset, counters: &HashMap<String, Vec<Option<Counter>>>) -> bool {
+    let known_chains = counters
+        .keys()
+        .all(|name| Chain::ALL.iter().any(|chain| chain.name() == name));
+    known_chains
+        && Chain::ALL.iter().all(|chain| {
+            let expected = ruleset
+                .rules
+                .iter()
+                .filter(|rule| rule.chain == *chain)
+                .count();
+            counters.get(chain.name()).map(Vec::len).unwrap_or(0) == expected
+        })
+}
+
+/// Dumps the rules of the table, and returns the counter of each rule, or `None` for rules
+/// without one, grouped by chain name.
+fn dump_counters(table_name: &str) -> Result<HashMap<String, Vec<Option<Counter>>>> {
+    let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
+    socket
+        .send(&get_rules_request(table_name))
+        .map_err(Error::NetlinkSendError)?;
+
+    let mut parser = RuleParser::new(table_name);
+    let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+    loop {
+        let length = socket.recv(&mut buffer).map_err(Error::NetlinkRecvError)?;
+        if length == 0 || parser.parse(&buffer[..length])? {
+            return Ok(parser.counters);
+        }
+    }
+}
+
+/// A request to dump all rules in the inet table with the given name.
+fn get_rules_request(table_name: &str) -> Vec<u8> {
+    let name = format!("{}\0", table_name);
+    let attribute_length = NLA_HEADER_LEN + name.len();
+    let length = NLMSG_HEADER_LEN + NFGENMSG_LEN + attribute_length;
+
+    let mut request = Vec::with_capacity(length + 3);
+    request.extend_from_slice(&(length as u32).to_ne_bytes());
+    request.extend_from_slice(&((NFNL_SUBSYS_NFTABLES << 8) | NFT_MSG_GETRULE).to_ne_bytes());
+    request.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    // Sequence number and port ID
+    request.extend_from_slice(&[0; 8]);
+    request.extend_from_slice(&[NFPROTO_INET, 0, 0, 0]);
+    request.extend_from_slice(&(attribute_length as u16).to_ne_bytes());
+    request.extend_from_slice(&NFTA_RULE_TABLE.to_ne_bytes());
+    request.extend_from_slice(name.as_bytes());
+    request.resize(align(request.len()), 0);
+    request
+}
+
+/// Collects the counters of the rules in a dump, which may be split over several reads.
+struct RuleParser<'a> {
+    table_name: &'a str,
+    counters: HashMap<String, Vec<Option<Counter>>>,
+}
+
+impl<'a> RuleParser<'a> {
+    fn new(table_name: &'a str) -> Self {
+        RuleParser {
+            table_name,
+            counters: HashMap::new(),
+        }
+    }
+
+    /// Parses the netlink messages in `buffer`. Returns true once the dump is complete.
+    fn parse(&mut self, mut buffer: &[u8]) -> Result<bool> {
+        while buffer.len() >= NLMSG_HEADER_LEN {
+            let length = read_u32(&buffer[0..4]) as usize;
+            if length < NLMSG_HEADER_LEN || length > buffer.len() {
+                return Err(Error::ProcessNetlinkError(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Malformed netlink message",
+                )));
+            }
+            match read_u16(&buffer[4..6]) {
+                NLMSG_DONE => return Ok(true),
+                NLMSG_ERROR => {
+                    let errno = if length >= NLMSG_HEADER_LEN + 4 {
+                        -(read_u32(&buffer[NLMSG_HEADER_LEN..NLMSG_HEADER_LEN + 4]) as i32)
+                    } else {
+                        0
+                    };
+                    if errno != 0 {
+                        return Err(Error::ProcessNetlinkError(
+                            std::io::Error::from_raw_os_error(errno),
+                        ));
+                    }
+                }
+                message_type
+                    if message_type == (NFNL_SUBSYS_NFTABLES << 8) | NFT_MSG_NEWRULE
+                        && length >= NLMSG_HEADER_LEN + NFGENMSG_LEN =>
+                {
+                    self.handle_rule(&buffer[NLMSG_HEADER_LEN + NFGENMSG_LEN..length]);
+                }
+                _ => (),
+            }
+            buffer = &buffer[align(length).min(buffer.len())..];
+        }
+        Ok(false)
+    }
+
+    fn handle_rule(&mut self, attributes: &[u8]) {
+        // Older kernels dump the rules of all tables
+        if find_attribute(attributes, NFTA_RULE_TABLE).and_then(parse_string)
+            != Some(self.table_name)
+        {
+            return;
+        }
+        let chain = match find_attribute(attributes, NFTA_RULE_CHAIN).and_then(parse_string) {
+            Some(chain) => chain,
+            None => return,
+        };
+        let counter = find_attribute(attributes, NFTA_RULE_EXPRESSIONS).and_then(find_counter);
+        self.counters
+            .entry(chain.to_owned())
+            .or_default()
+            .push(counter);
+    }
+}
+
+fn find_counter(expressions: &[u8]) -> Option<Counter> {
+    Attributes::new(expressions)
+        .filter(|(attribute_type, _)| *attribute_type == NFTA_LIST_ELEM)
+        .find(|(_, expression)| {
+            find_attribute(expression, NFTA_EXPR_NAME).and_then(parse_string) == Some("counter")
+        })
+        .and_then(|(_, expression)| find_attribute(expression, NFTA_EXPR_DATA))
+        .and_then(|data| {
+            Some(Counter {
+                packets: find_attribute(data, NFTA_COUNTER_PACKETS).and_then(read_be_u64)?,
+                bytes: find_attribute(data, NFTA_COUNTER_BYTES).and_then(read_be_u64)?,
+            })
+        })
+}
+
+fn read_be_u64(value: &[u8]) -> Option<u64> {
+    if value.len() != 8 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(value);
+    Some(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firewall::imp::{
+        netlink::test::{attribute, message},
+        ruleset::{Rule, Statement},
+    };
+
+    fn name(name: &str) -> Vec<u8> {
+        format!("{}\0", name).into_bytes()
+    }
+
+    fn expression(expression_name: &str, data: &[Vec<u8>]) -> Vec<u8> {
+        attribute(
+            NFTA_LIST_ELEM,
+            &[
+                attribute(NFTA_EXPR_NAME, &name(expression_name)),
+                attribute(NFTA_EXPR_DATA, &data.concat()),
+            ]
+            .concat(),
+        )
+    }
+
+    fn rule(table: &str, chain: &str, expressions: &[Vec<u8>]) -> Vec<u8> {
+        message(
+            NFT_MSG_NEWRULE,
+            NFPROTO_INET,
+            &[
+                attribute(NFTA_RULE_TABLE, &name(table)),
+                attribute(NFTA_RULE_CHAIN, &name(chain)),
+                attribute(NFTA_RULE_EXPRESSIONS, &expressions.concat()),
+            ],
+        )
+    }
+
+    fn counter(packets: u64, bytes: u64) -> Vec<u8> {
+        expression(
+            "counter",
+            &[
+                attribute(NFTA_COUNTER_BYTES, &bytes.to_be_bytes()),
+                attribute(NFTA_COUNTER_PACKETS, &packets.to_be_bytes()),
+            ],
+        )
+    }
+
+    fn done() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&((NLMSG_HEADER_LEN + 4) as u32).to_ne_bytes());
+        bytes.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+        bytes.extend_from_slice(&[0; 14]);
+        bytes
+    }
+
+    #[test]
+    fn test_collects_counters_per_chain() {
+        let mut parser = RuleParser::new("mullvad");
+        let messages = [
+            rule("mullvad", "out", &[counter(3, 180)]),
+            rule("mullvad", "out", &[expression("masq", &[])]),
+            rule("mullvad", "in", &[counter(432, 25920)]),
+        ]
+        .concat();
+        assert!(!parser.parse(&messages).unwrap());
+        assert!(parser.parse(&done()).unwrap());
+
+        assert_eq!(
+            parser.counters["out"],
+            vec![
+                Some(Counter {
+                    packets: 3,
+                    bytes: 180
+                }),
+                None
+            ]
+        );
+        assert_eq!(
+            parser.counters["in"],
+            vec![Some(Counter {
+                packets: 432,
+                bytes: 25920
+            })]
+        );
+    }
+
+    #[test]
+    fn test_compares_rule_counts() {
+        let rule = |chain| Rule {
+            chain,
+            matches: vec![],
+            statements: vec![Statement::Accept],
+        };
+        let ruleset = Ruleset {
+            table_name: "mullvad".to_owned(),
+            rules: vec![rule(Chain::Out), rule(Chain::Out), rule(Chain::In)],
+        };
+        let counters = |chains: &[(&str, usize)]| {
+            chains
+                .iter()
+                .map(|(chain, count)| (chain.to_string(), vec![None; *count]))
+                .collect::<HashMap<_, _>>()
+        };
+
+        assert!(rule_counts_match(
+            &ruleset,
+            &counters(&[("out", 2), ("in", 1)])
+        ));
+        assert!(!rule_counts_match(&ruleset, &counters(&[])));
+        assert!(!rule_counts_match(
+            &ruleset,
+            &counters(&[("out", 1), ("in", 1)])
+        ));
+        assert!(!rule_counts_match(
+            &ruleset,
+            &counters(&[("out", 2), ("in", 1), ("filter", 1)])
+        ));
+    }
+
+    #[test]
+    fn test_ignores_other_tables() {
+        let mut parser = RuleParser::new("mullvad");
+        let messages = [rule("filter", "out", &[counter(1, 60)]), done()].concat();
+        assert!(parser.parse(&messages).unwrap());
+        assert!(parser.counters.is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+//! Watches netfilter for changes that other programs make to the table of the applied policy,
+//! such as `nft flush ruleset` or a firewall manager replacing the whole ruleset.
+//!
+//! Every change to the nftables ruleset is announced on a netlink multicast group. The messages
+//! of one transaction are followed by a message announcing the new ruleset generation, which
+//! tells what thread made the change. Changes made by this process are ignored.
+//!
+//! Older kernels do not tell what thread made a change. Changes of unknown origin are instead
+//! reported only if the table no longer holds the rules of the applied policy.
+
+use super::{
+    applied_ruleset_intact,
+    netlink::{
+        align, find_attribute, parse_string, read_u16, read_u32, NFGENMSG_LEN,
+        NFNL_SUBSYS_NFTABLES, NFPROTO_INET, NLMSG_HEADER_LEN,
+    },
+    Error, Result, TABLE_NAME,
+};
+use crate::firewall::Tampering;
+use futures::sync::mpsc::UnboundedSender;
+use std::{io, mem, os::unix::io::RawFd, path::Path, sync::mpsc as sync_mpsc, thread};
+use talpid_types::ErrorExt;
+
+/// Multicast group that nftables announces ruleset changes on.
+const NFNLGRP_NFTABLES: libc::c_int = 7;
+const NFT_MSG_NEWGEN: u16 = 15;
+/// The attribute holding the table name. It has this type in all messages about tables and
+/// objects in tables.
+const NFTA_TABLE_NAME: u16 = 1;
+const NFTA_GEN_PROC_PID: u16 = 2;
+const NFTA_GEN_PROC_NAME: u16 = 3;
+
+const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Starts watching the netfilter ruleset. Every change to the table that was not made by this
+/// process is sent on `tampering_tx`. Stops when `tampering_tx` is closed.
+pub fn spawn_watchdog(tampering_tx: UnboundedSender<Tampering>) -> Result<()> {
+    let (startup_tx, startup_rx) = sync_mpsc::channel();
+    thread::spawn(move || {
+        let socket = match NotificationSocket::open() {
+            Ok(socket) => {
+                let _ = startup_tx.send(Ok(()));
+                socket
+            }
+            Err(error) => {
+                let _ = startup_tx.send(Err(error));
+                return;
+            }
+        };
+        if let Err(error) = watch(&socket, &tampering_tx) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Stopped watching the firewall rules for changes")
+            );
+        }
+    });
+    startup_rx
+        .recv()
+        .expect("Firewall watchdog thread stopped without reporting its startup result")
+}
+
+fn watch(socket: &NotificationSocket, tampering_tx: &UnboundedSender<Tampering>) -> Result<()> {
+    let table_name = TABLE_NAME.to_string_lossy().into_owned();
+    let mut parser = NotificationParser::new(&table_name, is_own_thread);
+    let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+    loop {
+        let tampering = match socket.recv(&mut buffer) {
+            Ok(length) => parser.parse(&buffer[..length]),
+            Err(error) if error.raw_os_error() == Some(libc::ENOBUFS) => {
+                log::warn!("Missed netfilter notifications, assuming the firewall was changed");
+                vec![Tampering {
+                    pid: None,
+                    process_name: None,
+                }]
+            }
+            Err(error) => return Err(Error::NetlinkRecvError(error)),
+        };
+        for tampering in tampering {
+            if tampering.pid.is_none() && !is_ruleset_changed() {
+                continue;
+            }
+            if tampering_tx.unbounded_send(tampering).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Checks whether a change of unknown origin altered the applied policy. Assumes that it did if
+/// the table can not be read.
+fn is_ruleset_changed() -> bool {
+    match applied_ruleset_intact() {
+        Ok(intact) => !intact,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Unable to compare the firewall rules to the policy")
+            );
+            true
+        }
+    }
+}
+
+/// The kernel reports the ID of the thread that made a change, so the change was made by this
+/// process if it has a thread with that ID.
+fn is_own_thread(tid: u32) -> bool {
+    Path::new("/proc/self/task").join(tid.to_string()).exists()
+}
+
+/// A netlink socket subscribed to nftables ruleset changes.
+struct NotificationSocket(RawFd);
+
+impl NotificationSocket {
+    fn open() -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_NETFILTER,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::NetlinkOpenError(io::Error::last_os_error()));
+        }
+        let socket = NotificationSocket(fd);
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = 1 << (NFNLGRP_NFTABLES - 1);
+        let result = unsafe {
+            libc::bind(
+                socket.0,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(Error::NetlinkSubscribeError(io::Error::last_os_error()));
+        }
+        Ok(socket)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = unsafe {
+            libc::recv(
+                self.0,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if length < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(length as usize)
+        }
+    }
+}
+
+impl Drop for NotificationSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Keeps track of whether the current transaction changed the table, until the transaction ends
+/// with a new generation message.
+struct NotificationParser<'a, F: Fn(u32) -> bool> {
+    table_name: &'a str,
+    is_own_thread: F,
+    table_changed: bool,
+}
+
+impl<'a, F: Fn(u32) -> bool> NotificationParser<'a, F> {
+    fn new(table_name: &'a str, is_own_thread: F) -> Self {
+        NotificationParser {
+            table_name,
+            is_own_thread,
+            table_changed: false,
+        }
+    }
+
+    /// Parses the netlink messages in `buffer`, returning the transactions that changed the
+    /// table and were made by other processes, or by an unknown process.
+    fn parse(&mut self, mut buffer: &[u8]) -> Vec<Tampering> {
+        let mut tampering = Vec::new();
+        while buffer.len() >= NLMSG_HEADER_LEN {
+            let length = read_u32(&buffer[0..4]) as usize;
+            if length < NLMSG_HEADER_LEN || length > buffer.len() {
+                log::warn!("Ignoring malformed netfilter notification");
+                break;
+            }
+            let message_type = read_u16(&buffer[4..6]);
+            if message_type >> 8 == NFNL_SUBSYS_NFTABLES
+                && length >= NLMSG_HEADER_LEN + NFGENMSG_LEN
+            {
+                let family = buffer[NLMSG_HEADER_LEN];
+                let attributes = &buffer[NLMSG_HEADER_LEN + NFGENMSG_LEN..length];
+                if let Some(incident) = self.handle_message(message_type & 0xff, family, attributes)
+                {
+                    tampering.push(incident);
+                }
+            }
+            buffer = &buffer[align(length).min(buffer.len())..];
+        }
+        tampering
+    }
+
+    fn handle_message(&mut self, message: u16, family: u8, attributes: &[u8]) -> Option<Tampering> {
+        if message != NFT_MSG_NEWGEN {
+            if family == NFPROTO_INET
+                && find_attribute(attributes, NFTA_TABLE_NAME).map(parse_string)
+                    == Some(Some(self.table_name))
+            {
+                self.table_changed = true;
+            }
+            return None;
+        }
+
+        if !mem::replace(&mut self.table_changed, false) {
+            return None;
+        }
+        let pid = find_attribute(attributes, NFTA_GEN_PROC_PID)
+            .filter(|value| value.len() == 4)
+            .map(|value| u32::from_be_bytes([value[0], value[1], value[2], value[3]]));
+        let process_name = find_attribute(attributes, NFTA_GEN_PROC_NAME)
+            .and_then(parse_string)
+            .map(str::to_owned);
+        match pid {
+            Some(pid) if (self.is_own_thread)(pid) => None,
+            // Older kernels do not tell who made the change
+            pid => Some(Tampering { pid, process_name }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firewall::imp::netlink::test::{attribute, message};
+
+    const NFT_MSG_DELTABLE: u16 = 2;
+    const NFT_MSG_NEWRULE: u16 = 6;
+    const NFPROTO_IPV4: u8 = 2;
+
+    fn table_message(message_type: u16, family: u8, table_name: &str) -> Vec<u8> {
+        let name = format!("{}\0", table_name);
+        message(
+            message_type,
+            family,
+            &[attribute(NFTA_TABLE_NAME, name.as_bytes())],
+        )
+    }
+
+    fn new_generation(pid: u32, process_name: &str) -> Vec<u8> {
+        let name = format!("{}\0", process_name);
+        message(
+            NFT_MSG_NEWGEN,
+            0,
+            &[
+                attribute(NFTA_GEN_PROC_PID, &pid.to_be_bytes()),
+                attribute(NFTA_GEN_PROC_NAME, name.as_bytes()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_reports_changes_by_other_processes() {
+        let mut parser = NotificationParser::new("mullvad", |tid| tid == 1);
+        let notifications = [
+            table_message(NFT_MSG_DELTABLE, NFPROTO_INET, "mullvad"),
+            new_generation(4242, "nft"),
+        ]
+        .concat();
+        assert_eq!(
+            parser.parse(&notifications),
+            vec![Tampering {
+                pid: Some(4242),
+                process_name: Some("nft".to_owned()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ignores_own_changes() {
+        let mut parser = NotificationParser::new("mullvad", |tid| tid == 1);
+        assert!(parser
+            .parse(&table_message(NFT_MSG_NEWRULE, NFPROTO_INET, "mullvad"))
+            .is_empty());
+        assert!(parser
+            .parse(&new_generation(1, "mullvad-daemon"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_reports_changes_of_unknown_origin() {
+        let mut parser = NotificationParser::new("mullvad", |tid| tid == 1);
+        let notifications = [
+            table_message(NFT_MSG_DELTABLE, NFPROTO_INET, "mullvad"),
+            message(NFT_MSG_NEWGEN, 0, &[]),
+        ]
+        .concat();
+        assert_eq!(
+            parser.parse(&notifications),
+            vec![Tampering {
+                pid: None,
+                process_name: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ignores_other_tables() {
+        let mut parser = NotificationParser::new("mullvad", |tid| tid == 1);
+        let notifications = [
+            table_message(NFT_MSG_NEWRULE, NFPROTO_INET, "filter"),
+            table_message(NFT_MSG_DELTABLE, NFPROTO_IPV4, "mullvad"),
+            new_generation(4242, "nft"),
+        ]
+        .concat();
+        assert!(parser.parse(&notifications).is_empty());
+    }
+}
@@ -89,15 +89,19 @@ impl Firewall {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 allow_lan,
+                lan_networks,
                 pingable_hosts,
+                allowed_endpoints,
+                ..
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(peer_endpoint)?];
                 rules.extend(self.get_allow_pingable_hosts(&pingable_hosts)?);
+                rules.append(&mut self.get_allowed_endpoints_rules(&allowed_endpoints)?);
                 if allow_lan {
                     // Important to block DNS after allow relay rule (so the relay can operate
                     // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
                 Ok(rules)
             }
@@ -105,6 +109,8 @@ impl Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_networks,
+                ..
             } => {
                 let mut rules = vec![];
                 let allow_tcp_dns_to_relay_rule = self
@@ -160,17 +166,21 @@ impl Firewall {
                 rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str())?);
 
                 if allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
 
                 Ok(rules)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
-                let mut rules = Vec::new();
+            FirewallPolicy::Blocked {
+                allow_lan,
+                lan_networks,
+                allowed_endpoints,
+            } => {
+                let mut rules = self.get_allowed_endpoints_rules(&allowed_endpoints)?;
                 if allow_lan {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
                 Ok(rules)
             }
@@ -191,6 +201,29 @@ impl Firewall {
             .build()?)
     }
 
+    fn get_allowed_endpoints_rules(
+        &self,
+        allowed_endpoints: &[net::AllowedEndpoint],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for endpoint in allowed_endpoints {
+            let rule = self
+                .create_rule_builder(FilterRuleAction::Pass)
+                .direction(pfctl::Direction::Out)
+                .to(pfctl::Endpoint::new(
+                    pfctl::Ip::from(endpoint.network),
+                    endpoint.port,
+                ))
+                .proto(as_pfctl_proto(endpoint.protocol))
+                .keep_state(pfctl::StatePolicy::Keep)
+                .tcp_flags(Self::get_tcp_flags())
+                .quick(true)
+                .build()?;
+            rules.push(rule);
+        }
+        Ok(rules)
+    }
+
     fn get_block_dns_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let block_tcp_dns_rule = self
             .create_rule_builder(FilterRuleAction::Drop)
@@ -264,19 +297,22 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
-    fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_allow_lan_rules(
+        &self,
+        lan_networks: &net::LanNetworks,
+    ) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in super::allowed_lan_nets(lan_networks) {
             let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
             rule_builder.quick(true);
             let allow_out = rule_builder
                 .direction(pfctl::Direction::Out)
                 .from(pfctl::Ip::Any)
-                .to(pfctl::Ip::from(*net))
+                .to(pfctl::Ip::from(net))
                 .build()?;
             let allow_in = rule_builder
                 .direction(pfctl::Direction::In)
-                .from(pfctl::Ip::from(*net))
+                .from(pfctl::Ip::from(net))
                 .to(pfctl::Ip::Any)
                 .build()?;
             rules.push(allow_out);
@@ -1,5 +1,6 @@
+use ipnetwork::IpNetwork;
 #[cfg(unix)]
-use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use ipnetwork::{Ipv4Network, Ipv6Network};
 #[cfg(unix)]
 use lazy_static::lazy_static;
 use std::fmt;
@@ -7,7 +8,11 @@ use std::fmt;
 use std::net::IpAddr;
 #[cfg(unix)]
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use talpid_types::net::Endpoint;
+use talpid_types::{
+    dns::EncryptedDnsProtocol,
+    net::{AllowedEndpoint, Endpoint, ForwardedPort, LanNetworks},
+    tunnel::ErrorDetails,
+};
 
 
 #[cfg(target_os = "macos")]
@@ -15,7 +20,7 @@ use talpid_types::net::Endpoint;
 mod imp;
 
 #[cfg(target_os = "linux")]
-#[path = "linux.rs"]
+#[path = "linux/mod.rs"]
 mod imp;
 
 #[cfg(windows)]
@@ -26,8 +31,25 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(target_os = "linux")]
+pub use self::imp::spawn_watchdog;
 pub use self::imp::Error;
 
+/// Name of the system component that enforces the firewall policy.
+#[cfg(target_os = "macos")]
+const BACKEND_NAME: &str = "pf";
+#[cfg(target_os = "linux")]
+const BACKEND_NAME: &str = "nftables";
+#[cfg(windows)]
+const BACKEND_NAME: &str = "winfw";
+#[cfg(target_os = "android")]
+const BACKEND_NAME: &str = "android";
+
+/// Describes a firewall error, so that it can be reported along with the error state cause.
+pub fn error_details(error: &Error) -> ErrorDetails {
+    ErrorDetails::from_error(BACKEND_NAME, error)
+}
+
 #[cfg(unix)]
 lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to and from these networks.
@@ -66,6 +88,19 @@ lazy_static! {
     ];
     static ref ROUTER_SOLICITATION_OUT_DST_ADDR: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
 }
+
+/// Returns the networks that are allowed when "allow local network" is enabled: the defaults in
+/// `ALLOWED_LAN_NETS`, minus the removed ones, plus the extra ones.
+#[cfg(all(unix, not(target_os = "android")))]
+fn allowed_lan_nets(lan_networks: &LanNetworks) -> Vec<IpNetwork> {
+    ALLOWED_LAN_NETS
+        .iter()
+        .filter(|net| !lan_networks.removed_defaults.contains(net))
+        .chain(lan_networks.extra.iter())
+        .cloned()
+        .collect()
+}
+
 #[cfg(all(unix, not(target_os = "android")))]
 const DHCPV4_SERVER_PORT: u16 = 67;
 #[cfg(all(unix, not(target_os = "android")))]
@@ -92,6 +127,15 @@ pub enum FirewallPolicy {
         pingable_hosts: Vec<IpAddr>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Changes to the networks considered LAN networks.
+        lan_networks: LanNetworks,
+        /// Destination networks that are reached outside the tunnel.
+        excluded_subnets: Vec<IpNetwork>,
+        /// Whether any processes are excluded from the tunnel, in which case their traffic is let
+        /// through outside it.
+        split_tunnel: bool,
+        /// Endpoints that can be reached outside the tunnel.
+        allowed_endpoints: Vec<AllowedEndpoint>,
     },
 
     /// Allow traffic only to server and over tunnel interface
@@ -100,14 +144,37 @@ pub enum FirewallPolicy {
         peer_endpoint: Endpoint,
         /// Metadata about the tunnel and tunnel interface.
         tunnel: crate::tunnel::TunnelMetadata,
+        /// DNS servers that can be reached through the tunnel. Servers on a LAN network can also
+        /// be reached outside the tunnel if LAN access is allowed.
+        dns_servers: Vec<IpAddr>,
+        /// The protocol that the DNS servers are reached with if queries to them are encrypted.
+        /// Otherwise they are reached on port 53.
+        encrypted_dns: Option<EncryptedDnsProtocol>,
+        /// DNS servers of split DNS rules, which are reached on port 53 outside the tunnel.
+        split_dns_servers: Vec<IpAddr>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Changes to the networks considered LAN networks.
+        lan_networks: LanNetworks,
+        /// Destination networks that are reached outside the tunnel.
+        excluded_subnets: Vec<IpNetwork>,
+        /// Whether any processes are excluded from the tunnel, in which case their traffic is let
+        /// through outside it.
+        split_tunnel: bool,
+        /// Ports that accept incoming connections through the tunnel.
+        forwarded_ports: Vec<ForwardedPort>,
+        /// Interfaces whose forwarded traffic is translated to the tunnel address.
+        shared_interfaces: Vec<String>,
     },
 
     /// Block all network traffic in and out from the computer.
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Changes to the networks considered LAN networks.
+        lan_networks: LanNetworks,
+        /// Endpoints that can still be reached.
+        allowed_endpoints: Vec<AllowedEndpoint>,
     },
 }
 
@@ -118,24 +185,36 @@ impl fmt::Display for FirewallPolicy {
                 peer_endpoint,
                 pingable_hosts,
                 allow_lan,
+                excluded_subnets,
+                allowed_endpoints,
+                ..
             } => write!(
                 f,
-                "Connecting to {} with gateways {}, {} LAN",
+                "Connecting to {} with gateways {}, {} LAN{}{}",
                 peer_endpoint,
                 pingable_hosts
                     .iter()
                     .map(ToString::to_string)
                     .collect::<Vec<String>>()
                     .join(","),
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                if *allow_lan { "Allowing" } else { "Blocking" },
+                display_excluded_subnets(excluded_subnets),
+                display_allowed_endpoints(allowed_endpoints)
             ),
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
+                dns_servers,
+                encrypted_dns,
+                split_dns_servers,
                 allow_lan,
+                excluded_subnets,
+                forwarded_ports,
+                shared_interfaces,
+                ..
             } => write!(
                 f,
-                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN{}{}{}{}{}",
                 peer_endpoint,
                 tunnel.interface,
                 tunnel
@@ -146,21 +225,148 @@ impl fmt::Display for FirewallPolicy {
                     .join(","),
                 tunnel.ipv4_gateway,
                 tunnel.ipv6_gateway,
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                if *allow_lan { "Allowing" } else { "Blocking" },
+                display_dns_servers(dns_servers, *encrypted_dns),
+                display_split_dns_servers(split_dns_servers),
+                display_excluded_subnets(excluded_subnets),
+                display_forwarded_ports(forwarded_ports),
+                display_shared_interfaces(shared_interfaces)
             ),
-            FirewallPolicy::Blocked { allow_lan } => write!(
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoints,
+                ..
+            } => write!(
                 f,
-                "Blocked, {} LAN",
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                "Blocked, {} LAN{}",
+                if *allow_lan { "Allowing" } else { "Blocking" },
+                display_allowed_endpoints(allowed_endpoints)
             ),
         }
     }
 }
 
+fn display_excluded_subnets(excluded_subnets: &[IpNetwork]) -> String {
+    if excluded_subnets.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ", excluding {}",
+            excluded_subnets
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+fn display_allowed_endpoints(allowed_endpoints: &[AllowedEndpoint]) -> String {
+    if allowed_endpoints.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ", allowing {}",
+            allowed_endpoints
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+fn display_dns_servers(
+    dns_servers: &[IpAddr],
+    encrypted_dns: Option<EncryptedDnsProtocol>,
+) -> String {
+    format!(
+        ", DNS {}{}",
+        dns_servers
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        encrypted_dns
+            .map(|protocol| format!(" over {}", protocol))
+            .unwrap_or_default()
+    )
+}
+
+fn display_split_dns_servers(split_dns_servers: &[IpAddr]) -> String {
+    if split_dns_servers.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ", split DNS {}",
+            split_dns_servers
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+fn display_forwarded_ports(forwarded_ports: &[ForwardedPort]) -> String {
+    if forwarded_ports.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ", forwarding {}",
+            forwarded_ports
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+fn display_shared_interfaces(shared_interfaces: &[String]) -> String {
+    if shared_interfaces.is_empty() {
+        String::new()
+    } else {
+        format!(", sharing with {}", shared_interfaces.join(","))
+    }
+}
+
+/// Renders the rules that the given policy results in as an `nft -f` script, without applying
+/// them.
+#[cfg(target_os = "linux")]
+pub fn render_policy(policy: &FirewallPolicy) -> String {
+    imp::render_policy(policy)
+}
+
+/// Returns how many packets and bytes each rule of the applied policy has matched. Rules that
+/// are not counted are left out.
+#[cfg(target_os = "linux")]
+pub fn rule_stats() -> Result<Vec<talpid_types::firewall::RuleStats>, Error> {
+    imp::rule_stats()
+}
+
+/// Renders the rules that keep traffic blocked before the daemon has started as an `nft -f`
+/// script. They only allow loopback and DHCP traffic, and do not depend on any settings.
+#[cfg(target_os = "linux")]
+pub fn render_boot_ruleset() -> String {
+    imp::render_boot_ruleset()
+}
+
+/// A change that another program made to the rules of the applied policy.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Tampering {
+    /// ID of the process, or thread, that made the change, if known.
+    pub pid: Option<u32>,
+    /// Name of the process that made the change, if known.
+    pub process_name: Option<String>,
+}
+
 /// Manages network security of the computer/device. Can apply and enforce firewall policies
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
     inner: imp::Firewall,
+    /// The policy that should currently be enforced, kept so that it can be restored.
+    policy: Option<FirewallPolicy>,
 }
 
 /// Arguments required when first initializing the firewall.
@@ -176,6 +382,7 @@ impl Firewall {
     pub fn new(args: FirewallArguments) -> Result<Self, Error> {
         Ok(Firewall {
             inner: imp::Firewall::new(args)?,
+            policy: None,
         })
     }
 
@@ -183,17 +390,58 @@ impl Firewall {
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
+        self.policy = Some(policy.clone());
         self.inner.apply_policy(policy)
     }
 
+    /// Applies the last applied `FirewallPolicy` again, restoring rules that were changed by other
+    /// programs. Does nothing if no policy is applied.
+    pub fn reapply_policy(&mut self) -> Result<(), Error> {
+        match self.policy.clone() {
+            Some(policy) => {
+                log::info!("Reapplying firewall policy: {}", policy);
+                self.inner.apply_policy(policy)
+            }
+            None => Ok(()),
+        }
+    }
+
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
+        self.policy = None;
         self.inner.reset_policy()
     }
 }
 
+/// A firewall able to enforce `FirewallPolicy`s. Implemented by [`Firewall`], and used by the
+/// tunnel state machine so that the system firewall can be replaced.
+pub trait FirewallBackend {
+    /// Applies and starts enforcing the given `FirewallPolicy`.
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error>;
+
+    /// Applies the last applied `FirewallPolicy` again, if any.
+    fn reapply_policy(&mut self) -> Result<(), Error>;
+
+    /// Resets/removes any currently enforced `FirewallPolicy`.
+    fn reset_policy(&mut self) -> Result<(), Error>;
+}
+
+impl FirewallBackend for Firewall {
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
+        Firewall::apply_policy(self, policy)
+    }
+
+    fn reapply_policy(&mut self) -> Result<(), Error> {
+        Firewall::reapply_policy(self)
+    }
+
+    fn reset_policy(&mut self) -> Result<(), Error> {
+        Firewall::reset_policy(self)
+    }
+}
+
 /// Abstract firewall interaction trait. Used by the OS specific implementations.
 trait FirewallT: Sized {
     /// The error type thrown by the implementer of this trait
@@ -209,3 +457,48 @@ trait FirewallT: Sized {
     /// modifying the system.
     fn reset_policy(&mut self) -> Result<(), Self::Error>;
 }
+
+#[cfg(all(test, unix, not(target_os = "android")))]
+mod test {
+    use super::*;
+
+    fn net(network: &str) -> IpNetwork {
+        network.parse().unwrap()
+    }
+
+    #[test]
+    fn test_default_allowed_lan_nets() {
+        assert_eq!(
+            allowed_lan_nets(&LanNetworks::default()),
+            ALLOWED_LAN_NETS.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_allowed_lan_nets_with_changes() {
+        let lan_networks = LanNetworks {
+            extra: vec![net("100.64.0.0/10"), net("fd00:aaaa::/32")],
+            removed_defaults: vec![net("10.0.0.0/8"), net("fc00::/7")],
+        };
+        assert_eq!(
+            allowed_lan_nets(&lan_networks),
+            vec![
+                net("172.16.0.0/12"),
+                net("192.168.0.0/16"),
+                net("169.254.0.0/16"),
+                net("fe80::/10"),
+                net("100.64.0.0/10"),
+                net("fd00:aaaa::/32"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_removing_unknown_default_has_no_effect() {
+        let lan_networks = LanNetworks {
+            extra: vec![],
+            removed_defaults: vec![net("10.0.0.0/16")],
+        };
+        assert_eq!(allowed_lan_nets(&lan_networks), ALLOWED_LAN_NETS.to_vec());
+    }
+}
@@ -5,8 +5,8 @@ use std::{net::IpAddr, ptr};
 use self::winfw::*;
 use super::{FirewallArguments, FirewallPolicy, FirewallT};
 use crate::winnet;
-use log::{debug, error, trace};
-use talpid_types::net::Endpoint;
+use log::{debug, error, trace, warn};
+use talpid_types::net::{AllowedEndpoint, Endpoint, LanNetworks};
 use widestring::WideCString;
 
 
@@ -80,7 +80,12 @@ impl FirewallT for Firewall {
                 peer_endpoint,
                 pingable_hosts,
                 allow_lan,
+                lan_networks,
+                allowed_endpoints,
+                ..
             } => {
+                Self::warn_if_custom_lan_networks(&lan_networks);
+                Self::warn_if_allowed_endpoints(&allowed_endpoints);
                 let cfg = &WinFwSettings::new(allow_lan);
                 // TODO: Determine interface alias at runtime
                 self.set_connecting_state(
@@ -94,11 +99,20 @@ impl FirewallT for Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_networks,
+                ..
             } => {
+                Self::warn_if_custom_lan_networks(&lan_networks);
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_connected_state(&peer_endpoint, &cfg, &tunnel)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                lan_networks,
+                allowed_endpoints,
+            } => {
+                Self::warn_if_custom_lan_networks(&lan_networks);
+                Self::warn_if_allowed_endpoints(&allowed_endpoints);
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_blocked_state(&cfg)
             }
@@ -126,6 +140,20 @@ impl Drop for Firewall {
 }
 
 impl Firewall {
+    /// The LAN networks are hardcoded in winfw, so the daemon rejects custom ones.
+    fn warn_if_custom_lan_networks(lan_networks: &LanNetworks) {
+        if *lan_networks != LanNetworks::default() {
+            warn!("Custom LAN networks are not supported on Windows, using the defaults");
+        }
+    }
+
+    /// winfw has no filters for arbitrary endpoints yet.
+    fn warn_if_allowed_endpoints(allowed_endpoints: &[AllowedEndpoint]) {
+        if !allowed_endpoints.is_empty() {
+            warn!("Allowed endpoints are not supported on Windows and remain blocked");
+        }
+    }
+
     fn set_connecting_state(
         &mut self,
         endpoint: &Endpoint,
@@ -52,5 +52,9 @@ mod mktemp;
 #[cfg(target_os = "linux")]
 mod linux;
 
+/// Excluding processes from the tunnel.
+#[cfg(target_os = "linux")]
+pub mod split_tunnel;
+
 /// A pair of functions to monitor and establish connectivity with ICMP
 mod ping_monitor;
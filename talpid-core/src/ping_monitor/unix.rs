@@ -1,4 +1,4 @@
-use std::{io, net::Ipv4Addr};
+use std::{io, net::Ipv4Addr, time::Duration};
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -9,17 +9,20 @@ pub enum Error {
     TimeoutError,
 }
 
-/// A pinger that sends ICMP requests without waiting for responses
+/// A pinger that sends ICMP requests without waiting for responses. The outcome of each request
+/// is collected once its ping process has exited, and can be read with `take_replies`.
 pub struct Pinger {
     addr: Ipv4Addr,
     interface_name: String,
     processes: Vec<duct::Handle>,
+    replies: Vec<Option<Duration>>,
 }
 
 impl Pinger {
     pub fn new(addr: Ipv4Addr, interface_name: String) -> Result<Self, Error> {
         Ok(Self {
             processes: vec![],
+            replies: vec![],
             addr,
             interface_name,
         })
@@ -35,17 +38,39 @@ impl Pinger {
         Ok(())
     }
 
+    /// Returns the round trip times of all requests that have completed since the last call.
+    /// Requests that went unanswered are returned as `None`.
+    pub fn take_replies(&mut self) -> Vec<Option<Duration>> {
+        self.try_deplete_process_list();
+        std::mem::replace(&mut self.replies, vec![])
+    }
+
     fn try_deplete_process_list(&mut self) {
+        let replies = &mut self.replies;
         self.processes.retain(|child| {
             match child.try_wait() {
                 // child has terminated, doesn't have to be retained
-                Ok(Some(_)) => false,
+                Ok(Some(output)) => {
+                    replies.push(if output.status.success() {
+                        parse_rtt(&String::from_utf8_lossy(&output.stdout))
+                    } else {
+                        None
+                    });
+                    false
+                }
                 _ => true,
             }
         });
     }
 }
 
+/// Parses the round trip time from the `time=<ms> ms` part of the output of `ping`.
+fn parse_rtt(output: &str) -> Option<Duration> {
+    let time = output.split("time=").nth(1)?;
+    let millis: f64 = time.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_micros((millis * 1000.0).round() as u64))
+}
+
 impl Drop for Pinger {
     fn drop(&mut self) {
         for child in self.processes.iter_mut() {
@@ -86,6 +111,21 @@ fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expressio
 
     duct::cmd("ping", args)
         .stdin_null()
-        .stdout_null()
+        .stdout_capture()
         .unchecked()
 }
+
+#[cfg(test)]
+mod test {
+    use super::parse_rtt;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_rtt() {
+        let output =
+            "PING 10.64.0.1 (10.64.0.1) from 10.64.0.2 wg-mullvad: 56(84) bytes of data.\n\
+                      64 bytes from 10.64.0.1: icmp_seq=1 ttl=64 time=31.4 ms\n";
+        assert_eq!(parse_rtt(output), Some(Duration::from_micros(31400)));
+        assert_eq!(parse_rtt("1 packets transmitted, 0 received"), None);
+    }
+}
@@ -60,6 +60,11 @@ impl Pinger {
         })
     }
 
+    /// Replies are not read on Windows, so no round trip times are ever reported.
+    pub fn take_replies(&mut self) -> Vec<Option<Duration>> {
+        vec![]
+    }
+
     pub fn send_icmp(&mut self) -> Result<()> {
         let dest = SocketAddr::new(IpAddr::from(self.addr), 0);
         let request = self.next_ping_request();
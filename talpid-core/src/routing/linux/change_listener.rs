@@ -1,6 +1,6 @@
 use crate::routing::{Node, Route};
 
-use super::RouteChange;
+use super::{netlink, RouteChange};
 use futures::{future::Either, sync::mpsc, Async, Future, Stream};
 use std::{collections::BTreeMap, io, net::IpAddr};
 
@@ -79,10 +79,16 @@ impl RouteChangeListener {
                 Ok(None)
             }
 
-            NetlinkPayload::Rtnl(RtnlMessage::NewRoute(new_route)) => {
+            // Only the main table holds the default routes that are tracked. The others may hold
+            // routes added by the route manager itself.
+            NetlinkPayload::Rtnl(RtnlMessage::NewRoute(new_route))
+                if netlink::is_in_main_table(&new_route) =>
+            {
                 self.get_route(new_route).map(RouteChange::Add).map(Some)
             }
-            NetlinkPayload::Rtnl(RtnlMessage::DelRoute(old_route)) => {
+            NetlinkPayload::Rtnl(RtnlMessage::DelRoute(old_route))
+                if netlink::is_in_main_table(&old_route) =>
+            {
                 self.get_route(old_route).map(RouteChange::Remove).map(Some)
             }
             _ => Ok(None),
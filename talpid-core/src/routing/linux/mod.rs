@@ -1,46 +1,73 @@
-use crate::routing::{NetNode, Node, Route};
+use crate::{
+    routing::{NetNode, Node, Route},
+    split_tunnel,
+};
 
 use ipnetwork::IpNetwork;
-use std::{
-    collections::{HashMap, HashSet},
-    io,
-    process::{Command, Stdio},
-};
+use std::collections::{HashMap, HashSet};
 
 mod change_listener;
 use change_listener::{Error as RouteChangeListenerError, RouteChangeListener};
 
-use futures::{sync::oneshot, Async, Future, Stream};
+mod netlink;
+use netlink::{IpVersion, NetlinkConnection, RoutingRule, Table};
+
+use futures::{
+    sync::{mpsc, oneshot},
+    Async, Future, Stream,
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Routing table holding the routes of the route manager when policy routing is used.
+const POLICY_ROUTING_TABLE_ID: u32 = 0x4d9f42;
+/// The tunnel marks its own encrypted traffic with this when policy routing is used. Such
+/// traffic does not use the routing table of policy routing, and is instead routed by the main
+/// table, including its default routes. It differs from the mark of excluded traffic, so that the
+/// firewall can tell the two apart.
+pub const TUNNEL_FWMARK: u32 = 0x6d6f6c65;
+
+/// Priority of the rule that lets excluded traffic use the main table, except for its default
+/// routes and the routes that cover the whole address space, such as those of the tunnel.
+const SPLIT_TUNNEL_MAIN_RULE_PRIORITY: u32 = 32762;
+/// Priority of the rule that sends excluded traffic via the default routes of the main table.
+/// Both rules for excluded traffic come before those of policy routing, which would otherwise
+/// send it into the tunnel.
+const SPLIT_TUNNEL_TABLE_RULE_PRIORITY: u32 = 32763;
+/// Priority of the rule that lets the routes of the main table take precedence over those of
+/// policy routing, except for its default routes.
+const POLICY_ROUTING_MAIN_RULE_PRIORITY: u32 = 32764;
+/// Priority of the rule that sends all traffic without [`TUNNEL_FWMARK`] via the routing table of
+/// policy routing.
+const POLICY_ROUTING_TABLE_RULE_PRIORITY: u32 = 32765;
+
 /// Errors that can happen in the Linux routing integration
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
+    /// Failed to open a netlink connection for managing routes.
+    #[error(display = "Failed to open a netlink connection")]
+    NetlinkConnectError(#[error(source)] netlink::Error),
+
     /// Failed to add route.
     #[error(display = "Failed to add route")]
-    FailedToAddRoute(#[error(source)] io::Error),
+    FailedToAddRoute(#[error(source)] netlink::Error),
 
     /// Failed to remove route.
     #[error(display = "Failed to remove route")]
-    FailedToRemoveRoute(#[error(source)] io::Error),
+    FailedToRemoveRoute(#[error(source)] netlink::Error),
 
-    /// Error while running "ip route".
-    #[error(display = "Error while running \"ip route\"")]
-    FailedToRunIp(#[error(source)] io::Error),
+    /// Failed to add routing rules for excluded traffic.
+    #[error(display = "Failed to add routing rules for split tunneling")]
+    FailedToAddSplitTunnelRule(#[error(source)] netlink::Error),
 
-    /// Invocation of `ip route` ended with a non-zero exit code
-    #[error(display = "ip returend a non-zero exit code")]
-    ErrorIpFailed,
+    /// Failed to add routing rules for policy routing.
+    #[error(display = "Failed to add routing rules for policy routing")]
+    FailedToAddPolicyRoutingRule(#[error(source)] netlink::Error),
 
-    /// Received unexpected output from `ip route`
-    #[error(display = "Received unexpected output from \"ip\"")]
-    UnexpectedOutput,
-
-    /// No default route exists
-    #[error(display = "No default route in \"ip route\" output")]
-    NoDefaultRoute,
+    /// Failed to list the default routes.
+    #[error(display = "Failed to get the default routes")]
+    FailedToGetDefaultRoutes(#[error(source)] netlink::Error),
 
     /// Route table change stream failed.
     #[error(display = "Route change listener failed")]
@@ -51,8 +78,26 @@ pub enum Error {
     ChangeListenerClosed,
 }
 
+/// Changes requested through a [`RouteManagerHandle`](super::RouteManagerHandle).
+pub enum RouteManagerCommand {
+    /// Applies the routes, in the routing table of policy routing if the flag is set, and sends
+    /// back the result.
+    AddRoutes(
+        HashMap<IpNetwork, NetNode>,
+        bool,
+        oneshot::Sender<Result<()>>,
+    ),
+    /// Removes all routes added so far, and signals when done.
+    ClearRoutes(oneshot::Sender<()>),
+    /// Adds or removes the routing rules and routes of excluded traffic, and signals when done.
+    SetSplitTunnel(bool, oneshot::Sender<()>),
+}
+
 pub struct RouteManagerImpl {
     changes: RouteChangeListener,
+    netlink: NetlinkConnection,
+    // table that the routes are added to
+    table: Table,
 
     // currently added routes
     added_routes: HashSet<Route>,
@@ -68,39 +113,36 @@ pub struct RouteManagerImpl {
     shutdown_finished_tx: Option<oneshot::Sender<()>>,
     shutdown_rx: oneshot::Receiver<oneshot::Sender<()>>,
     should_shut_down: bool,
+    commands: mpsc::UnboundedReceiver<RouteManagerCommand>,
+    /// Whether the routing rules of excluded traffic are in place.
+    split_tunnel: bool,
 }
 
 impl RouteManagerImpl {
-    /// Creates a new RouteManager.
+    /// Creates a new RouteManager. More routes can be added through `commands`.
     pub fn new(
         required_routes: HashMap<IpNetwork, NetNode>,
         shutdown_rx: oneshot::Receiver<oneshot::Sender<()>>,
+        commands: mpsc::UnboundedReceiver<RouteManagerCommand>,
     ) -> Result<Self> {
         let changes = RouteChangeListener::new().map_err(Error::ChangeListenerError)?;
+        let mut netlink = NetlinkConnection::new().map_err(Error::NetlinkConnectError)?;
 
-        let mut required_normal_routes = HashSet::new();
-        let mut required_default_routes = HashSet::new();
-
-        for (destination, node) in required_routes {
-            match node {
-                NetNode::RealNode(node) => {
-                    required_normal_routes.insert(Route::new(node, destination));
-                }
-                NetNode::DefaultNode => {
-                    required_default_routes.insert(destination);
-                }
-            }
-        }
-
-        let default_routes = Self::get_default_routes()?;
+        let default_routes = Self::get_default_routes(&mut netlink)?;
 
         let best_default_node_v4 = Self::pick_best_default_node(&default_routes, true);
         let best_default_node_v6 = Self::pick_best_default_node(&default_routes, false);
 
+        // Remove rules and routes left behind if the daemon was not shut down cleanly.
+        Self::delete_split_tunnel_rules(&mut netlink);
+        Self::delete_policy_routing(&mut netlink);
+
         let mut monitor = Self {
             changes,
+            netlink,
+            table: Table::Main,
 
-            required_default_routes,
+            required_default_routes: HashSet::new(),
             added_routes: HashSet::new(),
 
             default_routes,
@@ -110,23 +152,93 @@ impl RouteManagerImpl {
             shutdown_finished_tx: None,
             shutdown_rx,
             should_shut_down: false,
+            commands,
+            split_tunnel: false,
         };
-        for normal_route in required_normal_routes.iter() {
-            monitor.add_route(&normal_route)?;
+        monitor.add_required_routes(required_routes, false)?;
+        Ok(monitor)
+    }
+
+    /// Adds routes that stay in place until they are cleared. Routes through the default node
+    /// follow the best default route. If `policy_routing` is set, the routes are added to a
+    /// dedicated routing table, which all traffic without [`TUNNEL_FWMARK`] looks up after the
+    /// main table, and so are the routes added later, until they are cleared.
+    fn add_required_routes(
+        &mut self,
+        required_routes: HashMap<IpNetwork, NetNode>,
+        policy_routing: bool,
+    ) -> Result<()> {
+        if policy_routing && self.table == Table::Main {
+            Self::add_policy_routing_rules()?;
+            self.table = Table::Id(POLICY_ROUTING_TABLE_ID);
         }
 
-        for prefix in monitor.required_default_routes.clone().into_iter() {
-            if let (false, _, Some(default_node)) | (true, Some(default_node), _) = (
-                prefix.is_ipv4(),
-                &monitor.best_default_node_v4,
-                &monitor.best_default_node_v6,
-            ) {
-                // best to pick a single node identifier rather than device + ip
-                let route = Route::new(default_node.clone(), prefix);
-                monitor.add_route(&route)?;
+        for (destination, node) in required_routes {
+            match node {
+                NetNode::RealNode(node) => self.add_route(&Route::new(node, destination))?,
+                NetNode::DefaultNode => {
+                    self.required_default_routes.insert(destination);
+                    let default_node = if destination.is_ipv4() {
+                        self.best_default_node_v4.clone()
+                    } else {
+                        self.best_default_node_v6.clone()
+                    };
+                    // best to pick a single node identifier rather than device + ip
+                    if let Some(default_node) = default_node {
+                        self.add_route(&Route::new(default_node, destination))?;
+                    }
+                }
             }
         }
-        Ok(monitor)
+        Ok(())
+    }
+
+    fn process_commands(&mut self) {
+        while let Ok(Async::Ready(Some(command))) = self.commands.poll() {
+            match command {
+                RouteManagerCommand::AddRoutes(routes, policy_routing, result_tx) => {
+                    let result = self.add_required_routes(routes, policy_routing);
+                    if result_tx.send(result).is_err() {
+                        log::warn!("The routes were added after the caller stopped waiting");
+                    }
+                }
+                RouteManagerCommand::ClearRoutes(done_tx) => {
+                    self.clear_routes();
+                    let _ = done_tx.send(());
+                }
+                RouteManagerCommand::SetSplitTunnel(enabled, done_tx) => {
+                    self.set_split_tunnel(enabled);
+                    let _ = done_tx.send(());
+                }
+            }
+        }
+    }
+
+    /// Adds the routing rules of excluded traffic and keeps its default routes in sync with the
+    /// main table, or removes them all.
+    fn set_split_tunnel(&mut self, enabled: bool) {
+        if self.split_tunnel == enabled {
+            return;
+        }
+        if enabled {
+            if let Err(e) = Self::add_split_tunnel_rules(&mut self.netlink) {
+                // Excluded traffic is then routed through the tunnel, which is not worth failing
+                // for.
+                log::error!("Failed to add routing rules for excluded traffic - {}", e);
+                return;
+            }
+            for (node, v4) in &[
+                (self.best_default_node_v4.clone(), true),
+                (self.best_default_node_v6.clone(), false),
+            ] {
+                if let Some(node) = node {
+                    self.set_split_tunnel_default_route(node, *v4);
+                }
+            }
+        } else {
+            Self::delete_split_tunnel_rules(&mut self.netlink);
+        }
+        self.split_tunnel = enabled;
     }
 
     fn process_route_table_change(&mut self) -> Result<()> {
@@ -177,6 +289,9 @@ impl RouteManagerImpl {
                     log::error!("Failed to add new route {} - {}", &new_node, e);
                 }
             }
+            if self.split_tunnel {
+                self.set_split_tunnel_default_route(&new_node, true);
+            }
             self.best_default_node_v4 = Some(new_node);
         }
 
@@ -204,6 +319,9 @@ impl RouteManagerImpl {
                     log::error!("Failed to add new route {} - {}", &new_node, e);
                 }
             }
+            if self.split_tunnel {
+                self.set_split_tunnel_default_route(&new_node, false);
+            }
             self.best_default_node_v6 = Some(new_node);
         }
     }
@@ -229,70 +347,12 @@ impl RouteManagerImpl {
             .map(|route| route.node)
     }
 
-    fn route_cmd(action: &str, route: &Route) -> Command {
-        let mut cmd = Command::new("ip");
-
-        cmd.arg(ip_vers(&route))
-            .arg("route")
-            .arg(action)
-            .arg(route.prefix.to_string());
-
-        if let Some(addr) = route.node.get_address() {
-            cmd.arg("via").arg(addr.to_string());
-        };
-        if let Some(device) = route.node.get_device() {
-            cmd.arg("dev").arg(device);
-        };
-        if let Some(metric) = route.metric {
-            cmd.arg("metric").arg(metric.to_string());
-        };
-
-        cmd
-    }
-
-    fn run_cmd(mut cmd: Command, err: impl Fn(io::Error) -> Error) -> Result<()> {
-        log::trace!("running cmd - {:?}", &cmd);
-        let status = cmd.status().map_err(|e| err(e))?;
-        match status.code() {
-            Some(0) => Ok(()),
-            Some(i) => Err(err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("exit status {}", i),
-            ))),
-            None => Err(err(io::Error::new(
-                io::ErrorKind::Other,
-                "interrupted by signal",
-            ))),
-        }
-    }
-
-    fn get_default_routes_inner(ip_version: IpVersion) -> Result<Vec<Route>> {
-        let mut cmd = Command::new("ip");
-        cmd.arg(ip_version.to_route_arg()).arg("route").arg("show");
-
-        cmd.stdout(Stdio::piped())
-            .output()
-            .map_err(Error::FailedToRunIp)
-            .and_then(move |output| {
-                let output_lines = String::from_utf8(output.stdout.clone())
-                    .map_err(|_| Error::UnexpectedOutput)?;
-                Ok(output_lines
-                    .lines()
-                    .filter_map(|line| {
-                        if line.starts_with("default") {
-                            parse_ip_route_show_line(line, ip_version)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect())
-            })
-    }
-
     /// Adds routes to the system routing table.
     fn add_route(&mut self, route: &Route) -> Result<()> {
-        let cmd = Self::route_cmd("replace", route);
-        Self::run_cmd(cmd, Error::FailedToAddRoute)?;
+        log::trace!("Adding route {}", route);
+        self.netlink
+            .replace_route(route, self.table)
+            .map_err(Error::FailedToAddRoute)?;
         self.added_routes.insert(route.clone());
         Ok(())
     }
@@ -300,42 +360,164 @@ impl RouteManagerImpl {
     /// Removes previously set routes. If routes were set for specific tables, the whole tables
     /// will be removed.
     fn delete_route(&mut self, route: &Route) -> Result<()> {
-        let cmd = Self::route_cmd("delete", route);
-        Self::run_cmd(cmd, Error::FailedToRemoveRoute)?;
+        log::trace!("Removing route {}", route);
+        self.netlink
+            .delete_route(route, self.table)
+            .map_err(Error::FailedToRemoveRoute)?;
         self.added_routes.remove(route);
         Ok(())
     }
 
-    fn cleanup_routes(&mut self) {
+    /// Removes the added routes, and stops using the routing table of policy routing.
+    fn clear_routes(&mut self) {
         for route in self.added_routes.drain().collect::<Vec<_>>().iter() {
             if let Err(e) = self.delete_route(&route) {
                 log::error!("Failed to remove route - {} - {}", route, e);
             }
         }
+        self.required_default_routes.clear();
+        if self.table != Table::Main {
+            Self::delete_policy_routing(&mut self.netlink);
+            self.table = Table::Main;
+        }
     }
 
+    fn cleanup_routes(&mut self) {
+        self.clear_routes();
+        self.set_split_tunnel(false);
+    }
 
-    /// Retrieves the gateway for the default route
-    fn get_default_routes() -> Result<HashSet<Route>> {
-        let v4_routes = Self::get_default_routes_inner(IpVersion::V4)?;
-        let v6_routes = Self::get_default_routes_inner(IpVersion::V6)?;
-        Ok(v4_routes.into_iter().chain(v6_routes.into_iter()).collect())
+    /// Adds the rules that send all traffic without [`TUNNEL_FWMARK`] via the routing table of
+    /// policy routing. Like with `wg-quick`, the main table is looked up first, ignoring its
+    /// default routes, so that the routes of other interfaces still take precedence.
+    fn add_policy_routing_rules() -> Result<()> {
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            for rule in &Self::policy_routing_rules(*ip_version) {
+                match netlink::add_rule(rule) {
+                    // The kernel does not support IPv6.
+                    Err(netlink::Error::RequestRejected(ref error))
+                        if *ip_version == IpVersion::V6
+                            && error.raw_os_error() == Some(libc::EAFNOSUPPORT) =>
+                    {
+                        break
+                    }
+                    result => result.map_err(Error::FailedToAddPolicyRoutingRule)?,
+                }
+            }
+        }
+        Ok(())
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-enum IpVersion {
-    V4,
-    V6,
-}
+    fn delete_policy_routing(connection: &mut NetlinkConnection) {
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            for rule in &Self::policy_routing_rules(*ip_version) {
+                // Fails if the rule does not exist.
+                let _ = netlink::delete_rule(rule);
+            }
+            let table = Table::Id(POLICY_ROUTING_TABLE_ID);
+            if let Err(e) = connection.flush_table(*ip_version, table) {
+                log::error!("Failed to remove routes of policy routing - {}", e);
+            }
+        }
+    }
+
+    fn policy_routing_rules(ip_version: IpVersion) -> [RoutingRule; 2] {
+        [
+            RoutingRule {
+                ip_version,
+                mark: None,
+                invert: false,
+                table: Table::Main,
+                priority: POLICY_ROUTING_MAIN_RULE_PRIORITY,
+                suppress_prefix_length: Some(0),
+            },
+            RoutingRule {
+                ip_version,
+                mark: Some(TUNNEL_FWMARK),
+                invert: true,
+                table: Table::Id(POLICY_ROUTING_TABLE_ID),
+                priority: POLICY_ROUTING_TABLE_RULE_PRIORITY,
+                suppress_prefix_length: None,
+            },
+        ]
+    }
+
+    /// Adds the rules that route traffic marked by the firewall as excluded from the tunnel.
+    /// Such traffic is looked up in the main table, ignoring any route with a prefix of length 1
+    /// or less, since that is how the tunnel captures all traffic. The default routes are instead
+    /// looked up in a separate table that is kept in sync with the main table.
+    fn add_split_tunnel_rules(connection: &mut NetlinkConnection) -> Result<()> {
+        // Remove rules left behind if the daemon was not shut down cleanly.
+        Self::delete_split_tunnel_rules(connection);
+
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            for rule in &Self::split_tunnel_rules(*ip_version) {
+                netlink::add_rule(rule).map_err(Error::FailedToAddSplitTunnelRule)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_split_tunnel_rules(connection: &mut NetlinkConnection) {
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            for rule in &Self::split_tunnel_rules(*ip_version) {
+                // Fails if the rule does not exist.
+                let _ = netlink::delete_rule(rule);
+            }
+            let table = Table::Id(split_tunnel::ROUTING_TABLE_ID);
+            if let Err(e) = connection.flush_table(*ip_version, table) {
+                log::error!("Failed to remove routes for excluded traffic - {}", e);
+            }
+        }
+    }
+
+    fn split_tunnel_rules(ip_version: IpVersion) -> [RoutingRule; 2] {
+        [
+            RoutingRule {
+                ip_version,
+                mark: Some(split_tunnel::MARK),
+                invert: false,
+                table: Table::Main,
+                priority: SPLIT_TUNNEL_MAIN_RULE_PRIORITY,
+                suppress_prefix_length: Some(1),
+            },
+            RoutingRule {
+                ip_version,
+                mark: Some(split_tunnel::MARK),
+                invert: false,
+                table: Table::Id(split_tunnel::ROUTING_TABLE_ID),
+                priority: SPLIT_TUNNEL_TABLE_RULE_PRIORITY,
+                suppress_prefix_length: None,
+            },
+        ]
+    }
 
-impl IpVersion {
-    fn to_route_arg(self) -> &'static str {
-        match self {
-            IpVersion::V4 => "-4",
-            IpVersion::V6 => "-6",
+    /// Points the default route of excluded traffic at the given node.
+    fn set_split_tunnel_default_route(&mut self, node: &Node, v4: bool) {
+        let prefix = if v4 {
+            "0.0.0.0/0".parse().unwrap()
+        } else {
+            "::/0".parse().unwrap()
+        };
+        let route = Route::new(node.clone(), prefix);
+        let table = Table::Id(split_tunnel::ROUTING_TABLE_ID);
+        if let Err(e) = self.netlink.replace_route(&route, table) {
+            log::error!("Failed to set default route for excluded traffic - {}", e);
         }
     }
+
+    /// Retrieves the gateway for the default route
+    fn get_default_routes(connection: &mut NetlinkConnection) -> Result<HashSet<Route>> {
+        let mut routes = HashSet::new();
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            routes.extend(
+                connection
+                    .default_routes(*ip_version, Table::Main)
+                    .map_err(Error::FailedToGetDefaultRoutes)?,
+            );
+        }
+        Ok(routes)
+    }
 }
 
 impl Future for RouteManagerImpl {
@@ -353,6 +535,7 @@ impl Future for RouteManagerImpl {
                     self.should_shut_down = true;
                 }
             };
+            self.process_commands();
             self.process_route_table_change()?;
         }
         if self.should_shut_down {
@@ -375,66 +558,68 @@ impl Drop for RouteManagerImpl {
     }
 }
 
-// intended to parse lines sucha as the following:
-//      default via 192.168.1.1 dev wlp61s0 proto dhcp metric 600
-fn parse_ip_route_show_line(line: &str, ip_version: IpVersion) -> Option<Route> {
-    let mut node_ip = None;
-    let mut device = None;
-    let mut metric = None;
-
-    let mut tokens = line.split_whitespace();
-    let prefix_str = tokens.next()?;
-    let prefix = match prefix_str {
-        "default" => match ip_version {
-            IpVersion::V4 => "0.0.0.0/0".parse().unwrap(),
-            IpVersion::V6 => "::/0".parse().unwrap(),
-        },
-        prefix_str => prefix_str.parse().ok()?,
-    };
-
-    let tokens: Vec<&str> = tokens.collect();
-    for pair in tokens.chunks(2) {
-        if pair.len() != 2 {
-            log::error!("unexpected output from ip");
-            break;
+#[derive(Debug, PartialEq)]
+enum RouteChange {
+    Add(Route),
+    Remove(Route),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Priority of the rule that the kernel adds for the main table.
+    const MAIN_TABLE_RULE_PRIORITY: u32 = 32766;
+
+    #[test]
+    fn test_split_tunnel_rules() {
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            let [main_rule, table_rule] = RouteManagerImpl::split_tunnel_rules(*ip_version);
+
+            for rule in &[main_rule, table_rule] {
+                assert_eq!(rule.ip_version, *ip_version);
+                assert_eq!(rule.mark, Some(split_tunnel::MARK));
+                assert!(!rule.invert);
+                assert!(rule.priority < MAIN_TABLE_RULE_PRIORITY);
+            }
+
+            // The routes of the tunnel cover the whole address space with two /1 routes
+            assert_eq!(main_rule.table, Table::Main);
+            assert_eq!(main_rule.suppress_prefix_length, Some(1));
+
+            assert!(main_rule.priority < table_rule.priority);
+            assert_eq!(table_rule.table, Table::Id(split_tunnel::ROUTING_TABLE_ID));
+            assert_eq!(table_rule.suppress_prefix_length, None);
         }
-        let kind = pair[0];
-        let value = pair[1];
-
-        match kind {
-            "via" => node_ip = value.parse().ok(),
-            "dev" => device = Some(value.to_string()),
-            "metric" => metric = value.parse().ok(),
-            _ => continue,
-        };
     }
 
-    if node_ip.is_none() && device.is_none() {
-        None
-    } else {
-        let node = Node {
-            ip: node_ip,
-            device,
-        };
+    #[test]
+    fn test_policy_routing_rules() {
+        assert_ne!(TUNNEL_FWMARK, split_tunnel::MARK);
 
-        Some(Route {
-            node,
-            prefix,
-            metric,
-        })
-    }
-}
+        for ip_version in &[IpVersion::V4, IpVersion::V6] {
+            let [main_rule, table_rule] = RouteManagerImpl::policy_routing_rules(*ip_version);
 
-fn ip_vers(route: &Route) -> &'static str {
-    if route.prefix.is_ipv4() {
-        "-4"
-    } else {
-        "-6"
-    }
-}
+            for rule in &[main_rule, table_rule] {
+                assert_eq!(rule.ip_version, *ip_version);
+                assert!(rule.priority < MAIN_TABLE_RULE_PRIORITY);
+            }
 
-#[derive(Debug, PartialEq)]
-enum RouteChange {
-    Add(Route),
-    Remove(Route),
+            // Only the default routes of the main table are ignored
+            assert_eq!(main_rule.mark, None);
+            assert_eq!(main_rule.table, Table::Main);
+            assert_eq!(main_rule.suppress_prefix_length, Some(0));
+
+            // The encrypted traffic of the tunnel skips the table of policy routing
+            assert!(main_rule.priority < table_rule.priority);
+            assert_eq!(table_rule.mark, Some(TUNNEL_FWMARK));
+            assert!(table_rule.invert);
+            assert_eq!(table_rule.table, Table::Id(POLICY_ROUTING_TABLE_ID));
+
+            // Excluded traffic must be routed before it can reach the table of policy routing
+            for rule in &RouteManagerImpl::split_tunnel_rules(*ip_version) {
+                assert!(rule.priority < main_rule.priority);
+            }
+        }
+    }
 }
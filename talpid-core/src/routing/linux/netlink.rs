@@ -0,0 +1,603 @@
+//! Synchronous access to the routes and routing rules of the kernel over netlink.
+
+use crate::{
+    linux::{iface_index, IfaceIndexLookupError},
+    routing::{Node, Route},
+};
+use futures::{future::Either, Future, Stream};
+use ipnetwork::IpNetwork;
+use netlink_packet::{
+    LinkNla, NetlinkFlags, NetlinkMessage, NetlinkPayload, RouteMessage, RouteNla, RtnlMessage,
+};
+use rtnetlink::constants::{AF_INET, AF_INET6};
+use std::{collections::BTreeMap, io, net::IpAddr};
+
+/// Table ID of `RT_TABLE_UNSPEC`, used in the header when the table is given as an attribute.
+const RT_TABLE_UNSPEC: u8 = 0;
+const RT_TABLE_MAIN: u32 = 254;
+
+const RTM_NEWRULE: u16 = 32;
+const RTM_DELRULE: u16 = 33;
+const FR_ACT_TO_TBL: u8 = 1;
+const FIB_RULE_INVERT: u32 = 0x2;
+const FRA_PRIORITY: u16 = 6;
+const FRA_FWMARK: u16 = 10;
+const FRA_SUPPRESS_PREFIXLEN: u16 = 14;
+const FRA_TABLE: u16 = 15;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_HEADER_LEN: usize = 16;
+const FIB_RULE_HEADER_LEN: usize = 12;
+const NLA_HEADER_LEN: usize = 4;
+const RECV_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Errors that can happen when talking to the kernel over netlink.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to open a netlink connection.
+    #[error(display = "Failed to open a netlink connection")]
+    ConnectError(#[error(source)] io::Error),
+
+    /// The netlink connection failed.
+    #[error(display = "Netlink protocol error")]
+    NetlinkProtocolError(#[error(source)] failure::Compat<netlink_proto::Error>),
+
+    /// A netlink request could not be sent or its response could not be read.
+    #[error(display = "Netlink request failed")]
+    NetlinkError(#[error(source)] failure::Compat<rtnetlink::Error>),
+
+    /// The netlink connection stopped before the request was answered.
+    #[error(display = "Netlink connection closed unexpectedly")]
+    NetlinkConnectionClosed,
+
+    /// The kernel rejected the request.
+    #[error(display = "The kernel rejected the netlink request")]
+    RequestRejected(#[error(source)] io::Error),
+
+    /// A routing rule request could not be sent or its response could not be read.
+    #[error(display = "Failed to send routing rule request")]
+    RuleSocketError(#[error(source)] io::Error),
+
+    /// The kernel sent a response that could not be parsed.
+    #[error(display = "Received an invalid netlink response")]
+    InvalidResponse,
+
+    /// The network interface of a route does not exist.
+    #[error(display = "Failed to look up the interface of a route")]
+    UnknownInterface(#[error(source)] IfaceIndexLookupError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The routing table that a route belongs to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Table {
+    Main,
+    Id(u32),
+}
+
+impl Table {
+    fn id(self) -> u32 {
+        match self {
+            Table::Main => RT_TABLE_MAIN,
+            Table::Id(id) => id,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+impl IpVersion {
+    fn address_family(self) -> u8 {
+        match self {
+            IpVersion::V4 => AF_INET as u8,
+            IpVersion::V6 => AF_INET6 as u8,
+        }
+    }
+
+    fn of(prefix: &IpNetwork) -> Self {
+        if prefix.is_ipv4() {
+            IpVersion::V4
+        } else {
+            IpVersion::V6
+        }
+    }
+}
+
+/// A connection to the routing subsystem of the kernel.
+pub struct NetlinkConnection {
+    connection: Option<rtnetlink::Connection>,
+    handle: rtnetlink::Handle,
+}
+
+impl NetlinkConnection {
+    /// Opens a netlink connection.
+    pub fn new() -> Result<Self> {
+        let (connection, handle) = rtnetlink::new_connection().map_err(Error::ConnectError)?;
+        Ok(NetlinkConnection {
+            connection: Some(connection),
+            handle,
+        })
+    }
+
+    /// Adds the route to the table, or replaces the route to the same destination with the same
+    /// metric.
+    pub fn replace_route(&mut self, route: &Route, table: Table) -> Result<()> {
+        let message = route_message(route, table, false)?;
+        let flags =
+            libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_REPLACE;
+        self.request(RtnlMessage::NewRoute(message), flags)
+            .map(|_| ())
+    }
+
+    /// Removes the route from the table.
+    pub fn delete_route(&mut self, route: &Route, table: Table) -> Result<()> {
+        let message = route_message(route, table, true)?;
+        self.request(
+            RtnlMessage::DelRoute(message),
+            libc::NLM_F_REQUEST | libc::NLM_F_ACK,
+        )
+        .map(|_| ())
+    }
+
+    /// Lists the unicast routes that cover the whole address space of the IP version in the
+    /// table.
+    pub fn default_routes(&mut self, ip_version: IpVersion, table: Table) -> Result<Vec<Route>> {
+        let links = self.links()?;
+        Ok(self
+            .dump_routes(ip_version)?
+            .iter()
+            .filter(|message| {
+                message.header.destination_length == 0
+                    && message.header.kind == libc::RTN_UNICAST
+                    && route_table(message) == table.id()
+            })
+            .filter_map(|message| parse_default_route(message, ip_version, &links))
+            .collect())
+    }
+
+    /// Removes all routes of the IP version in the table.
+    pub fn flush_table(&mut self, ip_version: IpVersion, table: Table) -> Result<()> {
+        for mut message in self.dump_routes(ip_version)? {
+            if route_table(&message) != table.id() {
+                continue;
+            }
+            // Only keep the attributes that identify the route, and not those that the kernel only
+            // reports.
+            message.nlas.retain(|nla| match nla {
+                RouteNla::Destination(_)
+                | RouteNla::Gateway(_)
+                | RouteNla::Oif(_)
+                | RouteNla::Priority(_)
+                | RouteNla::Table(_) => true,
+                _ => false,
+            });
+            match self.request(
+                RtnlMessage::DelRoute(message),
+                libc::NLM_F_REQUEST | libc::NLM_F_ACK,
+            ) {
+                Err(Error::RequestRejected(ref error))
+                    if error.raw_os_error() == Some(libc::ESRCH) => {}
+                result => result.map(|_| ())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn dump_routes(&mut self, ip_version: IpVersion) -> Result<Vec<RouteMessage>> {
+        let mut message = RouteMessage::default();
+        message.header.address_family = ip_version.address_family();
+        Ok(self
+            .request(
+                RtnlMessage::GetRoute(message),
+                libc::NLM_F_REQUEST | libc::NLM_F_DUMP,
+            )?
+            .into_iter()
+            .filter_map(|message| match message.payload {
+                NetlinkPayload::Rtnl(RtnlMessage::NewRoute(route)) => Some(route),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Maps the index of each network interface to its name.
+    fn links(&mut self) -> Result<BTreeMap<u32, String>> {
+        let request = self.handle.link().get().execute().collect();
+        let links = self.execute_request(request)?;
+        Ok(links
+            .into_iter()
+            .filter_map(|link| {
+                let index = link.header.index;
+                link.nlas.into_iter().find_map(|nla| match nla {
+                    LinkNla::IfName(name) => Some((index, name)),
+                    _ => None,
+                })
+            })
+            .collect())
+    }
+
+    /// Sends the message and returns the responses, or the error that the kernel responded with.
+    fn request(&mut self, message: RtnlMessage, flags: libc::c_int) -> Result<Vec<NetlinkMessage>> {
+        let mut message = NetlinkMessage::from(message);
+        message.header.flags = NetlinkFlags::from(flags as u16);
+        let request = self.handle.request(message).collect();
+        let responses = self.execute_request(request)?;
+
+        for response in &responses {
+            if let NetlinkPayload::Error(ref error) = response.payload {
+                if error.code != 0 {
+                    return Err(Error::RequestRejected(io::Error::from_raw_os_error(
+                        -error.code,
+                    )));
+                }
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Drives the connection until the request has completed.
+    fn execute_request<R>(&mut self, request: R) -> Result<R::Item>
+    where
+        R: Future<Error = rtnetlink::Error>,
+    {
+        let connection = self
+            .connection
+            .take()
+            .ok_or(Error::NetlinkConnectionClosed)?;
+
+        let (result, connection) = match connection.select2(request).wait() {
+            Ok(Either::A(_)) => return Err(Error::NetlinkConnectionClosed),
+            Err(Either::A((error, _))) => {
+                return Err(Error::NetlinkProtocolError(failure::Fail::compat(error)))
+            }
+            Ok(Either::B((response, connection))) => (Ok(response), connection),
+            Err(Either::B((error, connection))) => (
+                Err(Error::NetlinkError(failure::Fail::compat(error))),
+                connection,
+            ),
+        };
+
+        self.connection = Some(connection);
+        result
+    }
+}
+
+/// Builds the message that adds or deletes the route. Mirrors what `ip route` sends, so that
+/// deleting a route only has to match the fields that were given when it was added.
+fn route_message(route: &Route, table: Table, delete: bool) -> Result<RouteMessage> {
+    let mut message = RouteMessage::default();
+    message.header.address_family = IpVersion::of(&route.prefix).address_family();
+    message.header.destination_length = route.prefix.prefix();
+
+    let table_id = table.id();
+    if table_id < 256 {
+        message.header.table = table_id as u8;
+    } else {
+        message.header.table = RT_TABLE_UNSPEC;
+        message.nlas.push(RouteNla::Table(table_id));
+    }
+
+    if delete {
+        message.header.scope = libc::RT_SCOPE_NOWHERE;
+    } else {
+        message.header.protocol = libc::RTPROT_BOOT;
+        message.header.kind = libc::RTN_UNICAST;
+        // IPv4 routes without a gateway only reach the link itself.
+        message.header.scope = if route.prefix.is_ipv4() && route.node.get_address().is_none() {
+            libc::RT_SCOPE_LINK
+        } else {
+            libc::RT_SCOPE_UNIVERSE
+        };
+    }
+
+    if route.prefix.prefix() > 0 {
+        message
+            .nlas
+            .push(RouteNla::Destination(ip_bytes(route.prefix.ip())));
+    }
+    if let Some(gateway) = route.node.get_address() {
+        message.nlas.push(RouteNla::Gateway(ip_bytes(gateway)));
+    }
+    if let Some(device) = route.node.get_device() {
+        let index = iface_index(device).map_err(Error::UnknownInterface)?;
+        message.nlas.push(RouteNla::Oif(index));
+    }
+    if let Some(metric) = route.metric {
+        message.nlas.push(RouteNla::Priority(metric));
+    }
+    Ok(message)
+}
+
+/// Returns true if the route belongs to the main table.
+pub fn is_in_main_table(message: &RouteMessage) -> bool {
+    route_table(message) == RT_TABLE_MAIN
+}
+
+fn route_table(message: &RouteMessage) -> u32 {
+    message
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            RouteNla::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or_else(|| u32::from(message.header.table))
+}
+
+fn parse_default_route(
+    message: &RouteMessage,
+    ip_version: IpVersion,
+    links: &BTreeMap<u32, String>,
+) -> Option<Route> {
+    let mut gateway = None;
+    let mut device = None;
+    let mut metric = None;
+
+    for nla in &message.nlas {
+        match nla {
+            RouteNla::Gateway(bytes) => gateway = parse_ip(bytes),
+            RouteNla::Oif(index) => device = links.get(index).cloned(),
+            RouteNla::Priority(priority) => metric = Some(*priority),
+            _ => (),
+        }
+    }
+
+    if gateway.is_none() && device.is_none() {
+        return None;
+    }
+    let prefix = match ip_version {
+        IpVersion::V4 => "0.0.0.0/0".parse().unwrap(),
+        IpVersion::V6 => "::/0".parse().unwrap(),
+    };
+    Some(Route {
+        node: Node {
+            ip: gateway,
+            device,
+        },
+        prefix,
+        metric,
+    })
+}
+
+fn ip_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+fn parse_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets))
+        }
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+/// A rule that makes packets look up a routing table.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RoutingRule {
+    pub ip_version: IpVersion,
+    /// Only matches packets with this firewall mark.
+    pub mark: Option<u32>,
+    /// Matches the packets that the rule would not otherwise match.
+    pub invert: bool,
+    pub table: Table,
+    pub priority: u32,
+    /// Ignores the routes in the table with a prefix length of this or less.
+    pub suppress_prefix_length: Option<u32>,
+}
+
+/// Adds the rule. Fails with `EEXIST` if an identical rule exists.
+pub fn add_rule(rule: &RoutingRule) -> Result<()> {
+    send_rule_request(&rule_request(
+        rule,
+        RTM_NEWRULE,
+        libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE | libc::NLM_F_EXCL,
+    ))
+}
+
+/// Deletes the rule. Fails with `ENOENT` if there is no such rule.
+pub fn delete_rule(rule: &RoutingRule) -> Result<()> {
+    send_rule_request(&rule_request(
+        rule,
+        RTM_DELRULE,
+        libc::NLM_F_REQUEST | libc::NLM_F_ACK,
+    ))
+}
+
+/// The netlink crates do not know about routing rules, so their messages are built by hand.
+fn rule_request(rule: &RoutingRule, message_type: u16, flags: libc::c_int) -> Vec<u8> {
+    let table_id = rule.table.id();
+    let mut attributes = vec![(FRA_PRIORITY, rule.priority)];
+    if let Some(mark) = rule.mark {
+        attributes.push((FRA_FWMARK, mark));
+    }
+    attributes.push((FRA_TABLE, table_id));
+    if let Some(length) = rule.suppress_prefix_length {
+        attributes.push((FRA_SUPPRESS_PREFIXLEN, length));
+    }
+
+    let length = NLMSG_HEADER_LEN + FIB_RULE_HEADER_LEN + attributes.len() * (NLA_HEADER_LEN + 4);
+    let mut request = Vec::with_capacity(length);
+    request.extend_from_slice(&(length as u32).to_ne_bytes());
+    request.extend_from_slice(&message_type.to_ne_bytes());
+    request.extend_from_slice(&(flags as u16).to_ne_bytes());
+    // Sequence number and port ID
+    request.extend_from_slice(&[0; 8]);
+
+    let table = if table_id < 256 {
+        table_id as u8
+    } else {
+        RT_TABLE_UNSPEC
+    };
+    // Family, destination and source prefix lengths, TOS, table, two reserved bytes and action
+    request.extend_from_slice(&[
+        rule.ip_version.address_family(),
+        0,
+        0,
+        0,
+        table,
+        0,
+        0,
+        FR_ACT_TO_TBL,
+    ]);
+    let rule_flags = if rule.invert { FIB_RULE_INVERT } else { 0 };
+    request.extend_from_slice(&rule_flags.to_ne_bytes());
+
+    for (attribute_type, value) in attributes {
+        request.extend_from_slice(&((NLA_HEADER_LEN + 4) as u16).to_ne_bytes());
+        request.extend_from_slice(&attribute_type.to_ne_bytes());
+        request.extend_from_slice(&value.to_ne_bytes());
+    }
+    request
+}
+
+fn send_rule_request(request: &[u8]) -> Result<()> {
+    let socket = mnl::Socket::new(mnl::Bus::Route).map_err(Error::RuleSocketError)?;
+    socket.send(request).map_err(Error::RuleSocketError)?;
+
+    let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+    let length = socket.recv(&mut buffer).map_err(Error::RuleSocketError)?;
+    match parse_ack(&buffer[..length]) {
+        Some(0) => Ok(()),
+        Some(errno) => Err(Error::RequestRejected(io::Error::from_raw_os_error(errno))),
+        None => Err(Error::InvalidResponse),
+    }
+}
+
+/// Returns the error number in the acknowledgement of a request, which is 0 on success.
+fn parse_ack(response: &[u8]) -> Option<i32> {
+    if response.len() < NLMSG_HEADER_LEN + 4
+        || u16::from_ne_bytes([response[4], response[5]]) != NLMSG_ERROR
+    {
+        return None;
+    }
+    let mut code = [0u8; 4];
+    code.copy_from_slice(&response[NLMSG_HEADER_LEN..NLMSG_HEADER_LEN + 4]);
+    Some(-i32::from_ne_bytes(code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule() -> RoutingRule {
+        RoutingRule {
+            ip_version: IpVersion::V4,
+            mark: Some(0xf41),
+            invert: false,
+            table: Table::Main,
+            priority: 32764,
+            suppress_prefix_length: Some(1),
+        }
+    }
+
+    fn attributes(request: &[u8]) -> Vec<(u16, u32)> {
+        request[NLMSG_HEADER_LEN + FIB_RULE_HEADER_LEN..]
+            .chunks(NLA_HEADER_LEN + 4)
+            .map(|attribute| {
+                assert_eq!(u16::from_ne_bytes([attribute[0], attribute[1]]), 8);
+                (
+                    u16::from_ne_bytes([attribute[2], attribute[3]]),
+                    u32::from_ne_bytes([attribute[4], attribute[5], attribute[6], attribute[7]]),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rule_request() {
+        let request = rule_request(&rule(), RTM_NEWRULE, libc::NLM_F_REQUEST);
+
+        assert_eq!(
+            u32::from_ne_bytes([request[0], request[1], request[2], request[3]]) as usize,
+            request.len()
+        );
+        assert_eq!(u16::from_ne_bytes([request[4], request[5]]), RTM_NEWRULE);
+        assert_eq!(
+            &request[NLMSG_HEADER_LEN..NLMSG_HEADER_LEN + 8],
+            &[AF_INET as u8, 0, 0, 0, 254, 0, 0, FR_ACT_TO_TBL]
+        );
+        assert_eq!(
+            attributes(&request),
+            vec![
+                (FRA_PRIORITY, 32764),
+                (FRA_FWMARK, 0xf41),
+                (FRA_TABLE, 254),
+                (FRA_SUPPRESS_PREFIXLEN, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rule_request_large_table_id() {
+        let rule = RoutingRule {
+            ip_version: IpVersion::V6,
+            table: Table::Id(0x4d9f41),
+            suppress_prefix_length: None,
+            ..rule()
+        };
+        let request = rule_request(&rule, RTM_DELRULE, libc::NLM_F_REQUEST);
+
+        assert_eq!(request[NLMSG_HEADER_LEN], AF_INET6 as u8);
+        assert_eq!(request[NLMSG_HEADER_LEN + 4], RT_TABLE_UNSPEC);
+        assert_eq!(
+            attributes(&request),
+            vec![
+                (FRA_PRIORITY, 32764),
+                (FRA_FWMARK, 0xf41),
+                (FRA_TABLE, 0x4d9f41),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rule_request_inverted_without_mark() {
+        let rule = RoutingRule {
+            mark: None,
+            invert: true,
+            ..rule()
+        };
+        let request = rule_request(&rule, RTM_NEWRULE, libc::NLM_F_REQUEST);
+
+        assert_eq!(
+            &request[NLMSG_HEADER_LEN + 8..NLMSG_HEADER_LEN + 12],
+            &FIB_RULE_INVERT.to_ne_bytes()
+        );
+        assert_eq!(
+            attributes(&request),
+            vec![
+                (FRA_PRIORITY, 32764),
+                (FRA_TABLE, 254),
+                (FRA_SUPPRESS_PREFIXLEN, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        let mut ack = vec![0u8; NLMSG_HEADER_LEN + 4];
+        ack[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        assert_eq!(parse_ack(&ack), Some(0));
+
+        ack[NLMSG_HEADER_LEN..].copy_from_slice(&(-libc::ENOENT).to_ne_bytes());
+        assert_eq!(parse_ack(&ack), Some(libc::ENOENT));
+
+        ack[4..6].copy_from_slice(&3u16.to_ne_bytes());
+        assert_eq!(parse_ack(&ack), None);
+    }
+}
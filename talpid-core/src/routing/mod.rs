@@ -13,6 +13,8 @@ mod imp;
 mod imp;
 
 pub use imp::{Error, RouteManager};
+#[cfg(target_os = "linux")]
+pub use imp::{RouteManagerHandle, TUNNEL_FWMARK};
 
 /// A netowrk route with a specific network node, destinaiton and an optional metric.
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
@@ -2,6 +2,8 @@
 #![cfg_attr(target_os = "windows", allow(dead_code))]
 // TODO: remove the allow(dead_code) for android once it's up to scratch.
 use super::NetNode;
+#[cfg(target_os = "linux")]
+use futures::sync::mpsc;
 use futures::{sync::oneshot, Future};
 use ipnetwork::IpNetwork;
 use std::collections::HashMap;
@@ -19,6 +21,8 @@ mod imp;
 mod imp;
 
 pub use imp::Error as PlatformError;
+#[cfg(target_os = "linux")]
+pub use imp::TUNNEL_FWMARK;
 
 /// Errors that can be encountered whilst initializing RouteManager
 #[derive(err_derive::Error, Debug)]
@@ -32,6 +36,14 @@ pub enum Error {
     /// Failed to spawn route manager future
     #[error(display = "Failed to spawn route manager on the provided executor")]
     FailedToSpawnManager,
+    /// The route manager failed to add the routes
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to add routes")]
+    FailedToAddRoutes(#[error(source, no_from)] imp::Error),
+    /// The route manager has been stopped
+    #[cfg(target_os = "linux")]
+    #[error(display = "The route manager is not running")]
+    RouteManagerDown,
 }
 
 /// RouteManager applies a set of routes to the route table.
@@ -39,6 +51,8 @@ pub enum Error {
 /// the route will be adjusted dynamically when the default route changes.
 pub struct RouteManager {
     tx: Option<oneshot::Sender<oneshot::Sender<()>>>,
+    #[cfg(target_os = "linux")]
+    command_tx: mpsc::UnboundedSender<imp::RouteManagerCommand>,
 }
 
 impl RouteManager {
@@ -46,11 +60,18 @@ impl RouteManager {
     /// Takes a map of network destinations and network nodes as an argument, and applies said
     /// routes.
     pub fn new(required_routes: HashMap<IpNetwork, NetNode>) -> Result<Self, Error> {
+        #[cfg(target_os = "linux")]
+        let (command_tx, command_rx) = mpsc::unbounded();
         let (tx, rx) = oneshot::channel();
         let (start_tx, start_rx) = oneshot::channel();
 
-        std::thread::spawn(
-            move || match imp::RouteManagerImpl::new(required_routes, rx) {
+        std::thread::spawn(move || {
+            match imp::RouteManagerImpl::new(
+                required_routes,
+                rx,
+                #[cfg(target_os = "linux")]
+                command_rx,
+            ) {
                 Ok(route_manager) => {
                     let _ = start_tx.send(Ok(()));
                     if let Err(e) = route_manager.wait() {
@@ -60,15 +81,27 @@ impl RouteManager {
                 Err(e) => {
                     let _ = start_tx.send(Err(Error::FailedToInitializeManager(e)));
                 }
-            },
-        );
+            }
+        });
         match start_rx.wait() {
-            Ok(Ok(())) => Ok(Self { tx: Some(tx) }),
+            Ok(Ok(())) => Ok(Self {
+                tx: Some(tx),
+                #[cfg(target_os = "linux")]
+                command_tx,
+            }),
             Ok(Err(e)) => Err(e),
             Err(_) => Err(Error::RoutingManagerThreadPanic),
         }
     }
 
+    /// Returns a handle that adds routes to this RouteManager while it is running.
+    #[cfg(target_os = "linux")]
+    pub fn handle(&self) -> RouteManagerHandle {
+        RouteManagerHandle {
+            tx: self.command_tx.clone(),
+        }
+    }
+
     /// Stops RouteManager and removes all of the applied routes.
     pub fn stop(&mut self) {
         if let Some(tx) = self.tx.take() {
@@ -90,3 +123,84 @@ impl Drop for RouteManager {
         self.stop();
     }
 }
+
+/// A handle that adds and removes routes of a running [`RouteManager`]. It can be shared by the
+/// tunnels, so that a single RouteManager manages all routes.
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+pub struct RouteManagerHandle {
+    tx: mpsc::UnboundedSender<imp::RouteManagerCommand>,
+}
+
+#[cfg(target_os = "linux")]
+impl RouteManagerHandle {
+    /// Applies the given routes until they are cleared.
+    pub fn add_routes(&self, routes: HashMap<IpNetwork, NetNode>) -> Result<(), Error> {
+        self.send_routes(routes, false)
+    }
+
+    /// Applies the given routes until they are cleared, adding them to a dedicated routing table
+    /// instead of the main table, so that they do not collide with routes added by other
+    /// programs. Routing rules send all traffic without [`TUNNEL_FWMARK`] via that table, unless
+    /// the main table has a more specific route for it. The rules and the table are removed when
+    /// the routes are cleared, and when the next RouteManager is started if the daemon was not
+    /// shut down cleanly.
+    pub fn add_policy_routed_routes(
+        &self,
+        routes: HashMap<IpNetwork, NetNode>,
+    ) -> Result<(), Error> {
+        self.send_routes(routes, true)
+    }
+
+    fn send_routes(
+        &self,
+        routes: HashMap<IpNetwork, NetNode>,
+        policy_routing: bool,
+    ) -> Result<(), Error> {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(imp::RouteManagerCommand::AddRoutes(
+                routes,
+                policy_routing,
+                result_tx,
+            ))
+            .map_err(|_| Error::RouteManagerDown)?;
+        result_rx
+            .wait()
+            .map_err(|_| Error::RouteManagerDown)?
+            .map_err(Error::FailedToAddRoutes)
+    }
+
+    /// Routes traffic that the firewall marked as excluded outside the tunnel, or stops doing so,
+    /// and waits until the routing rules are changed.
+    pub fn set_split_tunnel(&self, enabled: bool) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self
+            .tx
+            .unbounded_send(imp::RouteManagerCommand::SetSplitTunnel(enabled, done_tx))
+            .is_err()
+        {
+            log::error!("RouteManager already down!");
+            return;
+        }
+        if done_rx.wait().is_err() {
+            log::error!("RouteManager stopped before the split tunnel routes were changed");
+        }
+    }
+
+    /// Removes all routes added through any handle, and waits until they are removed.
+    pub fn clear_routes(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self
+            .tx
+            .unbounded_send(imp::RouteManagerCommand::ClearRoutes(done_tx))
+            .is_err()
+        {
+            log::error!("RouteManager already down!");
+            return;
+        }
+        if done_rx.wait().is_err() {
+            log::error!("RouteManager stopped before the routes were cleared");
+        }
+    }
+}
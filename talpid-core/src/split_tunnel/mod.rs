@@ -0,0 +1,211 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+const NET_CLS_DIR: &str = "/sys/fs/cgroup/net_cls";
+/// Where the net_cls controller is mounted if the system has not mounted it already.
+const NET_CLS_FALLBACK_DIR: &str = "/run/net_cls";
+const SPLIT_TUNNEL_CGROUP_NAME: &str = "mullvad-exclusions";
+const SRC_VALID_MARK_PATH: &str = "/proc/sys/net/ipv4/conf/all/src_valid_mark";
+
+/// Identifies packets coming from processes in the exclusion cgroup.
+pub const NET_CLS_CLASSID: u32 = 0x4d9f41;
+/// Value used to mark excluded packets and their connections.
+pub const MARK: u32 = 0xf41;
+/// Routing table holding the default routes used by excluded traffic.
+pub const ROUTING_TABLE_ID: u32 = 0x4d9f41;
+
+/// Errors related to split tunneling.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Unable to create the cgroup.
+    #[error(display = "Unable to create cgroup for excluded processes")]
+    CreateCGroup(#[error(source)] io::Error),
+
+    /// Unable to mount the net_cls controller.
+    #[error(display = "Unable to mount net_cls cgroup controller")]
+    MountNetCls(#[error(source)] nix::Error),
+
+    /// Unable to set the class ID of the cgroup.
+    #[error(display = "Unable to set class ID for cgroup")]
+    SetCGroupClassId(#[error(source)] io::Error),
+
+    /// Unable to let marked packets pass reverse path filtering.
+    #[error(display = "Unable to enable src_valid_mark")]
+    SetSrcValidMark(#[error(source)] io::Error),
+
+    /// Unable to add a PID to a cgroup.
+    #[error(display = "Unable to add PID to cgroup.procs")]
+    AddCGroupPid(#[error(source)] io::Error),
+
+    /// Unable to remove a PID from the exclusion cgroup.
+    #[error(display = "Unable to remove PID from cgroup")]
+    RemoveCGroupPid(#[error(source)] io::Error),
+
+    /// Unable to read the PIDs in the exclusion cgroup.
+    #[error(display = "Unable to list PIDs in cgroup")]
+    ListCGroupPids(#[error(source)] io::Error),
+}
+
+/// Manages PIDs in the Linux cgroup whose traffic is excluded from the tunnel.
+/// Processes started by an excluded process are also excluded.
+pub struct PidManager {
+    net_cls_path: PathBuf,
+}
+
+impl PidManager {
+    /// Creates the exclusion cgroup, mounting the net_cls controller first if needed.
+    pub fn new() -> Result<PidManager, Error> {
+        let net_cls_path = Self::net_cls_path()?;
+        let manager = PidManager { net_cls_path };
+        manager.create_cgroup()?;
+        enable_src_valid_mark()?;
+        Ok(manager)
+    }
+
+    fn net_cls_path() -> Result<PathBuf, Error> {
+        if Path::new(NET_CLS_DIR).exists() {
+            return Ok(PathBuf::from(NET_CLS_DIR));
+        }
+
+        let fallback = PathBuf::from(NET_CLS_FALLBACK_DIR);
+        fs::create_dir_all(&fallback).map_err(Error::CreateCGroup)?;
+        // The controller may already be mounted here by an earlier instance.
+        if !fallback.join("cgroup.procs").exists() {
+            nix::mount::mount(
+                Some("net_cls"),
+                &fallback,
+                Some("cgroup"),
+                nix::mount::MsFlags::empty(),
+                Some("net_cls"),
+            )
+            .map_err(Error::MountNetCls)?;
+        }
+        Ok(fallback)
+    }
+
+    fn create_cgroup(&self) -> Result<(), Error> {
+        let exclusions_dir = self.exclusions_dir();
+        if !exclusions_dir.exists() {
+            fs::create_dir(&exclusions_dir).map_err(Error::CreateCGroup)?;
+        }
+        fs::write(
+            exclusions_dir.join("net_cls.classid"),
+            NET_CLS_CLASSID.to_string().as_bytes(),
+        )
+        .map_err(Error::SetCGroupClassId)
+    }
+
+    fn exclusions_dir(&self) -> PathBuf {
+        self.net_cls_path.join(SPLIT_TUNNEL_CGROUP_NAME)
+    }
+
+    /// Excludes a process, and any processes it starts later, from the tunnel.
+    pub fn add(&self, pid: i32) -> Result<(), Error> {
+        write_pid(&self.exclusions_dir(), pid).map_err(Error::AddCGroupPid)
+    }
+
+    /// Moves a process back to the root cgroup, so that its traffic goes through the tunnel.
+    pub fn remove(&self, pid: i32) -> Result<(), Error> {
+        write_pid(&self.net_cls_path, pid).map_err(Error::RemoveCGroupPid)
+    }
+
+    /// Returns the PIDs of all excluded processes.
+    pub fn list(&self) -> Result<Vec<i32>, Error> {
+        let file = fs::File::open(self.exclusions_dir().join("cgroup.procs"))
+            .map_err(Error::ListCGroupPids)?;
+        let mut pids = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(Error::ListCGroupPids)?;
+            let pid = line
+                .trim()
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, line.clone()))
+                .map_err(Error::ListCGroupPids)?;
+            pids.push(pid);
+        }
+        Ok(pids)
+    }
+}
+
+fn write_pid(cgroup_dir: &Path, pid: i32) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(cgroup_dir.join("cgroup.procs"))?;
+    file.write_all(pid.to_string().as_bytes())
+}
+
+/// Replies to excluded connections are marked before routing, so reverse path filtering must
+/// take the mark into account or it would drop them.
+fn enable_src_valid_mark() -> Result<(), Error> {
+    fs::write(SRC_VALID_MARK_PATH, b"1").map_err(Error::SetSrcValidMark)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns a manager for a fake net_cls hierarchy, where the cgroup files are regular files.
+    fn fake_manager(net_cls_dir: &Path) -> PidManager {
+        let manager = PidManager {
+            net_cls_path: net_cls_dir.to_owned(),
+        };
+        manager.create_cgroup().unwrap();
+        fs::write(net_cls_dir.join("cgroup.procs"), b"").unwrap();
+        fs::write(manager.exclusions_dir().join("cgroup.procs"), b"").unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_cgroup_gets_class_id() {
+        let net_cls_dir = tempfile::tempdir().unwrap();
+        let manager = fake_manager(net_cls_dir.path());
+
+        let class_id =
+            fs::read_to_string(manager.exclusions_dir().join("net_cls.classid")).unwrap();
+        assert_eq!(class_id.parse::<u32>().unwrap(), NET_CLS_CLASSID);
+
+        // Creating the cgroup again must not fail when it already exists
+        manager.create_cgroup().unwrap();
+    }
+
+    #[test]
+    fn test_add_and_remove_pid() {
+        let net_cls_dir = tempfile::tempdir().unwrap();
+        let manager = fake_manager(net_cls_dir.path());
+
+        manager.add(1234).unwrap();
+        assert_eq!(
+            fs::read_to_string(manager.exclusions_dir().join("cgroup.procs")).unwrap(),
+            "1234"
+        );
+
+        manager.remove(1234).unwrap();
+        assert_eq!(
+            fs::read_to_string(net_cls_dir.path().join("cgroup.procs")).unwrap(),
+            "1234"
+        );
+    }
+
+    #[test]
+    fn test_list_pids() {
+        let net_cls_dir = tempfile::tempdir().unwrap();
+        let manager = fake_manager(net_cls_dir.path());
+
+        fs::write(manager.exclusions_dir().join("cgroup.procs"), b"1\n42\n").unwrap();
+        assert_eq!(manager.list().unwrap(), vec![1, 42]);
+
+        fs::write(
+            manager.exclusions_dir().join("cgroup.procs"),
+            b"1\nnot a pid\n",
+        )
+        .unwrap();
+        match manager.list() {
+            Err(Error::ListCGroupPids(_)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+}
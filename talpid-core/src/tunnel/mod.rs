@@ -1,15 +1,24 @@
 use self::tun_provider::TunProvider;
 use crate::logging;
+#[cfg(target_os = "linux")]
+use crate::routing::{NetNode, RouteManager, RouteManagerHandle};
 #[cfg(not(target_os = "android"))]
 use std::collections::HashMap;
 use std::{
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
-use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
+use talpid_types::{
+    net::{
+        validate_excluded_subnets, wireguard as wireguard_types, ExcludedSubnetError,
+        TunnelParameters,
+    },
+    tunnel::{ConnectionQuality, ErrorDetails},
+};
 
 /// A module for all OpenVPN related tunnel management.
 #[cfg(not(target_os = "android"))]
@@ -59,6 +68,45 @@ pub enum Error {
     /// There was an error listening for events from the Wireguard tunnel
     #[error(display = "Failed while listening for events from the Wireguard tunnel")]
     WireguardTunnelMonitoringError(#[error(source)] wireguard::Error),
+
+    /// An excluded network covers the relay or the tunnel gateway.
+    #[error(display = "Invalid excluded networks")]
+    ExcludedSubnetError(#[error(source)] ExcludedSubnetError),
+
+    /// Failed to start the route manager, or to add the routes of the OpenVPN tunnel.
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to set up routing")]
+    SetupRoutingError(#[error(source)] crate::routing::Error),
+}
+
+/// Describes a tunnel error, so that it can be reported along with the error state cause.
+pub fn error_details(error: &Error) -> ErrorDetails {
+    let backend = match error {
+        #[cfg(not(target_os = "android"))]
+        Error::OpenVpnTunnelMonitoringError(_) => "openvpn",
+        Error::WireguardConfigError(_) | Error::WireguardTunnelMonitoringError(_) => "wireguard",
+        #[cfg(windows)]
+        Error::WinnetError(_) => "winnet",
+        #[cfg(target_os = "linux")]
+        Error::SetupRoutingError(_) => "routing",
+        _ => "tunnel",
+    };
+    ErrorDetails::from_error(backend, error)
+}
+
+
+/// Checks that the gateways of a tunnel that just came up are not in an excluded network.
+pub fn check_tunnel_gateways(
+    tunnel_parameters: &TunnelParameters,
+    metadata: &TunnelMetadata,
+) -> Result<()> {
+    let mut gateways = vec![IpAddr::from(metadata.ipv4_gateway)];
+    gateways.extend(metadata.ipv6_gateway.map(IpAddr::from));
+    validate_excluded_subnets(
+        &tunnel_parameters.get_generic_options().excluded_subnets,
+        &gateways,
+    )
+    .map_err(Error::ExcludedSubnetError)
 }
 
 
@@ -71,6 +119,8 @@ pub enum TunnelEvent {
     Up(TunnelMetadata),
     /// Sent when the tunnel goes down.
     Down,
+    /// Sent when the measured quality of an established tunnel changes.
+    QualityChanged(ConnectionQuality),
 }
 
 /// Information about a VPN tunnel.
@@ -131,9 +181,121 @@ impl TunnelEvent {
         }
     }
 }
+
+/// Starts tunnels on behalf of the tunnel state machine. Abstracting this allows the state
+/// machine to be driven by something other than real OpenVPN and WireGuard tunnels.
+pub trait TunnelBackend {
+    /// Starts a new tunnel using the given parameters. `on_event` is called whenever the state of
+    /// the tunnel changes. The DNS servers of split DNS rules are routed outside the tunnel.
+    fn start(
+        &mut self,
+        tunnel_parameters: &TunnelParameters,
+        on_event: Box<dyn Fn(TunnelEvent) + Send + Sync>,
+        tun_provider: &mut TunProvider,
+        #[cfg(target_os = "linux")] split_dns_servers: &[IpAddr],
+    ) -> Result<Box<dyn TunnelHandle>>;
+
+    /// Adds or removes the routes that let excluded traffic bypass the tunnel.
+    #[cfg(target_os = "linux")]
+    fn set_split_tunnel(&mut self, enabled: bool);
+}
+
+/// A running tunnel, as started by a [`TunnelBackend`].
+pub trait TunnelHandle: Send {
+    /// Creates a handle that can close the tunnel while some other thread is blocked in `wait`.
+    fn close_handle(&self) -> Box<dyn TunnelCloseHandle>;
+
+    /// Consumes the tunnel and blocks until it exits or there is an error.
+    fn wait(self: Box<Self>) -> Result<()>;
+}
+
+/// A handle that can close a running tunnel.
+pub trait TunnelCloseHandle: Send {
+    /// Closes the underlying tunnel, making `TunnelHandle::wait` return.
+    fn close(self: Box<Self>) -> io::Result<()>;
+}
+
+/// The [`TunnelBackend`] starting real OpenVPN and WireGuard tunnels through [`TunnelMonitor`].
+pub struct DefaultTunnelBackend {
+    log_dir: Option<PathBuf>,
+    resource_dir: PathBuf,
+    /// Manages the routes of all tunnels. Started when the first tunnel is.
+    #[cfg(target_os = "linux")]
+    route_manager: Option<RouteManager>,
+    /// Whether the route manager should route excluded traffic outside the tunnel.
+    #[cfg(target_os = "linux")]
+    split_tunnel: bool,
+}
+
+impl DefaultTunnelBackend {
+    /// Creates a new backend. Tunnel logs are written to `log_dir`, if one is given.
+    pub fn new(log_dir: Option<PathBuf>, resource_dir: PathBuf) -> Self {
+        DefaultTunnelBackend {
+            log_dir,
+            resource_dir,
+            #[cfg(target_os = "linux")]
+            route_manager: None,
+            #[cfg(target_os = "linux")]
+            split_tunnel: false,
+        }
+    }
+
+    /// Returns a handle to the route manager, starting it if needed.
+    #[cfg(target_os = "linux")]
+    fn route_manager(&mut self) -> Result<RouteManagerHandle> {
+        if self.route_manager.is_none() {
+            let route_manager =
+                RouteManager::new(HashMap::new()).map_err(Error::SetupRoutingError)?;
+            if self.split_tunnel {
+                route_manager.handle().set_split_tunnel(true);
+            }
+            self.route_manager = Some(route_manager);
+        }
+        Ok(self.route_manager.as_ref().unwrap().handle())
+    }
+}
+
+impl TunnelBackend for DefaultTunnelBackend {
+    fn start(
+        &mut self,
+        tunnel_parameters: &TunnelParameters,
+        on_event: Box<dyn Fn(TunnelEvent) + Send + Sync>,
+        tun_provider: &mut TunProvider,
+        #[cfg(target_os = "linux")] split_dns_servers: &[IpAddr],
+    ) -> Result<Box<dyn TunnelHandle>> {
+        let on_event: Arc<dyn Fn(TunnelEvent) + Send + Sync> = Arc::from(on_event);
+        #[cfg(target_os = "linux")]
+        let route_manager = self.route_manager()?;
+        let monitor = TunnelMonitor::start(
+            tunnel_parameters,
+            &self.log_dir,
+            &self.resource_dir,
+            move |event| on_event(event),
+            tun_provider,
+            #[cfg(target_os = "linux")]
+            route_manager,
+            #[cfg(target_os = "linux")]
+            split_dns_servers,
+        )?;
+        Ok(Box::new(monitor))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_split_tunnel(&mut self, enabled: bool) {
+        self.split_tunnel = enabled;
+        if let Some(route_manager) = &self.route_manager {
+            route_manager.handle().set_split_tunnel(enabled);
+        }
+    }
+}
+
 /// Abstraction for monitoring a generic VPN tunnel.
 pub struct TunnelMonitor {
     monitor: InternalTunnelMonitor,
+    /// OpenVPN manages the tunnel routes itself, so the routes to the excluded networks and split
+    /// DNS servers are added through this handle, and cleared when the tunnel exits.
+    #[cfg(target_os = "linux")]
+    route_manager: Option<RouteManagerHandle>,
 }
 
 // TODO(emilsp) move most of the openvpn tunnel details to OpenVpnTunnelMonitor
@@ -147,24 +309,41 @@ impl TunnelMonitor {
         resource_dir: &Path,
         on_event: L,
         tun_provider: &mut TunProvider,
+        #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        #[cfg(target_os = "linux")] split_dns_servers: &[IpAddr],
     ) -> Result<Self>
     where
         L: Fn(TunnelEvent) + Send + Clone + Sync + 'static,
     {
         Self::ensure_ipv6_can_be_used_if_enabled(&tunnel_parameters)?;
+        Self::ensure_excluded_subnets_are_valid(&tunnel_parameters)?;
         let log_file = Self::prepare_tunnel_log_file(&tunnel_parameters, log_dir)?;
 
         match tunnel_parameters {
             #[cfg(not(target_os = "android"))]
-            TunnelParameters::OpenVpn(config) => {
-                Self::start_openvpn_tunnel(&config, log_file, resource_dir, on_event)
-            }
+            TunnelParameters::OpenVpn(config) => Self::start_openvpn_tunnel(
+                &config,
+                log_file,
+                resource_dir,
+                on_event,
+                #[cfg(target_os = "linux")]
+                route_manager,
+                #[cfg(target_os = "linux")]
+                split_dns_servers,
+            ),
             #[cfg(target_os = "android")]
             TunnelParameters::OpenVpn(_) => Err(Error::UnsupportedPlatform),
 
-            TunnelParameters::Wireguard(config) => {
-                Self::start_wireguard_tunnel(&config, log_file, on_event, tun_provider)
-            }
+            TunnelParameters::Wireguard(config) => Self::start_wireguard_tunnel(
+                &config,
+                log_file,
+                on_event,
+                tun_provider,
+                #[cfg(target_os = "linux")]
+                route_manager,
+                #[cfg(target_os = "linux")]
+                split_dns_servers,
+            ),
         }
     }
 
@@ -173,19 +352,30 @@ impl TunnelMonitor {
         log: Option<PathBuf>,
         on_event: L,
         tun_provider: &mut TunProvider,
+        #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        #[cfg(target_os = "linux")] split_dns_servers: &[IpAddr],
     ) -> Result<Self>
     where
         L: Fn(TunnelEvent) + Send + Sync + Clone + 'static,
     {
-        let config = wireguard::config::Config::from_parameters(&params)?;
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut config = wireguard::config::Config::from_parameters(&params)?;
+        #[cfg(target_os = "linux")]
+        {
+            config.split_dns_servers = split_dns_servers.to_vec();
+        }
         let monitor = wireguard::WireguardMonitor::start(
             &config,
             log.as_ref().map(|p| p.as_path()),
             on_event,
             tun_provider,
+            #[cfg(target_os = "linux")]
+            route_manager,
         )?;
         Ok(TunnelMonitor {
             monitor: InternalTunnelMonitor::Wireguard(monitor),
+            #[cfg(target_os = "linux")]
+            route_manager: None,
         })
     }
 
@@ -195,16 +385,67 @@ impl TunnelMonitor {
         log: Option<PathBuf>,
         resource_dir: &Path,
         on_event: L,
+        #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        #[cfg(target_os = "linux")] split_dns_servers: &[IpAddr],
     ) -> Result<Self>
     where
         L: Fn(TunnelEvent) + Send + Sync + 'static,
     {
-        let monitor = openvpn::OpenVpnMonitor::start(on_event, config, log, resource_dir)?;
+        #[cfg(target_os = "linux")]
+        route_manager
+            .add_routes(Self::excluded_routes(
+                &config.generic_options.excluded_subnets,
+                split_dns_servers,
+            ))
+            .map_err(Error::SetupRoutingError)?;
+        let monitor = match openvpn::OpenVpnMonitor::start(on_event, config, log, resource_dir) {
+            Ok(monitor) => monitor,
+            Err(error) => {
+                #[cfg(target_os = "linux")]
+                route_manager.clear_routes();
+                return Err(error.into());
+            }
+        };
         Ok(TunnelMonitor {
             monitor: InternalTunnelMonitor::OpenVpn(monitor),
+            #[cfg(target_os = "linux")]
+            route_manager: Some(route_manager),
         })
     }
 
+    /// Returns the routes to the excluded networks and to the DNS servers of split DNS rules,
+    /// which are reached outside the tunnel.
+    #[cfg(target_os = "linux")]
+    fn excluded_routes(
+        excluded_subnets: &[ipnetwork::IpNetwork],
+        split_dns_servers: &[IpAddr],
+    ) -> HashMap<ipnetwork::IpNetwork, NetNode> {
+        excluded_subnets
+            .iter()
+            .cloned()
+            .chain(split_dns_servers.iter().map(|server| (*server).into()))
+            .map(|destination| (destination, NetNode::DefaultNode))
+            .collect()
+    }
+
+    /// Excluded networks must not contain the relay, since it is reached outside the tunnel
+    /// anyway, nor the tunnel gateways, which must be reached through the tunnel. The gateways
+    /// of OpenVPN tunnels are only known once they are up, see [`check_tunnel_gateways`].
+    fn ensure_excluded_subnets_are_valid(tunnel_parameters: &TunnelParameters) -> Result<()> {
+        let endpoint = tunnel_parameters.get_tunnel_endpoint();
+        let mut addresses = vec![endpoint.endpoint.address.ip()];
+        addresses.extend(endpoint.proxy.map(|proxy| proxy.endpoint.address.ip()));
+        if let TunnelParameters::Wireguard(params) = tunnel_parameters {
+            addresses.push(params.connection.ipv4_gateway.into());
+            addresses.extend(params.connection.ipv6_gateway.map(IpAddr::from));
+        }
+        validate_excluded_subnets(
+            &tunnel_parameters.get_generic_options().excluded_subnets,
+            &addresses,
+        )
+        .map_err(Error::ExcludedSubnetError)
+    }
+
     fn ensure_ipv6_can_be_used_if_enabled(tunnel_parameters: &TunnelParameters) -> Result<()> {
         let options = tunnel_parameters.get_generic_options();
         if options.enable_ipv6 {
@@ -271,7 +512,24 @@ impl TunnelMonitor {
 
     /// Consumes the monitor and blocks until the tunnel exits or there is an error.
     pub fn wait(self) -> Result<()> {
-        self.monitor.wait().map_err(Error::from)
+        let result = self.monitor.wait().map_err(Error::from);
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(route_manager) = self.route_manager {
+                route_manager.clear_routes();
+            }
+        }
+        result
+    }
+}
+
+impl TunnelHandle for TunnelMonitor {
+    fn close_handle(&self) -> Box<dyn TunnelCloseHandle> {
+        Box::new(TunnelMonitor::close_handle(self))
+    }
+
+    fn wait(self: Box<Self>) -> Result<()> {
+        TunnelMonitor::wait(*self)
     }
 }
 
@@ -299,6 +557,12 @@ impl CloseHandle {
     }
 }
 
+impl TunnelCloseHandle for CloseHandle {
+    fn close(self: Box<Self>) -> io::Result<()> {
+        CloseHandle::close(*self)
+    }
+}
+
 enum InternalTunnelMonitor {
     #[cfg(not(target_os = "android"))]
     OpenVpn(openvpn::OpenVpnMonitor),
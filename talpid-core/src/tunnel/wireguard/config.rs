@@ -1,7 +1,9 @@
+use ipnetwork::IpNetwork;
 use std::{
     borrow::Cow,
     ffi::CString,
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
 };
 use talpid_types::net::{wireguard, GenericTunnelOptions};
 
@@ -17,6 +19,14 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// How long the tunnel may stay degraded before it is torn down to switch relay
+    pub degraded_timeout: Option<Duration>,
+    /// Destination networks that are routed outside the tunnel
+    pub excluded_subnets: Vec<IpNetwork>,
+    /// DNS servers of split DNS rules, which are routed outside the tunnel. Only used on Linux
+    pub split_dns_servers: Vec<IpAddr>,
+    /// Whether the routes are added to a dedicated routing table. Only used on Linux
+    pub policy_routing: bool,
 }
 
 /// Smallest MTU that supports IPv6
@@ -99,6 +109,12 @@ impl Config {
                 None
             },
             mtu,
+            degraded_timeout: wg_options
+                .degraded_timeout
+                .map(|secs| Duration::from_secs(u64::from(secs))),
+            excluded_subnets: generic_options.excluded_subnets.clone(),
+            split_dns_servers: vec![],
+            policy_routing: wg_options.policy_routing,
         })
     }
 
@@ -111,6 +127,14 @@ impl Config {
             .add("private_key", self.tunnel.private_key.to_bytes().as_ref())
             .add("listen_port", "0");
 
+        // Lets the encrypted traffic of the tunnel bypass the routing table of policy routing.
+        #[cfg(target_os = "linux")]
+        {
+            if self.policy_routing {
+                wg_conf.add("fwmark", crate::routing::TUNNEL_FWMARK.to_string().as_str());
+            }
+        }
+
         wg_conf.add("replace_peers", "true");
 
         for peer in &self.peers {
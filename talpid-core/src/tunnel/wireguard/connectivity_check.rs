@@ -1,9 +1,14 @@
-use crate::{ping_monitor::Pinger, tunnel::wireguard::stats::Stats};
+use crate::{
+    ping_monitor::Pinger,
+    tunnel::wireguard::{quality::QualityTracker, stats::Stats},
+};
 use std::{
+    iter,
     net::Ipv4Addr,
     sync::{mpsc, Mutex, Weak},
     time::{Duration, Instant},
 };
+use talpid_types::{tunnel::ConnectionQuality, ErrorExt};
 
 use super::{Tunnel, TunnelError};
 
@@ -25,6 +30,8 @@ const TRAFFIC_TIMEOUT: Duration = Duration::from_secs(120);
 const PING_TIMEOUT: Duration = Duration::from_secs(15);
 /// Number of seconds to wait between sending ICMP packets
 const SECONDS_PER_PING: Duration = Duration::from_secs(3);
+/// Interval between pings sent to measure the quality of an established connection.
+const QUALITY_PING_INTERVAL: Duration = Duration::from_secs(10);
 
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
@@ -61,6 +68,10 @@ pub enum Error {
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
 /// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+///
+/// While the connection is established, the monitor also measures its quality by sending a ping
+/// every `QUALITY_PING_INTERVAL`. If a `degraded_timeout` is set and the connection stays degraded
+/// for longer than that, the connection is considered broken as well.
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
     conn_state: ConnState,
@@ -68,6 +79,9 @@ pub struct ConnectivityMonitor {
     num_pings_sent: u32,
     pinger: Pinger,
     close_receiver: mpsc::Receiver<()>,
+    quality: QualityTracker,
+    last_quality_ping: Option<Instant>,
+    degraded_timeout: Option<Duration>,
 }
 
 
@@ -77,6 +91,7 @@ impl ConnectivityMonitor {
         interface: String,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
+        degraded_timeout: Option<Duration>,
     ) -> Result<Self, Error> {
         let pinger = Pinger::new(addr, interface).map_err(Error::PingError)?;
 
@@ -89,6 +104,9 @@ impl ConnectivityMonitor {
             num_pings_sent: 0,
             pinger,
             close_receiver,
+            quality: QualityTracker::new(),
+            last_quality_ping: None,
+            degraded_timeout,
         })
     }
 
@@ -111,8 +129,13 @@ impl ConnectivityMonitor {
         Ok(false)
     }
 
-    pub(super) fn run(&mut self) -> Result<(), Error> {
-        self.wait_loop(REGULAR_LOOP_SLEEP)
+    /// Monitors the connection until it breaks or the monitor is shut down. `on_quality_change`
+    /// is called whenever the measured quality of the connection changes significantly.
+    pub(super) fn run(
+        &mut self,
+        on_quality_change: impl Fn(ConnectionQuality),
+    ) -> Result<(), Error> {
+        self.wait_loop(REGULAR_LOOP_SLEEP, on_quality_change)
     }
 
     /// Returns true if monitor should be shut down
@@ -123,11 +146,63 @@ impl ConnectivityMonitor {
         }
     }
 
-    fn wait_loop(&mut self, iter_delay: Duration) -> Result<(), Error> {
-        while self.check_connectivity()? && !self.should_shut_down(iter_delay) {}
+    fn wait_loop(
+        &mut self,
+        iter_delay: Duration,
+        on_quality_change: impl Fn(ConnectionQuality),
+    ) -> Result<(), Error> {
+        while self.check_connectivity()? {
+            if let Some(quality) = self.check_quality() {
+                on_quality_change(quality);
+            }
+            if self.degraded_timed_out() {
+                log::warn!("Tunnel has been degraded for too long, considering it broken");
+                break;
+            }
+            if self.should_shut_down(iter_delay) {
+                break;
+            }
+        }
         Ok(())
     }
 
+    /// Sends a quality ping if one is due, and returns the quality of the connection if it has
+    /// changed enough to be worth reporting. A ping that can't be sent counts as a missed ping.
+    fn check_quality(&mut self) -> Option<ConnectionQuality> {
+        let now = Instant::now();
+        if self
+            .last_quality_ping
+            .map(|last_ping| now.duration_since(last_ping) >= QUALITY_PING_INTERVAL)
+            .unwrap_or(true)
+        {
+            if let Err(error) = self.pinger.send_icmp() {
+                log::warn!(
+                    "{}",
+                    Error::PingError(error).display_chain_with_msg("Failed to send quality ping")
+                );
+                self.quality.add_ping_replies(iter::once(None));
+            }
+            self.last_quality_ping = Some(now);
+        }
+        self.quality.add_ping_replies(self.pinger.take_replies());
+
+        let quality = self.quality.update(now);
+        if let Some(ref quality) = quality {
+            log::debug!("Tunnel quality: {}", quality);
+        }
+        quality
+    }
+
+    fn degraded_timed_out(&self) -> bool {
+        match (
+            self.degraded_timeout,
+            self.quality.degraded_for(Instant::now()),
+        ) {
+            (Some(timeout), Some(degraded_for)) => degraded_for >= timeout,
+            _ => false,
+        }
+    }
+
     /// Returns true if connection is established
     fn check_connectivity(&mut self) -> Result<bool, Error> {
         let now = Instant::now();
@@ -135,6 +210,7 @@ impl ConnectivityMonitor {
             None => Ok(false),
             Some(new_stats) => {
                 let new_stats = new_stats?;
+                self.quality.add_stats(new_stats);
 
                 if self.conn_state.update(now, new_stats) {
                     self.initial_ping_timestamp = None;
@@ -340,6 +416,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                ..Default::default()
             },
         );
 
@@ -363,6 +440,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                ..Default::default()
             },
         );
 
@@ -385,6 +463,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                ..Default::default()
             },
         );
 
@@ -394,6 +473,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 1,
+                ..Default::default()
             },
         );
 
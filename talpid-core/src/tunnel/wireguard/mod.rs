@@ -14,6 +14,7 @@ use talpid_types::ErrorExt;
 pub mod config;
 mod connectivity_check;
 mod logging;
+mod quality;
 mod stats;
 mod wireguard_go;
 
@@ -47,7 +48,11 @@ pub struct WireguardMonitor {
     /// Tunnel implementation
     tunnel: Arc<Mutex<Option<Box<dyn Tunnel>>>>,
     /// Route manager
+    #[cfg(not(target_os = "linux"))]
     route_handle: routing::RouteManager,
+    /// Handle to the route manager shared by all tunnels, which the routes are added through
+    #[cfg(target_os = "linux")]
+    route_handle: routing::RouteManagerHandle,
     /// Callback to signal tunnel events
     event_callback: Box<dyn Fn(TunnelEvent) + Send + Sync + 'static>,
     close_msg_sender: mpsc::Sender<CloseMsg>,
@@ -62,6 +67,7 @@ impl WireguardMonitor {
         log_path: Option<&Path>,
         on_event: F,
         tun_provider: &mut TunProvider,
+        #[cfg(target_os = "linux")] route_handle: routing::RouteManagerHandle,
     ) -> Result<WireguardMonitor> {
         let tunnel = Box::new(WgGoTunnel::start_tunnel(
             &config,
@@ -70,9 +76,20 @@ impl WireguardMonitor {
             Self::get_tunnel_routes(config),
         )?);
         let iface_name = tunnel.get_interface_name().to_string();
-        #[cfg_attr(not(windows), allow(unused_mut))]
-        let mut route_handle = routing::RouteManager::new(Self::get_routes(&iface_name, &config))
+        let routes = Self::get_routes(&iface_name, &config);
+        #[cfg(target_os = "linux")]
+        {
+            if config.policy_routing {
+                route_handle.add_policy_routed_routes(routes)
+            } else {
+                route_handle.add_routes(routes)
+            }
             .map_err(Error::SetupRoutingError)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        #[cfg_attr(not(windows), allow(unused_mut))]
+        let mut route_handle =
+            routing::RouteManager::new(routes).map_err(Error::SetupRoutingError)?;
 
         #[cfg(target_os = "windows")]
         route_handle
@@ -93,19 +110,30 @@ impl WireguardMonitor {
         let metadata = Self::tunnel_metadata(&iface_name, &config);
         let gateway = config.ipv4_gateway;
         let close_sender = monitor.close_msg_sender.clone();
-        let mut connectivity_monitor = connectivity_check::ConnectivityMonitor::new(
+        let mut connectivity_monitor = match connectivity_check::ConnectivityMonitor::new(
             gateway,
             iface_name,
             Arc::downgrade(&monitor.tunnel),
             pinger_rx,
-        )?;
+            config.degraded_timeout,
+        ) {
+            Ok(connectivity_monitor) => connectivity_monitor,
+            Err(error) => {
+                // The routes are not removed when the monitor is dropped
+                #[cfg(target_os = "linux")]
+                monitor.route_handle.clear_routes();
+                return Err(error.into());
+            }
+        };
 
         std::thread::spawn(move || {
             match connectivity_monitor.establish_connectivity() {
                 Ok(true) => {
                     (on_event)(TunnelEvent::Up(metadata));
 
-                    if let Err(error) = connectivity_monitor.run() {
+                    let on_quality_change =
+                        |quality| (on_event)(TunnelEvent::QualityChanged(quality));
+                    if let Err(error) = connectivity_monitor.run(on_quality_change) {
                         log::error!(
                             "{}",
                             error.display_chain_with_msg("Connectivity monitor failed")
@@ -147,6 +175,9 @@ impl WireguardMonitor {
         // Clear routes manually - otherwise there will be some log spam since the tunnel device
         // can be removed before the routes are cleared, which automatically clears some of the
         // routes that were set.
+        #[cfg(target_os = "linux")]
+        self.route_handle.clear_routes();
+        #[cfg(not(target_os = "linux"))]
         self.route_handle.stop();
 
         self.stop_tunnel();
@@ -201,6 +232,18 @@ impl WireguardMonitor {
             routes.insert(peer.endpoint.ip().into(), routing::NetNode::DefaultNode);
         }
 
+        // The firewall only lets traffic to excluded networks and split DNS servers through on
+        // Linux
+        #[cfg(target_os = "linux")]
+        {
+            for subnet in &config.excluded_subnets {
+                routes.insert(*subnet, routing::NetNode::DefaultNode);
+            }
+            for server in &config.split_dns_servers {
+                routes.insert((*server).into(), routing::NetNode::DefaultNode);
+            }
+        }
+
         routes
     }
 
@@ -302,3 +345,48 @@ pub enum TunnelError {
     #[error(display = "Failed to set up logging")]
     LoggingError(#[error(source)] logging::Error),
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use talpid_types::net::wireguard::{PeerConfig, PrivateKey, PublicKey, TunnelConfig};
+
+    #[test]
+    fn test_split_dns_servers_are_routed_outside_tunnel() {
+        let split_dns_servers = vec![
+            IpAddr::V4(Ipv4Addr::new(172, 16, 8, 53)),
+            IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53)),
+        ];
+        let config = Config {
+            tunnel: TunnelConfig {
+                private_key: PrivateKey::new_from_random(),
+                addresses: vec![IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2))],
+            },
+            peers: vec![PeerConfig {
+                public_key: PublicKey::from([0; 32]),
+                allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+                endpoint: "192.0.2.1:51820".parse().unwrap(),
+            }],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+            mtu: 1380,
+            degraded_timeout: None,
+            excluded_subnets: vec!["198.51.100.0/24".parse().unwrap()],
+            split_dns_servers: split_dns_servers.clone(),
+            policy_routing: false,
+        };
+
+        let routes = WireguardMonitor::get_routes("wg-mullvad", &config);
+        for server in split_dns_servers {
+            assert_eq!(
+                routes.get(&server.into()),
+                Some(&routing::NetNode::DefaultNode)
+            );
+        }
+        assert_eq!(
+            routes.get(&"198.51.100.0/24".parse().unwrap()),
+            Some(&routing::NetNode::DefaultNode)
+        );
+    }
+}
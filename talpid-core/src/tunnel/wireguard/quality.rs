@@ -0,0 +1,278 @@
+use super::stats::Stats;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use talpid_types::tunnel::ConnectionQuality;
+
+/// Number of traffic samples used to compute the rx stall ratio.
+const TRAFFIC_SAMPLE_WINDOW: usize = 30;
+/// Number of ping replies used to compute the ping loss and round trip time.
+const PING_SAMPLE_WINDOW: usize = 10;
+/// WireGuard renegotiates a session every 2 minutes while traffic is flowing. A handshake older
+/// than this means that rekeying is failing.
+const STALE_HANDSHAKE_AGE: Duration = Duration::from_secs(180);
+/// Round trip times above this start lowering the quality score.
+const RTT_PENALTY_THRESHOLD: Duration = Duration::from_millis(150);
+/// A tunnel with a score below this is considered degraded.
+const DEGRADED_SCORE: u8 = 60;
+/// Minimum change in score that is worth reporting, unless the degraded flag changes.
+const SCORE_REPORT_DELTA: u8 = 10;
+
+/// Keeps track of the quality of a working tunnel, based on the handshake age, the outcome of
+/// pings through the tunnel, and on how often sent traffic goes unanswered.
+pub struct QualityTracker {
+    last_stats: Option<Stats>,
+    stall_samples: VecDeque<bool>,
+    ping_replies: VecDeque<Option<Duration>>,
+    last_reported: Option<ConnectionQuality>,
+    degraded_since: Option<Instant>,
+}
+
+impl QualityTracker {
+    pub fn new() -> Self {
+        Self {
+            last_stats: None,
+            stall_samples: VecDeque::with_capacity(TRAFFIC_SAMPLE_WINDOW),
+            ping_replies: VecDeque::with_capacity(PING_SAMPLE_WINDOW),
+            last_reported: None,
+            degraded_since: None,
+        }
+    }
+
+    /// Records a new reading of the tunnel's traffic counters. A sample where traffic was sent but
+    /// nothing was received counts as a stall. Samples without any traffic are ignored.
+    pub fn add_stats(&mut self, stats: Stats) {
+        if let Some(last_stats) = self.last_stats.replace(stats) {
+            let rx_incremented = stats.rx_bytes > last_stats.rx_bytes;
+            let tx_incremented = stats.tx_bytes > last_stats.tx_bytes;
+            if rx_incremented || tx_incremented {
+                push_bounded(
+                    &mut self.stall_samples,
+                    tx_incremented && !rx_incremented,
+                    TRAFFIC_SAMPLE_WINDOW,
+                );
+            }
+        }
+    }
+
+    /// Records the outcome of pings sent through the tunnel. `None` means no reply was received.
+    pub fn add_ping_replies(&mut self, replies: impl IntoIterator<Item = Option<Duration>>) {
+        for reply in replies {
+            push_bounded(&mut self.ping_replies, reply, PING_SAMPLE_WINDOW);
+        }
+    }
+
+    /// Computes the current quality of the tunnel.
+    pub fn quality(&self) -> ConnectionQuality {
+        let handshake_age = self.last_stats.and_then(|stats| stats.handshake_age());
+        let ping_rtt = self.average_rtt();
+        let ping_loss = percentage(
+            self.ping_replies
+                .iter()
+                .filter(|reply| reply.is_none())
+                .count(),
+            self.ping_replies.len(),
+        );
+        let rx_stall = percentage(
+            self.stall_samples
+                .iter()
+                .filter(|stalled| **stalled)
+                .count(),
+            self.stall_samples.len(),
+        );
+
+        let mut score = 100i32;
+        if handshake_age
+            .map(|age| age > STALE_HANDSHAKE_AGE)
+            .unwrap_or(false)
+        {
+            score -= 50;
+        }
+        if let Some(rtt) = ping_rtt {
+            if rtt > RTT_PENALTY_THRESHOLD {
+                let excess_ms = (rtt - RTT_PENALTY_THRESHOLD).as_millis() as i32;
+                score -= (excess_ms / 10).min(30);
+            }
+        }
+        score -= i32::from(ping_loss) / 2;
+        score -= i32::from(rx_stall) / 2;
+        let score = score.max(0) as u8;
+
+        ConnectionQuality {
+            score,
+            degraded: score < DEGRADED_SCORE,
+            handshake_age: handshake_age.map(|age| age.as_secs()),
+            ping_rtt_ms: ping_rtt.map(|rtt| rtt.as_millis() as u32),
+            ping_loss,
+            rx_stall,
+        }
+    }
+
+    /// Recomputes the quality and returns it if it changed enough to be reported.
+    pub fn update(&mut self, now: Instant) -> Option<ConnectionQuality> {
+        let quality = self.quality();
+
+        if quality.degraded {
+            self.degraded_since.get_or_insert(now);
+        } else {
+            self.degraded_since = None;
+        }
+
+        let should_report = match &self.last_reported {
+            None => true,
+            Some(last_reported) => {
+                last_reported.degraded != quality.degraded
+                    || (i32::from(last_reported.score) - i32::from(quality.score)).abs()
+                        >= i32::from(SCORE_REPORT_DELTA)
+            }
+        };
+
+        if should_report {
+            self.last_reported = Some(quality.clone());
+            Some(quality)
+        } else {
+            None
+        }
+    }
+
+    /// Returns for how long the tunnel has been degraded, as of the last call to `update`.
+    pub fn degraded_for(&self, now: Instant) -> Option<Duration> {
+        self.degraded_since.map(|since| now.duration_since(since))
+    }
+
+    fn average_rtt(&self) -> Option<Duration> {
+        let rtts: Vec<Duration> = self
+            .ping_replies
+            .iter()
+            .filter_map(|reply| *reply)
+            .collect();
+        if rtts.is_empty() {
+            return None;
+        }
+        Some(rtts.iter().sum::<Duration>() / rtts.len() as u32)
+    }
+}
+
+fn push_bounded<T>(samples: &mut VecDeque<T>, sample: T, capacity: usize) {
+    if samples.len() >= capacity {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn percentage(part: usize, total: usize) -> u8 {
+    if total == 0 {
+        0
+    } else {
+        (part * 100 / total) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stats(tx_bytes: u64, rx_bytes: u64) -> Stats {
+        Stats {
+            tx_bytes,
+            rx_bytes,
+            last_handshake_time: None,
+        }
+    }
+
+    #[test]
+    fn test_initial_quality_is_perfect() {
+        let quality = QualityTracker::new().quality();
+        assert_eq!(quality.score, 100);
+        assert!(!quality.degraded);
+        assert_eq!(quality.ping_rtt_ms, None);
+        assert_eq!(quality.handshake_age, None);
+    }
+
+    #[test]
+    fn test_idle_samples_are_ignored() {
+        let mut tracker = QualityTracker::new();
+        for _ in 0..5 {
+            tracker.add_stats(stats(100, 100));
+        }
+        assert_eq!(tracker.quality().rx_stall, 0);
+    }
+
+    #[test]
+    fn test_rx_stall_degrades_connection() {
+        let mut tracker = QualityTracker::new();
+        for tx_bytes in 0..TRAFFIC_SAMPLE_WINDOW as u64 + 1 {
+            tracker.add_stats(stats(tx_bytes * 100, 100));
+        }
+        let quality = tracker.quality();
+        assert_eq!(quality.rx_stall, 100);
+        assert!(quality.degraded);
+    }
+
+    #[test]
+    fn test_ping_loss_and_rtt() {
+        let mut tracker = QualityTracker::new();
+        tracker.add_ping_replies(vec![
+            Some(Duration::from_millis(20)),
+            None,
+            Some(Duration::from_millis(40)),
+            None,
+        ]);
+        let quality = tracker.quality();
+        assert_eq!(quality.ping_rtt_ms, Some(30));
+        assert_eq!(quality.ping_loss, 50);
+        assert_eq!(quality.score, 75);
+        assert!(!quality.degraded);
+    }
+
+    #[test]
+    fn test_stale_handshake_lowers_score() {
+        let mut tracker = QualityTracker::new();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        tracker.add_stats(Stats {
+            last_handshake_time: Some(now - STALE_HANDSHAKE_AGE - Duration::from_secs(10)),
+            ..stats(0, 0)
+        });
+        let quality = tracker.quality();
+        assert_eq!(quality.score, 50);
+        assert!(quality.degraded);
+    }
+
+    #[test]
+    fn test_only_significant_changes_are_reported() {
+        let mut tracker = QualityTracker::new();
+        let now = Instant::now();
+        assert!(tracker.update(now).is_some());
+        assert!(tracker.update(now).is_none());
+
+        tracker.add_ping_replies(vec![Some(Duration::from_millis(10)); 9]);
+        tracker.add_ping_replies(vec![None]);
+        assert!(tracker.update(now).is_none());
+
+        tracker.add_ping_replies(vec![None; 10]);
+        let quality = tracker
+            .update(now)
+            .expect("Degraded quality was not reported");
+        assert!(quality.degraded);
+    }
+
+    #[test]
+    fn test_degraded_duration() {
+        let mut tracker = QualityTracker::new();
+        let start = Instant::now();
+        tracker.add_ping_replies(vec![None; PING_SAMPLE_WINDOW]);
+        tracker.update(start);
+        tracker.update(start + Duration::from_secs(5));
+        assert_eq!(
+            tracker.degraded_for(start + Duration::from_secs(10)),
+            Some(Duration::from_secs(10))
+        );
+
+        tracker.add_ping_replies(vec![Some(Duration::from_millis(10)); PING_SAMPLE_WINDOW]);
+        tracker.update(start + Duration::from_secs(11));
+        assert_eq!(tracker.degraded_for(start + Duration::from_secs(12)), None);
+    }
+}
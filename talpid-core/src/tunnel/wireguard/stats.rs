@@ -1,3 +1,5 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 #[derive(err_derive::Error, Debug, PartialEq)]
 pub enum Error {
     #[error(display = "Failed to parse integer from string \"_0\"")]
@@ -12,12 +14,17 @@ pub enum Error {
 pub struct Stats {
     pub tx_bytes: u64,
     pub rx_bytes: u64,
+    /// Time of the last successful handshake, as a duration since the UNIX epoch. `None` if no
+    /// handshake has been completed yet.
+    pub last_handshake_time: Option<Duration>,
 }
 
 impl Stats {
     pub fn parse_config_str(config: &str) -> Result<Self, Error> {
         let mut tx_bytes = None;
         let mut rx_bytes = None;
+        let mut handshake_sec = 0;
+        let mut handshake_nsec = 0;
 
         // parts iterates over keys and values
         let parts = config.split('\n').filter_map(|line| {
@@ -45,22 +52,56 @@ impl Stats {
                             .map_err(|err| Error::IntParseError(value.to_string(), err))?,
                     );
                 }
+                "last_handshake_time_sec" => {
+                    handshake_sec = Self::parse_value(value)?;
+                }
+                "last_handshake_time_nsec" => {
+                    handshake_nsec = Self::parse_value(value)?;
+                }
 
                 _ => continue,
             }
         }
 
+        let last_handshake_time = if handshake_sec == 0 && handshake_nsec == 0 {
+            None
+        } else {
+            Some(Duration::new(handshake_sec, handshake_nsec as u32))
+        };
+
         match (tx_bytes, rx_bytes) {
-            (Some(tx_bytes), Some(rx_bytes)) => Ok(Self { tx_bytes, rx_bytes }),
+            (Some(tx_bytes), Some(rx_bytes)) => Ok(Self {
+                tx_bytes,
+                rx_bytes,
+                last_handshake_time,
+            }),
             _ => Err(Error::KeyNotFoundError),
         }
     }
+
+    /// Returns the time elapsed since the last successful handshake, if there has been one.
+    pub fn handshake_age(&self) -> Option<Duration> {
+        let handshake_time = UNIX_EPOCH + self.last_handshake_time?;
+        Some(
+            SystemTime::now()
+                .duration_since(handshake_time)
+                .unwrap_or_default(),
+        )
+    }
+
+    fn parse_value(value: &str) -> Result<u64, Error> {
+        value
+            .trim()
+            .parse()
+            .map_err(|err| Error::IntParseError(value.to_string(), err))
+    }
 }
 
 
 #[cfg(test)]
 mod test {
     use super::{Error, Stats};
+    use std::time::Duration;
 
     #[test]
     fn test_parsing() {
@@ -69,6 +110,19 @@ mod test {
         let stats = Stats::parse_config_str(valid_input).expect("Failed to parse valid input");
         assert_eq!(stats.rx_bytes, 2396);
         assert_eq!(stats.tx_bytes, 2740);
+        assert_eq!(
+            stats.last_handshake_time,
+            Some(Duration::new(1578420649, 369416131))
+        );
+    }
+
+    #[test]
+    fn test_parsing_no_handshake() {
+        let valid_input = "private_key=0000000000000000000000000000000000000000000000000000000000000000\npublic_key=0000000000000000000000000000000000000000000000000000000000000000\nprotocol_version=1\nendpoint=000.000.000.000:00000\nlast_handshake_time_sec=0\nlast_handshake_time_nsec=0\ntx_bytes=148\nrx_bytes=0\npersistent_keepalive_interval=0\nallowed_ip=0.0.0.0/0\n";
+
+        let stats = Stats::parse_config_str(valid_input).expect("Failed to parse valid input");
+        assert_eq!(stats.last_handshake_time, None);
+        assert_eq!(stats.handshake_age(), None);
     }
 
     #[test]
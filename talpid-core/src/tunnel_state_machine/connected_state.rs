@@ -3,16 +3,18 @@ use super::{
     SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
-    firewall::FirewallPolicy,
-    tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
+    dns,
+    firewall::{self, FirewallPolicy},
+    tunnel::{TunnelCloseHandle, TunnelEvent, TunnelMetadata},
 };
 use futures::{
     sync::{mpsc, oneshot},
     Async, Future, Stream,
 };
 use talpid_types::{
+    dns::{split_dns_servers, DnsMode},
     net::{Endpoint, TunnelParameters},
-    tunnel::ErrorStateCause,
+    tunnel::{ErrorDetails, ErrorStateCause, TunnelInterface},
     ErrorExt,
 };
 
@@ -21,7 +23,7 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
-    pub close_handle: Option<CloseHandle>,
+    pub close_handle: Option<Box<dyn TunnelCloseHandle>>,
 }
 
 /// The tunnel is up and working.
@@ -30,7 +32,7 @@ pub struct ConnectedState {
     tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
-    close_handle: Option<CloseHandle>,
+    close_handle: Option<Box<dyn TunnelCloseHandle>>,
 }
 
 impl ConnectedState {
@@ -51,10 +53,23 @@ impl ConnectedState {
         // If a proxy is specified we need to pass it on as the peer endpoint.
         let peer_endpoint = self.get_endpoint_from_params();
 
+        let generic_options = self.tunnel_parameters.get_generic_options();
+
         let policy = FirewallPolicy::Connected {
             peer_endpoint,
             tunnel: self.metadata.clone(),
+            dns_servers: shared_values.dns_config.dns_servers(&self.metadata),
+            encrypted_dns: match &shared_values.dns_config.dns_mode {
+                DnsMode::Encrypted(server) => Some(server.protocol),
+                DnsMode::Plain => None,
+            },
+            split_dns_servers: split_dns_servers(&shared_values.dns_config.split_dns),
             allow_lan: shared_values.allow_lan,
+            lan_networks: shared_values.lan_networks.clone(),
+            excluded_subnets: generic_options.excluded_subnets.clone(),
+            split_tunnel: shared_values.split_tunnel,
+            forwarded_ports: generic_options.forwarded_ports.clone(),
+            shared_interfaces: generic_options.shared_interfaces.clone(),
         };
         shared_values.firewall.apply_policy(policy)
     }
@@ -69,21 +84,30 @@ impl ConnectedState {
         }
     }
 
-    fn set_dns(
-        &self,
-        shared_values: &mut SharedTunnelStateValues,
-    ) -> Result<(), crate::dns::Error> {
-        let mut dns_ips = vec![self.metadata.ipv4_gateway.into()];
-        if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-            dns_ips.push(ipv6_gateway.into());
-        };
-
+    /// Points the system DNS at the DNS servers, or at the local resolver that forwards queries
+    /// to them if it is enabled, blocks domains or encrypts queries.
+    fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<(), ErrorDetails> {
+        let mut dns_ips = shared_values.dns_config.dns_servers(&self.metadata);
+        if shared_values.uses_dns_resolver() {
+            let resolver_ip = shared_values
+                .start_dns_resolver(&dns_ips)
+                .map_err(|error| ErrorDetails::from_error("stub resolver", &error))?;
+            dns_ips = vec![resolver_ip];
+        } else {
+            shared_values.stop_dns_resolver();
+        }
         shared_values
             .dns_monitor
-            .set(&self.metadata.interface, &dns_ips)
+            .set(
+                &self.metadata.interface,
+                &dns_ips,
+                &shared_values.dns_config.split_dns,
+            )
+            .map_err(|error| dns::error_details(&error))
     }
 
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
+        shared_values.pause_dns_resolver();
         if let Err(error) = shared_values.dns_monitor.reset() {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
         }
@@ -101,6 +125,62 @@ impl ConnectedState {
         ))
     }
 
+    fn reapply_firewall_policy(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        use self::EventConsequence::*;
+
+        match self.set_firewall_policy(shared_values) {
+            Ok(()) => SameState(self),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to apply firewall policy for connected state"
+                    )
+                );
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(
+                        firewall::error_details(&error),
+                    )),
+                )
+            }
+        }
+    }
+
+    /// Allows the current DNS servers in the firewall and then switches the system to them.
+    fn reapply_dns_config(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        use self::EventConsequence::*;
+
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to apply firewall policy for connected state")
+            );
+            return self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(
+                    firewall::error_details(&error),
+                )),
+            );
+        }
+        match self.set_dns(shared_values) {
+            Ok(()) => SameState(self),
+            Err(error) => {
+                log::error!("Failed to set system DNS settings: {}", error.message);
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetDnsError(error)),
+                )
+            }
+        }
+    }
+
     fn handle_commands(
         self,
         commands: &mut mpsc::UnboundedReceiver<TunnelCommand>,
@@ -113,21 +193,34 @@ impl ConnectedState {
                 if let Err(error_cause) = shared_values.set_allow_lan(allow_lan) {
                     self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
                 } else {
-                    match self.set_firewall_policy(shared_values) {
-                        Ok(()) => SameState(self),
-                        Err(error) => {
-                            log::error!(
-                                "{}",
-                                error.display_chain_with_msg(
-                                    "Failed to apply firewall policy for connected state"
-                                )
-                            );
-                            self.disconnect(
-                                shared_values,
-                                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError),
-                            )
-                        }
-                    }
+                    self.reapply_firewall_policy(shared_values)
+                }
+            }
+            Ok(TunnelCommand::LanNetworks(lan_networks)) => {
+                if shared_values.set_lan_networks(lan_networks) {
+                    self.reapply_firewall_policy(shared_values)
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::AllowedEndpoints(allowed_endpoints)) => {
+                // The connected policy does not allow them, so nothing needs to be reapplied
+                shared_values.set_allowed_endpoints(allowed_endpoints);
+                SameState(self)
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::SplitTunnel(split_tunnel)) => {
+                if shared_values.set_split_tunnel(split_tunnel) {
+                    self.reapply_firewall_policy(shared_values)
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::Dns(dns_config)) => {
+                if shared_values.set_dns_config(dns_config) {
+                    self.reapply_dns_config(shared_values)
+                } else {
+                    SameState(self)
                 }
             }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -167,6 +260,11 @@ impl ConnectedState {
             Ok(TunnelEvent::Down) | Err(_) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            // The tunnel stays connected, but the new quality is reported as a transition
+            Ok(TunnelEvent::QualityChanged(quality)) => NewState((
+                TunnelStateWrapper::from(self),
+                TunnelStateTransition::QualityChanged(quality),
+            )),
             Ok(_) => SameState(self),
         }
     }
@@ -219,26 +317,29 @@ impl TunnelState for ConnectedState {
                 (
                     connected_state.close_handle,
                     connected_state.tunnel_close_event,
-                    AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError),
+                    AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(
+                        firewall::error_details(&error),
+                    )),
                 ),
             )
         } else if let Err(error) = connected_state.set_dns(shared_values) {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to set system DNS settings")
-            );
+            log::error!("Failed to set system DNS settings: {}", error.message);
             DisconnectingState::enter(
                 shared_values,
                 (
                     connected_state.close_handle,
                     connected_state.tunnel_close_event,
-                    AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                    AfterDisconnect::Block(ErrorStateCause::SetDnsError(error)),
                 ),
             )
         } else {
+            let interface = TunnelInterface {
+                name: connected_state.metadata.interface.clone(),
+                ips: connected_state.metadata.ips.clone(),
+            };
             (
                 TunnelStateWrapper::from(connected_state),
-                TunnelStateTransition::Connected(tunnel_endpoint),
+                TunnelStateTransition::Connected(tunnel_endpoint, interface),
             )
         }
     }
@@ -4,9 +4,10 @@ use super::{
     TunnelStateWrapper,
 };
 use crate::{
-    firewall::FirewallPolicy,
+    firewall::{self, FirewallPolicy},
     tunnel::{
-        self, tun_provider::TunProvider, CloseHandle, TunnelEvent, TunnelMetadata, TunnelMonitor,
+        self, tun_provider::TunProvider, TunnelBackend, TunnelCloseHandle, TunnelEvent,
+        TunnelHandle, TunnelMetadata,
     },
 };
 use futures::{
@@ -16,10 +17,11 @@ use futures::{
 use log::{debug, error, info, trace, warn};
 use std::{
     net::IpAddr,
-    path::{Path, PathBuf},
     thread,
     time::{Duration, Instant},
 };
+#[cfg(target_os = "linux")]
+use talpid_types::dns::split_dns_servers;
 use talpid_types::{
     net::{openvpn, TunnelParameters},
     tunnel::ErrorStateCause,
@@ -35,7 +37,7 @@ pub struct ConnectingState {
     tunnel_events: mpsc::UnboundedReceiver<TunnelEvent>,
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: Option<oneshot::Receiver<Option<ErrorStateCause>>>,
-    close_handle: Option<CloseHandle>,
+    close_handle: Option<Box<dyn TunnelCloseHandle>>,
     retry_attempt: u32,
 }
 
@@ -56,27 +58,31 @@ impl ConnectingState {
             peer_endpoint,
             pingable_hosts: gateway_list_from_params(params),
             allow_lan: shared_values.allow_lan,
+            lan_networks: shared_values.lan_networks.clone(),
+            excluded_subnets: params.get_generic_options().excluded_subnets.clone(),
+            split_tunnel: shared_values.split_tunnel,
+            allowed_endpoints: shared_values.allowed_endpoints.clone(),
         };
         shared_values.firewall.apply_policy(policy)
     }
 
     fn start_tunnel(
         parameters: TunnelParameters,
-        log_dir: &Option<PathBuf>,
-        resource_dir: &Path,
+        tunnel_backend: &mut dyn TunnelBackend,
         tun_provider: &mut TunProvider,
+        #[cfg(target_os = "linux")] split_dns_servers: &[IpAddr],
         retry_attempt: u32,
     ) -> crate::tunnel::Result<Self> {
         let (event_tx, event_rx) = mpsc::unbounded();
         let on_tunnel_event = move |event| {
             let _ = event_tx.unbounded_send(event);
         };
-        let monitor = TunnelMonitor::start(
+        let monitor = tunnel_backend.start(
             &parameters,
-            log_dir,
-            resource_dir,
-            on_tunnel_event,
+            Box::new(on_tunnel_event),
             tun_provider,
+            #[cfg(target_os = "linux")]
+            split_dns_servers,
         )?;
         let close_handle = Some(monitor.close_handle());
         let tunnel_close_event = Self::spawn_tunnel_monitor_wait_thread(monitor);
@@ -91,7 +97,7 @@ impl ConnectingState {
     }
 
     fn spawn_tunnel_monitor_wait_thread(
-        tunnel_monitor: TunnelMonitor,
+        tunnel_monitor: Box<dyn TunnelHandle>,
     ) -> Option<oneshot::Receiver<Option<ErrorStateCause>>> {
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
 
@@ -120,7 +126,7 @@ impl ConnectingState {
         Some(tunnel_close_event_rx)
     }
 
-    fn wait_for_tunnel_monitor(tunnel_monitor: TunnelMonitor) -> Option<ErrorStateCause> {
+    fn wait_for_tunnel_monitor(tunnel_monitor: Box<dyn TunnelHandle>) -> Option<ErrorStateCause> {
         match tunnel_monitor.wait() {
             Ok(_) => None,
             Err(error) => match error {
@@ -173,6 +179,32 @@ impl ConnectingState {
         ))
     }
 
+    fn reapply_firewall_policy(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence<Self> {
+        use self::EventConsequence::*;
+
+        match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
+            Ok(()) => SameState(self),
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to apply firewall policy for connecting state"
+                    )
+                );
+
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(
+                        firewall::error_details(&error),
+                    )),
+                )
+            }
+        }
+    }
+
     fn handle_commands(
         self,
         commands: &mut mpsc::UnboundedReceiver<TunnelCommand>,
@@ -185,24 +217,36 @@ impl ConnectingState {
                 if let Err(error_cause) = shared_values.set_allow_lan(allow_lan) {
                     self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
                 } else {
-                    match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
-                        Ok(()) => SameState(self),
-                        Err(error) => {
-                            error!(
-                                "{}",
-                                error.display_chain_with_msg(
-                                    "Failed to apply firewall policy for connecting state"
-                                )
-                            );
-
-                            self.disconnect(
-                                shared_values,
-                                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError),
-                            )
-                        }
-                    }
+                    self.reapply_firewall_policy(shared_values)
                 }
             }
+            Ok(TunnelCommand::LanNetworks(lan_networks)) => {
+                if shared_values.set_lan_networks(lan_networks) {
+                    self.reapply_firewall_policy(shared_values)
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::AllowedEndpoints(allowed_endpoints)) => {
+                if shared_values.set_allowed_endpoints(allowed_endpoints) {
+                    self.reapply_firewall_policy(shared_values)
+                } else {
+                    SameState(self)
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::SplitTunnel(split_tunnel)) => {
+                if shared_values.set_split_tunnel(split_tunnel) {
+                    self.reapply_firewall_policy(shared_values)
+                } else {
+                    SameState(self)
+                }
+            }
+            Ok(TunnelCommand::Dns(dns_config)) => {
+                // Only used once connected
+                shared_values.set_dns_config(dns_config);
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
@@ -242,10 +286,19 @@ impl ConnectingState {
                 shared_values,
                 AfterDisconnect::Block(ErrorStateCause::AuthFailed(reason)),
             ),
-            Ok(TunnelEvent::Up(metadata)) => NewState(ConnectedState::enter(
-                shared_values,
-                self.into_connected_state_bootstrap(metadata),
-            )),
+            Ok(TunnelEvent::Up(metadata)) => {
+                if let Err(error) =
+                    tunnel::check_tunnel_gateways(&self.tunnel_parameters, &metadata)
+                {
+                    error!("{}", error.display_chain());
+                    let cause = ErrorStateCause::StartTunnelError(tunnel::error_details(&error));
+                    return self.disconnect(shared_values, AfterDisconnect::Block(cause));
+                }
+                NewState(ConnectedState::enter(
+                    shared_values,
+                    self.into_connected_state_bootstrap(metadata),
+                ))
+            }
             Ok(_) => SameState(self),
             Err(_) => {
                 debug!("The tunnel disconnected unexpectedly");
@@ -337,7 +390,10 @@ impl TunnelState for ConnectingState {
                             "Failed to apply firewall policy for connecting state"
                         )
                     );
-                    ErrorState::enter(shared_values, ErrorStateCause::StartTunnelError)
+                    ErrorState::enter(
+                        shared_values,
+                        ErrorStateCause::StartTunnelError(firewall::error_details(&error)),
+                    )
                 } else {
                     #[cfg(target_os = "android")]
                     {
@@ -351,11 +407,14 @@ impl TunnelState for ConnectingState {
                         }
                     }
 
+                    #[cfg(target_os = "linux")]
+                    let split_dns_servers = split_dns_servers(&shared_values.dns_config.split_dns);
                     match Self::start_tunnel(
                         tunnel_parameters,
-                        &shared_values.log_dir,
-                        &shared_values.resource_dir,
+                        shared_values.tunnel_backend.as_mut(),
                         &mut shared_values.tun_provider,
+                        #[cfg(target_os = "linux")]
+                        &split_dns_servers,
                         retry_attempt,
                     ) {
                         Ok(connecting_state) => {
@@ -395,7 +454,9 @@ impl TunnelState for ConnectingState {
                                     | tunnel::Error::WinnetError(
                                         crate::winnet::Error::GetTapAlias,
                                     ) => ErrorStateCause::TapAdapterProblem,
-                                    _ => ErrorStateCause::StartTunnelError,
+                                    error => ErrorStateCause::StartTunnelError(
+                                        tunnel::error_details(&error),
+                                    ),
                                 };
                                 ErrorState::enter(shared_values, block_reason)
                             }
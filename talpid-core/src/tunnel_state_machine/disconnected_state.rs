@@ -14,6 +14,8 @@ impl DisconnectedState {
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                lan_networks: shared_values.lan_networks.clone(),
+                allowed_endpoints: shared_values.allowed_endpoints.clone(),
             };
             shared_values.firewall.apply_policy(policy).map_err(|e| {
                 e.display_chain_with_msg(
@@ -69,6 +71,29 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self)
             }
+            Ok(TunnelCommand::LanNetworks(lan_networks)) => {
+                if shared_values.set_lan_networks(lan_networks) {
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
+            Ok(TunnelCommand::AllowedEndpoints(allowed_endpoints)) => {
+                if shared_values.set_allowed_endpoints(allowed_endpoints) {
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
+            #[cfg(target_os = "linux")]
+            Ok(TunnelCommand::SplitTunnel(split_tunnel)) => {
+                // Excluded traffic is only let through while connecting or connected
+                shared_values.set_split_tunnel(split_tunnel);
+                SameState(self)
+            }
+            Ok(TunnelCommand::Dns(dns_config)) => {
+                // Only used while connected
+                shared_values.set_dns_config(dns_config);
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 if shared_values.block_when_disconnected != block_when_disconnected {
                     shared_values.block_when_disconnected = block_when_disconnected;
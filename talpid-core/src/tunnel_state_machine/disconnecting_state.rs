@@ -2,7 +2,7 @@ use super::{
     ConnectingState, DisconnectedState, ErrorState, EventConsequence, SharedTunnelStateValues,
     TunnelCommand, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
-use crate::tunnel::CloseHandle;
+use crate::tunnel::TunnelCloseHandle;
 use futures::{
     sync::{mpsc, oneshot},
     Async, Future, Stream,
@@ -35,6 +35,23 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Nothing
                 }
+                Ok(TunnelCommand::LanNetworks(lan_networks)) => {
+                    shared_values.set_lan_networks(lan_networks);
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::AllowedEndpoints(allowed_endpoints)) => {
+                    shared_values.set_allowed_endpoints(allowed_endpoints);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::SplitTunnel(split_tunnel)) => {
+                    shared_values.set_split_tunnel(split_tunnel);
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::Dns(dns_config)) => {
+                    shared_values.set_dns_config(dns_config);
+                    AfterDisconnect::Nothing
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
@@ -52,6 +69,23 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Block(reason)
                 }
+                Ok(TunnelCommand::LanNetworks(lan_networks)) => {
+                    shared_values.set_lan_networks(lan_networks);
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::AllowedEndpoints(allowed_endpoints)) => {
+                    shared_values.set_allowed_endpoints(allowed_endpoints);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::SplitTunnel(split_tunnel)) => {
+                    shared_values.set_split_tunnel(split_tunnel);
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::Dns(dns_config)) => {
+                    shared_values.set_dns_config(dns_config);
+                    AfterDisconnect::Block(reason)
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
@@ -74,6 +108,23 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Ok(TunnelCommand::LanNetworks(lan_networks)) => {
+                    shared_values.set_lan_networks(lan_networks);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::AllowedEndpoints(allowed_endpoints)) => {
+                    shared_values.set_allowed_endpoints(allowed_endpoints);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Ok(TunnelCommand::SplitTunnel(split_tunnel)) => {
+                    shared_values.set_split_tunnel(split_tunnel);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Ok(TunnelCommand::Dns(dns_config)) => {
+                    shared_values.set_dns_config(dns_config);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Reconnect(retry_attempt)
@@ -136,7 +187,7 @@ impl DisconnectingState {
 
 impl TunnelState for DisconnectingState {
     type Bootstrap = (
-        Option<CloseHandle>,
+        Option<Box<dyn TunnelCloseHandle>>,
         Option<oneshot::Receiver<Option<ErrorStateCause>>>,
         AfterDisconnect,
     );
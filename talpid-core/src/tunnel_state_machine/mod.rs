@@ -15,11 +15,14 @@ use self::{
     error_state::ErrorState,
 };
 use crate::{
-    dns::DnsMonitor,
-    firewall::{Firewall, FirewallArguments},
+    dns::{
+        stub::{self, Blocklists, StubResolver, Upstream},
+        DnsBackend, DnsMonitor,
+    },
+    firewall::{self, Firewall, FirewallArguments, FirewallBackend, Tampering},
     mpsc::Sender,
     offline,
-    tunnel::tun_provider::TunProvider,
+    tunnel::{tun_provider::TunProvider, DefaultTunnelBackend, TunnelBackend, TunnelMetadata},
 };
 use futures::{
     sync::{mpsc, oneshot},
@@ -27,15 +30,19 @@ use futures::{
 };
 use std::{
     io,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
-    sync::{mpsc as sync_mpsc, Arc},
+    sync::{mpsc as sync_mpsc, Arc, Weak},
     thread,
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(target_os = "linux")]
+use talpid_types::dns::DnsManager;
 use talpid_types::{
-    net::TunnelParameters,
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    dns::{DnsMode, SplitDnsRule},
+    net::{AllowedEndpoint, LanNetworks, TunnelParameters},
+    tunnel::{ErrorStateCause, FirewallIncident, ParameterGenerationError, TunnelStateTransition},
     ErrorExt,
 };
 use tokio_core::reactor::Core;
@@ -67,6 +74,9 @@ pub enum Error {
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
 pub fn spawn(
     allow_lan: bool,
+    lan_networks: LanNetworks,
+    allowed_endpoints: Vec<AllowedEndpoint>,
+    dns_config: DnsConfig,
     block_when_disconnected: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     log_dir: Option<PathBuf>,
@@ -86,6 +96,19 @@ pub fn spawn(
     .map_err(Error::OfflineMonitorError)?;
     let is_offline = offline_monitor.is_offline();
 
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    let (tampering_tx, tampering_rx) = mpsc::unbounded();
+    #[cfg(target_os = "linux")]
+    {
+        if let Err(error) = firewall::spawn_watchdog(tampering_tx) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Unable to watch the firewall rules for changes")
+            );
+        }
+    }
+    let weak_command_tx = Arc::downgrade(&command_tx);
+
     let tun_provider = TunProvider::new(
         #[cfg(target_os = "android")]
         android_context,
@@ -97,6 +120,9 @@ pub fn spawn(
     thread::spawn(move || {
         match create_event_loop(
             allow_lan,
+            lan_networks,
+            allowed_endpoints,
+            dns_config,
             block_when_disconnected,
             is_offline,
             tunnel_parameters_generator,
@@ -105,6 +131,8 @@ pub fn spawn(
             resource_dir,
             cache_dir,
             command_rx,
+            weak_command_tx,
+            tampering_rx,
             state_change_listener,
             shutdown_tx,
         ) {
@@ -137,6 +165,9 @@ pub fn spawn(
 
 fn create_event_loop(
     allow_lan: bool,
+    lan_networks: LanNetworks,
+    allowed_endpoints: Vec<AllowedEndpoint>,
+    dns_config: DnsConfig,
     block_when_disconnected: bool,
     is_offline: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
@@ -145,21 +176,46 @@ fn create_event_loop(
     resource_dir: PathBuf,
     cache_dir: impl AsRef<Path>,
     commands: mpsc::UnboundedReceiver<TunnelCommand>,
+    command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    firewall_tampering: mpsc::UnboundedReceiver<Tampering>,
     state_change_listener: impl Sender<TunnelStateTransition>,
     shutdown_tx: oneshot::Sender<()>,
 ) -> Result<(Core, impl Future<Item = (), Error = Error>), Error> {
     let reactor = Core::new().map_err(Error::ReactorError)?;
+    let args = if block_when_disconnected {
+        FirewallArguments {
+            initialize_blocked: true,
+            allow_lan: Some(allow_lan),
+        }
+    } else {
+        FirewallArguments {
+            initialize_blocked: false,
+            allow_lan: None,
+        }
+    };
+    let firewall = Firewall::new(args).map_err(Error::InitFirewallError)?;
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    let mut dns_monitor = DnsMonitor::new(cache_dir).map_err(Error::InitDnsMonitorError)?;
+    #[cfg(target_os = "linux")]
+    dns_monitor.set_manager(dns_config.dns_manager);
+    let tunnel_backend = DefaultTunnelBackend::new(log_dir, resource_dir);
+
     let state_machine = TunnelStateMachine::new(
         allow_lan,
+        lan_networks,
+        allowed_endpoints,
+        dns_config,
         block_when_disconnected,
         is_offline,
         tunnel_parameters_generator,
         tun_provider,
-        log_dir,
-        resource_dir,
-        cache_dir,
+        Box::new(firewall),
+        Box::new(dns_monitor),
+        Box::new(tunnel_backend),
         commands,
-    )?;
+        command_tx,
+        firewall_tampering,
+    );
 
     let future = state_machine
         .for_each(move |state_change_event| {
@@ -177,10 +233,57 @@ fn create_event_loop(
     Ok((reactor, future))
 }
 
+/// How DNS is resolved while connected.
+#[derive(Clone)]
+pub struct DnsConfig {
+    /// DNS servers to use instead of the tunnel gateways. The gateways are used if empty.
+    pub custom_dns: Vec<IpAddr>,
+    /// Whether the system DNS is pointed at the local DNS resolver.
+    pub local_dns_resolver: bool,
+    /// The domains that the local DNS resolver blocks. The resolver is used whenever any are
+    /// blocked. Lists can be replaced through a clone of this, without sending a new config.
+    pub blocklists: Blocklists,
+    /// Whether queries are encrypted by the local DNS resolver.
+    pub dns_mode: DnsMode,
+    /// Domains that are resolved by other DNS servers, reached outside the tunnel.
+    pub split_dns: Vec<SplitDnsRule>,
+    /// Which DNS manager the system DNS is set through. It is detected if `None`.
+    #[cfg(target_os = "linux")]
+    pub dns_manager: Option<DnsManager>,
+}
+
+impl DnsConfig {
+    /// Returns the addresses of the encrypted DNS server if one is used, the custom DNS servers
+    /// if there are any, and otherwise the gateways of the given tunnel.
+    pub fn dns_servers(&self, metadata: &TunnelMetadata) -> Vec<IpAddr> {
+        if let DnsMode::Encrypted(server) = &self.dns_mode {
+            return server.bootstrap_addresses.clone();
+        }
+        if !self.custom_dns.is_empty() {
+            return self.custom_dns.clone();
+        }
+        let mut dns_ips = vec![metadata.ipv4_gateway.into()];
+        if let Some(ipv6_gateway) = metadata.ipv6_gateway {
+            dns_ips.push(ipv6_gateway.into());
+        };
+        dns_ips
+    }
+}
+
 /// Representation of external commands for the tunnel state machine.
 pub enum TunnelCommand {
     /// Enable or disable LAN access in the firewall.
     AllowLan(bool),
+    /// Change which networks are considered LAN networks.
+    LanNetworks(LanNetworks),
+    /// Change which endpoints can be reached while connecting or blocked.
+    AllowedEndpoints(Vec<AllowedEndpoint>),
+    /// Let the traffic of excluded processes bypass the tunnel, or stop doing so once no
+    /// processes are excluded.
+    #[cfg(target_os = "linux")]
+    SplitTunnel(bool),
+    /// Change how DNS is resolved while connected.
+    Dns(DnsConfig),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
     /// Notify the state machine of the connectivity of the device.
@@ -202,51 +305,79 @@ pub enum TunnelCommand {
 struct TunnelStateMachine {
     current_state: Option<TunnelStateWrapper>,
     commands: mpsc::UnboundedReceiver<TunnelCommand>,
+    /// Used to block all traffic when the firewall policy could not be restored.
+    command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    /// Changes that other programs made to the applied firewall rules.
+    firewall_tampering: mpsc::UnboundedReceiver<Tampering>,
     shared_values: SharedTunnelStateValues,
 }
 
 impl TunnelStateMachine {
     fn new(
         allow_lan: bool,
+        lan_networks: LanNetworks,
+        allowed_endpoints: Vec<AllowedEndpoint>,
+        dns_config: DnsConfig,
         block_when_disconnected: bool,
         is_offline: bool,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
         tun_provider: TunProvider,
-        log_dir: Option<PathBuf>,
-        resource_dir: PathBuf,
-        cache_dir: impl AsRef<Path>,
+        firewall: Box<dyn FirewallBackend>,
+        dns_monitor: Box<dyn DnsBackend>,
+        tunnel_backend: Box<dyn TunnelBackend>,
         commands: mpsc::UnboundedReceiver<TunnelCommand>,
-    ) -> Result<Self, Error> {
-        let args = if block_when_disconnected {
-            FirewallArguments {
-                initialize_blocked: true,
-                allow_lan: Some(allow_lan),
-            }
-        } else {
-            FirewallArguments {
-                initialize_blocked: false,
-                allow_lan: None,
-            }
-        };
-        let firewall = Firewall::new(args).map_err(Error::InitFirewallError)?;
-        let dns_monitor = DnsMonitor::new(cache_dir).map_err(Error::InitDnsMonitorError)?;
+        command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+        firewall_tampering: mpsc::UnboundedReceiver<Tampering>,
+    ) -> Self {
         let mut shared_values = SharedTunnelStateValues {
             firewall,
             dns_monitor,
+            tunnel_backend,
             allow_lan,
+            lan_networks,
+            allowed_endpoints,
+            split_tunnel: false,
+            dns_blocking_enabled: dns_config.blocklists.is_enabled(),
+            dns_config,
+            dns_resolver: None,
             block_when_disconnected,
             is_offline,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
             tun_provider,
-            log_dir,
-            resource_dir,
         };
 
         let (initial_state, _) = DisconnectedState::enter(&mut shared_values, ());
-        Ok(TunnelStateMachine {
+        TunnelStateMachine {
             current_state: Some(initial_state),
             commands,
+            command_tx,
+            firewall_tampering,
             shared_values,
+        }
+    }
+
+    /// Applies the firewall policy of the current state again after another program changed it.
+    /// If that fails, all traffic is blocked in the error state.
+    fn restore_firewall_policy(&mut self, tampering: Tampering) -> TunnelStateTransition {
+        let restored = match self.shared_values.firewall.reapply_policy() {
+            Ok(()) => true,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to restore the firewall policy")
+                );
+                let cause =
+                    ErrorStateCause::SetFirewallPolicyError(firewall::error_details(&error));
+                if let Some(command_tx) = self.command_tx.upgrade() {
+                    let _ = command_tx.unbounded_send(TunnelCommand::Block(cause));
+                }
+                false
+            }
+        };
+        TunnelStateTransition::FirewallTampered(FirewallIncident {
+            pid: tampering.pid,
+            process_name: tampering.process_name,
+            restored,
         })
     }
 }
@@ -256,6 +387,12 @@ impl Stream for TunnelStateMachine {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.current_state.is_some() {
+            if let Ok(Async::Ready(Some(tampering))) = self.firewall_tampering.poll() {
+                return Ok(Async::Ready(Some(self.restore_firewall_policy(tampering))));
+            }
+        }
+
         while let Some(state_wrapper) = self.current_state.take() {
             match state_wrapper.handle_event(&mut self.commands, &mut self.shared_values) {
                 TunnelStateMachineAction::Repeat(repeat_state_wrapper) => {
@@ -314,10 +451,25 @@ pub trait TunnelParametersGenerator: Send + 'static {
 
 /// Values that are common to all tunnel states.
 struct SharedTunnelStateValues {
-    firewall: Firewall,
-    dns_monitor: DnsMonitor,
+    firewall: Box<dyn FirewallBackend>,
+    dns_monitor: Box<dyn DnsBackend>,
+    /// Starts the tunnels.
+    tunnel_backend: Box<dyn TunnelBackend>,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
+    /// Changes to the networks that LAN access applies to.
+    lan_networks: LanNetworks,
+    /// Endpoints that can be reached outside the tunnel while connecting or blocked.
+    allowed_endpoints: Vec<AllowedEndpoint>,
+    /// Whether any processes are excluded from the tunnel. Only ever set on Linux.
+    split_tunnel: bool,
+    /// How DNS is resolved while connected.
+    dns_config: DnsConfig,
+    /// Whether any domains were blocked when the DNS config was last applied. The lists can
+    /// change without a new config being set.
+    dns_blocking_enabled: bool,
+    /// The local DNS resolver, while it is in use.
+    dns_resolver: Option<StubResolver>,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
     /// True when the computer is known to be offline.
@@ -326,10 +478,6 @@ struct SharedTunnelStateValues {
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
     /// The provider of tunnel devices.
     tun_provider: TunProvider,
-    /// Directory to store tunnel log file.
-    log_dir: Option<PathBuf>,
-    /// Resource directory path.
-    resource_dir: PathBuf,
 }
 
 impl SharedTunnelStateValues {
@@ -347,13 +495,129 @@ impl SharedTunnelStateValues {
                             if allow_lan { "allowing" } else { "blocking" }
                         ))
                     );
-                    return Err(ErrorStateCause::StartTunnelError);
+                    return Err(ErrorStateCause::StartTunnelError(
+                        talpid_types::tunnel::ErrorDetails::from_error("tun provider", &error),
+                    ));
                 }
             }
         }
 
         Ok(())
     }
+
+    /// Returns true if the LAN networks changed and the firewall policy must be reapplied.
+    pub fn set_lan_networks(&mut self, lan_networks: LanNetworks) -> bool {
+        if self.lan_networks != lan_networks {
+            self.lan_networks = lan_networks;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if the allowed endpoints changed and the firewall policy must be reapplied.
+    pub fn set_allowed_endpoints(&mut self, allowed_endpoints: Vec<AllowedEndpoint>) -> bool {
+        if self.allowed_endpoints != allowed_endpoints {
+            self.allowed_endpoints = allowed_endpoints;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if split tunneling was turned on or off and the firewall policy must be
+    /// reapplied. The routes of excluded traffic are changed right away.
+    #[cfg(target_os = "linux")]
+    pub fn set_split_tunnel(&mut self, split_tunnel: bool) -> bool {
+        if self.split_tunnel != split_tunnel {
+            self.split_tunnel = split_tunnel;
+            self.tunnel_backend.set_split_tunnel(split_tunnel);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns true if the DNS config changed and must be reapplied. The blocklists only matter
+    /// if blocking was turned on or off, since the local DNS resolver reads them directly.
+    pub fn set_dns_config(&mut self, dns_config: DnsConfig) -> bool {
+        let dns_blocking_enabled = dns_config.blocklists.is_enabled();
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut changed = self.dns_config.custom_dns != dns_config.custom_dns
+            || self.dns_config.local_dns_resolver != dns_config.local_dns_resolver
+            || self.dns_blocking_enabled != dns_blocking_enabled
+            || self.dns_config.dns_mode != dns_config.dns_mode
+            || self.dns_config.split_dns != dns_config.split_dns;
+        // The DNS monitor keeps the manager, since it chooses the manager each time DNS is set
+        #[cfg(target_os = "linux")]
+        {
+            if self.dns_config.dns_manager != dns_config.dns_manager {
+                changed |= self.dns_monitor.set_manager(dns_config.dns_manager);
+            }
+        }
+        self.dns_config = dns_config;
+        self.dns_blocking_enabled = dns_blocking_enabled;
+        changed
+    }
+
+    /// Returns true if the system DNS should be pointed at the local DNS resolver while
+    /// connected. It is also used to emulate split DNS if the system DNS manager can not route
+    /// domains to other servers by itself.
+    pub fn uses_dns_resolver(&self) -> bool {
+        let dns_config = &self.dns_config;
+        dns_config.local_dns_resolver
+            || dns_config.blocklists.is_enabled()
+            || dns_config.dns_mode != DnsMode::Plain
+            || (!dns_config.split_dns.is_empty() && !self.dns_monitor.supports_split_dns())
+    }
+
+    /// Makes the local DNS resolver forward queries to the given servers, or to the encrypted
+    /// DNS server if one is used, and starts it if it is not running. Returns the address the
+    /// resolver listens on.
+    pub fn start_dns_resolver(&mut self, servers: &[IpAddr]) -> Result<IpAddr, stub::Error> {
+        let upstream = match &self.dns_config.dns_mode {
+            DnsMode::Plain => Upstream::Plain(
+                servers
+                    .iter()
+                    .map(|server| SocketAddr::new(*server, 53))
+                    .collect(),
+            ),
+            DnsMode::Encrypted(server) => Upstream::Encrypted(server.clone()),
+        };
+        let resolver = match self.dns_resolver.take() {
+            Some(resolver) => {
+                resolver.set_upstream(upstream);
+                resolver
+            }
+            None => StubResolver::start(
+                stub::DEFAULT_LISTEN_ADDR,
+                upstream,
+                self.dns_config.blocklists.clone(),
+            )?,
+        };
+        // Split DNS is only emulated if the system DNS manager can not route the domains itself
+        if self.dns_config.split_dns.is_empty() || self.dns_monitor.supports_split_dns() {
+            resolver.set_split_dns(vec![]);
+        } else {
+            resolver.set_split_dns(self.dns_config.split_dns.clone());
+        }
+        let address = resolver.local_addr().ip();
+        self.dns_resolver = Some(resolver);
+        Ok(address)
+    }
+
+    pub fn stop_dns_resolver(&mut self) {
+        self.dns_resolver = None;
+    }
+
+    /// Makes the local DNS resolver, if it is running, answer all queries with failures instead
+    /// of forwarding them, while not connected.
+    pub fn pause_dns_resolver(&self) {
+        if let Some(resolver) = &self.dns_resolver {
+            resolver.set_upstream(Upstream::Plain(vec![]));
+            resolver.set_split_dns(vec![]);
+        }
+    }
 }
 
 /// Asynchronous result of an attempt to progress a state.
@@ -462,3 +726,1100 @@ state_wrapper! {
         Error(ErrorState),
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::{
+        firewall::{self, FirewallPolicy},
+        tunnel::{self, TunnelCloseHandle, TunnelEvent, TunnelHandle, TunnelMetadata},
+    };
+    use futures::executor::{self, Notify, Spawn};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Mutex,
+    };
+    use talpid_types::{
+        net::{
+            openvpn, AllowedEndpoint, Endpoint, GenericTunnelOptions, LanNetworks,
+            TransportProtocol,
+        },
+        tunnel::{ActionAfterDisconnect, ConnectionQuality},
+    };
+
+    /// Everything the fake backends have been asked to do, in order.
+    #[derive(Debug, Clone, PartialEq)]
+    enum BackendCall {
+        ApplyPolicy(FirewallPolicy),
+        ReapplyPolicy,
+        ResetPolicy,
+        SetDns(String, Vec<IpAddr>, Vec<SplitDnsRule>),
+        ResetDns,
+        #[cfg(target_os = "linux")]
+        SetDnsManager(Option<DnsManager>),
+        StartTunnel(u32),
+        SetSplitTunnel(bool),
+    }
+
+    #[derive(Default)]
+    struct Failures {
+        firewall: bool,
+        dns: bool,
+        start_tunnel: bool,
+        parameters: Option<ParameterGenerationError>,
+    }
+
+    #[derive(Clone, Default)]
+    struct Backends {
+        calls: Arc<Mutex<Vec<BackendCall>>>,
+        failures: Arc<Mutex<Failures>>,
+        tunnels: Arc<Mutex<Vec<MockTunnelControl>>>,
+    }
+
+    impl Backends {
+        fn record(&self, call: BackendCall) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn take_calls(&self) -> Vec<BackendCall> {
+            std::mem::replace(&mut *self.calls.lock().unwrap(), vec![])
+        }
+
+        fn last_tunnel(&self) -> MockTunnelControl {
+            self.tunnels
+                .lock()
+                .unwrap()
+                .last()
+                .cloned()
+                .expect("No tunnel has been started")
+        }
+    }
+
+    struct MockFirewall(Backends);
+
+    impl FirewallBackend for MockFirewall {
+        fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), firewall::Error> {
+            self.0.record(BackendCall::ApplyPolicy(policy));
+            if self.0.failures.lock().unwrap().firewall {
+                Err(firewall::Error::NetfilterTableNotSetError)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn reapply_policy(&mut self) -> Result<(), firewall::Error> {
+            self.0.record(BackendCall::ReapplyPolicy);
+            if self.0.failures.lock().unwrap().firewall {
+                Err(firewall::Error::NetfilterTableNotSetError)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn reset_policy(&mut self) -> Result<(), firewall::Error> {
+            self.0.record(BackendCall::ResetPolicy);
+            Ok(())
+        }
+    }
+
+    struct MockDns(Backends);
+
+    impl DnsBackend for MockDns {
+        fn set(
+            &mut self,
+            interface: &str,
+            servers: &[IpAddr],
+            split_dns: &[SplitDnsRule],
+        ) -> Result<(), crate::dns::Error> {
+            self.0.record(BackendCall::SetDns(
+                interface.to_owned(),
+                servers.to_vec(),
+                split_dns.to_vec(),
+            ));
+            if self.0.failures.lock().unwrap().dns {
+                Err(crate::dns::Error::NoDnsMonitor)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn supports_split_dns(&self) -> bool {
+            true
+        }
+
+        fn reset(&mut self) -> Result<(), crate::dns::Error> {
+            self.0.record(BackendCall::ResetDns);
+            Ok(())
+        }
+
+        #[cfg(target_os = "linux")]
+        fn set_manager(&mut self, manager: Option<DnsManager>) -> bool {
+            self.0.record(BackendCall::SetDnsManager(manager));
+            true
+        }
+    }
+
+    /// Allows a test to script the events and the exit of a tunnel started by `MockTunnelBackend`.
+    #[derive(Clone)]
+    struct MockTunnelControl {
+        on_event: Arc<dyn Fn(TunnelEvent) + Send + Sync>,
+        exit_tx: Arc<Mutex<sync_mpsc::Sender<tunnel::Result<()>>>>,
+    }
+
+    impl MockTunnelControl {
+        fn send_event(&self, event: TunnelEvent) {
+            (self.on_event)(event);
+        }
+
+        fn crash(&self) {
+            let _ = self
+                .exit_tx
+                .lock()
+                .unwrap()
+                .send(Err(tunnel::Error::UnsupportedPlatform));
+        }
+    }
+
+    struct MockTunnelBackend(Backends);
+
+    impl TunnelBackend for MockTunnelBackend {
+        fn start(
+            &mut self,
+            tunnel_parameters: &TunnelParameters,
+            on_event: Box<dyn Fn(TunnelEvent) + Send + Sync>,
+            _tun_provider: &mut TunProvider,
+            _split_dns_servers: &[IpAddr],
+        ) -> tunnel::Result<Box<dyn TunnelHandle>> {
+            let port = tunnel_parameters
+                .get_tunnel_endpoint()
+                .endpoint
+                .address
+                .port();
+            self.0.record(BackendCall::StartTunnel(u32::from(port)));
+            if self.0.failures.lock().unwrap().start_tunnel {
+                return Err(tunnel::Error::UnsupportedPlatform);
+            }
+
+            let (exit_tx, exit_rx) = sync_mpsc::channel();
+            let exit_tx = Arc::new(Mutex::new(exit_tx));
+            self.0.tunnels.lock().unwrap().push(MockTunnelControl {
+                on_event: Arc::from(on_event),
+                exit_tx: exit_tx.clone(),
+            });
+            Ok(Box::new(MockTunnel {
+                exit_rx: Mutex::new(exit_rx),
+                exit_tx,
+            }))
+        }
+
+        fn set_split_tunnel(&mut self, enabled: bool) {
+            self.0.record(BackendCall::SetSplitTunnel(enabled));
+        }
+    }
+
+    struct MockTunnel {
+        exit_rx: Mutex<sync_mpsc::Receiver<tunnel::Result<()>>>,
+        exit_tx: Arc<Mutex<sync_mpsc::Sender<tunnel::Result<()>>>>,
+    }
+
+    impl TunnelHandle for MockTunnel {
+        fn close_handle(&self) -> Box<dyn TunnelCloseHandle> {
+            Box::new(MockCloseHandle(self.exit_tx.clone()))
+        }
+
+        fn wait(self: Box<Self>) -> tunnel::Result<()> {
+            self.exit_rx.lock().unwrap().recv().unwrap_or(Ok(()))
+        }
+    }
+
+    struct MockCloseHandle(Arc<Mutex<sync_mpsc::Sender<tunnel::Result<()>>>>);
+
+    impl TunnelCloseHandle for MockCloseHandle {
+        fn close(self: Box<Self>) -> io::Result<()> {
+            let _ = self.0.lock().unwrap().send(Ok(()));
+            Ok(())
+        }
+    }
+
+    /// Generates OpenVPN parameters, using the retry attempt as the relay port so that tests can
+    /// tell attempts apart.
+    struct MockParametersGenerator(Backends);
+
+    impl TunnelParametersGenerator for MockParametersGenerator {
+        fn generate(
+            &mut self,
+            retry_attempt: u32,
+        ) -> Result<TunnelParameters, ParameterGenerationError> {
+            if let Some(error) = self.0.failures.lock().unwrap().parameters.clone() {
+                return Err(error);
+            }
+            Ok(openvpn::TunnelParameters {
+                config: openvpn::ConnectionConfig::new(
+                    relay_endpoint(retry_attempt),
+                    "user".to_owned(),
+                    "pass".to_owned(),
+                ),
+                options: openvpn::TunnelOptions::default(),
+                generic_options: GenericTunnelOptions {
+                    enable_ipv6: false,
+                    excluded_subnets: vec![],
+                    forwarded_ports: vec![],
+                    shared_interfaces: vec![],
+                },
+                proxy: None,
+            }
+            .into())
+        }
+    }
+
+    fn relay_endpoint(retry_attempt: u32) -> Endpoint {
+        Endpoint::new(
+            Ipv4Addr::new(10, 0, 0, 1),
+            retry_attempt as u16,
+            TransportProtocol::Udp,
+        )
+    }
+
+    fn tunnel_metadata() -> TunnelMetadata {
+        TunnelMetadata {
+            interface: "tun0".to_owned(),
+            ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+            ipv4_gateway: Ipv4Addr::new(10, 8, 0, 1),
+            ipv6_gateway: None,
+        }
+    }
+
+    fn dns_config() -> DnsConfig {
+        DnsConfig {
+            custom_dns: vec![],
+            local_dns_resolver: false,
+            blocklists: Blocklists::default(),
+            dns_mode: DnsMode::Plain,
+            split_dns: vec![],
+            #[cfg(target_os = "linux")]
+            dns_manager: None,
+        }
+    }
+
+    struct NoopNotify;
+
+    impl Notify for NoopNotify {
+        fn notify(&self, _id: usize) {}
+    }
+
+    struct TestStateMachine {
+        state_machine: Spawn<TunnelStateMachine>,
+        commands: Arc<mpsc::UnboundedSender<TunnelCommand>>,
+        firewall_tampering: mpsc::UnboundedSender<Tampering>,
+        backends: Backends,
+    }
+
+    impl TestStateMachine {
+        fn new(allow_lan: bool, block_when_disconnected: bool, is_offline: bool) -> Self {
+            Self::with_backends(
+                allow_lan,
+                block_when_disconnected,
+                is_offline,
+                Backends::default(),
+            )
+        }
+
+        fn with_backends(
+            allow_lan: bool,
+            block_when_disconnected: bool,
+            is_offline: bool,
+            backends: Backends,
+        ) -> Self {
+            let (commands, commands_rx) = mpsc::unbounded();
+            let commands = Arc::new(commands);
+            let (firewall_tampering, firewall_tampering_rx) = mpsc::unbounded();
+            let state_machine = TunnelStateMachine::new(
+                allow_lan,
+                LanNetworks::default(),
+                vec![],
+                dns_config(),
+                block_when_disconnected,
+                is_offline,
+                MockParametersGenerator(backends.clone()),
+                TunProvider::new(),
+                Box::new(MockFirewall(backends.clone())),
+                Box::new(MockDns(backends.clone())),
+                Box::new(MockTunnelBackend(backends.clone())),
+                commands_rx,
+                Arc::downgrade(&commands),
+                firewall_tampering_rx,
+            );
+            TestStateMachine {
+                state_machine: executor::spawn(state_machine),
+                commands,
+                firewall_tampering,
+                backends,
+            }
+        }
+
+        fn send(&self, command: TunnelCommand) {
+            self.commands
+                .unbounded_send(command)
+                .expect("State machine has stopped");
+        }
+
+        /// Blocks until the state machine performs its next transition.
+        fn next_transition(&mut self) -> TunnelStateTransition {
+            self.state_machine
+                .wait_stream()
+                .expect("State machine has finished")
+                .expect("State machine failed")
+        }
+
+        /// Lets the state machine handle all pending events, and asserts that it stays in the
+        /// same state.
+        fn assert_no_transition(&mut self) {
+            match self
+                .state_machine
+                .poll_stream_notify(&Arc::new(NoopNotify), 0)
+            {
+                Ok(Async::NotReady) => (),
+                other => panic!("Unexpected state machine poll result: {:?}", other),
+            }
+        }
+
+        fn connect(&mut self) -> MockTunnelControl {
+            self.send(TunnelCommand::Connect);
+            assert_eq!(
+                self.next_transition(),
+                TunnelStateTransition::Connecting(
+                    TunnelParameters::from(expected_parameters(0)).get_tunnel_endpoint()
+                )
+            );
+            let tunnel = self.backends.last_tunnel();
+            tunnel.send_event(TunnelEvent::Up(tunnel_metadata()));
+            match self.next_transition() {
+                TunnelStateTransition::Connected(..) => (),
+                other => panic!("Expected connected state, got {:?}", other),
+            }
+            self.backends.take_calls();
+            tunnel
+        }
+    }
+
+    fn expected_parameters(retry_attempt: u32) -> openvpn::TunnelParameters {
+        match MockParametersGenerator(Backends::default())
+            .generate(retry_attempt)
+            .unwrap()
+        {
+            TunnelParameters::OpenVpn(params) => params,
+            _ => unreachable!(),
+        }
+    }
+
+    fn assert_error_state(
+        transition: TunnelStateTransition,
+        cause: ErrorStateCause,
+        is_blocking: bool,
+    ) {
+        match transition {
+            TunnelStateTransition::Error(error_state) => {
+                assert_eq!(*error_state.cause(), cause);
+                assert_eq!(error_state.is_blocking(), is_blocking);
+            }
+            other => panic!("Expected error state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_starts_disconnected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        assert_eq!(test.backends.take_calls(), vec![BackendCall::ResetPolicy]);
+        test.assert_no_transition();
+    }
+
+    #[test]
+    fn test_starts_blocked_when_block_when_disconnected() {
+        let test = TestStateMachine::new(true, true, false);
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![BackendCall::ApplyPolicy(FirewallPolicy::Blocked {
+                allow_lan: true,
+                lan_networks: LanNetworks::default(),
+                allowed_endpoints: vec![],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_connect_and_disconnect() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.backends.take_calls();
+
+        test.send(TunnelCommand::Connect);
+        match test.next_transition() {
+            TunnelStateTransition::Connecting(endpoint) => {
+                assert_eq!(endpoint.endpoint, relay_endpoint(0))
+            }
+            other => panic!("Expected connecting state, got {:?}", other),
+        }
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::ApplyPolicy(FirewallPolicy::Connecting {
+                    peer_endpoint: relay_endpoint(0),
+                    pingable_hosts: vec![],
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    excluded_subnets: vec![],
+                    split_tunnel: false,
+                    allowed_endpoints: vec![],
+                }),
+                BackendCall::StartTunnel(0),
+            ]
+        );
+
+        test.backends
+            .last_tunnel()
+            .send_event(TunnelEvent::Up(tunnel_metadata()));
+        match test.next_transition() {
+            TunnelStateTransition::Connected(endpoint, interface) => {
+                assert_eq!(endpoint.endpoint, relay_endpoint(0));
+                assert_eq!(interface.name, tunnel_metadata().interface);
+                assert_eq!(interface.ips, tunnel_metadata().ips);
+            }
+            other => panic!("Expected connected state, got {:?}", other),
+        }
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::ApplyPolicy(FirewallPolicy::Connected {
+                    peer_endpoint: relay_endpoint(0),
+                    tunnel: tunnel_metadata(),
+                    dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                    encrypted_dns: None,
+                    split_dns_servers: vec![],
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    excluded_subnets: vec![],
+                    split_tunnel: false,
+                    forwarded_ports: vec![],
+                    shared_interfaces: vec![],
+                }),
+                BackendCall::SetDns(
+                    "tun0".to_owned(),
+                    vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                    vec![]
+                ),
+            ]
+        );
+
+        test.send(TunnelCommand::Disconnect);
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Nothing)
+        );
+        assert_eq!(test.next_transition(), TunnelStateTransition::Disconnected);
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![BackendCall::ResetDns, BackendCall::ResetPolicy]
+        );
+    }
+
+    #[test]
+    fn test_disconnect_while_connecting() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.send(TunnelCommand::Connect);
+        test.next_transition();
+
+        test.send(TunnelCommand::Disconnect);
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Nothing)
+        );
+        assert_eq!(test.next_transition(), TunnelStateTransition::Disconnected);
+    }
+
+    #[test]
+    fn test_blocks_after_disconnect_when_block_when_disconnected() {
+        let mut test = TestStateMachine::new(false, true, false);
+        test.connect();
+
+        test.send(TunnelCommand::Disconnect);
+        test.next_transition();
+        assert_eq!(test.next_transition(), TunnelStateTransition::Disconnected);
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::ResetDns,
+                BackendCall::ApplyPolicy(FirewallPolicy::Blocked {
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    allowed_endpoints: vec![],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_toggle_block_when_disconnected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.backends.take_calls();
+
+        test.send(TunnelCommand::BlockWhenDisconnected(true));
+        test.assert_no_transition();
+        test.send(TunnelCommand::BlockWhenDisconnected(false));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::ApplyPolicy(FirewallPolicy::Blocked {
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    allowed_endpoints: vec![],
+                }),
+                BackendCall::ResetPolicy,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allow_lan_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.send(TunnelCommand::AllowLan(true));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![BackendCall::ApplyPolicy(FirewallPolicy::Connected {
+                peer_endpoint: relay_endpoint(0),
+                tunnel: tunnel_metadata(),
+                dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                encrypted_dns: None,
+                split_dns_servers: vec![],
+                allow_lan: true,
+                lan_networks: LanNetworks::default(),
+                excluded_subnets: vec![],
+                split_tunnel: false,
+                forwarded_ports: vec![],
+                shared_interfaces: vec![],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_change_lan_networks_while_connected() {
+        let mut test = TestStateMachine::new(true, false, false);
+        test.connect();
+
+        let lan_networks = LanNetworks {
+            extra: vec!["100.64.0.0/10".parse().unwrap()],
+            removed_defaults: vec!["10.0.0.0/8".parse().unwrap()],
+        };
+        test.send(TunnelCommand::LanNetworks(lan_networks.clone()));
+        test.assert_no_transition();
+        test.send(TunnelCommand::LanNetworks(lan_networks.clone()));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![BackendCall::ApplyPolicy(FirewallPolicy::Connected {
+                peer_endpoint: relay_endpoint(0),
+                tunnel: tunnel_metadata(),
+                dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                encrypted_dns: None,
+                split_dns_servers: vec![],
+                allow_lan: true,
+                lan_networks,
+                excluded_subnets: vec![],
+                split_tunnel: false,
+                forwarded_ports: vec![],
+                shared_interfaces: vec![],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_change_custom_dns_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        let custom_dns = vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 53))];
+        let config = DnsConfig {
+            custom_dns: custom_dns.clone(),
+            ..dns_config()
+        };
+        test.send(TunnelCommand::Dns(config.clone()));
+        test.assert_no_transition();
+        test.send(TunnelCommand::Dns(config));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::ApplyPolicy(FirewallPolicy::Connected {
+                    peer_endpoint: relay_endpoint(0),
+                    tunnel: tunnel_metadata(),
+                    dns_servers: custom_dns.clone(),
+                    encrypted_dns: None,
+                    split_dns_servers: vec![],
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    excluded_subnets: vec![],
+                    split_tunnel: false,
+                    forwarded_ports: vec![],
+                    shared_interfaces: vec![],
+                }),
+                BackendCall::SetDns("tun0".to_owned(), custom_dns, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_split_dns_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        let office_dns = IpAddr::V4(Ipv4Addr::new(192, 168, 10, 53));
+        let split_dns = vec![SplitDnsRule {
+            domain: "corp.example".to_owned(),
+            servers: vec![office_dns],
+        }];
+        let config = DnsConfig {
+            split_dns: split_dns.clone(),
+            ..dns_config()
+        };
+        test.send(TunnelCommand::Dns(config.clone()));
+        test.assert_no_transition();
+        test.send(TunnelCommand::Dns(config));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::ApplyPolicy(FirewallPolicy::Connected {
+                    peer_endpoint: relay_endpoint(0),
+                    tunnel: tunnel_metadata(),
+                    dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                    encrypted_dns: None,
+                    split_dns_servers: vec![office_dns],
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    excluded_subnets: vec![],
+                    split_tunnel: false,
+                    forwarded_ports: vec![],
+                    shared_interfaces: vec![],
+                }),
+                BackendCall::SetDns(
+                    "tun0".to_owned(),
+                    vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                    split_dns
+                ),
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_change_dns_manager_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.send(TunnelCommand::Dns(DnsConfig {
+            dns_manager: Some(DnsManager::StaticFile),
+            ..dns_config()
+        }));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::SetDnsManager(Some(DnsManager::StaticFile)),
+                BackendCall::ApplyPolicy(FirewallPolicy::Connected {
+                    peer_endpoint: relay_endpoint(0),
+                    tunnel: tunnel_metadata(),
+                    dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                    encrypted_dns: None,
+                    split_dns_servers: vec![],
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    excluded_subnets: vec![],
+                    split_tunnel: false,
+                    forwarded_ports: vec![],
+                    shared_interfaces: vec![],
+                }),
+                BackendCall::SetDns(
+                    "tun0".to_owned(),
+                    vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                    vec![]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_tunnel_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.send(TunnelCommand::SplitTunnel(true));
+        test.assert_no_transition();
+        test.send(TunnelCommand::SplitTunnel(true));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![
+                BackendCall::SetSplitTunnel(true),
+                BackendCall::ApplyPolicy(FirewallPolicy::Connected {
+                    peer_endpoint: relay_endpoint(0),
+                    tunnel: tunnel_metadata(),
+                    dns_servers: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1))],
+                    encrypted_dns: None,
+                    split_dns_servers: vec![],
+                    allow_lan: false,
+                    lan_networks: LanNetworks::default(),
+                    excluded_subnets: vec![],
+                    split_tunnel: true,
+                    forwarded_ports: vec![],
+                    shared_interfaces: vec![],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_allowed_endpoints_while_blocked() {
+        let mut test = TestStateMachine::new(false, true, false);
+        test.backends.take_calls();
+
+        let allowed_endpoints = vec![AllowedEndpoint {
+            network: "203.0.113.0/24".parse().unwrap(),
+            port: 443,
+            protocol: TransportProtocol::Tcp,
+        }];
+        test.send(TunnelCommand::AllowedEndpoints(allowed_endpoints.clone()));
+        test.assert_no_transition();
+        test.send(TunnelCommand::AllowedEndpoints(allowed_endpoints.clone()));
+        test.assert_no_transition();
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![BackendCall::ApplyPolicy(FirewallPolicy::Blocked {
+                allow_lan: false,
+                lan_networks: LanNetworks::default(),
+                allowed_endpoints,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_reconnect_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.send(TunnelCommand::Connect);
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Reconnect)
+        );
+        match test.next_transition() {
+            TunnelStateTransition::Connecting(endpoint) => {
+                assert_eq!(endpoint.endpoint, relay_endpoint(0))
+            }
+            other => panic!("Expected connecting state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_quality_changes_are_reported_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        let tunnel = test.connect();
+
+        let quality = ConnectionQuality {
+            score: 40,
+            degraded: true,
+            handshake_age: Some(10),
+            ping_rtt_ms: Some(500),
+            ping_loss: 60,
+            rx_stall: 0,
+        };
+        tunnel.send_event(TunnelEvent::QualityChanged(quality.clone()));
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::QualityChanged(quality)
+        );
+        assert_eq!(test.backends.take_calls(), vec![]);
+        test.assert_no_transition();
+    }
+
+    #[test]
+    fn test_tunnel_down_reconnects() {
+        let mut test = TestStateMachine::new(false, false, false);
+        let tunnel = test.connect();
+
+        tunnel.send_event(TunnelEvent::Down);
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Reconnect)
+        );
+        match test.next_transition() {
+            TunnelStateTransition::Connecting(_) => (),
+            other => panic!("Expected connecting state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tunnel_crash_while_connecting_increases_retry_attempt() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.send(TunnelCommand::Connect);
+        test.next_transition();
+
+        test.backends.last_tunnel().crash();
+        match test.next_transition() {
+            TunnelStateTransition::Connecting(endpoint) => {
+                assert_eq!(endpoint.endpoint, relay_endpoint(1))
+            }
+            other => panic!("Expected connecting state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_auth_failed_while_connecting() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.send(TunnelCommand::Connect);
+        test.next_transition();
+
+        test.backends
+            .last_tunnel()
+            .send_event(TunnelEvent::AuthFailed(Some("[EXPIRED]".to_owned())));
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Block)
+        );
+        assert_error_state(
+            test.next_transition(),
+            ErrorStateCause::AuthFailed(Some("[EXPIRED]".to_owned())),
+            true,
+        );
+    }
+
+    #[test]
+    fn test_connect_while_offline() {
+        let mut test = TestStateMachine::new(false, false, true);
+        test.backends.take_calls();
+
+        test.send(TunnelCommand::Connect);
+        assert_error_state(test.next_transition(), ErrorStateCause::IsOffline, true);
+        assert_eq!(
+            test.backends.take_calls(),
+            vec![BackendCall::ApplyPolicy(FirewallPolicy::Blocked {
+                allow_lan: false,
+                lan_networks: LanNetworks::default(),
+                allowed_endpoints: vec![],
+            })]
+        );
+
+        test.send(TunnelCommand::IsOffline(false));
+        match test.next_transition() {
+            TunnelStateTransition::Connecting(_) => (),
+            other => panic!("Expected connecting state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_going_offline_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.send(TunnelCommand::IsOffline(true));
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Block)
+        );
+        assert_error_state(test.next_transition(), ErrorStateCause::IsOffline, true);
+    }
+
+    #[test]
+    fn test_going_online_while_disconnecting_reconnects() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.send(TunnelCommand::IsOffline(true));
+        test.send(TunnelCommand::IsOffline(false));
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Block)
+        );
+        match test.next_transition() {
+            TunnelStateTransition::Connecting(_) => (),
+            other => panic!("Expected connecting state, got {:?}", other),
+        }
+    }
+
+    fn start_tunnel_error() -> ErrorStateCause {
+        ErrorStateCause::StartTunnelError(tunnel::error_details(
+            &tunnel::Error::UnsupportedPlatform,
+        ))
+    }
+
+    #[test]
+    fn test_block_and_unblock() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.send(TunnelCommand::Block(start_tunnel_error()));
+        assert_error_state(test.next_transition(), start_tunnel_error(), true);
+
+        test.send(TunnelCommand::Disconnect);
+        assert_eq!(test.next_transition(), TunnelStateTransition::Disconnected);
+    }
+
+    #[test]
+    fn test_connect_from_error_state() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.send(TunnelCommand::Block(start_tunnel_error()));
+        test.next_transition();
+
+        test.send(TunnelCommand::Connect);
+        match test.next_transition() {
+            TunnelStateTransition::Connecting(_) => (),
+            other => panic!("Expected connecting state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parameter_generation_failure() {
+        let backends = Backends::default();
+        backends.failures.lock().unwrap().parameters =
+            Some(ParameterGenerationError::NoMatchingRelay);
+        let mut test = TestStateMachine::with_backends(false, false, false, backends);
+
+        test.send(TunnelCommand::Connect);
+        assert_error_state(
+            test.next_transition(),
+            ErrorStateCause::TunnelParameterError(ParameterGenerationError::NoMatchingRelay),
+            true,
+        );
+    }
+
+    #[test]
+    fn test_start_tunnel_failure() {
+        let backends = Backends::default();
+        backends.failures.lock().unwrap().start_tunnel = true;
+        let mut test = TestStateMachine::with_backends(false, false, false, backends);
+
+        test.send(TunnelCommand::Connect);
+        let cause = start_tunnel_error();
+        assert_error_state(test.next_transition(), cause.clone(), true);
+
+        let details = cause.details().unwrap();
+        assert_eq!(details.backend, "tunnel");
+        assert_eq!(details.errno, None);
+        assert!(!details.retry_likely);
+    }
+
+    #[test]
+    fn test_firewall_failure_while_connecting() {
+        let backends = Backends::default();
+        backends.failures.lock().unwrap().firewall = true;
+        let mut test = TestStateMachine::with_backends(false, false, false, backends);
+
+        test.send(TunnelCommand::Connect);
+        assert_error_state(
+            test.next_transition(),
+            ErrorStateCause::StartTunnelError(firewall::error_details(
+                &firewall::Error::NetfilterTableNotSetError,
+            )),
+            false,
+        );
+    }
+
+    #[test]
+    fn test_firewall_failure_when_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.send(TunnelCommand::Connect);
+        test.next_transition();
+
+        test.backends.failures.lock().unwrap().firewall = true;
+        test.backends
+            .last_tunnel()
+            .send_event(TunnelEvent::Up(tunnel_metadata()));
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Block)
+        );
+        let cause = ErrorStateCause::SetFirewallPolicyError(firewall::error_details(
+            &firewall::Error::NetfilterTableNotSetError,
+        ));
+        assert_error_state(test.next_transition(), cause.clone(), false);
+        assert_eq!(cause.details().unwrap().backend, "nftables");
+    }
+
+    fn tampering() -> Tampering {
+        Tampering {
+            pid: Some(4242),
+            process_name: Some("nft".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_firewall_tampering_while_connected() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.firewall_tampering.unbounded_send(tampering()).unwrap();
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::FirewallTampered(FirewallIncident {
+                pid: Some(4242),
+                process_name: Some("nft".to_owned()),
+                restored: true,
+            })
+        );
+        assert_eq!(test.backends.take_calls(), vec![BackendCall::ReapplyPolicy]);
+        test.assert_no_transition();
+    }
+
+    #[test]
+    fn test_unrestorable_firewall_tampering_blocks() {
+        let mut test = TestStateMachine::new(false, false, false);
+        test.connect();
+
+        test.backends.failures.lock().unwrap().firewall = true;
+        test.firewall_tampering.unbounded_send(tampering()).unwrap();
+        match test.next_transition() {
+            TunnelStateTransition::FirewallTampered(incident) => assert!(!incident.restored),
+            other => panic!("Expected firewall incident, got {:?}", other),
+        }
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Block)
+        );
+        let cause = ErrorStateCause::SetFirewallPolicyError(firewall::error_details(
+            &firewall::Error::NetfilterTableNotSetError,
+        ));
+        assert_error_state(test.next_transition(), cause, false);
+    }
+
+    #[test]
+    fn test_dns_failure_when_connected() {
+        let backends = Backends::default();
+        backends.failures.lock().unwrap().dns = true;
+        let mut test = TestStateMachine::with_backends(false, false, false, backends);
+        test.send(TunnelCommand::Connect);
+        test.next_transition();
+
+        test.backends
+            .last_tunnel()
+            .send_event(TunnelEvent::Up(tunnel_metadata()));
+        assert_eq!(
+            test.next_transition(),
+            TunnelStateTransition::Disconnecting(ActionAfterDisconnect::Block)
+        );
+        let cause = ErrorStateCause::SetDnsError(crate::dns::error_details(
+            &crate::dns::Error::NoDnsMonitor,
+        ));
+        assert_error_state(test.next_transition(), cause.clone(), true);
+        assert_eq!(cause.details().unwrap().backend, "none");
+    }
+
+    #[test]
+    fn test_shuts_down_when_commands_close() {
+        let TestStateMachine {
+            mut state_machine,
+            commands,
+            ..
+        } = TestStateMachine::new(false, false, false);
+        std::mem::drop(commands);
+        assert!(state_machine.wait_stream().is_none());
+    }
+}
@@ -0,0 +1,681 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fmt, net::IpAddr, str::FromStr};
+
+/// A category of domains that the local DNS resolver can block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlocklistCategory {
+    /// Domains that serve ads.
+    Ads,
+    /// Domains that track users across sites and apps.
+    Trackers,
+    /// Domains that spread malware or host phishing sites.
+    Malware,
+}
+
+impl BlocklistCategory {
+    pub const ALL: [BlocklistCategory; 3] = [
+        BlocklistCategory::Ads,
+        BlocklistCategory::Trackers,
+        BlocklistCategory::Malware,
+    ];
+
+    /// The name of the category, as used in file names and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            BlocklistCategory::Ads => "ads",
+            BlocklistCategory::Trackers => "trackers",
+            BlocklistCategory::Malware => "malware",
+        }
+    }
+}
+
+impl fmt::Display for BlocklistCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BlocklistCategory {
+    type Err = BlocklistCategoryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BlocklistCategory::ALL
+            .iter()
+            .find(|category| category.name() == s)
+            .copied()
+            .ok_or(BlocklistCategoryParseError)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlocklistCategoryParseError;
+
+impl fmt::Display for BlocklistCategoryParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Not a valid blocklist category")
+    }
+}
+
+/// How the local DNS resolver answers queries for blocked names.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockedResponse {
+    /// Answer that the name does not exist.
+    Nxdomain,
+    /// Answer address queries with `0.0.0.0` or `::`, and other queries with no records.
+    Unspecified,
+}
+
+impl Default for BlockedResponse {
+    fn default() -> Self {
+        BlockedResponse::Nxdomain
+    }
+}
+
+impl fmt::Display for BlockedResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockedResponse::Nxdomain => f.write_str("NXDOMAIN"),
+            BlockedResponse::Unspecified => f.write_str("unspecified address"),
+        }
+    }
+}
+
+/// Which domains the local DNS resolver blocks, and how.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsBlocking {
+    /// The categories of domains to block. Nothing is blocked if empty.
+    pub categories: BTreeSet<BlocklistCategory>,
+    pub response: BlockedResponse,
+}
+
+/// How many queries the blocklist of a category has blocked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlocklistStats {
+    pub category: BlocklistCategory,
+    /// How many domains are on the loaded list.
+    pub domains: usize,
+    /// How many queries have been answered as blocked since the daemon started.
+    pub blocked_queries: u64,
+}
+
+/// How DNS queries are sent while connected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsMode {
+    /// Unencrypted queries to the relay or the custom DNS servers.
+    Plain,
+    /// Encrypted queries to the given server, sent by the local DNS resolver.
+    Encrypted(EncryptedDnsServer),
+}
+
+impl Default for DnsMode {
+    fn default() -> Self {
+        DnsMode::Plain
+    }
+}
+
+impl fmt::Display for DnsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsMode::Plain => f.write_str("plain"),
+            DnsMode::Encrypted(server) => server.fmt(f),
+        }
+    }
+}
+
+/// How queries to an encrypted DNS server are encrypted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsProtocol {
+    /// DNS-over-HTTPS, as specified in RFC 8484.
+    Https,
+    /// DNS-over-TLS, as specified in RFC 7858.
+    Tls,
+}
+
+impl EncryptedDnsProtocol {
+    /// The TCP port that servers of the protocol listen on.
+    pub fn port(self) -> u16 {
+        match self {
+            EncryptedDnsProtocol::Https => 443,
+            EncryptedDnsProtocol::Tls => 853,
+        }
+    }
+}
+
+impl fmt::Display for EncryptedDnsProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptedDnsProtocol::Https => f.write_str("DNS-over-HTTPS"),
+            EncryptedDnsProtocol::Tls => f.write_str("DNS-over-TLS"),
+        }
+    }
+}
+
+/// A DNS server that is reached over an encrypted connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedDnsServer {
+    pub protocol: EncryptedDnsProtocol,
+    /// The name that the certificate of the server must be valid for.
+    pub hostname: String,
+    /// The path that queries are posted to over HTTPS, such as `/dns-query`. Unused over TLS.
+    #[serde(default)]
+    pub path: String,
+    /// The addresses to connect to, in order of preference. The hostname is never resolved,
+    /// since that would require DNS.
+    pub bootstrap_addresses: Vec<IpAddr>,
+}
+
+/// An encrypted DNS server that can not be used.
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+pub enum InvalidEncryptedDnsServer {
+    /// The hostname is empty or contains characters that are not allowed in a hostname.
+    #[error(display = "The DNS server hostname \"{}\" is invalid", _0)]
+    InvalidHostname(String),
+
+    /// The path does not start with a slash or contains whitespace.
+    #[error(display = "The DNS-over-HTTPS path \"{}\" is invalid", _0)]
+    InvalidPath(String),
+
+    /// There are no addresses to connect to.
+    #[error(display = "The encrypted DNS server has no bootstrap addresses")]
+    NoBootstrapAddresses,
+}
+
+/// Checks that the encrypted DNS server of the mode, if any, has addresses to connect to, and a
+/// hostname and path that can be sent to it.
+pub fn validate_dns_mode(dns_mode: &DnsMode) -> Result<(), InvalidEncryptedDnsServer> {
+    let server = match dns_mode {
+        DnsMode::Plain => return Ok(()),
+        DnsMode::Encrypted(server) => server,
+    };
+    let valid_hostname = !server.hostname.is_empty()
+        && server
+            .hostname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if !valid_hostname {
+        return Err(InvalidEncryptedDnsServer::InvalidHostname(
+            server.hostname.clone(),
+        ));
+    }
+    let valid_path = server.path.is_empty()
+        || (server.path.starts_with('/') && server.path.chars().all(|c| c.is_ascii_graphic()));
+    if server.protocol == EncryptedDnsProtocol::Https && !valid_path {
+        return Err(InvalidEncryptedDnsServer::InvalidPath(server.path.clone()));
+    }
+    if server.bootstrap_addresses.is_empty() {
+        return Err(InvalidEncryptedDnsServer::NoBootstrapAddresses);
+    }
+    Ok(())
+}
+
+impl fmt::Display for EncryptedDnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.protocol {
+            EncryptedDnsProtocol::Https => write!(
+                f,
+                "{} to https://{}{}",
+                self.protocol, self.hostname, self.path
+            )?,
+            EncryptedDnsProtocol::Tls => write!(f, "{} to {}", self.protocol, self.hostname)?,
+        }
+        write!(
+            f,
+            " at {}",
+            self.bootstrap_addresses
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// Sends queries for a domain and its subdomains to the given resolvers instead of the tunnel
+/// DNS. The resolvers are reached outside the tunnel, so that internal domains of a network that
+/// only its own resolvers know keep working while connected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitDnsRule {
+    /// The domain suffix, such as `corp.example`, without a leading or trailing dot.
+    pub domain: String,
+    /// The resolvers to query, in order of preference.
+    pub servers: Vec<IpAddr>,
+}
+
+impl SplitDnsRule {
+    /// Returns true if the name is the domain of the rule or one of its subdomains.
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.');
+        if name.len() < self.domain.len() {
+            return false;
+        }
+        let (prefix, suffix) = name.split_at(name.len() - self.domain.len());
+        suffix.eq_ignore_ascii_case(&self.domain) && (prefix.is_empty() || prefix.ends_with('.'))
+    }
+}
+
+impl fmt::Display for SplitDnsRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} via {}",
+            self.domain,
+            self.servers
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// Returns the servers of all rules, without duplicates.
+pub fn split_dns_servers(rules: &[SplitDnsRule]) -> Vec<IpAddr> {
+    let mut servers = Vec::new();
+    for server in rules.iter().flat_map(|rule| rule.servers.iter()) {
+        if !servers.contains(server) {
+            servers.push(*server);
+        }
+    }
+    servers
+}
+
+/// A split DNS rule that can not be used.
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+pub enum InvalidSplitDnsRule {
+    /// The domain is empty or contains characters that are not allowed in a domain name.
+    #[error(display = "The split DNS domain \"{}\" is invalid", _0)]
+    InvalidDomain(String),
+
+    /// There are no resolvers to send the queries to.
+    #[error(display = "The split DNS rule for {} has no DNS servers", _0)]
+    NoServers(String),
+}
+
+/// Checks that the rule has a valid domain and resolvers to send queries for it to.
+pub fn validate_split_dns_rule(rule: &SplitDnsRule) -> Result<(), InvalidSplitDnsRule> {
+    let valid_domain = !rule.domain.is_empty()
+        && !rule.domain.starts_with('.')
+        && !rule.domain.ends_with('.')
+        && !rule.domain.contains("..")
+        && rule
+            .domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_');
+    if !valid_domain {
+        return Err(InvalidSplitDnsRule::InvalidDomain(rule.domain.clone()));
+    }
+    if rule.servers.is_empty() {
+        return Err(InvalidSplitDnsRule::NoServers(rule.domain.clone()));
+    }
+    Ok(())
+}
+
+/// A program that the system DNS servers are set through on Linux.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsManager {
+    /// systemd-resolved, which is given the servers of the tunnel interface over D-Bus.
+    SystemdResolved,
+    /// NetworkManager, which is given global DNS servers over D-Bus.
+    NetworkManager,
+    /// The `resolvconf` program, which is given a record for the tunnel interface.
+    Resolvconf,
+    /// `/etc/resolv.conf`, which is written directly and rewritten if another program changes it.
+    StaticFile,
+}
+
+impl DnsManager {
+    /// All DNS managers, in the order they are tried when detecting which one to use.
+    pub const ALL: [DnsManager; 4] = [
+        DnsManager::SystemdResolved,
+        DnsManager::NetworkManager,
+        DnsManager::Resolvconf,
+        DnsManager::StaticFile,
+    ];
+
+    /// The name of the DNS manager, as used on the command line and in the `TALPID_DNS_MODULE`
+    /// environment variable.
+    pub fn name(self) -> &'static str {
+        match self {
+            DnsManager::SystemdResolved => "systemd",
+            DnsManager::NetworkManager => "network-manager",
+            DnsManager::Resolvconf => "resolvconf",
+            DnsManager::StaticFile => "static-file",
+        }
+    }
+}
+
+impl fmt::Display for DnsManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for DnsManager {
+    type Err = DnsManagerParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DnsManager::ALL
+            .iter()
+            .find(|manager| manager.name() == s)
+            .copied()
+            .ok_or(DnsManagerParseError)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsManagerParseError;
+
+impl fmt::Display for DnsManagerParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Not a valid DNS manager")
+    }
+}
+
+/// Why a DNS manager was chosen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsManagerSelection {
+    /// It was the first DNS manager that works on this system.
+    Detected,
+    /// It was forced by the `TALPID_DNS_MODULE` environment variable.
+    Environment,
+    /// It was forced by the settings.
+    Settings,
+}
+
+impl fmt::Display for DnsManagerSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsManagerSelection::Detected => f.write_str("detected"),
+            DnsManagerSelection::Environment => {
+                f.write_str("forced by the TALPID_DNS_MODULE environment variable")
+            }
+            DnsManagerSelection::Settings => f.write_str("forced by the settings"),
+        }
+    }
+}
+
+/// A DNS manager that was tried and can not be used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedDnsManager {
+    pub manager: DnsManager,
+    /// The error that it failed with.
+    pub reason: String,
+}
+
+/// The DNS config that was set through a DNS manager.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppliedDnsConfig {
+    pub manager: DnsManager,
+    /// The tunnel interface that the servers were set for.
+    pub interface: String,
+    pub servers: Vec<IpAddr>,
+    pub split_dns: Vec<SplitDnsRule>,
+}
+
+/// Whether the system still uses the DNS config that was set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsSystemState {
+    /// No DNS config is set.
+    NotSet,
+    /// The system uses the servers that were set.
+    Matches,
+    /// The system uses other servers than the ones that were set.
+    Differs { servers: Vec<IpAddr> },
+    /// The servers that the system uses could not be read.
+    Unknown { reason: String },
+}
+
+impl fmt::Display for DnsSystemState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsSystemState::NotSet => f.write_str("nothing is set"),
+            DnsSystemState::Matches => f.write_str("uses the servers that were set"),
+            DnsSystemState::Differs { servers } => write!(
+                f,
+                "uses other servers: {}",
+                servers
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            DnsSystemState::Unknown { reason } => write!(f, "unknown, {}", reason),
+        }
+    }
+}
+
+/// Which DNS manager the system DNS is set through on Linux and why, what was set through it,
+/// and whether the system still uses it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsManagerDiagnostics {
+    /// The DNS manager that is used, or `None` if none of them can be used.
+    pub manager: Option<DnsManager>,
+    pub selection: DnsManagerSelection,
+    /// The DNS managers that were tried before it, and why they can not be used.
+    pub rejected: Vec<RejectedDnsManager>,
+    /// The DNS config that is currently set, if any.
+    pub applied: Option<AppliedDnsConfig>,
+    pub system_state: DnsSystemState,
+}
+
+impl fmt::Display for DnsManagerDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.manager {
+            Some(manager) => writeln!(f, "DNS manager: {} ({})", manager, self.selection)?,
+            None => writeln!(f, "DNS manager: none can be used ({})", self.selection)?,
+        }
+        for rejected in &self.rejected {
+            writeln!(
+                f,
+                "Unusable DNS manager: {}\n    {}",
+                rejected.manager,
+                rejected.reason.replace('\n', "\n    ")
+            )?;
+        }
+        match &self.applied {
+            Some(applied) => {
+                writeln!(
+                    f,
+                    "Set DNS servers: {} for {} via {}",
+                    applied
+                        .servers
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    applied.interface,
+                    applied.manager
+                )?;
+                for rule in &applied.split_dns {
+                    writeln!(f, "Set split DNS rule: {}", rule)?;
+                }
+            }
+            None => writeln!(f, "Set DNS servers: none")?,
+        }
+        write!(f, "System DNS: {}", self.system_state)
+    }
+}
+
+/// Where the DNS leak test makes up names to query, and where it asks which resolvers queried
+/// them. The authoritative server of `domain` records the resolvers that ask for names under it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsLeakCheckerEndpoint {
+    /// The domain that the unique names are made under.
+    pub domain: String,
+    /// The HTTPS URL that the unique id of a test is appended to. It returns a JSON array of the
+    /// resolvers that queried names with that id.
+    pub url: String,
+}
+
+impl Default for DnsLeakCheckerEndpoint {
+    fn default() -> Self {
+        DnsLeakCheckerEndpoint {
+            domain: "dnsleak.am.i.mullvad.net".to_owned(),
+            url: "https://am.i.mullvad.net/dnsleak/".to_owned(),
+        }
+    }
+}
+
+/// A resolver that queried one of the names of a DNS leak test.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeakTestResolver {
+    pub address: IpAddr,
+    /// Whether the checker knows the resolver to be one that is reached through the tunnel.
+    pub trusted: bool,
+    pub hostname: Option<String>,
+}
+
+impl fmt::Display for LeakTestResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if let Some(hostname) = &self.hostname {
+            write!(f, " ({})", hostname)?;
+        }
+        if self.trusted {
+            write!(f, " - trusted")
+        } else {
+            write!(f, " - NOT TRUSTED")
+        }
+    }
+}
+
+/// What happened to a query sent straight to a resolver outside the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectQueryResult {
+    /// The firewall dropped the query, or no response arrived.
+    Blocked,
+    /// The resolver answered, so the query left the device.
+    Answered,
+    /// The interface has no route to the resolver.
+    NoRoute,
+    /// The query could not be sent for another reason.
+    Failed { reason: String },
+}
+
+impl fmt::Display for DirectQueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DirectQueryResult::Blocked => write!(f, "blocked"),
+            DirectQueryResult::Answered => write!(f, "ANSWERED"),
+            DirectQueryResult::NoRoute => write!(f, "no route"),
+            DirectQueryResult::Failed { reason } => write!(f, "not sent: {}", reason),
+        }
+    }
+}
+
+/// A query sent to port 53 of a resolver outside the tunnel, from a network interface.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectQueryTest {
+    pub server: IpAddr,
+    pub interface: String,
+    pub result: DirectQueryResult,
+}
+
+/// The outcome of a DNS leak test.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsLeakVerdict {
+    /// Only trusted resolvers were seen, and no direct query was answered.
+    NoLeak,
+    /// An untrusted resolver was seen, or a direct query was answered.
+    Leak,
+    /// No leak was seen, but the checker saw no resolvers, or no direct query could be shown to
+    /// be blocked.
+    Inconclusive,
+}
+
+impl fmt::Display for DnsLeakVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsLeakVerdict::NoLeak => write!(f, "no DNS leak detected"),
+            DnsLeakVerdict::Leak => write!(f, "DNS IS LEAKING"),
+            DnsLeakVerdict::Inconclusive => write!(f, "inconclusive"),
+        }
+    }
+}
+
+/// What a DNS leak test saw while connected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsLeakReport {
+    pub tunnel_interface: String,
+    /// The domain that the unique names were queried under.
+    pub checker_domain: String,
+    /// How many unique names were looked up through the system resolver.
+    pub queries: usize,
+    /// The resolvers that the checker saw querying the names.
+    pub resolvers: Vec<LeakTestResolver>,
+    /// Why the checker could not tell which resolvers queried the names, if it could not.
+    pub checker_error: Option<String>,
+    pub direct_queries: Vec<DirectQueryTest>,
+}
+
+impl DnsLeakReport {
+    pub fn verdict(&self) -> DnsLeakVerdict {
+        let leaking_resolver = self.resolvers.iter().any(|resolver| !resolver.trusted);
+        let answered = self
+            .direct_queries
+            .iter()
+            .any(|test| test.result == DirectQueryResult::Answered);
+        if leaking_resolver || answered {
+            DnsLeakVerdict::Leak
+        } else if self.checker_error.is_some()
+            || self.resolvers.is_empty()
+            || !self
+                .direct_queries
+                .iter()
+                .any(|test| test.result == DirectQueryResult::Blocked)
+            || self.direct_queries.iter().any(|test| match test.result {
+                DirectQueryResult::Failed { .. } => true,
+                _ => false,
+            })
+        {
+            DnsLeakVerdict::Inconclusive
+        } else {
+            DnsLeakVerdict::NoLeak
+        }
+    }
+}
+
+impl fmt::Display for DnsLeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Tunnel interface: {}", self.tunnel_interface)?;
+        writeln!(
+            f,
+            "Looked up {} unique names under {}",
+            self.queries, self.checker_domain
+        )?;
+        match &self.checker_error {
+            Some(error) => writeln!(
+                f,
+                "Resolvers: unknown\n    {}",
+                error.replace('\n', "\n    ")
+            )?,
+            None if self.resolvers.is_empty() => writeln!(f, "Resolvers: none seen")?,
+            None => {
+                for resolver in &self.resolvers {
+                    writeln!(f, "Resolver: {}", resolver)?;
+                }
+            }
+        }
+        if self.direct_queries.is_empty() {
+            writeln!(f, "Direct queries: none sent")?;
+        }
+        for test in &self.direct_queries {
+            writeln!(
+                f,
+                "Direct query to {} from {}: {}",
+                test.server, test.interface, test.result
+            )?;
+        }
+        write!(f, "Result: {}", self.verdict())
+    }
+}
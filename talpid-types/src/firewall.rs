@@ -0,0 +1,40 @@
+use crate::net::Endpoint;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Describes a tunnel state whose firewall rules should be rendered without being applied. The
+/// remaining parts of the firewall policy, such as whether LAN traffic is allowed, are taken from
+/// the current settings.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallPolicyQuery {
+    /// The state while the tunnel to the given relay is being established.
+    Connecting {
+        peer_endpoint: Endpoint,
+        /// Hosts that are pinged through the tunnel to check that it is up.
+        pingable_hosts: Vec<IpAddr>,
+    },
+    /// The state while the tunnel is up.
+    Connected {
+        peer_endpoint: Endpoint,
+        /// The name of the tunnel interface.
+        interface: String,
+        /// The local IPs on the tunnel interface.
+        ips: Vec<IpAddr>,
+        ipv4_gateway: Ipv4Addr,
+        ipv6_gateway: Option<Ipv6Addr>,
+    },
+    /// The state where all traffic is blocked.
+    Blocked,
+}
+
+/// The packets and bytes that a rule of the applied firewall policy has matched.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RuleStats {
+    /// The name of the chain that the rule is in.
+    pub chain: String,
+    /// The rule, in the syntax that `nft` uses.
+    pub rule: String,
+    pub packets: u64,
+    pub bytes: u64,
+}
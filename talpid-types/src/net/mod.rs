@@ -182,6 +182,159 @@ pub struct GenericTunnelOptions {
     /// Enable configuration of IPv6 on the tunnel interface, allowing IPv6 communication to be
     /// forwarded through the tunnel.
     pub enable_ipv6: bool,
+    /// Destination networks that are reached outside the tunnel, through the default gateway.
+    /// Only applied on Linux.
+    #[serde(default)]
+    pub excluded_subnets: Vec<ipnetwork::IpNetwork>,
+    /// Ports that accept incoming connections through the tunnel. Only applied on Linux.
+    #[serde(default)]
+    pub forwarded_ports: Vec<ForwardedPort>,
+    /// Interfaces, such as container or virtual machine bridges, whose forwarded traffic is
+    /// translated to the tunnel address so that it can be sent through the tunnel. Only applied
+    /// on Linux.
+    #[serde(default)]
+    pub shared_interfaces: Vec<String>,
+}
+
+/// Excluded subnets that would prevent the tunnel from working.
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+pub enum ExcludedSubnetError {
+    /// The network is as large as the routes that send all traffic into the tunnel.
+    #[error(display = "The excluded network {} is too large", _0)]
+    TooLarge(ipnetwork::IpNetwork),
+
+    /// The network contains an address that must be reached through or via the tunnel.
+    #[error(
+        display = "The excluded network {} contains the relay or tunnel address {}",
+        _0,
+        _1
+    )]
+    ContainsAddress(ipnetwork::IpNetwork, IpAddr),
+}
+
+/// Checks that none of the excluded subnets covers a whole half of the address space, since the
+/// tunnel is routed that way, or any of the given relay or tunnel gateway addresses.
+pub fn validate_excluded_subnets(
+    excluded_subnets: &[ipnetwork::IpNetwork],
+    addresses: &[IpAddr],
+) -> Result<(), ExcludedSubnetError> {
+    for subnet in excluded_subnets {
+        if subnet.prefix() <= 1 {
+            return Err(ExcludedSubnetError::TooLarge(*subnet));
+        }
+        if let Some(address) = addresses.iter().find(|address| subnet.contains(**address)) {
+            return Err(ExcludedSubnetError::ContainsAddress(*subnet, *address));
+        }
+    }
+    Ok(())
+}
+
+/// Adjustments to the private networks that can be reached when LAN access is allowed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(default)]
+pub struct LanNetworks {
+    /// Networks that are allowed in addition to the default private networks.
+    pub extra: Vec<ipnetwork::IpNetwork>,
+    /// Default private networks that should not be allowed.
+    pub removed_defaults: Vec<ipnetwork::IpNetwork>,
+}
+
+/// Local network adjustments that can't be applied.
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+pub enum LanNetworksError {
+    /// The extra network covers so much of the address space that allowing it would let most
+    /// traffic bypass the tunnel.
+    #[error(display = "The local network {} is too large", _0)]
+    TooLarge(ipnetwork::IpNetwork),
+
+    /// The firewall on this platform only allows the default local networks.
+    #[error(display = "Custom local networks are not supported on this platform")]
+    Unsupported,
+}
+
+/// Checks that none of the extra LAN networks covers a whole half of the address space, like the
+/// routes that send all traffic into the tunnel do.
+pub fn validate_lan_networks(lan_networks: &LanNetworks) -> Result<(), LanNetworksError> {
+    match lan_networks
+        .extra
+        .iter()
+        .find(|network| network.prefix() <= 1)
+    {
+        Some(network) => Err(LanNetworksError::TooLarge(*network)),
+        None => Ok(()),
+    }
+}
+
+/// A destination that the firewall allows outside the tunnel while connecting or blocked, such as
+/// a captive portal or a single sign-on service that is needed to get online.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct AllowedEndpoint {
+    /// The host or network that can be reached.
+    pub network: ipnetwork::IpNetwork,
+    /// The destination port that can be reached.
+    pub port: u16,
+    pub protocol: TransportProtocol,
+}
+
+impl fmt::Display for AllowedEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} port {} over {}",
+            self.network, self.port, self.protocol
+        )
+    }
+}
+
+/// A port, or range of ports, on the tunnel interface that accepts incoming connections. Relays
+/// that forward ports to the device make them reachable from the internet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct ForwardedPort {
+    pub protocol: TransportProtocol,
+    /// The first port in the range.
+    pub start: u16,
+    /// The last port in the range. Equal to `start` for a single port.
+    pub end: u16,
+}
+
+impl ForwardedPort {
+    pub fn single(protocol: TransportProtocol, port: u16) -> Self {
+        ForwardedPort {
+            protocol,
+            start: port,
+            end: port,
+        }
+    }
+}
+
+impl fmt::Display for ForwardedPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "port {} over {}", self.start, self.protocol)
+        } else {
+            write!(
+                f,
+                "ports {}-{} over {}",
+                self.start, self.end, self.protocol
+            )
+        }
+    }
+}
+
+/// A forwarded port range that does not contain any valid port.
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+#[error(display = "The forwarded port range {}-{} is invalid", _0, _1)]
+pub struct InvalidPortRange(pub u16, pub u16);
+
+/// Checks that every forwarded port range is non-empty and does not include port 0.
+pub fn validate_forwarded_ports(forwarded_ports: &[ForwardedPort]) -> Result<(), InvalidPortRange> {
+    match forwarded_ports
+        .iter()
+        .find(|port| port.start == 0 || port.start > port.end)
+    {
+        Some(port) => Err(InvalidPortRange(port.start, port.end)),
+        None => Ok(()),
+    }
 }
 
 /// Returns a vector of IP networks representing all of the internet.
@@ -191,3 +344,108 @@ pub fn all_of_the_internet() -> Vec<ipnetwork::IpNetwork> {
         "::0/0".parse().expect("Failed to parse ipv6 network"),
     ]
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn relay() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(185, 65, 134, 80))
+    }
+
+    fn gateways() -> Vec<IpAddr> {
+        vec![
+            IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+            IpAddr::V6(Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1)),
+        ]
+    }
+
+    fn validate(subnets: &[&str], addresses: &[IpAddr]) -> Result<(), ExcludedSubnetError> {
+        let subnets = subnets
+            .iter()
+            .map(|subnet| subnet.parse().unwrap())
+            .collect::<Vec<ipnetwork::IpNetwork>>();
+        validate_excluded_subnets(&subnets, addresses)
+    }
+
+    #[test]
+    fn test_excluded_subnets_prefix_boundary() {
+        for subnet in &["0.0.0.0/0", "0.0.0.0/1", "128.0.0.0/1", "::/0", "8000::/1"] {
+            assert_eq!(
+                validate(&[subnet], &[]),
+                Err(ExcludedSubnetError::TooLarge(subnet.parse().unwrap()))
+            );
+        }
+        assert_eq!(validate(&["0.0.0.0/2", "c000::/2"], &[]), Ok(()));
+    }
+
+    #[test]
+    fn test_excluded_subnet_containing_relay() {
+        let mut addresses = vec![relay()];
+        addresses.extend(gateways());
+        assert_eq!(
+            validate(&["192.168.1.0/24", "185.65.0.0/16"], &addresses),
+            Err(ExcludedSubnetError::ContainsAddress(
+                "185.65.0.0/16".parse().unwrap(),
+                relay()
+            ))
+        );
+        assert_eq!(validate(&["185.65.135.0/24"], &addresses), Ok(()));
+    }
+
+    #[test]
+    fn test_excluded_subnet_containing_gateway() {
+        assert_eq!(
+            validate(&["10.0.0.0/8"], &gateways()),
+            Err(ExcludedSubnetError::ContainsAddress(
+                "10.0.0.0/8".parse().unwrap(),
+                gateways()[0]
+            ))
+        );
+        assert_eq!(validate(&["10.65.0.0/16"], &gateways()), Ok(()));
+    }
+
+    #[test]
+    fn test_lan_networks_prefix_boundary() {
+        for network in &["0.0.0.0/0", "0.0.0.0/1", "128.0.0.0/1", "::/0", "8000::/1"] {
+            let lan_networks = LanNetworks {
+                extra: vec!["100.64.0.0/10".parse().unwrap(), network.parse().unwrap()],
+                removed_defaults: vec![],
+            };
+            assert_eq!(
+                validate_lan_networks(&lan_networks),
+                Err(LanNetworksError::TooLarge(network.parse().unwrap()))
+            );
+        }
+
+        let lan_networks = LanNetworks {
+            extra: vec!["0.0.0.0/2".parse().unwrap(), "fd00::/8".parse().unwrap()],
+            removed_defaults: vec!["10.0.0.0/8".parse().unwrap()],
+        };
+        assert_eq!(validate_lan_networks(&lan_networks), Ok(()));
+    }
+
+    #[test]
+    fn test_removed_default_lan_networks_are_not_validated() {
+        let lan_networks = LanNetworks {
+            extra: vec![],
+            removed_defaults: vec!["0.0.0.0/0".parse().unwrap()],
+        };
+        assert_eq!(validate_lan_networks(&lan_networks), Ok(()));
+    }
+
+    #[test]
+    fn test_excluded_ipv6_subnets() {
+        assert_eq!(
+            validate(&["fc00::/7"], &gateways()),
+            Err(ExcludedSubnetError::ContainsAddress(
+                "fc00::/7".parse().unwrap(),
+                gateways()[1]
+            ))
+        );
+        // An IPv6 network never contains an IPv4 address, and vice versa
+        assert_eq!(validate(&["2001:db8::/32", "::/2"], &gateways()), Ok(()));
+        assert_eq!(validate(&["2001:db8::/32"], &[relay()]), Ok(()));
+    }
+}
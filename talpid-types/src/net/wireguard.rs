@@ -66,6 +66,14 @@ pub struct TunnelOptions {
     /// Interval used for automatic key rotation, in hours
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub automatic_rotation: Option<u32>,
+    /// Number of seconds a tunnel may stay degraded before switching to another relay
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub degraded_timeout: Option<u32>,
+    /// Add the tunnel routes to a dedicated routing table that is selected with routing rules,
+    /// instead of to the main table. Only applied on Linux.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub policy_routing: bool,
 }
 
 /// Wireguard x25519 private key
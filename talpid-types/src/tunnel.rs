@@ -2,7 +2,7 @@ use crate::net::TunnelEndpoint;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{error::Error, fmt, io, iter, net::IpAddr};
 
 /// Event resulting from a transition to a new tunnel state.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -14,11 +14,90 @@ pub enum TunnelStateTransition {
     /// Network is secured but tunnel is still connecting.
     Connecting(TunnelEndpoint),
     /// Tunnel is connected.
-    Connected(TunnelEndpoint),
+    Connected(TunnelEndpoint, TunnelInterface),
     /// Disconnecting tunnel.
     Disconnecting(ActionAfterDisconnect),
     /// Tunnel is disconnected but secured by blocking all connections.
     Error(ErrorState),
+    /// The measured quality of the connected tunnel changed. The tunnel remains connected.
+    QualityChanged(ConnectionQuality),
+    /// Another program changed the firewall rules of the current state. The state is unchanged,
+    /// unless the rules could not be restored.
+    FirewallTampered(FirewallIncident),
+}
+
+/// The network interface of a connected tunnel.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TunnelInterface {
+    /// Name of the tunnel device.
+    pub name: String,
+    /// The local IPs on the tunnel device.
+    pub ips: Vec<IpAddr>,
+}
+
+/// Measured quality of a connected tunnel.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ConnectionQuality {
+    /// Overall quality score, from 0 (unusable) to 100 (perfect).
+    pub score: u8,
+    /// True when the tunnel is alive but working poorly.
+    pub degraded: bool,
+    /// Seconds since the last successful handshake with the peer, if known.
+    pub handshake_age: Option<u64>,
+    /// Average round trip time of recent pings through the tunnel, in milliseconds.
+    pub ping_rtt_ms: Option<u32>,
+    /// Percentage of recent pings through the tunnel that went unanswered.
+    pub ping_loss: u8,
+    /// Percentage of recent samples where traffic was sent but none was received.
+    pub rx_stall: u8,
+}
+
+impl fmt::Display for ConnectionQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "score {}{}, ping loss {}%, rx stall {}%",
+            self.score,
+            if self.degraded { " (degraded)" } else { "" },
+            self.ping_loss,
+            self.rx_stall
+        )?;
+        if let Some(rtt) = self.ping_rtt_ms {
+            write!(f, ", rtt {} ms", rtt)?;
+        }
+        if let Some(handshake_age) = self.handshake_age {
+            write!(f, ", last handshake {} s ago", handshake_age)?;
+        }
+        Ok(())
+    }
+}
+
+/// A change that another program made to the firewall rules that enforce the tunnel state.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FirewallIncident {
+    /// ID of the process that made the change, if known.
+    pub pid: Option<u32>,
+    /// Name of the process that made the change, if known.
+    pub process_name: Option<String>,
+    /// True if the rules were applied again. Otherwise all traffic is blocked in the error state.
+    pub restored: bool,
+}
+
+impl fmt::Display for FirewallIncident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Firewall rules changed by ")?;
+        match (&self.process_name, self.pid) {
+            (Some(name), Some(pid)) => write!(f, "{} (PID {})", name, pid)?,
+            (None, Some(pid)) => write!(f, "PID {}", pid)?,
+            (Some(name), None) => write!(f, "{}", name)?,
+            (None, None) => write!(f, "an unknown process")?,
+        }
+        if self.restored {
+            write!(f, ", restored")
+        } else {
+            write!(f, ", unable to restore")
+        }
+    }
 }
 
 /// Action that will be taken after disconnection is complete.
@@ -71,14 +150,16 @@ impl ErrorState {
 pub enum ErrorStateCause {
     /// Authentication with remote server failed.
     AuthFailed(Option<String>),
+    /// The account has no time left, so no tunnel can be authenticated.
+    AccountExpired,
     /// Failed to configure IPv6 because it's disabled in the platform.
     Ipv6Unavailable,
     /// Failed to set firewall policy.
-    SetFirewallPolicyError,
+    SetFirewallPolicyError(#[cfg_attr(target_os = "android", jnix(skip))] ErrorDetails),
     /// Failed to set system DNS server.
-    SetDnsError,
+    SetDnsError(#[cfg_attr(target_os = "android", jnix(skip))] ErrorDetails),
     /// Failed to start connection to remote server.
-    StartTunnelError,
+    StartTunnelError(#[cfg_attr(target_os = "android", jnix(skip))] ErrorDetails),
     /// Tunnel parameter generation failure
     TunnelParameterError(ParameterGenerationError),
     /// This device is offline, no tunnels can be established.
@@ -87,6 +168,70 @@ pub enum ErrorStateCause {
     TapAdapterProblem,
 }
 
+impl ErrorStateCause {
+    /// Returns the details of the failure behind this cause, if there are any.
+    pub fn details(&self) -> Option<&ErrorDetails> {
+        match self {
+            ErrorStateCause::SetFirewallPolicyError(details)
+            | ErrorStateCause::SetDnsError(details)
+            | ErrorStateCause::StartTunnelError(details) => Some(details),
+            _ => None,
+        }
+    }
+}
+
+/// Details about the failure behind an error state cause.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ErrorDetails {
+    /// The system component that failed, such as `nftables` or `systemd-resolved`.
+    pub backend: String,
+    /// OS error code of the underlying failure, if there is one.
+    pub errno: Option<i32>,
+    /// Kind of the underlying I/O failure, if there is one.
+    pub kind: Option<String>,
+    /// Whether trying again is likely to help.
+    pub retry_likely: bool,
+    /// Description of the whole chain of errors.
+    pub message: String,
+}
+
+impl ErrorDetails {
+    /// Creates the details of `error`. The error code and kind are taken from the first I/O error
+    /// in the chain of sources, which also decides if a retry is likely to help.
+    pub fn from_error(backend: impl Into<String>, error: &(dyn Error + 'static)) -> Self {
+        let chain = || iter::successors(Some(error), |&error| error.source());
+        let io_error = chain().find_map(|error| error.downcast_ref::<io::Error>());
+
+        ErrorDetails {
+            backend: backend.into(),
+            errno: io_error.and_then(|error| error.raw_os_error()),
+            kind: io_error.map(|error| format!("{:?}", error.kind())),
+            retry_likely: io_error
+                .map(|error| is_transient(error.kind()))
+                .unwrap_or(false),
+            message: chain()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>()
+                .join(": "),
+        }
+    }
+}
+
+fn is_transient(kind: io::ErrorKind) -> bool {
+    use io::ErrorKind::*;
+    match kind {
+        Interrupted | WouldBlock | TimedOut | ConnectionRefused | ConnectionReset
+        | ConnectionAborted | BrokenPipe | AddrInUse => true,
+        _ => false,
+    }
+}
+
+impl fmt::Display for ErrorDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.backend, self.message)
+    }
+}
+
 /// Errors that can occur when generating tunnel parameters.
 #[derive(err_derive::Error, Debug, Serialize, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -121,10 +266,11 @@ impl fmt::Display for ErrorStateCause {
                     }
                 );
             }
+            AccountExpired => "The account has expired",
             Ipv6Unavailable => "Failed to configure IPv6 because it's disabled in the platform",
-            SetFirewallPolicyError => "Failed to set firewall policy",
-            SetDnsError => "Failed to set system DNS server",
-            StartTunnelError => "Failed to start connection to remote server",
+            SetFirewallPolicyError(_) => "Failed to set firewall policy",
+            SetDnsError(_) => "Failed to set system DNS server",
+            StartTunnelError(_) => "Failed to start connection to remote server",
             TunnelParameterError(ref err) => {
                 return write!(f, "Failure to generate tunnel parameters: {}", err);
             }